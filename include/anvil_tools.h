@@ -0,0 +1,173 @@
+#ifndef ANVIL_TOOLS_H
+#define ANVIL_TOOLS_H
+
+/* Generated by cbindgen from src/capi.rs. Don't edit this file by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ The result of a function which can fail.
+ */
+typedef enum AnvilStatus {
+  /*
+   The function succeeded
+   */
+  ANVIL_OK = 0,
+  /*
+   The region doesn't have the chunk which was asked for
+   */
+  ANVIL_NOT_FOUND = 1,
+  /*
+   The function failed, and `anvil_last_error` says why
+   */
+  ANVIL_ERROR = -1,
+  /*
+   The chunk position is outside of the region (0 to 31 on each axis)
+   */
+  ANVIL_INVALID_POSITION = -2,
+} AnvilStatus;
+
+/*
+ A region file opened for reading.
+ */
+typedef struct AnvilRegion AnvilRegion;
+
+/*
+ A region file opened for writing.
+ */
+typedef struct AnvilWriter AnvilWriter;
+
+/*
+ A block of bytes owned by the library, which must be released with `anvil_buffer_free`.
+ */
+typedef struct AnvilBuffer {
+  uint8_t *data;
+  size_t len;
+} AnvilBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Returns the error of the last function which failed on the calling thread, or null if none has. The string is
+ owned by the library and stays valid until the next function which fails on the same thread.
+ */
+const char *anvil_last_error(void);
+
+/*
+ Opens a region file for reading, returning null if it can't be opened.
+
+ # Safety
+
+ `path` must be a null-terminated string.
+ */
+struct AnvilRegion *anvil_region_open(const char *path);
+
+/*
+ Closes a region file opened with `anvil_region_open`. Passing null does nothing.
+
+ # Safety
+
+ `region` must be null or a region which hasn't been closed yet.
+ */
+void anvil_region_close(struct AnvilRegion *region);
+
+/*
+ Reads and decompresses the NBT data of a chunk given its position within the region (0 to 31 on each axis). The
+ data is written to `out` if the chunk is found, which must then be released with `anvil_buffer_free`.
+
+ # Safety
+
+ `region` must be an open region, and `out` must point to writable memory for a buffer.
+ */
+enum AnvilStatus anvil_region_get_chunk(const struct AnvilRegion *region,
+                                        int32_t x,
+                                        int32_t z,
+                                        struct AnvilBuffer *out);
+
+/*
+ Returns when a chunk was last saved as seconds since the Unix epoch, or zero if the time wasn't recorded, can't be
+ read, or the position is outside of the region.
+
+ # Safety
+
+ `region` must be an open region.
+ */
+uint32_t anvil_region_get_timestamp(const struct AnvilRegion *region,
+                                    int32_t x,
+                                    int32_t z);
+
+/*
+ Creates a region file for writing, replacing any file which already exists at the path, or returns null if it
+ can't be created.
+
+ # Safety
+
+ `path` must be a null-terminated string.
+ */
+struct AnvilWriter *anvil_writer_create(const char *path);
+
+/*
+ Opens an existing region file for writing without truncating it, keeping the chunks which are already present, or
+ returns null if it can't be opened.
+
+ # Safety
+
+ `path` must be a null-terminated string.
+ */
+struct AnvilWriter *anvil_writer_open(const char *path);
+
+/*
+ Compresses the NBT data of a chunk and adds it to the region at its position within the region, replacing any
+ chunk already there.
+
+ # Safety
+
+ `writer` must be an open writer, and `data` must point to `len` readable bytes.
+ */
+enum AnvilStatus anvil_writer_add_chunk(struct AnvilWriter *writer,
+                                        int32_t x,
+                                        int32_t z,
+                                        const uint8_t *data,
+                                        size_t len);
+
+/*
+ Records when a chunk was last saved, as seconds since the Unix epoch.
+
+ # Safety
+
+ `writer` must be an open writer.
+ */
+enum AnvilStatus anvil_writer_set_timestamp(struct AnvilWriter *writer,
+                                            int32_t x,
+                                            int32_t z,
+                                            uint32_t timestamp);
+
+/*
+ Writes every chunk which is still held back and closes the region file. The writer is closed even if this fails.
+
+ # Safety
+
+ `writer` must be null or a writer which hasn't been closed yet.
+ */
+enum AnvilStatus anvil_writer_close(struct AnvilWriter *writer);
+
+/*
+ Releases a buffer returned by the library. Passing a buffer with null data does nothing.
+
+ # Safety
+
+ `buffer` must have been returned by the library and not released already.
+ */
+void anvil_buffer_free(struct AnvilBuffer buffer);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* ANVIL_TOOLS_H */
@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// A version of the archive layout: which entries an archive holds, how they are named and in what order they come, and
+/// how its checksums are computed. Every version is described here so that readers keep understanding the archives
+/// packed by older builds, and so that `pack --format-version` can still write archives which older builds can read.
+///
+/// Every version so far stores each chunk at `r.<x>.<z>/c.<x>.<z>.nbt` (or `.snbt`, or `.gz`, `.zlib` and friends for
+/// raw chunks), optionally preceded by the `LABELS` and `PROFILE` entries and followed by the `DEDUP` and `CHECKSUMS`
+/// entries, where the checksums are the xxHash64 of each chunk's NBT data.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ArchiveLayout {
+    /// Archives packed before the layout was versioned, which don't have a `METADATA` entry. Builds from before it
+    /// refuse any entry they don't know, so this is the version to write for them.
+    Unversioned,
+    /// Starts with a `METADATA` entry, which gives the version of the layout before anything else is read.
+    Metadata,
+}
+
+impl ArchiveLayout {
+    /// The layout written by this build, and the newest one it can read.
+    pub const CURRENT: ArchiveLayout = ArchiveLayout::Metadata;
+
+    const ALL: [ArchiveLayout; 2] = [ArchiveLayout::Unversioned, ArchiveLayout::Metadata];
+
+    /// Returns the layout of a format version, or `None` if it is newer than this build knows about.
+    pub fn from_version(version: u32) -> Option<Self> {
+        ArchiveLayout::ALL
+            .iter()
+            .copied()
+            .find(|layout| layout.version() == version)
+    }
+
+    pub fn version(self) -> u32 {
+        match self {
+            ArchiveLayout::Unversioned => 0,
+            ArchiveLayout::Metadata => 1,
+        }
+    }
+
+    /// Returns true if archives in this layout start with a `METADATA` entry.
+    pub fn has_metadata(self) -> bool {
+        self >= ArchiveLayout::Metadata
+    }
+}
+
+impl fmt::Display for ArchiveLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.version())
+    }
+}
+
+pub fn parse_format_version(value: &str) -> Result<ArchiveLayout, String> {
+    let version = value
+        .parse::<u32>()
+        .map_err(|_| format!("Expected a format version, but found {}", value))?;
+
+    ArchiveLayout::from_version(version).ok_or_else(|| {
+        format!(
+            "Format version {} is newer than this build can write (up to {})",
+            version,
+            ArchiveLayout::CURRENT
+        )
+    })
+}
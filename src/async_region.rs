@@ -0,0 +1,168 @@
+//! Async wrappers around region files for programs which run on a Tokio executor. Region files are read through memory
+//! maps or positioned reads, neither of which has an async form, so each operation runs on Tokio's blocking thread
+//! pool and the executor's own threads are never blocked.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use tokio::task;
+
+use crate::region::{Chunk, ChunkCompression, ChunkPos, RawChunk, RegionFile, RegionFileWriter};
+
+/// A region file which is read from asynchronously. Cloning it is cheap, and every clone reads from the same file.
+#[derive(Clone)]
+pub struct AsyncRegionFile {
+    region: Arc<RegionFile>,
+}
+
+impl AsyncRegionFile {
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+        let region = run_blocking(move || RegionFile::open(&path)).await?;
+
+        Ok(AsyncRegionFile {
+            region: Arc::new(region),
+        })
+    }
+
+    /// Reads and decompresses a chunk given its position within the region, or returns `None` if the region doesn't
+    /// have the chunk.
+    pub async fn get_chunk(&self, position: ChunkPos) -> Result<Option<Chunk>, io::Error> {
+        let region = self.region.clone();
+
+        run_blocking(move || {
+            region
+                .get_raw_chunk(position)?
+                .map(|raw_chunk| raw_chunk.decompress())
+                .transpose()
+        })
+        .await
+    }
+
+    /// Reads a chunk given its position within the region without decompressing it, or returns `None` if the region
+    /// doesn't have the chunk.
+    pub async fn get_raw_chunk(&self, position: ChunkPos) -> Result<Option<RawChunk>, io::Error> {
+        let region = self.region.clone();
+
+        run_blocking(move || region.get_raw_chunk(position)).await
+    }
+
+    /// Returns when a chunk was last saved as seconds since the Unix epoch, given its position within the region, or
+    /// zero if the time wasn't recorded.
+    pub async fn timestamp(&self, position: ChunkPos) -> Result<u32, io::Error> {
+        let region = self.region.clone();
+
+        run_blocking(move || region.timestamp(position)).await
+    }
+
+    /// Returns the region file which is being read, for the operations which have no async form.
+    pub fn blocking(&self) -> &RegionFile {
+        &self.region
+    }
+}
+
+/// A region file which is written to asynchronously. Chunks are written in the order their calls complete, so calls
+/// which must happen in order should be awaited one after the other.
+pub struct AsyncRegionFileWriter {
+    writer: Arc<Mutex<Option<RegionFileWriter>>>,
+}
+
+impl AsyncRegionFileWriter {
+    /// Creates a region file, replacing any file which already exists at the path.
+    pub async fn create(path: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+
+        AsyncRegionFileWriter::from_writer(
+            run_blocking(move || RegionFileWriter::create(&path)).await?,
+        )
+    }
+
+    /// Opens an existing region file for writing without truncating it, keeping the chunks which are already present.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+
+        AsyncRegionFileWriter::from_writer(
+            run_blocking(move || RegionFileWriter::open(&path)).await?,
+        )
+    }
+
+    fn from_writer(writer: RegionFileWriter) -> Result<Self, io::Error> {
+        Ok(AsyncRegionFileWriter {
+            writer: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    /// Sets how the chunks added with `add_chunk` are compressed, given a level from 0 to 9 for the types which have
+    /// levels.
+    pub fn set_compression(&self, compression: ChunkCompression, level: u32) {
+        if let Some(writer) = self.writer.lock().unwrap().as_mut() {
+            writer.set_compression(compression, level);
+        }
+    }
+
+    /// Compresses a chunk and adds it to the region, replacing any chunk at the same position.
+    pub async fn add_chunk(&self, chunk: Chunk) -> Result<(), io::Error> {
+        self.with_writer(move |writer| writer.add_chunk(&chunk))
+            .await
+    }
+
+    /// Adds a chunk which is already compressed, copying its payload verbatim.
+    pub async fn add_raw_chunk(&self, chunk: RawChunk) -> Result<(), io::Error> {
+        self.with_writer(move |writer| writer.add_raw_chunk(&chunk))
+            .await
+    }
+
+    /// Records when a chunk was last saved, as seconds since the Unix epoch.
+    pub async fn set_timestamp(&self, position: ChunkPos, timestamp: u32) -> Result<(), io::Error> {
+        self.with_writer(move |writer| {
+            writer.set_timestamp(position, timestamp);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Writes every chunk which is held back and closes the region file. Dropping the writer instead closes the file
+    /// on the thread which drops it, which blocks that thread.
+    pub async fn finish(self) -> Result<(), io::Error> {
+        let writer = self.writer.clone();
+
+        run_blocking(move || {
+            let writer = writer.lock().unwrap().take();
+
+            match writer {
+                Some(writer) => writer.finish(),
+                None => Ok(()),
+            }
+        })
+        .await
+    }
+
+    async fn with_writer<F>(&self, operation: F) -> Result<(), io::Error>
+    where
+        F: FnOnce(&mut RegionFileWriter) -> Result<(), io::Error> + Send + 'static,
+    {
+        let writer = self.writer.clone();
+
+        run_blocking(move || match writer.lock().unwrap().as_mut() {
+            Some(writer) => operation(writer),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        })
+        .await
+    }
+}
+
+/// Runs an operation on Tokio's blocking thread pool, resuming the panic of the operation if it panicked.
+async fn run_blocking<F, T>(operation: F) -> Result<T, io::Error>
+where
+    F: FnOnce() -> Result<T, io::Error> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(operation).await {
+        Ok(result) => result,
+        Err(err) => match err.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(err) => Err(io::Error::new(io::ErrorKind::Interrupted, err)),
+        },
+    }
+}
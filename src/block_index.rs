@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::blocks::{block_state_matches, count_block_states};
+use crate::messages::{self, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+const INDEX_MAGIC: &[u8; 4] = b"ATBI";
+const INDEX_VERSION: u8 = 1;
+
+/// Precomputed block state counts for every chunk of a world, so that queries over the blocks in a world do not need to
+/// decode every section again.
+///
+/// The index is stored as a magic number and version, followed by a zlib stream holding a table of the block state
+/// strings and then the counts of each chunk, grouped by region. Each region also records the size and modification
+/// time of the file it was built from, which lets readers detect regions that have changed since the index was built.
+#[derive(Default)]
+pub struct BlockIndex {
+    states: Vec<String>,
+    regions: Vec<RegionIndex>,
+}
+
+pub struct RegionIndex {
+    pub name: String,
+    pub file_size: u64,
+    pub modified: u64,
+    pub chunks: Vec<ChunkCounts>,
+}
+
+/// The number of blocks of each state in a chunk, keyed by their index in the state table.
+pub struct ChunkCounts {
+    pub position: ChunkPos,
+    pub counts: Vec<(u32, u32)>,
+}
+
+impl BlockIndex {
+    pub fn load(path: &Path) -> Result<BlockIndex, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != INDEX_MAGIC {
+            return Err(Message::new(messages::BLOCK_INDEX_INVALID)
+                .arg("path", path.display())
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        let version = reader.read_u8()?;
+
+        if version != INDEX_VERSION {
+            return Err(Message::new(messages::BLOCK_INDEX_VERSION)
+                .arg("version", version)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        let mut reader = ZlibDecoder::new(reader);
+        let mut index = BlockIndex::default();
+
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            index.states.push(read_string(&mut reader)?);
+        }
+
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let name = read_string(&mut reader)?;
+            let file_size = reader.read_u64::<BigEndian>()?;
+            let modified = reader.read_u64::<BigEndian>()?;
+
+            let region = RegionFile::try_parse_name(&name).ok_or_else(|| {
+                Message::new(messages::BLOCK_INDEX_REGION_NAME)
+                    .arg("name", &name)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+            let mut chunks = Vec::new();
+
+            for _ in 0..reader.read_u32::<BigEndian>()? {
+                let x = reader.read_u8()? as i32;
+                let z = reader.read_u8()? as i32;
+
+                let mut counts = Vec::new();
+
+                for _ in 0..reader.read_u32::<BigEndian>()? {
+                    let state = reader.read_u32::<BigEndian>()?;
+                    let count = reader.read_u32::<BigEndian>()?;
+
+                    if state as usize >= index.states.len() {
+                        return Err(Message::new(messages::BLOCK_INDEX_UNKNOWN_STATE)
+                            .into_error(io::ErrorKind::InvalidData));
+                    }
+
+                    counts.push((state, count));
+                }
+
+                chunks.push(ChunkCounts {
+                    position: ChunkPos {
+                        x: (region.x * 32) + x,
+                        z: (region.z * 32) + z,
+                    },
+                    counts,
+                });
+            }
+
+            index.regions.push(RegionIndex {
+                name,
+                file_size,
+                modified,
+                chunks,
+            });
+        }
+
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        read_only::ensure_writable(path)?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u8(INDEX_VERSION)?;
+
+        let mut writer = ZlibEncoder::new(writer, Compression::default());
+        writer.write_u32::<BigEndian>(self.states.len() as u32)?;
+
+        for state in &self.states {
+            write_string(&mut writer, state)?;
+        }
+
+        writer.write_u32::<BigEndian>(self.regions.len() as u32)?;
+
+        for region in &self.regions {
+            write_string(&mut writer, &region.name)?;
+            writer.write_u64::<BigEndian>(region.file_size)?;
+            writer.write_u64::<BigEndian>(region.modified)?;
+            writer.write_u32::<BigEndian>(region.chunks.len() as u32)?;
+
+            for chunk in &region.chunks {
+                writer.write_u8(chunk.position.x.rem_euclid(32) as u8)?;
+                writer.write_u8(chunk.position.z.rem_euclid(32) as u8)?;
+                writer.write_u32::<BigEndian>(chunk.counts.len() as u32)?;
+
+                for (state, count) in &chunk.counts {
+                    writer.write_u32::<BigEndian>(*state)?;
+                    writer.write_u32::<BigEndian>(*count)?;
+                }
+            }
+        }
+
+        writer.finish()?.flush()
+    }
+
+    /// Adds the block state counts of a region to the index, replacing any which were previously recorded for it.
+    pub fn add_region(&mut self, name: String, file: FileStamp, chunks: Vec<ChunkBlockCounts>) {
+        let mut state_ids: HashMap<String, u32> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| (state.clone(), id as u32))
+            .collect();
+
+        let chunks = chunks
+            .into_iter()
+            .map(|(position, counts)| {
+                let mut counts: Vec<(u32, u32)> = counts
+                    .into_iter()
+                    .map(|(state, count)| {
+                        let next_id = self.states.len() as u32;
+                        let id = *state_ids.entry(state.clone()).or_insert_with(|| {
+                            self.states.push(state);
+                            next_id
+                        });
+
+                        (id, count as u32)
+                    })
+                    .collect();
+                counts.sort_unstable();
+
+                ChunkCounts { position, counts }
+            })
+            .collect();
+
+        self.regions.retain(|region| region.name != name);
+        self.regions.push(RegionIndex {
+            name,
+            file_size: file.size,
+            modified: file.modified,
+            chunks,
+        });
+        self.regions.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Removes the regions whose names do not satisfy a predicate.
+    pub fn retain_regions<F: Fn(&str) -> bool>(&mut self, predicate: F) {
+        self.regions.retain(|region| predicate(&region.name));
+    }
+
+    pub fn regions(&self) -> &[RegionIndex] {
+        &self.regions
+    }
+
+    pub fn region(&self, name: &str) -> Option<&RegionIndex> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// Returns the entry of a region file if the index holds one which is still current, so that its chunks don't need
+    /// to be decoded again.
+    pub fn current_region(&self, path: &Path) -> Result<Option<&RegionIndex>, io::Error> {
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        match self.region(&name) {
+            Some(region) if region.is_current(&FileStamp::of(path)?) => Ok(Some(region)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the number of blocks of each state in a chunk of the index, keyed by the state strings.
+    pub fn chunk_states(&self, chunk: &ChunkCounts) -> HashMap<String, u64> {
+        chunk
+            .counts
+            .iter()
+            .map(|(state, count)| (self.states[*state as usize].clone(), *count as u64))
+            .collect()
+    }
+
+    /// Returns the ids of the block states which match a query, which may be either a block name or an exact state.
+    pub fn matching_states(&self, query: &str) -> Vec<u32> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| block_state_matches(state, query))
+            .map(|(id, _)| id as u32)
+            .collect()
+    }
+}
+
+impl ChunkCounts {
+    pub fn count_of(&self, states: &[u32]) -> u64 {
+        self.counts
+            .iter()
+            .filter(|(state, _)| states.contains(state))
+            .map(|(_, count)| *count as u64)
+            .sum()
+    }
+}
+
+impl RegionIndex {
+    /// Returns the counts of the chunk at an absolute position, if the region has one there.
+    pub fn chunk(&self, position: ChunkPos) -> Option<&ChunkCounts> {
+        self.chunks.iter().find(|chunk| chunk.position == position)
+    }
+
+    /// Returns true if the region file has not changed since the index was built from it.
+    pub fn is_current(&self, file: &FileStamp) -> bool {
+        self.file_size == file.size && self.modified == file.modified
+    }
+}
+
+/// The block state counts of a chunk at an absolute position, as produced by decoding it.
+pub type ChunkBlockCounts = (ChunkPos, HashMap<String, u64>);
+
+/// The size and modification time of a region file, used to tell if an index entry is out of date.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Result<FileStamp, io::Error> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Ok(FileStamp {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Decodes every chunk of a region file and counts its blocks by state. Chunks are returned with their absolute
+/// positions.
+pub fn count_region_blocks(path: &Path) -> Result<Vec<ChunkBlockCounts>, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy();
+    let region_pos = RegionFile::try_parse_name(&name).ok_or_else(|| {
+        Message::new(messages::REGION_NOT_REGION)
+            .arg("path", path.display())
+            .into_error(io::ErrorKind::InvalidInput)
+    })?;
+
+    let region = RegionFile::open(path)?;
+    let mut chunks = Vec::new();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        let nbt = read_nbt(&chunk.data)?;
+        let counts = count_block_states(&nbt).map_err(|err| {
+            Message::new(messages::BLOCK_COUNT_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        let position = ChunkPos {
+            x: (region_pos.x * 32) + chunk.position.x,
+            z: (region_pos.z * 32) + chunk.position.z,
+        };
+
+        chunks.push((position, counts));
+    }
+
+    Ok(chunks)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, io::Error> {
+    let length = reader.read_u16::<BigEndian>()?;
+
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data)?;
+
+    String::from_utf8(data).map_err(|_| {
+        Message::new(messages::BLOCK_INDEX_INVALID_STRING).into_error(io::ErrorKind::InvalidData)
+    })
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> Result<(), io::Error> {
+    writer.write_u16::<BigEndian>(string.len() as u16)?;
+    writer.write_all(string.as_bytes())
+}
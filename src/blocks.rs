@@ -0,0 +1,1111 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use fastnbt::{LongArray, Value};
+
+use crate::nbt::chunk_level;
+
+/// The number of blocks in a 16x16x16 section.
+pub const SECTION_VOLUME: usize = 16 * 16 * 16;
+
+/// The number of 4x4x4 biome cells in a section.
+pub const BIOME_CELLS: usize = 4 * 4 * 4;
+
+/// The first data version (20w17a) which stopped packing block states across the boundaries of longs.
+const DATA_VERSION_NON_SPANNING: i32 = 2527;
+
+/// The first data version (17w47a) which stored block states in palettes.
+const DATA_VERSION_PALETTES: i32 = 1451;
+
+/// The heightmaps which the game keeps in chunks which have finished generating.
+pub const HEIGHTMAP_TYPES: &[&str] = &[
+    "MOTION_BLOCKING",
+    "MOTION_BLOCKING_NO_LEAVES",
+    "OCEAN_FLOOR",
+    "WORLD_SURFACE",
+];
+
+/// Blocks which don't block motion, by name without the `minecraft:` namespace. Anything else which isn't air is taken
+/// to block motion, so this only approximates the game's own rules, which depend on each block's collision shape.
+const NON_BLOCKING_BLOCKS: &[&str] = &[
+    "water",
+    "lava",
+    "bubble_column",
+    "seagrass",
+    "tall_seagrass",
+    "kelp",
+    "kelp_plant",
+    "grass",
+    "short_grass",
+    "tall_grass",
+    "fern",
+    "large_fern",
+    "dead_bush",
+    "dandelion",
+    "poppy",
+    "blue_orchid",
+    "allium",
+    "azure_bluet",
+    "oxeye_daisy",
+    "cornflower",
+    "lily_of_the_valley",
+    "wither_rose",
+    "torchflower",
+    "sunflower",
+    "lilac",
+    "rose_bush",
+    "peony",
+    "pink_petals",
+    "brown_mushroom",
+    "red_mushroom",
+    "sugar_cane",
+    "vine",
+    "glow_lichen",
+    "hanging_roots",
+    "spore_blossom",
+    "sweet_berry_bush",
+    "wheat",
+    "carrots",
+    "potatoes",
+    "beetroots",
+    "nether_wart",
+    "nether_sprouts",
+    "crimson_roots",
+    "warped_roots",
+    "crimson_fungus",
+    "warped_fungus",
+    "fire",
+    "soul_fire",
+    "cobweb",
+    "lever",
+    "redstone_wire",
+    "tripwire",
+    "tripwire_hook",
+    "ladder",
+    "light",
+    "structure_void",
+];
+
+/// Suffixes of the names of blocks which don't block motion.
+const NON_BLOCKING_SUFFIXES: &[&str] = &[
+    "_sapling",
+    "_tulip",
+    "torch",
+    "_sign",
+    "_banner",
+    "_button",
+    "_pressure_plate",
+    "rail",
+    "_vines",
+    "_vines_plant",
+];
+
+/// Flags describing how a block affects each heightmap.
+const HEIGHTMAP_SOLID: u8 = 1;
+const HEIGHTMAP_FLUID: u8 = 2;
+const HEIGHTMAP_LEAVES: u8 = 4;
+const HEIGHTMAP_NOT_AIR: u8 = 8;
+
+/// A 16x16x16 section of a chunk's blocks, decoded from its palette and packed block state indices. Indices are
+/// stored in the same YZX order Minecraft uses, so the block at (x, y, z) within the section is at
+/// `(y * 16 + z) * 16 + x`.
+pub struct BlockSection {
+    pub y: i32,
+    pub palette: Vec<Value>,
+    pub indices: Vec<u16>,
+}
+
+impl BlockSection {
+    /// Counts the blocks in the section by their palette entry.
+    pub fn palette_counts(&self) -> Vec<u32> {
+        let mut counts = vec![0u32; self.palette.len()];
+
+        for &index in &self.indices {
+            counts[index as usize] += 1;
+        }
+
+        counts
+    }
+
+    /// Counts the blocks in each horizontal layer of the section by their palette entry, from the bottom layer up.
+    pub fn layer_palette_counts(&self) -> Vec<Vec<u32>> {
+        self.indices
+            .chunks(16 * 16)
+            .map(|layer| {
+                let mut counts = vec![0u32; self.palette.len()];
+
+                for &index in layer {
+                    counts[index as usize] += 1;
+                }
+
+                counts
+            })
+            .collect()
+    }
+}
+
+/// The biomes of a 16x16x16 section, which are stored for each 4x4x4 cell of blocks. Indices are in YZX order, so the
+/// cell at (x, y, z) within the section is at `(y * 4 + z) * 4 + x`.
+pub struct BiomeSection {
+    pub y: i32,
+    pub palette: Vec<String>,
+    pub indices: Vec<u16>,
+}
+
+/// Returns the data version of a chunk, or zero if it predates data versions.
+pub fn data_version(nbt: &Value) -> i32 {
+    match nbt {
+        Value::Compound(root) => match root.get("DataVersion") {
+            Some(Value::Int(version)) => *version,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Decodes the block sections of a chunk saved by Minecraft 1.13 or newer. Sections which have no block data (such as
+/// those which only store light) are omitted.
+pub fn decode_sections(nbt: &Value) -> Result<Vec<BlockSection>, String> {
+    let level = chunk_level(nbt).ok_or_else(|| String::from("Chunk is not a compound"))?;
+    let spanning = data_version(nbt) < DATA_VERSION_NON_SPANNING;
+
+    let sections = match level.get("sections").or_else(|| level.get("Sections")) {
+        Some(Value::List(sections)) => sections,
+        Some(_) => return Err(String::from("Chunk sections are not a list")),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut decoded = Vec::with_capacity(sections.len());
+
+    for section in sections {
+        let section = match section {
+            Value::Compound(section) => section,
+            _ => return Err(String::from("Chunk section is not a compound")),
+        };
+
+        let y = match section.get("Y") {
+            Some(Value::Byte(y)) => *y as i32,
+            Some(Value::Int(y)) => *y,
+            _ => return Err(String::from("Chunk section is missing its Y coordinate")),
+        };
+
+        // 1.18 moved the palette and block states into a compound, and renamed them
+        let (palette, data) = match section.get("block_states") {
+            Some(Value::Compound(block_states)) => {
+                (block_states.get("palette"), block_states.get("data"))
+            }
+            _ => (section.get("Palette"), section.get("BlockStates")),
+        };
+
+        let palette = match palette {
+            Some(Value::List(palette)) if !palette.is_empty() => palette,
+            Some(Value::List(_)) | None => continue,
+            Some(_) => return Err(format!("Palette of section {} is not a list", y)),
+        };
+
+        let indices = match data {
+            Some(Value::LongArray(data)) => unpack_indices(
+                data,
+                SECTION_VOLUME,
+                bits_per_block(palette.len()),
+                palette.len(),
+                spanning,
+            )
+            .map_err(|err| format!("Block states of section {} are invalid: {}", y, err))?,
+            None if palette.len() == 1 => vec![0; SECTION_VOLUME],
+            _ => return Err(format!("Section {} is missing its block states", y)),
+        };
+
+        decoded.push(BlockSection {
+            y,
+            palette: palette.clone(),
+            indices,
+        });
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes the biomes of each section of a chunk saved by Minecraft 1.18 or newer, which stores them in a palette like
+/// block states. Sections without biomes are omitted, as are all sections of chunks saved by older versions, which
+/// stored biomes as numeric IDs for the whole chunk instead.
+pub fn decode_biome_sections(nbt: &Value) -> Result<Vec<BiomeSection>, String> {
+    let level = chunk_level(nbt).ok_or_else(|| String::from("Chunk is not a compound"))?;
+
+    let sections = match level.get("sections") {
+        Some(Value::List(sections)) => sections,
+        Some(_) => return Err(String::from("Chunk sections are not a list")),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut decoded = Vec::with_capacity(sections.len());
+
+    for section in sections {
+        let section = match section {
+            Value::Compound(section) => section,
+            _ => return Err(String::from("Chunk section is not a compound")),
+        };
+
+        let y = match section.get("Y") {
+            Some(Value::Byte(y)) => *y as i32,
+            Some(Value::Int(y)) => *y,
+            _ => return Err(String::from("Chunk section is missing its Y coordinate")),
+        };
+
+        let (palette, data) = match section.get("biomes") {
+            Some(Value::Compound(biomes)) => (biomes.get("palette"), biomes.get("data")),
+            _ => continue,
+        };
+
+        let palette: Vec<String> = match palette {
+            Some(Value::List(palette)) if !palette.is_empty() => palette
+                .iter()
+                .map(|entry| match entry {
+                    Value::String(biome) => Ok(biome.clone()),
+                    _ => Err(format!(
+                        "Biome palette of section {} is not a list of strings",
+                        y
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(Value::List(_)) | None => continue,
+            Some(_) => return Err(format!("Biome palette of section {} is not a list", y)),
+        };
+
+        // A palette with a single biome needs no bits at all to index it
+        let indices = match data {
+            _ if palette.len() == 1 => vec![0; BIOME_CELLS],
+            Some(Value::LongArray(data)) => unpack_indices(
+                data,
+                BIOME_CELLS,
+                bits_per_biome(palette.len()),
+                palette.len(),
+                false,
+            )
+            .map_err(|err| format!("Biomes of section {} are invalid: {}", y, err))?,
+            _ => return Err(format!("Section {} is missing its biome data", y)),
+        };
+
+        decoded.push(BiomeSection {
+            y,
+            palette,
+            indices,
+        });
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes a heightmap of a chunk, such as `MOTION_BLOCKING`, into the height of each column in ZX order. Heights are
+/// counted from the bottom of the world, so they are the Y level above the highest block of each column minus the
+/// world's minimum Y. Returns `None` if the chunk doesn't have the heightmap, which is the case for stripped chunks.
+pub fn decode_heightmap(nbt: &Value, name: &str) -> Result<Option<Vec<u16>>, String> {
+    let level = chunk_level(nbt).ok_or_else(|| String::from("Chunk is not a compound"))?;
+
+    let data = match level.get("Heightmaps") {
+        Some(Value::Compound(heightmaps)) => match heightmaps.get(name) {
+            Some(Value::LongArray(data)) => data,
+            Some(_) => return Err(format!("Heightmap {} is not a long array", name)),
+            None => return Ok(None),
+        },
+        Some(_) => return Err(String::from("Chunk heightmaps are not a compound")),
+        None => return Ok(None),
+    };
+
+    const COLUMNS: usize = 16 * 16;
+
+    // The number of bits per column depends on the height of the world, so it is worked out from the length of the
+    // data instead
+    let spanning = data_version(nbt) < DATA_VERSION_NON_SPANNING;
+    let bits = if data.is_empty() {
+        0
+    } else if spanning {
+        (data.len() * 64) / COLUMNS
+    } else {
+        64 / COLUMNS.div_ceil(data.len())
+    };
+
+    if bits == 0 || bits > 16 {
+        return Err(format!(
+            "Heightmap {} has an unexpected length of {} longs",
+            name,
+            data.len()
+        ));
+    }
+
+    let mask = (1u64 << bits) - 1;
+    let per_long = 64 / bits;
+
+    let heights = (0..COLUMNS)
+        .map(|index| {
+            let value = if spanning {
+                let bit = index * bits;
+                let (long, offset) = (bit / 64, bit % 64);
+
+                let mut value = (data[long] as u64) >> offset;
+
+                if offset + bits > 64 {
+                    value |= (data[long + 1] as u64) << (64 - offset);
+                }
+
+                value & mask
+            } else {
+                ((data[index / per_long] as u64) >> ((index % per_long) * bits)) & mask
+            };
+
+            value as u16
+        })
+        .collect();
+
+    Ok(Some(heights))
+}
+
+/// Heightmaps by name, each packed into longs as the game stores them.
+pub type PackedHeightmaps = Vec<(&'static str, Vec<i64>)>;
+
+/// Recomputes the standard heightmaps of a chunk from its blocks, packed in the same way as the game would save them.
+/// Each heightmap holds the height above the highest matching block of every column, counted from the bottom of the
+/// world, where `WORLD_SURFACE` matches any block which isn't air, `OCEAN_FLOOR` any block which blocks motion, and
+/// `MOTION_BLOCKING` blocks which block motion or hold a fluid, ignoring leaves for `MOTION_BLOCKING_NO_LEAVES`. Returns
+/// `None` for chunks from before 1.13, which didn't store blocks in palettes.
+pub fn compute_heightmaps(nbt: &Value) -> Result<Option<PackedHeightmaps>, String> {
+    let version = data_version(nbt);
+
+    if version < DATA_VERSION_PALETTES {
+        return Ok(None);
+    }
+
+    let mut sections = decode_sections(nbt)?;
+    sections.sort_by_key(|section| std::cmp::Reverse(section.y));
+
+    // Since 1.18 the lowest section of the world is recorded in the chunk, as worlds can extend below zero
+    let min_y = match nbt {
+        Value::Compound(root) => match root.get("yPos") {
+            Some(Value::Int(y_pos)) => *y_pos * 16,
+            _ => 0,
+        },
+        _ => 0,
+    };
+
+    let max_y = sections
+        .first()
+        .map(|section| (section.y + 1) * 16)
+        .unwrap_or(0)
+        .max(min_y + 256);
+
+    const COLUMNS: usize = 16 * 16;
+
+    let matches: [fn(u8) -> bool; 4] = [
+        |flags| flags & (HEIGHTMAP_SOLID | HEIGHTMAP_FLUID) != 0,
+        |flags| flags & (HEIGHTMAP_SOLID | HEIGHTMAP_FLUID) != 0 && flags & HEIGHTMAP_LEAVES == 0,
+        |flags| flags & HEIGHTMAP_SOLID != 0,
+        |flags| flags & HEIGHTMAP_NOT_AIR != 0,
+    ];
+
+    let mut heights = vec![[0u16; COLUMNS]; HEIGHTMAP_TYPES.len()];
+    let mut remaining = COLUMNS * HEIGHTMAP_TYPES.len();
+
+    for section in &sections {
+        let flags: Vec<u8> = section.palette.iter().map(heightmap_flags).collect();
+
+        // Sections of nothing but air can't raise any heightmap
+        if flags.iter().all(|&flags| flags == 0) {
+            continue;
+        }
+
+        for y in (0..16).rev() {
+            let height = ((section.y * 16) + y as i32 + 1 - min_y).max(0) as u16;
+            let layer = &section.indices[(y * COLUMNS)..((y + 1) * COLUMNS)];
+
+            for (column, &index) in layer.iter().enumerate() {
+                let block = flags[index as usize];
+
+                for (heightmap, matches) in heights.iter_mut().zip(&matches) {
+                    if heightmap[column] == 0 && matches(block) {
+                        heightmap[column] = height;
+                        remaining -= 1;
+                    }
+                }
+            }
+
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    let bits = (u32::BITS - ((max_y - min_y) as u32).leading_zeros()) as usize;
+    let spanning = version < DATA_VERSION_NON_SPANNING;
+
+    Ok(Some(
+        HEIGHTMAP_TYPES
+            .iter()
+            .zip(&heights)
+            .map(|(&name, heights)| (name, pack_values(heights, bits, spanning)))
+            .collect(),
+    ))
+}
+
+/// Classifies a palette entry for the heightmaps, returning zero for air.
+fn heightmap_flags(entry: &Value) -> u8 {
+    let name = block_name(entry).unwrap_or("minecraft:air");
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+
+    if matches!(name, "air" | "cave_air" | "void_air") {
+        return 0;
+    }
+
+    let mut flags = HEIGHTMAP_NOT_AIR;
+
+    let waterlogged = match entry {
+        Value::Compound(entry) => match entry.get("Properties") {
+            Some(Value::Compound(properties)) => {
+                matches!(properties.get("waterlogged"), Some(Value::String(value)) if value == "true")
+            }
+            _ => false,
+        },
+        _ => false,
+    };
+
+    if waterlogged
+        || matches!(
+            name,
+            "water"
+                | "lava"
+                | "bubble_column"
+                | "seagrass"
+                | "tall_seagrass"
+                | "kelp"
+                | "kelp_plant"
+        )
+    {
+        flags |= HEIGHTMAP_FLUID;
+    }
+
+    if !NON_BLOCKING_BLOCKS.contains(&name)
+        && !NON_BLOCKING_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+    {
+        flags |= HEIGHTMAP_SOLID;
+    }
+
+    if name.ends_with("_leaves") {
+        flags |= HEIGHTMAP_LEAVES;
+    }
+
+    flags
+}
+
+/// Packs values of `bits` each into longs, either spanning the boundaries of longs or padded to fit within them. This is
+/// the inverse of `unpack_indices`, and is used for both heightmaps and block states.
+pub fn pack_values(values: &[u16], bits: usize, spanning: bool) -> Vec<i64> {
+    if spanning {
+        let mut data = vec![0u64; (values.len() * bits).div_ceil(64)];
+
+        for (index, &value) in values.iter().enumerate() {
+            let bit = index * bits;
+            let (long, offset) = (bit / 64, bit % 64);
+
+            data[long] |= (value as u64) << offset;
+
+            if offset + bits > 64 {
+                data[long + 1] |= (value as u64) >> (64 - offset);
+            }
+        }
+
+        data.into_iter().map(|long| long as i64).collect()
+    } else {
+        let per_long = 64 / bits;
+        let mut data = vec![0u64; values.len().div_ceil(per_long)];
+
+        for (index, &value) in values.iter().enumerate() {
+            data[index / per_long] |= (value as u64) << ((index % per_long) * bits);
+        }
+
+        data.into_iter().map(|long| long as i64).collect()
+    }
+}
+
+/// Replaces one block with another wherever it appears in a chunk's palettes. `from` is either a block name, which matches
+/// every state of the block, or an exact block state as written by `block_state_string`.
+#[derive(Clone)]
+pub struct BlockReplacement {
+    pub from: String,
+    pub to: Value,
+}
+
+/// Rewrites the palettes of a chunk's sections according to the first replacement matching each entry, adding the number
+/// of blocks each replacement changed to `counts`. Returns false if no replacement matched, in which case the chunk is
+/// left as it was.
+pub fn replace_blocks(
+    nbt: &mut Value,
+    replacements: &[BlockReplacement],
+    counts: &mut [u64],
+) -> Result<bool, String> {
+    edit_sections(nbt, |_, palette, indices| {
+        let matched: Vec<Option<usize>> = palette
+            .iter()
+            .map(|entry| {
+                let state = block_state_string(entry);
+
+                replacements
+                    .iter()
+                    .position(|replacement| block_state_matches(&state, &replacement.from))
+            })
+            .collect();
+
+        if matched.iter().all(Option::is_none) {
+            return false;
+        }
+
+        let mut uses = vec![0u64; palette.len()];
+
+        for &index in indices.iter() {
+            uses[index as usize] += 1;
+        }
+
+        for ((entry, matched), uses) in palette.iter_mut().zip(&matched).zip(&uses) {
+            if let Some(replacement) = matched {
+                counts[*replacement] += uses;
+                *entry = replacements[*replacement].to.clone();
+            }
+        }
+
+        true
+    })
+}
+
+/// Replaces every block in the given columns of a chunk with air, returning the number of blocks replaced. Cave air and
+/// void air are left as they are. Columns are indexed by `z * 16 + x` within the chunk.
+pub fn clear_columns(nbt: &mut Value, columns: &[bool; 256]) -> Result<u64, String> {
+    let mut cleared = 0;
+
+    edit_sections(nbt, |_, palette, indices| {
+        let cleared_before = cleared;
+        let mut air = None;
+
+        for (position, index) in indices.iter_mut().enumerate() {
+            if !columns[position % 256] || is_air(&palette[*index as usize]) {
+                continue;
+            }
+
+            let air = *air.get_or_insert_with(|| {
+                let mut entry = HashMap::new();
+                entry.insert(
+                    String::from("Name"),
+                    Value::String(String::from("minecraft:air")),
+                );
+
+                palette.push(Value::Compound(entry));
+                palette.len() - 1
+            });
+
+            *index = air as u16;
+            cleared += 1;
+        }
+
+        cleared > cleared_before
+    })?;
+
+    Ok(cleared)
+}
+
+/// Sets blocks of a chunk to entries of `palette`, where `block_at` is given the position of each block as its x and z
+/// within the chunk and its absolute y, and returns the index into `palette` of the block to place there, or `None` to
+/// keep the block which is there. Returns the number of blocks set. Blocks are only set within the chunk's existing
+/// sections.
+pub fn set_blocks<F>(nbt: &mut Value, palette: &[Value], mut block_at: F) -> Result<u64, String>
+where
+    F: FnMut(i32, i32, i32) -> Option<usize>,
+{
+    let mut set = 0;
+
+    edit_sections(nbt, |section_y, section_palette, indices| {
+        let set_before = set;
+        let mut states: Option<HashMap<String, u16>> = None;
+        let mut mapped: HashMap<usize, u16> = HashMap::new();
+
+        for (position, index) in indices.iter_mut().enumerate() {
+            let (x, y, z) = (
+                (position % 16) as i32,
+                (section_y * 16) + (position / 256) as i32,
+                ((position / 16) % 16) as i32,
+            );
+
+            let block = match block_at(x, y, z) {
+                Some(block) => block,
+                None => continue,
+            };
+
+            *index = *mapped.entry(block).or_insert_with(|| {
+                let states = states.get_or_insert_with(|| {
+                    section_palette
+                        .iter()
+                        .enumerate()
+                        .map(|(index, entry)| (block_state_string(entry), index as u16))
+                        .collect()
+                });
+
+                *states
+                    .entry(block_state_string(&palette[block]))
+                    .or_insert_with(|| {
+                        section_palette.push(palette[block].clone());
+                        (section_palette.len() - 1) as u16
+                    })
+            });
+            set += 1;
+        }
+
+        set > set_before
+    })?;
+
+    Ok(set)
+}
+
+/// Adds empty sections to a chunk from before 1.18 for each section Y in `range` it is missing, as those versions didn't
+/// save sections of nothing but air. Chunks since 1.18 save every section of the world, and are left as they are.
+pub fn add_missing_sections(nbt: &mut Value, range: RangeInclusive<i32>) {
+    if data_version(nbt) < DATA_VERSION_PALETTES {
+        return;
+    }
+
+    let level = match nbt {
+        Value::Compound(root) => match root.get_mut("Level") {
+            Some(Value::Compound(level)) => level,
+            _ => return,
+        },
+        _ => return,
+    };
+
+    let sections = match level
+        .entry(String::from("Sections"))
+        .or_insert_with(|| Value::List(Vec::new()))
+    {
+        Value::List(sections) => sections,
+        _ => return,
+    };
+
+    let existing: Vec<i32> = sections
+        .iter()
+        .filter_map(|section| match section {
+            Value::Compound(section) => match section.get("Y") {
+                Some(Value::Byte(y)) => Some(*y as i32),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    for y in range {
+        if existing.contains(&y) {
+            continue;
+        }
+
+        let mut air = HashMap::new();
+        air.insert(
+            String::from("Name"),
+            Value::String(String::from("minecraft:air")),
+        );
+
+        let mut section = HashMap::new();
+        section.insert(String::from("Y"), Value::Byte(y as i8));
+        section.insert(
+            String::from("Palette"),
+            Value::List(vec![Value::Compound(air)]),
+        );
+        section.insert(
+            String::from("BlockStates"),
+            Value::LongArray(LongArray::new(vec![
+                0;
+                SECTION_VOLUME * bits_per_block(1) / 64
+            ])),
+        );
+
+        sections.push(Value::Compound(section));
+    }
+}
+
+/// Returns true if a palette entry is any of the kinds of air.
+pub fn is_air(entry: &Value) -> bool {
+    matches!(
+        block_name(entry),
+        Some("minecraft:air" | "minecraft:cave_air" | "minecraft:void_air")
+    )
+}
+
+/// Decodes the palette and block states of each of a chunk's sections for `edit`, which returns true if it changed them.
+/// The palette of each changed section is then compacted, merging entries which are the same block state and dropping
+/// entries which no block uses, and the block states are repacked for its new size. Returns true if any section
+/// changed. Chunks from before 1.13 have no palettes, and are never changed.
+fn edit_sections<F>(nbt: &mut Value, mut edit: F) -> Result<bool, String>
+where
+    F: FnMut(i32, &mut Vec<Value>, &mut Vec<u16>) -> bool,
+{
+    let version = data_version(nbt);
+
+    if version < DATA_VERSION_PALETTES {
+        return Ok(false);
+    }
+
+    let spanning = version < DATA_VERSION_NON_SPANNING;
+
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return Err(String::from("Chunk is not a compound")),
+    };
+
+    let level = match root.get_mut("Level") {
+        Some(Value::Compound(level)) => level,
+        _ => root,
+    };
+
+    let sections = match level.get_mut("sections") {
+        Some(Value::List(sections)) => sections,
+        Some(_) => return Err(String::from("Chunk sections are not a list")),
+        None => match level.get_mut("Sections") {
+            Some(Value::List(sections)) => sections,
+            Some(_) => return Err(String::from("Chunk sections are not a list")),
+            None => return Ok(false),
+        },
+    };
+
+    let mut changed = false;
+
+    for section in sections {
+        let section = match section {
+            Value::Compound(section) => section,
+            _ => return Err(String::from("Chunk section is not a compound")),
+        };
+
+        let y = match section.get("Y") {
+            Some(Value::Byte(y)) => *y as i32,
+            Some(Value::Int(y)) => *y,
+            _ => return Err(String::from("Chunk section is missing its Y coordinate")),
+        };
+
+        // 1.18 moved the palette and block states into a compound, and renamed them
+        let modern = matches!(section.get("block_states"), Some(Value::Compound(_)));
+        let (block_states, keys) = match section.get_mut("block_states") {
+            Some(Value::Compound(block_states)) if modern => (block_states, ("palette", "data")),
+            _ => (section, ("Palette", "BlockStates")),
+        };
+
+        changed |= edit_section(block_states, keys, spanning, |palette, indices| {
+            edit(y, palette, indices)
+        })
+        .map_err(|err| format!("Section {} could not be rewritten: {}", y, err))?;
+    }
+
+    Ok(changed)
+}
+
+/// Edits a single section, whose palette and block states are stored under the given keys.
+fn edit_section<F>(
+    block_states: &mut HashMap<String, Value>,
+    (palette_key, data_key): (&str, &str),
+    spanning: bool,
+    mut edit: F,
+) -> Result<bool, String>
+where
+    F: FnMut(&mut Vec<Value>, &mut Vec<u16>) -> bool,
+{
+    let mut palette = match block_states.get(palette_key) {
+        Some(Value::List(palette)) if !palette.is_empty() => palette.clone(),
+        Some(Value::List(_)) | None => return Ok(false),
+        Some(_) => return Err(String::from("Palette is not a list")),
+    };
+
+    let mut indices = match block_states.get(data_key) {
+        Some(Value::LongArray(data)) => unpack_indices(
+            data,
+            SECTION_VOLUME,
+            bits_per_block(palette.len()),
+            palette.len(),
+            spanning,
+        )?,
+        None if palette.len() == 1 => vec![0; SECTION_VOLUME],
+        _ => return Err(String::from("Block states are missing")),
+    };
+
+    if !edit(&mut palette, &mut indices) {
+        return Ok(false);
+    }
+
+    let mut used = vec![false; palette.len()];
+
+    for &index in &indices {
+        used[index as usize] = true;
+    }
+
+    // Entries are merged by their block state, so that two entries which now describe the same block become one
+    let mut new_palette: Vec<Value> = Vec::new();
+    let mut new_states: HashMap<String, u16> = HashMap::new();
+    let mut remap = vec![0u16; palette.len()];
+
+    for (index, entry) in palette.into_iter().enumerate() {
+        if !used[index] {
+            continue;
+        }
+
+        let state = block_state_string(&entry);
+
+        remap[index] = match new_states.get(&state) {
+            Some(&new_index) => new_index,
+            None => {
+                let new_index = new_palette.len() as u16;
+
+                new_palette.push(entry);
+                new_states.insert(state, new_index);
+                new_index
+            }
+        };
+    }
+
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+
+    // Since 1.18 a section of a single block state stores no block states at all
+    if new_palette.len() == 1 && palette_key == "palette" {
+        block_states.remove(data_key);
+    } else {
+        let data = pack_values(&indices, bits_per_block(new_palette.len()), spanning);
+        block_states.insert(
+            String::from(data_key),
+            Value::LongArray(LongArray::new(data)),
+        );
+    }
+
+    block_states.insert(String::from(palette_key), Value::List(new_palette));
+
+    Ok(true)
+}
+
+/// Parses a block state written as in commands, such as `minecraft:oak_log[axis=y]`, into a palette entry. Blocks
+/// without a namespace are given the `minecraft:` namespace.
+pub fn parse_block_state(value: &str) -> Result<Value, String> {
+    let value = value.trim();
+
+    let (name, properties) = match value.split_once('[') {
+        Some((name, properties)) => {
+            let properties = properties
+                .strip_suffix(']')
+                .ok_or_else(|| format!("Expected ] at the end of the block state: {}", value))?;
+
+            (name, Some(properties))
+        }
+        None => (value, None),
+    };
+
+    if name.is_empty() {
+        return Err(format!("Block state has no name: {}", value));
+    }
+
+    let mut entry = HashMap::new();
+    entry.insert(String::from("Name"), Value::String(qualify(name)));
+
+    if let Some(properties) = properties {
+        let mut parsed = HashMap::new();
+
+        for property in properties
+            .split(',')
+            .filter(|property| !property.trim().is_empty())
+        {
+            let (key, property_value) = property.split_once('=').ok_or_else(|| {
+                format!("Expected a property in the form key=value: {}", property)
+            })?;
+
+            parsed.insert(
+                String::from(key.trim()),
+                Value::String(String::from(property_value.trim())),
+            );
+        }
+
+        if !parsed.is_empty() {
+            entry.insert(String::from("Properties"), Value::Compound(parsed));
+        }
+    }
+
+    Ok(Value::Compound(entry))
+}
+
+/// Returns the number of bits used to store each palette index in the packed biomes, which unlike block states has no
+/// minimum.
+fn bits_per_biome(palette_len: usize) -> usize {
+    (usize::BITS - (palette_len.max(1) - 1).leading_zeros()) as usize
+}
+
+/// Returns the number of bits used to store each palette index in the packed block states.
+pub fn bits_per_block(palette_len: usize) -> usize {
+    let bits = (usize::BITS - (palette_len.max(1) - 1).leading_zeros()) as usize;
+    bits.max(4)
+}
+
+/// Unpacks `count` palette indices of `bits` each, which either span the boundaries of longs or are padded to fit
+/// within them.
+fn unpack_indices(
+    data: &[i64],
+    count: usize,
+    bits: usize,
+    palette_len: usize,
+    spanning: bool,
+) -> Result<Vec<u16>, String> {
+    let mask = (1u64 << bits) - 1;
+
+    let required_longs = if spanning {
+        (count * bits).div_ceil(64)
+    } else {
+        let per_long = 64 / bits;
+        count.div_ceil(per_long)
+    };
+
+    if data.len() < required_longs {
+        return Err(format!(
+            "expected {} longs, but found {}",
+            required_longs,
+            data.len()
+        ));
+    }
+
+    let mut indices = Vec::with_capacity(count);
+
+    for index in 0..count {
+        let value = if spanning {
+            let bit = index * bits;
+            let (long, offset) = (bit / 64, bit % 64);
+
+            let mut value = (data[long] as u64) >> offset;
+
+            if offset + bits > 64 {
+                value |= (data[long + 1] as u64) << (64 - offset);
+            }
+
+            value & mask
+        } else {
+            let per_long = 64 / bits;
+            let (long, offset) = (index / per_long, (index % per_long) * bits);
+
+            ((data[long] as u64) >> offset) & mask
+        };
+
+        if value as usize >= palette_len {
+            return Err(format!("palette index {} is out of range", value));
+        }
+
+        indices.push(value as u16);
+    }
+
+    Ok(indices)
+}
+
+/// Returns the names of the blocks in the palettes of a chunk's sections, without decoding which blocks use them. A
+/// palette can keep entries which no block uses any more, so a block being listed doesn't guarantee it is present.
+pub fn palette_block_names(nbt: &Value) -> Vec<&str> {
+    let level = match chunk_level(nbt) {
+        Some(level) => level,
+        None => return Vec::new(),
+    };
+
+    let sections = match level.get("sections").or_else(|| level.get("Sections")) {
+        Some(Value::List(sections)) => sections,
+        _ => return Vec::new(),
+    };
+
+    let mut names = Vec::new();
+
+    for section in sections {
+        let section = match section {
+            Value::Compound(section) => section,
+            _ => continue,
+        };
+
+        let palette = match section.get("block_states") {
+            Some(Value::Compound(block_states)) => block_states.get("palette"),
+            _ => section.get("Palette"),
+        };
+
+        if let Some(Value::List(palette)) = palette {
+            names.extend(palette.iter().filter_map(block_name));
+        }
+    }
+
+    names
+}
+
+/// Returns the name of the block in a palette entry, such as `minecraft:chest`.
+pub fn block_name(entry: &Value) -> Option<&str> {
+    match entry {
+        Value::Compound(entry) => match entry.get("Name") {
+            Some(Value::String(name)) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns a canonical description of the block state in a palette entry, with its properties sorted by name, such as
+/// `minecraft:chest[facing=north,type=single,waterlogged=false]`.
+pub fn block_state_string(entry: &Value) -> String {
+    let mut state = String::from(block_name(entry).unwrap_or("minecraft:air"));
+
+    if let Value::Compound(entry) = entry {
+        if let Some(Value::Compound(properties)) = entry.get("Properties") {
+            let mut properties: Vec<(&String, &Value)> = properties.iter().collect();
+            properties.sort_by_key(|(name, _)| *name);
+
+            state.push('[');
+
+            for (index, (name, value)) in properties.iter().enumerate() {
+                if index > 0 {
+                    state.push(',');
+                }
+
+                match value {
+                    Value::String(value) => write!(state, "{}={}", name, value).unwrap(),
+                    _ => write!(state, "{}=?", name).unwrap(),
+                }
+            }
+
+            state.push(']');
+        }
+    }
+
+    state
+}
+
+/// Returns true if a block state string refers to the given block, either by its exact state or by its name alone.
+pub fn block_state_matches(state: &str, query: &str) -> bool {
+    state == query || state.split('[').next() == Some(query)
+}
+
+/// Counts the blocks of each state in a chunk, keyed by their canonical block state string.
+pub fn count_block_states(nbt: &Value) -> Result<HashMap<String, u64>, String> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+
+    for section in decode_sections(nbt)? {
+        for (entry, count) in section.palette.iter().zip(section.palette_counts()) {
+            if count > 0 {
+                *counts.entry(block_state_string(entry)).or_default() += count as u64;
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Adds the `minecraft:` namespace to an ID which doesn't have one, so that `spawner` matches `minecraft:spawner`.
+pub fn qualify(id: &str) -> String {
+    let name = id.split('[').next().unwrap_or(id);
+
+    if name.contains(':') {
+        String::from(id)
+    } else {
+        format!("minecraft:{}", id)
+    }
+}
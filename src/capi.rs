@@ -0,0 +1,334 @@
+//! C bindings for reading and writing region files, so that plugins written in other languages can reuse this parser.
+//! The functions are declared in `include/anvil_tools.h`, which is generated from this module by cbindgen.
+//!
+//! Functions which can fail return `ANVIL_ERROR` or a null pointer, after which `anvil_last_error` describes what went
+//! wrong on the calling thread. Chunk positions are checked before they are used, and those outside of the region are
+//! refused with `ANVIL_INVALID_POSITION`. Panics are caught at the boundary and reported as errors, since they must
+//! never unwind into the caller.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use crate::messages::{self, Message};
+use crate::region::{Chunk, ChunkPos, RegionFile, RegionFileWriter};
+
+/// A region file opened for reading.
+pub struct AnvilRegion(RegionFile);
+
+/// A region file opened for writing.
+pub struct AnvilWriter(RegionFileWriter);
+
+/// The result of a function which can fail.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum AnvilStatus {
+    /// The function succeeded
+    AnvilOk = 0,
+    /// The region doesn't have the chunk which was asked for
+    AnvilNotFound = 1,
+    /// The function failed, and `anvil_last_error` says why
+    AnvilError = -1,
+    /// The chunk position is outside of the region (0 to 31 on each axis)
+    AnvilInvalidPosition = -2,
+}
+
+/// A block of bytes owned by the library, which must be released with `anvil_buffer_free`.
+#[repr(C)]
+pub struct AnvilBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Returns the error of the last function which failed on the calling thread, or null if none has. The string is
+/// owned by the library and stays valid until the next function which fails on the same thread.
+#[no_mangle]
+pub extern "C" fn anvil_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Opens a region file for reading, returning null if it can't be opened.
+///
+/// # Safety
+///
+/// `path` must be a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_region_open(path: *const c_char) -> *mut AnvilRegion {
+    let region = guard(|| {
+        let path = c_path(path)?;
+
+        Ok(Box::new(AnvilRegion(RegionFile::open(path)?)))
+    });
+
+    region.map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Closes a region file opened with `anvil_region_open`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `region` must be null or a region which hasn't been closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_region_close(region: *mut AnvilRegion) {
+    if !region.is_null() {
+        drop(Box::from_raw(region));
+    }
+}
+
+/// Reads and decompresses the NBT data of a chunk given its position within the region (0 to 31 on each axis). The
+/// data is written to `out` if the chunk is found, which must then be released with `anvil_buffer_free`.
+///
+/// # Safety
+///
+/// `region` must be an open region, and `out` must point to writable memory for a buffer.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_region_get_chunk(
+    region: *const AnvilRegion,
+    x: i32,
+    z: i32,
+    out: *mut AnvilBuffer,
+) -> AnvilStatus {
+    let region = &(*region).0;
+
+    let position = match chunk_position(x, z) {
+        Some(position) => position,
+        None => return AnvilStatus::AnvilInvalidPosition,
+    };
+
+    let chunk = guard(|| {
+        region
+            .get_raw_chunk(position)?
+            .map(|raw_chunk| raw_chunk.decompress())
+            .transpose()
+    });
+
+    match chunk {
+        Some(Some(chunk)) => {
+            *out = into_buffer(chunk.data);
+            AnvilStatus::AnvilOk
+        }
+        Some(None) => AnvilStatus::AnvilNotFound,
+        None => AnvilStatus::AnvilError,
+    }
+}
+
+/// Returns when a chunk was last saved as seconds since the Unix epoch, or zero if the time wasn't recorded, can't be
+/// read, or the position is outside of the region.
+///
+/// # Safety
+///
+/// `region` must be an open region.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_region_get_timestamp(
+    region: *const AnvilRegion,
+    x: i32,
+    z: i32,
+) -> u32 {
+    let region = &(*region).0;
+
+    match chunk_position(x, z) {
+        Some(position) => guard(|| region.timestamp(position)).unwrap_or(0),
+        None => 0,
+    }
+}
+
+/// Creates a region file for writing, replacing any file which already exists at the path, or returns null if it
+/// can't be created.
+///
+/// # Safety
+///
+/// `path` must be a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_writer_create(path: *const c_char) -> *mut AnvilWriter {
+    let writer = guard(|| {
+        let path = c_path(path)?;
+
+        Ok(Box::new(AnvilWriter(RegionFileWriter::create(path)?)))
+    });
+
+    writer.map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Opens an existing region file for writing without truncating it, keeping the chunks which are already present, or
+/// returns null if it can't be opened.
+///
+/// # Safety
+///
+/// `path` must be a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_writer_open(path: *const c_char) -> *mut AnvilWriter {
+    let writer = guard(|| {
+        let path = c_path(path)?;
+
+        Ok(Box::new(AnvilWriter(RegionFileWriter::open(path)?)))
+    });
+
+    writer.map_or(ptr::null_mut(), Box::into_raw)
+}
+
+/// Compresses the NBT data of a chunk and adds it to the region at its position within the region, replacing any
+/// chunk already there.
+///
+/// # Safety
+///
+/// `writer` must be an open writer, and `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_writer_add_chunk(
+    writer: *mut AnvilWriter,
+    x: i32,
+    z: i32,
+    data: *const u8,
+    len: usize,
+) -> AnvilStatus {
+    let writer = &mut (*writer).0;
+    let data = c_bytes(data, len);
+
+    let position = match chunk_position(x, z) {
+        Some(position) => position,
+        None => return AnvilStatus::AnvilInvalidPosition,
+    };
+
+    let result = guard(|| {
+        writer.add_chunk(&Chunk {
+            position,
+            data: data.into(),
+        })
+    });
+
+    status(result)
+}
+
+/// Records when a chunk was last saved, as seconds since the Unix epoch.
+///
+/// # Safety
+///
+/// `writer` must be an open writer.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_writer_set_timestamp(
+    writer: *mut AnvilWriter,
+    x: i32,
+    z: i32,
+    timestamp: u32,
+) -> AnvilStatus {
+    match chunk_position(x, z) {
+        Some(position) => {
+            (*writer).0.set_timestamp(position, timestamp);
+            AnvilStatus::AnvilOk
+        }
+        None => AnvilStatus::AnvilInvalidPosition,
+    }
+}
+
+/// Writes every chunk which is still held back and closes the region file. The writer is closed even if this fails.
+///
+/// # Safety
+///
+/// `writer` must be null or a writer which hasn't been closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_writer_close(writer: *mut AnvilWriter) -> AnvilStatus {
+    if writer.is_null() {
+        return AnvilStatus::AnvilOk;
+    }
+
+    let writer = Box::from_raw(writer);
+
+    status(guard(move || writer.0.finish()))
+}
+
+/// Releases a buffer returned by the library. Passing a buffer with null data does nothing.
+///
+/// # Safety
+///
+/// `buffer` must have been returned by the library and not released already.
+#[no_mangle]
+pub unsafe extern "C" fn anvil_buffer_free(buffer: AnvilBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// Runs an operation, recording its error or panic as the last error of the thread and returning `None` if it fails.
+fn guard<T, F>(operation: F) -> Option<T>
+where
+    F: FnOnce() -> Result<T, io::Error>,
+{
+    let error = match panic::catch_unwind(AssertUnwindSafe(operation)) {
+        Ok(Ok(value)) => return Some(value),
+        Ok(Err(err)) => messages::error_message(&err).to_string(),
+        Err(_) => Message::new(messages::CAPI_PANIC).to_string(),
+    };
+
+    set_last_error(error);
+
+    None
+}
+
+fn set_last_error(error: String) {
+    LAST_ERROR.with(|last_error| {
+        // Messages can't contain null bytes unless an argument does, so those are dropped rather than losing the error
+        *last_error.borrow_mut() = Some(CString::new(error.replace('\0', "")).unwrap());
+    });
+}
+
+/// Checks a chunk position given by the caller, which the region code would otherwise wrap around or index out of
+/// bounds with, recording the last error if it is outside of the region.
+fn chunk_position(x: i32, z: i32) -> Option<ChunkPos> {
+    if (0..32).contains(&x) && (0..32).contains(&z) {
+        return Some(ChunkPos { x, z });
+    }
+
+    set_last_error(
+        Message::new(messages::CAPI_INVALID_POSITION)
+            .arg("x", x)
+            .arg("z", z)
+            .to_string(),
+    );
+
+    None
+}
+
+fn status(result: Option<()>) -> AnvilStatus {
+    match result {
+        Some(()) => AnvilStatus::AnvilOk,
+        None => AnvilStatus::AnvilError,
+    }
+}
+
+fn into_buffer(data: Box<[u8]>) -> AnvilBuffer {
+    let len = data.len();
+
+    AnvilBuffer {
+        data: Box::into_raw(data) as *mut u8,
+        len,
+    }
+}
+
+unsafe fn c_path<'a>(path: *const c_char) -> Result<&'a Path, io::Error> {
+    let path = CStr::from_ptr(path).to_str().map_err(|_| {
+        Message::new(messages::CAPI_INVALID_PATH).into_error(io::ErrorKind::InvalidInput)
+    })?;
+
+    Ok(Path::new(path))
+}
+
+unsafe fn c_bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+
+    std::slice::from_raw_parts(data, len)
+}
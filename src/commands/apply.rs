@@ -0,0 +1,234 @@
+use clap::Parser;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::commands::archive::{unpack_files_with_observer, UnpackOptions};
+use crate::commands::delete_chunks::delete_from_region;
+use crate::manifest::{hash_chunk_data, Manifest};
+use crate::messages::{self, report, Message, MessageId};
+use crate::observer::ProgressObserver;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+use crate::retry::RetryOptions;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Directory of region (.mca) files of the base world, which the archives are applied to in place"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        required = true,
+        help = "Path of an archive to apply, repeated for every archive in the order they were packed"
+    )]
+    input_file: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Path of the manifest written when the last archive was packed, which every chunk of the world is checked against once the archives are applied"
+    )]
+    manifest: Option<String>,
+
+    #[clap(long, help = "Threads used for writing and hashing region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+}
+
+pub fn apply(options: &Options) -> Result<(), io::Error> {
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(output_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", output_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    // The manifest is read before anything is applied, so that a bad path doesn't leave the world half-updated
+    let manifest = match &options.manifest {
+        Some(manifest_path) => {
+            let manifest_path = Path::new(manifest_path);
+
+            if !Path::exists(manifest_path) {
+                return Err(Message::new(messages::INPUT_FILE_MISSING)
+                    .arg("path", manifest_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            Some(Manifest::load(manifest_path)?)
+        }
+        None => None,
+    };
+
+    for input_file in &options.input_file {
+        if !Path::exists(Path::new(input_file)) {
+            return Err(Message::new(messages::INPUT_FILE_MISSING)
+                .arg("path", input_file)
+                .into_error(io::ErrorKind::NotFound));
+        }
+    }
+
+    // Each archive is merged on its own, so that a chunk in a later archive replaces the same chunk from an earlier one.
+    // Unpacking already checks every chunk against the checksums of its archive.
+    for (index, input_file) in options.input_file.iter().enumerate() {
+        let unpack_options = UnpackOptions::update(
+            input_file,
+            &options.output_dir,
+            options.threads,
+            &options.retry,
+        );
+
+        unpack_files_with_observer(
+            &unpack_options,
+            &ProgressObserver::new("apply", "Applying archives"),
+        )?;
+
+        report!(
+            info,
+            messages::APPLY_ARCHIVE,
+            archive = input_file.as_str(),
+            index = index + 1,
+            archives = options.input_file.len()
+        );
+    }
+
+    match manifest {
+        Some(manifest) => verify_world(output_path, &manifest, options.threads),
+        None => Ok(()),
+    }
+}
+
+/// Checks every chunk of the world against the manifest of the last archive. Delta archives can't record chunks which
+/// were deleted, so chunks which the manifest doesn't list are removed from the world rather than reported.
+fn verify_world(
+    output_path: &Path,
+    manifest: &Manifest,
+    threads: Option<u32>,
+) -> Result<(), io::Error> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for entry in fs::read_dir(output_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let results = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| hash_region(path))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let mut actual = Manifest::new();
+
+    for (region, hashes) in results {
+        for (chunk, hash) in hashes {
+            actual.insert(region, chunk, hash);
+        }
+    }
+
+    let mut unlisted: BTreeMap<(i32, i32), HashSet<ChunkPos>> = BTreeMap::new();
+
+    for (region, chunk, _) in actual.iter() {
+        if manifest.get(region, chunk).is_none() {
+            unlisted
+                .entry((region.x, region.z))
+                .or_default()
+                .insert(chunk);
+        }
+    }
+
+    let mut removed = 0;
+
+    for (&(x, z), chunks) in &unlisted {
+        let path = output_path.join(format!("r.{}.{}.mca", x, z));
+        let (deleted_chunks, remaining_chunks) = delete_from_region(&path, chunks)?;
+
+        if remaining_chunks == 0 {
+            fs::remove_file(&path)?;
+        }
+
+        removed += deleted_chunks;
+    }
+
+    if removed > 0 {
+        report!(info, messages::APPLY_REMOVED, chunks = removed);
+    }
+
+    let mut mismatches: Vec<(ChunkPos, ChunkPos, MessageId)> = manifest
+        .iter()
+        .filter_map(|(region, chunk, hash)| {
+            let reason = match actual.get(region, chunk) {
+                Some(actual_hash) if actual_hash == hash => return None,
+                Some(_) => messages::MANIFEST_CHANGED,
+                None => messages::MANIFEST_MISSING,
+            };
+
+            Some((region, chunk, reason))
+        })
+        .collect();
+
+    mismatches.sort_by_key(|&(region, chunk, _)| (region.x, region.z, chunk.z, chunk.x));
+
+    for &(region, chunk, reason) in &mismatches {
+        report!(
+            warn,
+            reason,
+            region = format!("r.{}.{}.mca", region.x, region.z),
+            x = chunk.x,
+            z = chunk.z
+        );
+    }
+
+    if !mismatches.is_empty() {
+        return Err(Message::new(messages::MANIFEST_MISMATCHED)
+            .arg("chunks", mismatches.len())
+            .into_error(io::ErrorKind::InvalidData));
+    }
+
+    report!(
+        info,
+        messages::APPLY_VERIFIED,
+        chunks = actual.iter().count() - removed,
+        regions = files.len()
+    );
+
+    Ok(())
+}
+
+/// Hashes the NBT of every chunk of a region file as it is stored, in the same way as `pack --manifest`.
+fn hash_region(path: &Path) -> Result<(ChunkPos, Vec<(ChunkPos, u64)>), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let mut hashes = Vec::new();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        hashes.push((chunk.position, hash_chunk_data(&chunk.data)));
+    }
+
+    Ok((region_position, hashes))
+}
@@ -1,19 +1,53 @@
-use clap::Parser;
-use indicatif::ProgressBar;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use clap::{Parser, ValueEnum};
+use indicatif::{HumanBytes, HumanDuration};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
 use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::region::{Chunk, ChunkPos, RegionFile, RegionFileWriter};
+use crate::archive_version::{parse_format_version, ArchiveLayout};
+use crate::compression::{decompressed_reader, ArchiveCompression, ArchiveEncoder};
+use crate::dedup::{dedup_key, relocate_chunk, ChunkReferences, DedupKey};
+use crate::dimension::{DimensionOptions, REGION_DIR};
+use crate::encryption::{
+    check_targets, parse_encryption_target, ArchiveEncryptor, EncryptionTarget,
+};
+use crate::filter::{
+    matches_pattern, parse_coordinate_box, CoordinateBox, FilterOptions, StatusFilter,
+    StatusOptions,
+};
+use crate::journal::Journal;
+use crate::keep_going::{KeepGoingOptions, SkippedChunks};
+use crate::labels::{LabelRules, RegionLabels};
+use crate::manifest::{hash_chunk_data, ChunkHasher, Manifest};
+use crate::messages::{self, report, Message};
+use crate::metadata::ArchiveMetadata;
+use crate::observer::{NullObserver, OperationObserver, ProgressObserver};
+use crate::position_check::{PositionCheck, PositionCheckOptions};
+use crate::profile::{ArchiveProfile, ProfileTransform, ProfiledChunk};
+use crate::read_only;
+use crate::region::{
+    AnyRegionFile, Chunk, ChunkCompression, ChunkLayout, ChunkPos, RawChunk, RegionFile,
+    RegionFileWriter,
+};
+use crate::retry::{RetryOptions, RetryPolicy};
+use crate::snbt::{from_snbt, to_snbt};
+use crate::stripping::{needs_strip, strip_chunk, StripReport, StripRules, STRIP_PATHS};
+use crate::temp;
 use atty::Stream;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 pub struct PackOptions {
-    #[clap(long, help = "Input directory of region (.mca) files to archive")]
+    #[clap(
+        long,
+        help = "Input directory of region files to archive, in the Anvil (.mca), Linear (.linear) or MCRegion (.mcr) format"
+    )]
     input_dir: String,
 
     #[clap(
@@ -23,6 +57,52 @@ pub struct PackOptions {
     )]
     output_file: Option<String>,
 
+    #[clap(
+        long,
+        value_enum,
+        help = "Container format of the archive",
+        default_value = "tar"
+    )]
+    format: ArchiveFormat,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Header format of the entries of tar archives",
+        default_value = "gnu"
+    )]
+    tar_format: TarFormat,
+
+    #[clap(
+        long,
+        help = "Version of the archive layout to write, so that older builds of anvil-tools can read the archive (default is the newest)",
+        value_parser = parse_format_version
+    )]
+    format_version: Option<ArchiveLayout>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Compression applied to the whole tar archive, spread over the threads given by --threads",
+        default_value = "none"
+    )]
+    compression: ArchiveCompression,
+
+    #[clap(
+        long,
+        help = "Encrypt the tar archive with age, to a recipient given as age:<recipient> (may be repeated) or to the passphrase in --passphrase-file given as passphrase",
+        value_parser = parse_encryption_target
+    )]
+    encrypt: Vec<EncryptionTarget>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Modification time recorded for each entry of the archive",
+        default_value = "zero"
+    )]
+    entry_time: EntryTime,
+
     #[clap(
         long,
         help = "Strip cached data from chunks before archiving",
@@ -30,260 +110,2816 @@ pub struct PackOptions {
     )]
     strip: bool,
 
+    #[clap(
+        long,
+        help = "Store chunks with identical contents only once, recording the duplicates in a reference table"
+    )]
+    dedup: bool,
+
+    #[clap(
+        long,
+        help = "Decompress chunks straight into the archive instead of buffering them, which uses less memory but decompresses every chunk twice",
+        conflicts_with_all = ["strip", "dedup", "min_status", "status"]
+    )]
+    stream_chunks: bool,
+
+    #[clap(
+        long,
+        help = "Store chunks as they are compressed in the region files instead of decompressing them, which makes packing much faster but the archive compress worse",
+        conflicts_with_all = ["strip", "dedup", "stream_chunks", "min_status", "status", "manifest"]
+    )]
+    raw: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the chunk entries of the archive",
+        default_value = "nbt",
+        conflicts_with_all = ["stream_chunks", "raw"]
+    )]
+    entry_format: EntryFormat,
+
+    #[clap(
+        long,
+        help = "Approximate limit on the memory used by chunks waiting to be archived (e.g. 512M, 2G), which also streams chunks too large to buffer",
+        value_parser = parse_byte_size
+    )]
+    max_memory: Option<u64>,
+
     #[clap(long, help = "Threads used for reading region files")]
     threads: Option<u32>,
 
     #[clap(long, help = "Allow binary data to be piped to a TTY")]
     ignore_tty: bool,
+
+    #[clap(
+        long,
+        help = "Path of the chunk hash manifest to record (and compare against with --incremental)"
+    )]
+    manifest: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only archive chunks which have changed since the manifest was last written",
+        requires = "manifest"
+    )]
+    incremental: bool,
+
+    #[clap(
+        long,
+        help = "Only archive chunks modified after this point, given as a Unix timestamp to compare against the times recorded in the region files, or as the path of a manifest written by an earlier pack",
+        value_parser = parse_since,
+        conflicts_with = "incremental"
+    )]
+    since: Option<Since>,
+
+    #[clap(
+        long,
+        help = "Path of a journal which records progress so an interrupted pack can be resumed",
+        requires = "output_file"
+    )]
+    journal: Option<String>,
+
+    #[clap(
+        long,
+        help = "Path of a file assigning labels (such as tenants or owners) to regions, which are recorded in the archive"
+    )]
+    labels: Option<String>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+
+    #[clap(flatten)]
+    status: StatusOptions,
+
+    #[clap(flatten)]
+    position_check: PositionCheckOptions,
+
+    #[clap(flatten)]
+    dimensions: DimensionOptions,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+
+    #[clap(flatten)]
+    keep_going: KeepGoingOptions,
 }
 
-pub fn pack_files(options: &PackOptions) -> Result<(), io::Error> {
-    let input_path = Path::new(&options.input_dir);
+/// The container format of an archive. Both formats use the same layout of entries, but zip archives compress each
+/// entry individually and can be opened natively on more platforms, while tar archives can be streamed and compressed
+/// as a whole by external tools.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
 
-    if !Path::is_dir(input_path) {
-        panic!("Input directory does not exist");
-    }
+/// The header format of the entries of a tar archive. Chunk entries never need the extensions of either format, so
+/// this only matters to tools which only understand one of them.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum TarFormat {
+    /// GNU tar headers, as written by GNU tar itself
+    Gnu,
+    /// POSIX pax headers, with the path and modification time of each entry in an extended header before it
+    Pax,
+}
 
-    match &options.output_file {
-        Some(output_file) => {
-            let output_path = Path::new(output_file);
+/// Where the modification time of each entry of an archive comes from. Zip archives don't record it.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum EntryTime {
+    /// The Unix epoch, so that packing the same chunks twice produces an identical archive
+    Zero,
+    /// The time the pack started
+    Now,
+    /// The time each chunk was last saved, as recorded in its region file, and the time the pack started for other
+    /// entries
+    Region,
+}
+
+/// The format which chunks are stored in within an archive.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum EntryFormat {
+    /// Binary NBT, as it is stored in region files
+    Nbt,
+    /// SNBT text, which can be searched and compared with text tools but takes more space
+    Snbt,
+}
+
+/// The point after which chunks must have been modified to be packed with `--since`.
+#[derive(Clone)]
+pub enum Since {
+    /// Seconds since the Unix epoch, compared against the time each chunk was last saved as recorded in its region
+    /// file. Chunks which were never given a time are always packed.
+    Timestamp(u64),
+    /// The path of a manifest written by an earlier pack, which chunks are compared against by their hashes
+    Manifest(String),
+}
 
-            let file = File::create(output_path)?;
-            let file_write = BufWriter::new(file);
+/// Tracks chunk hashes across a pack run. The previous manifest is only consulted for incremental packs, while the
+/// current manifest receives the hash of every chunk seen so it can be written out once packing completes.
+struct ManifestState {
+    previous: Option<Manifest>,
+    current: Manifest,
+}
+
+/// The journal of a pack run. Regions are committed to the journal once all of their chunks have been written to the
+/// archive, along with the position in the archive where they end, so that a resumed run can discard anything written
+/// after the last complete region.
+struct PackJournal {
+    journal: Journal,
+    completed_regions: HashSet<String>,
+}
+
+/// The number of bytes of distinct chunks a deduplicating pack run keeps in memory to compare later chunks against,
+/// unless `--max-memory` leaves less. A chunk whose original has been evicted is stored again and becomes the original
+/// of any copies after it.
+const DEDUP_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The chunks stored most recently by a deduplicating pack run, keyed by a hash of their contents which ignores their
+/// position, and the chunks which were left out because they duplicate one of them. The least recently used chunks are
+/// evicted once their contents take up more than the cache's share of memory.
+struct DedupState {
+    originals: LruCache<u64, DedupOriginal>,
+    cached_bytes: u64,
+    max_bytes: u64,
+    references: ChunkReferences,
+}
+
+struct DedupOriginal {
+    position: (ChunkPos, ChunkPos),
+    contents: Box<[u8]>,
+}
 
-            pack_region_directory(&mut tar::Builder::new(file_write), input_path, options)
+impl DedupState {
+    /// Returns the original of a chunk whose contents were already stored, or records the chunk as the original of
+    /// any later copies. A chunk which only shares the hash of an original is stored in full.
+    fn find_original(
+        &mut self,
+        key: DedupKey,
+        position: (ChunkPos, ChunkPos),
+    ) -> Option<(ChunkPos, ChunkPos)> {
+        if let Some(original) = self.originals.get(&key.hash) {
+            return if original.contents == key.contents {
+                Some(original.position)
+            } else {
+                None
+            };
         }
-        None => {
-            if atty::is(Stream::Stdout) && !options.ignore_tty {
-                panic!("Refusing to pipe binary data to a terminal")
-            }
 
-            pack_region_directory(&mut tar::Builder::new(io::stdout()), input_path, options)
+        self.cached_bytes += key.contents.len() as u64;
+        self.originals.put(
+            key.hash,
+            DedupOriginal {
+                position,
+                contents: key.contents,
+            },
+        );
+
+        while self.cached_bytes > self.max_bytes {
+            match self.originals.pop_lru() {
+                Some((_, evicted)) => self.cached_bytes -= evicted.contents.len() as u64,
+                None => break,
+            }
         }
+
+        None
     }
 }
 
-fn pack_region_directory<W>(
-    archive: &mut tar::Builder<W>,
-    input_dir: &Path,
-    options: &PackOptions,
-) -> Result<(), io::Error>
-where
-    W: io::Write,
-{
-    let files: Vec<PathBuf> = fs::read_dir(input_dir)?
-        .filter_map(|entry| {
-            entry
-                .map(|entry| entry.path())
-                .map(|path| if path.is_file() { Some(path) } else { None })
-                .transpose()
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+/// State which is carried across every region file of a pack run.
+struct PackState {
+    manifest: Option<ManifestState>,
+    checksums: Manifest,
+    dedup: Option<DedupState>,
+    strip_report: Mutex<StripReport>,
+    unchanged_chunks: usize,
+    /// Chunks which were left out by `--since` because the region file records them as saved before the timestamp.
+    unmodified_chunks: usize,
+    packed_chunks: usize,
+    journal: Option<PackJournal>,
+    label_rules: Option<LabelRules>,
+    status_filter: StatusFilter,
+    position_check: PositionCheck,
+    retry: RetryPolicy,
+    skipped: SkippedChunks,
+    /// The modification time of entries which aren't dated by their chunk, in seconds since the Unix epoch.
+    modified: u64,
+}
 
-    let bar = ProgressBar::new(files.len() as u64);
-    bar.set_message("Packing region files");
+impl PackState {
+    /// Restores the state of an interrupted pack run from the records in its journal, returning the position in the
+    /// archive where the last complete region ends.
+    fn resume(&mut self, records: &[String]) -> u64 {
+        let mut resume_position = 0;
+        let mut pending_chunks = Vec::new();
+        let mut pending_references = Vec::new();
 
-    files.iter().try_for_each(|path| {
-        bar.inc(1);
-        pack_region(&path, archive, options)
-    })?;
+        for record in records {
+            let fields: Vec<&str> = record.split(' ').collect();
 
-    bar.finish();
+            match fields[..] {
+                ["chunk", path, checksum, hash, ..] => {
+                    if let Some((region, chunk)) = parse_chunk_entry_path(Path::new(path)) {
+                        let checksum = u64::from_str_radix(checksum, 16).ok();
+                        let hash = u64::from_str_radix(hash, 16).ok();
 
-    Ok(())
+                        pending_chunks.push((region, chunk, checksum, hash));
+                    }
+                }
+                ["reference", path, original, hash] => {
+                    let positions = parse_chunk_entry_path(Path::new(path))
+                        .zip(parse_chunk_entry_path(Path::new(original)));
+
+                    if let Some(((region, chunk), original)) = positions {
+                        let hash = u64::from_str_radix(hash, 16).ok();
+
+                        pending_references.push((region, chunk, original, hash));
+                    }
+                }
+                ["region", name, position] => {
+                    let position = match position.parse::<u64>() {
+                        Ok(position) => position,
+                        Err(_) => continue,
+                    };
+
+                    // The contents of chunks stored before the interruption are gone, so their copies after resuming
+                    // are stored again rather than referring to them
+                    for (region, chunk, checksum, hash) in pending_chunks.drain(..) {
+                        match checksum {
+                            Some(checksum) => self.checksums.insert(region, chunk, checksum),
+                            None => self.unchanged_chunks += 1,
+                        }
+
+                        if let (Some(manifest), Some(hash)) = (&mut self.manifest, hash) {
+                            manifest.current.insert(region, chunk, hash);
+                        }
+                    }
+
+                    for (region, chunk, original, hash) in pending_references.drain(..) {
+                        if let Some(dedup) = &mut self.dedup {
+                            dedup.references.insert(region, chunk, original);
+                        }
+
+                        if let (Some(manifest), Some(hash)) = (&mut self.manifest, hash) {
+                            manifest.current.insert(region, chunk, hash);
+                        }
+                    }
+
+                    if let Some(journal) = &mut self.journal {
+                        journal.completed_regions.insert(name.to_owned());
+                    }
+
+                    resume_position = position;
+                }
+                _ => {}
+            }
+        }
+
+        resume_position
+    }
 }
 
-fn pack_region<W>(
-    path: &Path,
-    archive: &mut tar::Builder<W>,
-    options: &PackOptions,
-) -> Result<(), io::Error>
+/// Counts the bytes written through it, so that the position within the output archive is known.
+struct CountingWriter<W> {
+    inner: W,
+    position: u64,
+}
+
+impl<W> io::Write for CountingWriter<W>
 where
     W: io::Write,
 {
-    let region_name = path.file_name().map(|f| f.to_string_lossy()).unwrap();
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
 
-    let region_position = RegionFile::parse_name(&region_name);
-    let region_file = RegionFile::open(&path)?;
+        Ok(written)
+    }
 
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(options.threads.unwrap_or(1) as usize)
-        .build()
-        .unwrap();
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
-    pool.in_place_scope(|scope| {
-        let (tx, rx) = std::sync::mpsc::sync_channel(4);
-
-        scope.spawn(|_| {
-            region_file
-                .stream_chunks()
-                .par_bridge()
-                .try_for_each(move |result| -> Result<(), io::Error> {
-                    if let Some(mut chunk) = result? {
-                        if options.strip {
-                            chunk = crate::commands::strip::strip_chunk(&chunk)?;
-                        }
+pub fn pack_files(options: &PackOptions) -> Result<(), io::Error> {
+    if !options.dimensions.is_active() {
+        return pack_files_with_observer(
+            options,
+            &ProgressObserver::new("pack", "Packing region files"),
+        );
+    }
 
-                        tx.send(chunk).unwrap();
-                    }
+    // Each dimension is packed into its own archive, so that their regions don't collide
+    if options.output_file.is_none() && options.dimensions.selected().len() > 1 {
+        return Err(
+            Message::new(messages::DIMENSION_STDOUT).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
 
-                    Ok(())
-                })
-                .unwrap();
-        });
+    // Chunks skipped in any dimension are reported once every dimension has been packed
+    let skipped = SkippedChunks::new(&options.keep_going);
 
-        rx.iter()
-            .try_for_each(|chunk: Chunk| {
-                let path = format!(
-                    "r.{}.{}/c.{}.{}.nbt",
-                    region_position.x, region_position.z, chunk.position.x, chunk.position.z
-                );
-                archive.append_data(
-                    &mut {
-                        let mut header = tar::Header::new_gnu();
-                        header.set_size(chunk.data.len() as u64);
-                        header
-                    },
-                    path,
-                    &*chunk.data,
-                )
-            })
-            .unwrap();
-    });
+    options.dimensions.for_each(
+        Path::new(&options.input_dir),
+        REGION_DIR,
+        |dimension, input_dir| {
+            let dimension_path =
+                |path: &Option<String>| path.as_ref().map(|path| dimension.file_path(path));
 
-    Ok(())
-}
+            let dimension_options = PackOptions {
+                input_dir: input_dir.to_string_lossy().into_owned(),
+                output_file: dimension_path(&options.output_file),
+                manifest: dimension_path(&options.manifest),
+                journal: dimension_path(&options.journal),
+                since: match &options.since {
+                    Some(Since::Manifest(path)) => Some(Since::Manifest(dimension.file_path(path))),
+                    since => since.clone(),
+                },
+                ..options.clone()
+            };
 
-#[derive(Parser)]
-pub struct UnpackOptions {
-    #[clap(
-        long,
-        help = "Path of the archive file to unpack (default is pipe from stdin)"
-    )]
-    input_file: Option<String>,
+            pack_files_skipping(
+                &dimension_options,
+                &ProgressObserver::new("pack", "Packing region files"),
+                &skipped,
+            )
+        },
+    )?;
 
-    #[clap(long, help = "Directory where the unpacked region files will be saved")]
-    output_dir: String,
+    skipped.finish(&NullObserver)
+}
 
-    #[clap(long, help = "Allow binary data to be piped to a TTY")]
-    ignore_tty: bool,
+/// Packs a directory of region files, passing the progress of the operation to the observer.
+pub fn pack_files_with_observer(
+    options: &PackOptions,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error> {
+    let skipped = SkippedChunks::new(&options.keep_going);
+
+    pack_files_skipping(options, observer, &skipped)?;
+    skipped.finish(observer)
 }
 
-pub fn unpack_files(options: &UnpackOptions) -> Result<(), io::Error> {
-    let output_dir = Path::new(&options.output_dir);
+/// Packs a directory of region files, recording the chunks skipped by `--keep-going` without reporting them.
+fn pack_files_skipping(
+    options: &PackOptions,
+    observer: &dyn OperationObserver,
+    skipped: &SkippedChunks,
+) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
 
-    match &options.input_file {
-        Some(input_path) => {
-            let input_path = Path::new(input_path);
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
 
-            if !Path::exists(input_path) {
-                panic!("Input file does not exist");
-            }
+    if options.format == ArchiveFormat::Zip {
+        if options.output_file.is_none() {
+            return Err(Message::new(messages::PACK_ZIP_REQUIRES_FILE)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
 
-            let file = File::open(input_path)?;
+        if options.journal.is_some() {
+            return Err(Message::new(messages::PACK_JOURNAL_TAR_ONLY)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
 
-            unpack_files_with_reader(&mut BufReader::new(file), output_dir)
+        if options.compression != ArchiveCompression::None {
+            return Err(
+                Message::new(messages::PACK_ZIP_COMPRESSED).into_error(io::ErrorKind::InvalidInput)
+            );
         }
-        None => {
-            if atty::is(Stream::Stdin) && !options.ignore_tty {
-                panic!("Refusing to pipe binary data from a terminal")
-            }
 
-            unpack_files_with_reader(&mut io::stdin(), output_dir)
+        if !options.encrypt.is_empty() {
+            return Err(
+                Message::new(messages::PACK_ZIP_ENCRYPTED).into_error(io::ErrorKind::InvalidInput)
+            );
         }
     }
-}
 
-struct ChunkEntry {
-    data: Box<[u8]>,
-    region: ChunkPos,
-    chunk: ChunkPos,
-}
+    // A compressed stream can't be cut short and continued where it was cut, so resuming one isn't possible
+    if options.journal.is_some() && options.compression != ArchiveCompression::None {
+        return Err(Message::new(messages::PACK_JOURNAL_UNCOMPRESSED_ONLY)
+            .into_error(io::ErrorKind::InvalidInput));
+    }
 
-fn unpack_files_with_reader<R>(reader: &mut R, output_dir: &Path) -> Result<(), io::Error>
-where
-    R: io::Read,
-{
-    let mut archive = tar::Archive::new(reader);
-    let output_dir = output_dir.to_owned();
+    // The same goes for an encrypted stream, whose chunks are numbered and authenticated in order
+    if options.journal.is_some() && !options.encrypt.is_empty() {
+        return Err(Message::new(messages::PACK_JOURNAL_UNENCRYPTED_ONLY)
+            .into_error(io::ErrorKind::InvalidInput));
+    }
 
-    let (sender, receiver) = std::sync::mpsc::sync_channel(4);
-    let region_cache: RegionFileCache =
-        Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(8).unwrap())));
+    check_targets(&options.encrypt)?;
 
-    let receive_thread = std::thread::spawn(move || -> Result<(), io::Error> {
-        receiver
-            .iter()
-            .try_for_each(|entry| unpack_file(&output_dir, region_cache.clone(), entry))
-    });
+    let output_paths = vec![&options.output_file, &options.manifest, &options.journal];
+
+    for output_path in output_paths.into_iter().flatten() {
+        read_only::ensure_writable(Path::new(output_path))?;
+    }
 
-    for entry in archive.entries()? {
-        let mut entry = entry?;
+    match &options.since {
+        // Chunks which weren't modified are never read, so there would be no hashes to record for them
+        Some(Since::Timestamp(_)) if options.manifest.is_some() => {
+            return Err(Message::new(messages::PACK_SINCE_TIMESTAMP_MANIFEST)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
+        Some(Since::Manifest(_)) if options.raw => {
+            return Err(Message::new(messages::PACK_SINCE_MANIFEST_RAW)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
+        Some(Since::Manifest(since_path)) if !Path::exists(Path::new(since_path)) => {
+            return Err(Message::new(messages::INPUT_FILE_MISSING)
+                .arg("path", since_path)
+                .into_error(io::ErrorKind::NotFound));
+        }
+        _ => {}
+    }
 
-        let path = entry.path().expect("Couldn't determine path of tar entry");
+    let manifest = match (&options.manifest, &options.since) {
+        (_, Some(Since::Manifest(since_path))) => Some(ManifestState {
+            previous: Some(Manifest::load(Path::new(since_path))?),
+            current: Manifest::new(),
+        }),
+        (Some(manifest_path), _) => Some(ManifestState {
+            previous: if options.incremental {
+                Some(Manifest::load_or_default(Path::new(manifest_path))?)
+            } else {
+                None
+            },
+            current: Manifest::new(),
+        }),
+        (None, _) => None,
+    };
 
-        let region_name = path
-            .parent()
-            .expect("Missing region location in tar entry path")
-            .to_string_lossy();
+    let mut state = PackState {
+        manifest,
+        checksums: Manifest::new(),
+        dedup: if options.dedup {
+            // The cache takes at most half of the memory limit, leaving the rest for chunks waiting to be written
+            Some(DedupState {
+                originals: LruCache::unbounded(),
+                cached_bytes: 0,
+                max_bytes: options.max_memory.map_or(DEDUP_CACHE_BYTES, |max_memory| {
+                    DEDUP_CACHE_BYTES.min(max_memory / 2)
+                }),
+                references: ChunkReferences::default(),
+            })
+        } else {
+            None
+        },
+        strip_report: Mutex::new(StripReport::default()),
+        unchanged_chunks: 0,
+        unmodified_chunks: 0,
+        packed_chunks: 0,
+        journal: None,
+        label_rules: match &options.labels {
+            Some(labels_path) => Some(LabelRules::load(Path::new(labels_path))?),
+            None => None,
+        },
+        status_filter: StatusFilter::new(&options.status),
+        position_check: PositionCheck::new(&options.position_check),
+        retry: RetryPolicy::new(&options.retry),
+        skipped: skipped.clone(),
+        modified: match options.entry_time {
+            EntryTime::Zero => 0,
+            EntryTime::Now | EntryTime::Region => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        },
+    };
 
-        let chunk_name = path
-            .file_name()
-            .expect("Missing chunk location in tar entry path")
-            .to_string_lossy();
+    let mut resume_position = 0;
 
-        let region_position = RegionFile::parse_name(&region_name);
-        let chunk_position = RegionFile::parse_name(&chunk_name);
+    if let Some(journal_path) = &options.journal {
+        let (journal, records) = Journal::open(Path::new(journal_path), "pack")?;
 
-        let mut data = Vec::with_capacity(entry.size() as usize);
-        entry.read_to_end(&mut data)?;
+        state.journal = Some(PackJournal {
+            journal,
+            completed_regions: HashSet::new(),
+        });
 
-        sender
-            .send(ChunkEntry {
-                data: data.into_boxed_slice(),
-                region: region_position,
-                chunk: chunk_position,
-            })
-            .unwrap();
+        resume_position = state.resume(&records);
     }
 
-    drop(sender);
+    match &options.output_file {
+        Some(output_file) => {
+            let output_path = Path::new(output_file);
 
-    receive_thread.join().unwrap()?;
+            let file = if resume_position > 0 {
+                let mut file = state
+                    .retry
+                    .run(|| OpenOptions::new().write(true).open(output_path))?;
 
-    Ok(())
-}
+                file.set_len(resume_position)?;
+                file.seek(SeekFrom::Start(resume_position))?;
 
-use lru::LruCache;
+                file
+            } else {
+                state.retry.run(|| File::create(output_path))?
+            };
 
-type RegionFileCache = Arc<Mutex<LruCache<ChunkPos, Arc<Mutex<RegionFileWriter>>>>>;
+            let file_write = BufWriter::new(state.retry.wrap(file));
 
-fn unpack_file(
-    output_dir: &Path,
-    region_cache: RegionFileCache,
-    entry: ChunkEntry,
-) -> Result<(), io::Error> {
-    let region_writer: Arc<Mutex<RegionFileWriter>> = {
-        let mut region_cache = region_cache.lock().unwrap();
+            match options.format {
+                ArchiveFormat::Tar => pack_region_directory(
+                    &mut TarArchive::new(file_write, resume_position, options)?,
+                    input_path,
+                    &mut state,
+                    options,
+                    observer,
+                )?,
+                ArchiveFormat::Zip => pack_region_directory(
+                    &mut zip::ZipWriter::new(file_write),
+                    input_path,
+                    &mut state,
+                    options,
+                    observer,
+                )?,
+            }
+        }
+        None => {
+            if atty::is(Stream::Stdout) && !options.ignore_tty {
+                return Err(Message::new(messages::TTY_OUTPUT_REFUSED)
+                    .into_error(io::ErrorKind::InvalidInput));
+            }
 
-        match region_cache.get(&entry.region) {
-            Some(r) => r.clone(),
-            None => {
-                let region_path =
-                    output_dir.join(format!("r.{}.{}.mca", entry.region.x, entry.region.z));
+            pack_region_directory(
+                &mut TarArchive::new(io::stdout(), 0, options)?,
+                input_path,
+                &mut state,
+                options,
+                observer,
+            )?;
+        }
+    }
 
-                let writer = Arc::new(Mutex::new(RegionFileWriter::create(&region_path)?));
-                region_cache.put(entry.region, writer.clone());
+    if let Some(journal) = state.journal.take() {
+        journal.journal.finish()?;
+    }
 
-                writer
-            }
+    if options.strip {
+        state.strip_report.into_inner().unwrap().print();
+    }
+
+    match &options.since {
+        Some(Since::Timestamp(since)) => {
+            report!(
+                info,
+                messages::PACK_UNMODIFIED,
+                unmodified_chunks = state.unmodified_chunks,
+                since = *since
+            );
         }
-    };
+        _ if options.since.is_some() || options.incremental => {
+            report!(
+                info,
+                messages::PACK_UNCHANGED,
+                unchanged_chunks = state.unchanged_chunks
+            );
+        }
+        _ => {}
+    }
 
-    region_writer.lock().unwrap().add_chunk(&Chunk {
-        position: entry.chunk,
-        data: entry.data,
-    })?;
+    if let Some(dedup) = &state.dedup {
+        let deduplicated_chunks = dedup.references.iter().count();
+
+        report!(
+            info,
+            messages::PACK_DEDUPLICATED,
+            deduplicated_chunks = deduplicated_chunks
+        );
+    }
+
+    state.status_filter.report();
+    state.position_check.report(observer);
+    state.retry.report(observer);
+
+    if let (Some(manifest_path), Some(manifest)) = (&options.manifest, state.manifest) {
+        manifest.current.save(Path::new(manifest_path))?;
+    }
 
     Ok(())
 }
+
+/// The path of the archive entry which lists the checksum of every chunk entry. It is written after all chunks, so an
+/// archive which is missing it may also have been truncated.
+pub const CHECKSUMS_ENTRY_PATH: &str = "CHECKSUMS";
+
+/// The key of the PAX extended header record which gives the xxHash64 checksum of the chunk entry it precedes in a tar
+/// archive, so that a corrupt chunk is found as soon as it is read, before anything is written from it.
+const CHECKSUM_PAX_KEY: &str = "ANVILTOOLS.xxh64";
+
+/// The path of the archive entry which lists the chunks left out of a deduplicated archive, along with the chunk which
+/// has the same contents. It is written after all chunks, but before the checksums.
+pub const DEDUP_ENTRY_PATH: &str = "DEDUP";
+
+/// The path of the archive entry which describes the archive and the world it was packed from. It is the first entry of
+/// the archive.
+pub const METADATA_ENTRY_PATH: &str = "METADATA";
+
+/// The path of the archive entry which lists the labels of each region, if the archive was packed with labels. It is
+/// written before all chunks, after the metadata.
+pub const LABELS_ENTRY_PATH: &str = "LABELS";
+
+/// The path of the archive entry which records the profile of transformations applied to the chunks of the archive
+/// when it was packed. It is written before all chunks, after the labels.
+pub const PROFILE_ENTRY_PATH: &str = "PROFILE";
+
+/// Returns the path of a chunk's entry within the archive.
+pub fn chunk_entry_path(region: ChunkPos, chunk: ChunkPos) -> String {
+    format!("r.{}.{}/c.{}.{}.nbt", region.x, region.z, chunk.x, chunk.z)
+}
+
+/// The extension of the entries of chunks packed with `--entry-format snbt`.
+const SNBT_ENTRY_EXTENSION: &str = ".snbt";
+
+/// Returns the path of the entry of a chunk which is stored as SNBT text.
+pub fn snbt_chunk_entry_path(region: ChunkPos, chunk: ChunkPos) -> String {
+    format!(
+        "r.{}.{}/c.{}.{}{}",
+        region.x, region.z, chunk.x, chunk.z, SNBT_ENTRY_EXTENSION
+    )
+}
+
+/// Checks whether a chunk entry holds SNBT text rather than binary NBT data.
+pub fn is_snbt_chunk_entry(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(SNBT_ENTRY_EXTENSION))
+}
+
+/// The extensions of the entries of chunks packed with `--raw`, by the compression type which they keep from their region
+/// file. Uncompressed chunks are stored in the same way as any other chunk.
+const RAW_ENTRY_EXTENSIONS: [(u8, &str); 3] = [(1, ".nbt.gz"), (2, ".nbt.zlib"), (3, ".nbt")];
+
+/// Returns the path of the entry of a chunk which is stored compressed in the given way, or `None` if the compression
+/// type is unknown.
+pub fn raw_chunk_entry_path(region: ChunkPos, chunk: ChunkPos, compression: u8) -> Option<String> {
+    let (_, extension) = RAW_ENTRY_EXTENSIONS
+        .iter()
+        .find(|&&(entry_compression, _)| entry_compression == compression)?;
+
+    Some(format!(
+        "r.{}.{}/c.{}.{}{}",
+        region.x, region.z, chunk.x, chunk.z, extension
+    ))
+}
+
+/// Returns the compression type of a chunk entry which was packed with `--raw`, or `None` if the entry holds plain NBT
+/// data.
+pub fn raw_chunk_entry_compression(path: &Path) -> Option<u8> {
+    let name = path.file_name()?.to_str()?;
+
+    RAW_ENTRY_EXTENSIONS
+        .iter()
+        .filter(|&&(_, extension)| extension != ".nbt")
+        .find(|&&(_, extension)| name.ends_with(extension))
+        .map(|&(compression, _)| compression)
+}
+
+/// Returns the NBT data of a chunk entry, decompressing entries which were packed with `--raw` and parsing entries which
+/// were packed as SNBT.
+pub fn chunk_entry_nbt<'a>(path: &Path, data: &'a [u8]) -> Result<Cow<'a, [u8]>, io::Error> {
+    if is_snbt_chunk_entry(path) {
+        return Ok(Cow::Owned(from_snbt(data)?));
+    }
+
+    match (
+        raw_chunk_entry_compression(path),
+        parse_chunk_entry_path(path),
+    ) {
+        (Some(compression), Some((_, chunk))) => {
+            let raw_chunk = RawChunk::from_parts(chunk, compression, data);
+
+            Ok(Cow::Owned(raw_chunk.decompress()?.data.into_vec()))
+        }
+        _ => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Parses the region and chunk positions out of the path of a chunk's entry within the archive, returning `None` if
+/// the path does not follow the archive layout.
+pub fn parse_chunk_entry_path(path: &Path) -> Option<(ChunkPos, ChunkPos)> {
+    let region_name = path.parent()?.to_str()?;
+    let chunk_name = path.file_name()?.to_str()?;
+
+    Some((
+        RegionFile::try_parse_name(region_name)?,
+        RegionFile::try_parse_name(chunk_name)?,
+    ))
+}
+
+/// A writer for the entries of an archive, which hides the differences between the container formats.
+trait ArchiveWriter {
+    /// Appends an entry, given when it was last modified in seconds since the Unix epoch.
+    fn append_entry(&mut self, path: &str, modified: u64, data: &[u8]) -> Result<(), io::Error>;
+
+    /// Appends an entry whose data is read from a reader, which must produce exactly `size` bytes.
+    fn append_entry_from(
+        &mut self,
+        path: &str,
+        modified: u64,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), io::Error>;
+
+    /// Records the checksum of the entry which is appended next. Zip archives store a CRC-32 with every entry already,
+    /// which is checked as the entry is read, so only tar archives need to record anything.
+    fn append_checksum(&mut self, _checksum: u64) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    /// Flushes the entries written so far, returning the position in the output where the next entry will begin.
+    fn flush_position(&mut self) -> Result<u64, io::Error>;
+
+    /// Finishes writing the archive, returning its total size.
+    fn finish(&mut self) -> Result<u64, io::Error>;
+}
+
+/// A tar archive being written, along with the format of the headers of its entries and the checksum of the entry
+/// which is appended next. The position of the archive is counted after it has been compressed, but before it is
+/// encrypted.
+struct TarArchive<W: io::Write> {
+    builder: tar::Builder<ArchiveEncoder<CountingWriter<ArchiveEncryptor<W>>>>,
+    format: TarFormat,
+    checksum: Option<u64>,
+}
+
+impl<W> TarArchive<W>
+where
+    W: io::Write,
+{
+    fn new(writer: W, position: u64, options: &PackOptions) -> Result<Self, io::Error> {
+        let writer = CountingWriter {
+            inner: ArchiveEncryptor::new(writer, &options.encrypt)?,
+            position,
+        };
+
+        Ok(TarArchive {
+            builder: tar::Builder::new(ArchiveEncoder::new(
+                writer,
+                options.compression,
+                options.threads.unwrap_or(1),
+            )?),
+            format: options.tar_format,
+            checksum: None,
+        })
+    }
+
+    /// Writes the extended header of the entry which is appended next, holding its checksum if one was given, along
+    /// with its path and modification time in pax archives. Nothing is written if there are no records.
+    fn append_extensions(&mut self, path: &str, modified: u64) -> Result<(), io::Error> {
+        let mut records = Vec::new();
+
+        if let Some(checksum) = self.checksum.take() {
+            records.push((CHECKSUM_PAX_KEY, format!("{:016x}", checksum)));
+        }
+
+        if self.format == TarFormat::Pax {
+            records.push(("path", String::from(path)));
+            records.push(("mtime", modified.to_string()));
+        }
+
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        self.builder
+            .append_pax_extensions(records.iter().map(|(key, value)| (*key, value.as_bytes())))
+    }
+
+    /// Creates the header of a regular file, which is readable by everyone once extracted. The checksum is filled in
+    /// when the entry is appended.
+    fn entry_header(&self, size: u64, modified: u64) -> tar::Header {
+        let mut header = match self.format {
+            TarFormat::Gnu => tar::Header::new_gnu(),
+            TarFormat::Pax => tar::Header::new_ustar(),
+        };
+
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(modified);
+        header
+    }
+}
+
+impl<W> ArchiveWriter for TarArchive<W>
+where
+    W: io::Write,
+{
+    fn append_entry(&mut self, path: &str, modified: u64, data: &[u8]) -> Result<(), io::Error> {
+        self.append_extensions(path, modified)?;
+
+        let mut header = self.entry_header(data.len() as u64, modified);
+        self.builder.append_data(&mut header, path, data)
+    }
+
+    fn append_entry_from(
+        &mut self,
+        path: &str,
+        modified: u64,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), io::Error> {
+        self.append_extensions(path, modified)?;
+
+        let mut header = self.entry_header(size, modified);
+        self.builder.append_data(&mut header, path, reader)
+    }
+
+    fn append_checksum(&mut self, checksum: u64) -> Result<(), io::Error> {
+        self.checksum = Some(checksum);
+
+        Ok(())
+    }
+
+    fn flush_position(&mut self) -> Result<u64, io::Error> {
+        self.builder.get_mut().flush()?;
+
+        Ok(self.builder.get_ref().get_ref().position)
+    }
+
+    fn finish(&mut self) -> Result<u64, io::Error> {
+        self.builder.finish()?;
+        self.builder.get_mut().finish()?;
+        self.builder.get_mut().get_mut().inner.finish()?;
+
+        Ok(self.builder.get_ref().get_ref().position)
+    }
+}
+
+impl<W> ArchiveWriter for zip::ZipWriter<W>
+where
+    W: io::Write + io::Seek,
+{
+    fn append_entry(&mut self, path: &str, _modified: u64, data: &[u8]) -> Result<(), io::Error> {
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+        self.start_file(path, options)?;
+        self.write_all(data)
+    }
+
+    fn append_entry_from(
+        &mut self,
+        path: &str,
+        _modified: u64,
+        _size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), io::Error> {
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+        self.start_file(path, options)?;
+        io::copy(reader, self)?;
+
+        Ok(())
+    }
+
+    fn flush_position(&mut self) -> Result<u64, io::Error> {
+        Err(Message::new(messages::PACK_ZIP_NOT_RESUMABLE).into_error(io::ErrorKind::Unsupported))
+    }
+
+    fn finish(&mut self) -> Result<u64, io::Error> {
+        let mut writer = zip::ZipWriter::finish(self)?;
+        writer.flush()?;
+
+        writer.stream_position()
+    }
+}
+
+fn pack_region_directory<A>(
+    archive: &mut A,
+    input_dir: &Path,
+    state: &mut PackState,
+    options: &PackOptions,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error>
+where
+    A: ArchiveWriter,
+{
+    let files: Vec<PathBuf> = fs::read_dir(input_dir)?
+        .filter_map(|entry| {
+            entry
+                .map(|entry| entry.path())
+                .map(|path| if path.is_file() { Some(path) } else { None })
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|path| {
+            let region_name = path.file_name().unwrap().to_string_lossy();
+
+            AnyRegionFile::is_region_name(&region_name)
+                && !AnyRegionFile::is_superseded(path)
+                && options
+                    .filter
+                    .includes_region(RegionFile::parse_name(&region_name))
+        })
+        .collect();
+
+    // Regions which were completed by an interrupted run are left out entirely, so that the throughput and ETA only
+    // reflect the work which is left to do
+    let mut pending_files = Vec::with_capacity(files.len());
+
+    for path in &files {
+        let completed = match &state.journal {
+            Some(journal) => path
+                .file_name()
+                .map(|name| journal.completed_regions.contains(&*name.to_string_lossy()))
+                .unwrap_or(false),
+            None => false,
+        };
+
+        if !completed {
+            pending_files.push((path, fs::metadata(path)?.len()));
+        }
+    }
+
+    let input_bytes: u64 = pending_files.iter().map(|(_, size)| size).sum();
+
+    let input_chunks = pending_files
+        .iter()
+        .map(|(path, _)| AnyRegionFile::count_chunks(path))
+        .sum::<Result<u64, io::Error>>()?;
+
+    // The metadata, labels and profile are written before any chunks so that unpack can check and filter by them while
+    // streaming. A resumed run has already written them, unless it was interrupted before finishing its first region,
+    // in which case every region is still pending.
+    let resumed = match &state.journal {
+        Some(journal) => !journal.completed_regions.is_empty(),
+        None => false,
+    };
+
+    let layout = options.format_version.unwrap_or(ArchiveLayout::CURRENT);
+
+    if !resumed && layout.has_metadata() {
+        let mut metadata_data = Vec::new();
+        // Archives dated to the Unix epoch must come out identical every time, so they don't record when they were packed
+        let created = match options.entry_time {
+            EntryTime::Zero => None,
+            EntryTime::Now | EntryTime::Region => Some(state.modified),
+        };
+
+        ArchiveMetadata::new(input_dir, input_chunks, created).write_to(&mut metadata_data)?;
+
+        archive.append_entry(METADATA_ENTRY_PATH, state.modified, &metadata_data)?;
+    }
+
+    if let (Some(label_rules), false) = (&state.label_rules, resumed) {
+        let mut labels = RegionLabels::default();
+
+        for path in &files {
+            let region = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+            labels.insert(region, label_rules.labels_for(region));
+        }
+
+        let mut label_data = Vec::new();
+        labels.write_to(&mut label_data)?;
+
+        archive.append_entry(LABELS_ENTRY_PATH, state.modified, &label_data)?;
+    }
+
+    if !resumed {
+        let mut profile = ArchiveProfile::default();
+
+        if options.strip {
+            profile.removed_tags = STRIP_PATHS.iter().map(|&path| String::from(path)).collect();
+        }
+
+        state.status_filter.record_profile(&mut profile);
+        options.filter.record_profile(&mut profile);
+
+        let mut profile_data = Vec::new();
+        profile.write_to(&mut profile_data)?;
+
+        archive.append_entry(PROFILE_ENTRY_PATH, state.modified, &profile_data)?;
+    }
+
+    // A single pool is shared by every region, rather than starting new threads for each of them
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+
+    observer.started(Some(input_bytes), Some(input_chunks));
+
+    pack_regions(archive, &pending_files, &pool, state, options, observer)?;
+
+    observer.finished();
+
+    if let Some(dedup) = &state.dedup {
+        let mut reference_data = Vec::new();
+        dedup.references.write_to(&mut reference_data)?;
+
+        archive.append_entry(DEDUP_ENTRY_PATH, state.modified, &reference_data)?;
+    }
+
+    let mut checksum_data = Vec::new();
+    state.checksums.write_to(&mut checksum_data)?;
+
+    archive.append_entry(CHECKSUMS_ENTRY_PATH, state.modified, &checksum_data)?;
+    let output_bytes = archive.finish()?;
+
+    report!(
+        info,
+        messages::PACK_SUMMARY,
+        chunks = state.packed_chunks,
+        regions = pending_files.len(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        elapsed = HumanDuration(started.elapsed()).to_string(),
+        input_bytes = input_bytes,
+        input_size = HumanBytes(input_bytes).to_string(),
+        output_bytes = output_bytes,
+        output_size = HumanBytes(output_bytes).to_string()
+    );
+
+    Ok(())
+}
+
+/// A chunk read by a pack worker. The content is omitted if it has not changed since the previous manifest, in which
+/// case only its hash is passed along to be recorded in the updated manifest.
+struct PackedChunk {
+    region: ChunkPos,
+    position: ChunkPos,
+    hash: Option<u64>,
+    dedup_key: Option<DedupKey>,
+    checksum: Option<u64>,
+    /// When the chunk's entry was last modified, in seconds since the Unix epoch.
+    modified: u64,
+    content: Option<PackedContent>,
+}
+
+enum PackedContent {
+    /// The decompressed NBT data of the chunk.
+    Data(Chunk),
+    /// The chunk as it is stored in the region file, which is decompressed as it is written into the archive, along
+    /// with the size of its decompressed data.
+    Stream(RawChunk, u64),
+    /// The chunk as it is stored in the region file, which is written into the archive without decompressing it.
+    Raw(RawChunk),
+    /// The NBT data of the chunk converted to SNBT text.
+    Snbt(Box<[u8]>),
+}
+
+impl PackedChunk {
+    /// The number of bytes held in memory by the chunk until it is written.
+    fn buffered_size(&self) -> u64 {
+        let content_size = match &self.content {
+            Some(PackedContent::Data(chunk)) => chunk.data.len() as u64,
+            Some(PackedContent::Snbt(text)) => text.len() as u64,
+            Some(PackedContent::Stream(raw_chunk, _)) | Some(PackedContent::Raw(raw_chunk)) => {
+                raw_chunk.payload.len() as u64
+            }
+            None => 0,
+        };
+
+        // Deduplicated chunks also hold a copy of their contents with the position cleared
+        let key_size = self
+            .dedup_key
+            .as_ref()
+            .map_or(0, |key| key.contents.len() as u64);
+
+        content_size + key_size
+    }
+}
+
+/// A step of writing the archive, which are carried out strictly in the order they were issued.
+enum PackStep {
+    /// A chunk which was read by a worker, or nothing if the chunk was skipped by a filter.
+    Chunk(Option<PackedChunk>),
+    /// Every chunk of a region has been written.
+    RegionEnd {
+        region: ChunkPos,
+        name: String,
+        size: u64,
+        started: Instant,
+    },
+}
+
+/// Packs the chunks of every region. Chunks are read from each region in turn and processed by the pool, so that the
+/// chunks of several regions can be in progress at once, while the current thread writes them to the archive in the
+/// order they were read. This keeps the layout of the archive the same no matter how many threads are used.
+fn pack_regions<A>(
+    archive: &mut A,
+    files: &[(&PathBuf, u64)],
+    pool: &rayon::ThreadPool,
+    state: &mut PackState,
+    options: &PackOptions,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error>
+where
+    A: ArchiveWriter,
+{
+    let (previous_manifest, mut current_manifest) = match &mut state.manifest {
+        Some(manifest) => (manifest.previous.as_ref(), Some(&mut manifest.current)),
+        None => (None, None),
+    };
+    let record_hashes = current_manifest.is_some();
+
+    let retry = &state.retry;
+    let skipped = &state.skipped;
+    let strip_report = &state.strip_report;
+    let status_filter = &state.status_filter;
+    let position_check = &state.position_check;
+    let checksums = &mut state.checksums;
+    let dedup = &mut state.dedup;
+    let dedup_cache_bytes = dedup.as_ref().map_or(0, |dedup| dedup.max_bytes);
+    let unchanged_chunks = &mut state.unchanged_chunks;
+    let unmodified_chunks = &mut state.unmodified_chunks;
+    let packed_chunks = &mut state.packed_chunks;
+    let journal = &mut state.journal;
+    let default_modified = state.modified;
+
+    // When memory is limited, chunks which would take up too much of the budget on their own are streamed into the
+    // archive instead of being buffered, unless they need to be decoded
+    let can_stream = !options.strip
+        && !options.dedup
+        && !status_filter.is_active()
+        && options.entry_format == EntryFormat::Nbt;
+    let stream_threshold = match options.max_memory {
+        Some(max_memory) if can_stream => Some(max_memory / pool.current_num_threads() as u64),
+        _ => None,
+    };
+
+    let pack_chunk = |region_position: ChunkPos,
+                      raw_chunk: RawChunk,
+                      modified: u64|
+     -> Result<Option<PackedChunk>, io::Error> {
+        let is_unchanged = |hash: Option<u64>| match (previous_manifest, hash) {
+            (Some(previous), Some(hash)) => {
+                previous.get(region_position, raw_chunk.position) == Some(hash)
+            }
+            _ => false,
+        };
+
+        // Raw chunks are only checked for a compression type which the archive can record
+        if options.raw {
+            if raw_chunk_entry_path(region_position, raw_chunk.position, raw_chunk.compression())
+                .is_none()
+            {
+                return Err(Message::new(messages::REGION_UNKNOWN_COMPRESSION)
+                    .arg("compression", raw_chunk.compression())
+                    .arg("x", raw_chunk.position.x)
+                    .arg("z", raw_chunk.position.z)
+                    .into_error(io::ErrorKind::InvalidData));
+            }
+
+            return Ok(Some(PackedChunk {
+                region: region_position,
+                position: raw_chunk.position,
+                hash: None,
+                dedup_key: None,
+                checksum: Some(hash_chunk_data(raw_chunk.compressed_data())),
+                modified,
+                content: Some(PackedContent::Raw(raw_chunk)),
+            }));
+        }
+
+        let buffered_chunk = if options.stream_chunks {
+            None
+        } else {
+            match stream_threshold {
+                Some(threshold) => raw_chunk.decompress_limited(threshold)?,
+                None => Some(raw_chunk.decompress()?),
+            }
+        };
+
+        let mut chunk = match buffered_chunk {
+            Some(chunk) => chunk,
+            None => {
+                // The size of an entry must be known before it is written, so the chunk is decompressed once to measure
+                // and hash it, and again while it is written into the archive
+                let mut hasher = ChunkHasher::default();
+                io::copy(&mut raw_chunk.decompressed_reader()?, &mut hasher)?;
+
+                let (size, checksum) = hasher.finish();
+                let hash = if record_hashes { Some(checksum) } else { None };
+                let unchanged = is_unchanged(hash);
+
+                return Ok(Some(PackedChunk {
+                    region: region_position,
+                    position: raw_chunk.position,
+                    hash,
+                    dedup_key: None,
+                    checksum: Some(checksum),
+                    modified,
+                    content: if unchanged {
+                        None
+                    } else {
+                        Some(PackedContent::Stream(raw_chunk, size))
+                    },
+                }));
+            }
+        };
+
+        position_check.check(region_position, &mut chunk)?;
+
+        if !status_filter.includes(&chunk.data)? {
+            return Ok(None);
+        }
+
+        let hash = if record_hashes {
+            Some(hash_chunk_data(&chunk.data))
+        } else {
+            None
+        };
+
+        if is_unchanged(hash) {
+            return Ok(Some(PackedChunk {
+                region: region_position,
+                position: chunk.position,
+                hash,
+                dedup_key: None,
+                checksum: None,
+                modified,
+                content: None,
+            }));
+        }
+
+        if options.strip {
+            let mut report = StripReport::default();
+
+            let rules = StripRules::default();
+
+            if needs_strip(&chunk.data, &rules)? {
+                chunk = strip_chunk(&chunk, &rules, &mut report)?;
+            } else {
+                report.record_unchanged(chunk.data.len() as u64);
+            }
+
+            strip_report.lock().unwrap().merge(&report);
+        }
+
+        let dedup_key = if options.dedup {
+            Some(dedup_key(&chunk.data)?)
+        } else {
+            None
+        };
+
+        // Checksums cover the entry as it is stored, so that they can be checked without converting it back
+        let position = chunk.position;
+        let (checksum, content) = match options.entry_format {
+            EntryFormat::Nbt => (hash_chunk_data(&chunk.data), PackedContent::Data(chunk)),
+            EntryFormat::Snbt => {
+                let text = to_snbt(&chunk.data)?.into_bytes().into_boxed_slice();
+
+                (hash_chunk_data(&text), PackedContent::Snbt(text))
+            }
+        };
+
+        Ok(Some(PackedChunk {
+            region: region_position,
+            position,
+            hash,
+            dedup_key,
+            checksum: Some(checksum),
+            modified,
+            content: Some(content),
+        }))
+    };
+    let pack_chunk = &pack_chunk;
+
+    let mut journal_records = Vec::new();
+    let mut region_chunks = 0;
+
+    let mut next_step = 0;
+
+    // Carries out every step which is ready, stopping at the first step whose chunk is still being read
+    let mut write_ready = |archive: &mut A,
+                           pending: &mut BTreeMap<usize, PackStep>,
+                           buffered_bytes: &mut u64|
+     -> Result<(), io::Error> {
+        while let Some(step) = pending.remove(&next_step) {
+            next_step += 1;
+
+            match step {
+                PackStep::Chunk(Some(mut packed)) => {
+                    let region_position = packed.region;
+                    *buffered_bytes -= packed.buffered_size();
+
+                    observer.chunk_processed(region_position, packed.position);
+
+                    if let (Some(manifest), Some(hash)) = (current_manifest.as_mut(), packed.hash) {
+                        manifest.insert(region_position, packed.position, hash);
+                    }
+
+                    let checksum = packed.checksum;
+
+                    // A chunk whose contents were already stored is only recorded as a reference to the first copy
+                    let original = match (dedup.as_mut(), packed.dedup_key.take()) {
+                        (Some(dedup), Some(key)) => {
+                            dedup.find_original(key, (region_position, packed.position))
+                        }
+                        _ => None,
+                    };
+
+                    if journal.is_some() {
+                        let hash = packed
+                            .hash
+                            .map_or(String::from("-"), |hash| format!("{:016x}", hash));
+
+                        journal_records.push(match original {
+                            Some((original_region, original_chunk)) => format!(
+                                "reference {} {} {}",
+                                chunk_entry_path(region_position, packed.position),
+                                chunk_entry_path(original_region, original_chunk),
+                                hash
+                            ),
+                            None => format!(
+                                "chunk {} {} {}",
+                                chunk_entry_path(region_position, packed.position),
+                                checksum.map_or(String::from("-"), |checksum| format!(
+                                    "{:016x}",
+                                    checksum
+                                )),
+                                hash
+                            ),
+                        });
+                    }
+
+                    if let (Some(dedup), Some(original)) = (dedup.as_mut(), original) {
+                        dedup
+                            .references
+                            .insert(region_position, packed.position, original);
+                        *packed_chunks += 1;
+                        region_chunks += 1;
+
+                        continue;
+                    }
+
+                    let (content, checksum) = match (packed.content, checksum) {
+                        (Some(content), Some(checksum)) => (content, checksum),
+                        _ => {
+                            *unchanged_chunks += 1;
+                            continue;
+                        }
+                    };
+
+                    checksums.insert(region_position, packed.position, checksum);
+                    *packed_chunks += 1;
+                    region_chunks += 1;
+
+                    let path = match &content {
+                        PackedContent::Raw(raw_chunk) => raw_chunk_entry_path(
+                            region_position,
+                            packed.position,
+                            raw_chunk.compression(),
+                        )
+                        .unwrap(),
+                        PackedContent::Snbt(_) => {
+                            snbt_chunk_entry_path(region_position, packed.position)
+                        }
+                        _ => chunk_entry_path(region_position, packed.position),
+                    };
+
+                    archive.append_checksum(checksum)?;
+
+                    match content {
+                        PackedContent::Data(chunk) => {
+                            archive.append_entry(&path, packed.modified, &chunk.data)?
+                        }
+                        PackedContent::Stream(raw_chunk, size) => archive.append_entry_from(
+                            &path,
+                            packed.modified,
+                            size,
+                            &mut raw_chunk.decompressed_reader()?,
+                        )?,
+                        PackedContent::Raw(raw_chunk) => archive.append_entry(
+                            &path,
+                            packed.modified,
+                            raw_chunk.compressed_data(),
+                        )?,
+                        PackedContent::Snbt(text) => {
+                            archive.append_entry(&path, packed.modified, &text)?
+                        }
+                    }
+                }
+                PackStep::Chunk(None) => {}
+                PackStep::RegionEnd {
+                    region,
+                    name,
+                    size,
+                    started,
+                } => {
+                    if let Some(journal) = journal.as_mut() {
+                        journal_records.push(format!(
+                            "region {} {}",
+                            name,
+                            archive.flush_position()?
+                        ));
+                        journal.journal.record(&journal_records)?;
+                        journal_records.clear();
+                    }
+
+                    report!(
+                        debug,
+                        messages::PACK_REGION,
+                        region = name,
+                        chunks = region_chunks,
+                        elapsed_ms = started.elapsed().as_millis() as u64
+                    );
+
+                    region_chunks = 0;
+                    observer.region_finished(region, size);
+                }
+            }
+        }
+
+        Ok(())
+    };
+
+    // Limits how many chunks can be waiting in memory, since the pool reads them faster than they can be written
+    let max_in_flight = 4 * pool.current_num_threads();
+
+    // The originals kept for deduplication count against the memory limit as if the cache were always full
+    let exceeds_budget = |bytes: u64| match options.max_memory {
+        Some(max_memory) => bytes + dedup_cache_bytes > max_memory,
+        None => false,
+    };
+
+    pool.in_place_scope(|scope| -> Result<(), io::Error> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut pending = BTreeMap::new();
+        let mut issued_steps = 0;
+        let mut in_flight = 0;
+
+        // The bytes held by chunks which are being read or waiting to be written, which start out as the size of the
+        // compressed chunk and are corrected once the chunk has been read
+        let mut buffered_bytes = 0;
+
+        let mut receive = |archive: &mut A,
+                           pending: &mut BTreeMap<usize, PackStep>,
+                           in_flight: &mut usize,
+                           buffered_bytes: &mut u64|
+         -> Result<(), io::Error> {
+            let (step, reserved, region, chunk, result): (
+                usize,
+                u64,
+                ChunkPos,
+                ChunkPos,
+                Result<Option<PackedChunk>, io::Error>,
+            ) = rx.recv().unwrap();
+            *in_flight -= 1;
+
+            // A skipped chunk still takes its step, so that the chunks after it aren't held back
+            let packed = skipped.check(region, chunk, result, observer)?.flatten();
+            *buffered_bytes -= reserved;
+            *buffered_bytes += packed.as_ref().map_or(0, PackedChunk::buffered_size);
+
+            pending.insert(step, PackStep::Chunk(packed));
+            write_ready(archive, pending, buffered_bytes)
+        };
+
+        for &(path, size) in files {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let region_position = RegionFile::parse_name(&name);
+            let region_file = retry.run(|| AnyRegionFile::open(path))?;
+            let started = Instant::now();
+
+            for (index, result) in retry.stream_any_raw_chunks(&region_file).enumerate() {
+                // Every slot of the region is visited in order, so a chunk which can't be read is still known by its
+                // position
+                let position = ChunkPos {
+                    x: (index % 32) as i32,
+                    z: (index / 32) as i32,
+                };
+
+                let raw_chunk = match skipped.check(region_position, position, result, observer)? {
+                    Some(Some(raw_chunk)) => raw_chunk,
+                    _ => continue,
+                };
+
+                if !options
+                    .filter
+                    .includes_chunk(region_position, raw_chunk.position)
+                {
+                    continue;
+                }
+
+                if let Some(Since::Timestamp(since)) = options.since {
+                    let timestamp = retry.run(|| region_file.timestamp(raw_chunk.position))? as u64;
+
+                    if timestamp != 0 && timestamp <= since {
+                        *unmodified_chunks += 1;
+                        continue;
+                    }
+                }
+
+                let reserved = raw_chunk.payload.len() as u64;
+
+                // Chunks which were never given a time by the game fall back to the time of the pack
+                let modified = match options.entry_time {
+                    EntryTime::Region => {
+                        match retry.run(|| region_file.timestamp(raw_chunk.position))? {
+                            0 => default_modified,
+                            timestamp => timestamp as u64,
+                        }
+                    }
+                    _ => default_modified,
+                };
+
+                while in_flight >= max_in_flight
+                    || (in_flight > 0 && exceeds_budget(buffered_bytes + reserved))
+                {
+                    receive(archive, &mut pending, &mut in_flight, &mut buffered_bytes)?;
+                }
+
+                let tx = tx.clone();
+                let step = issued_steps;
+
+                scope.spawn(move |_| {
+                    // The writer only stops listening once it has failed, so there's no one left to report to
+                    let _ = tx.send((
+                        step,
+                        reserved,
+                        region_position,
+                        position,
+                        pack_chunk(region_position, raw_chunk, modified),
+                    ));
+                });
+
+                issued_steps += 1;
+                in_flight += 1;
+                buffered_bytes += reserved;
+            }
+
+            pending.insert(
+                issued_steps,
+                PackStep::RegionEnd {
+                    region: region_position,
+                    name,
+                    size,
+                    started,
+                },
+            );
+            issued_steps += 1;
+        }
+
+        while in_flight > 0 {
+            receive(archive, &mut pending, &mut in_flight, &mut buffered_bytes)?;
+        }
+
+        // The end of the last region may still be waiting if its chunks were all written already
+        write_ready(archive, &mut pending, &mut buffered_bytes)
+    })?;
+
+    Ok(())
+}
+
+#[derive(Parser)]
+pub struct UnpackOptions {
+    #[clap(
+        long,
+        help = "Path of an archive file to unpack, which may be repeated to unpack several archives concurrently (default is pipe from stdin)"
+    )]
+    input_file: Vec<String>,
+
+    #[clap(long, help = "Directory where the unpacked region files will be saved")]
+    output_dir: String,
+
+    #[clap(long, help = "Allow binary data to be piped to a TTY")]
+    ignore_tty: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "What to do when a chunk appears more than once across the input archives",
+        default_value = "error"
+    )]
+    on_collision: CollisionPolicy,
+
+    #[clap(
+        long,
+        help = "Path of a journal which records progress so an interrupted unpack can be resumed"
+    )]
+    journal: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only unpack regions which were packed with this label (may be repeated)"
+    )]
+    label: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Only unpack regions whose names match this pattern, such as r.0.* or r.-1.?, where * matches any text and ? any single character (may be repeated)"
+    )]
+    include: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Only unpack chunks within this box of absolute chunk coordinates, such as -16..16,-16..16",
+        value_parser = parse_coordinate_box,
+        allow_hyphen_values = true
+    )]
+    include_chunks: Option<CoordinateBox>,
+
+    #[clap(
+        long,
+        help = "Merge the unpacked chunks into existing region files in the output directory, keeping any chunks which aren't in the archive"
+    )]
+    update: bool,
+
+    #[clap(
+        long,
+        help = "Apply the profile the archive was packed with to the chunks already in the output directory, so that the whole replica is stripped and filtered in the same way",
+        requires = "update"
+    )]
+    reapply_profile: bool,
+
+    #[clap(
+        long,
+        help = "Restore archives whose format version is newer than this build supports, rather than refusing them"
+    )]
+    force: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Compression of the chunks in the unpacked region files",
+        default_value = "zlib"
+    )]
+    chunk_compression: ChunkCompression,
+
+    #[clap(
+        long,
+        help = "Compression level of the chunks in the unpacked region files, from 0 (fastest) to 9 (smallest)",
+        default_value_t = 9,
+        value_parser = clap::value_parser!(u32).range(0..=9)
+    )]
+    compression_level: u32,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Order in which the chunks of each unpacked region file are laid out",
+        default_value = "arrival"
+    )]
+    chunk_layout: ChunkLayout,
+
+    #[clap(
+        long,
+        help = "Threads used for writing region files, each of which handles a share of the regions"
+    )]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+
+    #[clap(flatten)]
+    keep_going: KeepGoingOptions,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum CollisionPolicy {
+    /// Fail the unpack
+    Error,
+    /// Keep the chunk from the archive given first on the command line
+    First,
+    /// Keep the chunk from the archive given last on the command line
+    Last,
+}
+
+/// The directory which chunks are unpacked into, how they are compressed and laid out in its region files, and the
+/// chunks which were skipped instead of being written to them.
+struct UnpackOutput<'a> {
+    dir: &'a Path,
+    compression: ChunkCompression,
+    compression_level: u32,
+    layout: ChunkLayout,
+    skipped: SkippedChunks,
+}
+
+impl UnpackOptions {
+    /// Options which merge a single archive into the existing region files of a directory, keeping the chunks which
+    /// aren't in the archive.
+    pub fn update(
+        input_file: &str,
+        output_dir: &str,
+        threads: Option<u32>,
+        retry: &RetryOptions,
+    ) -> Self {
+        UnpackOptions {
+            input_file: vec![input_file.to_string()],
+            output_dir: output_dir.to_string(),
+            ignore_tty: false,
+            on_collision: CollisionPolicy::Error,
+            journal: None,
+            label: Vec::new(),
+            include: Vec::new(),
+            include_chunks: None,
+            update: true,
+            reapply_profile: false,
+            force: false,
+            chunk_compression: ChunkCompression::Zlib,
+            compression_level: 9,
+            chunk_layout: ChunkLayout::Arrival,
+            threads,
+            retry: retry.clone(),
+            keep_going: KeepGoingOptions::default(),
+        }
+    }
+}
+
+pub fn unpack_files(options: &UnpackOptions) -> Result<(), io::Error> {
+    unpack_files_with_observer(
+        options,
+        &ProgressObserver::new("unpack", "Unpacking archives"),
+    )
+}
+
+/// Unpacks archives into a directory of region files, passing the progress of the operation to the observer. The
+/// chunks of a region can arrive from any archive at any point, so regions are never reported as finished, and the
+/// size of the input isn't known up front.
+pub fn unpack_files_with_observer(
+    options: &UnpackOptions,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error> {
+    let output_dir = Path::new(&options.output_dir);
+    read_only::ensure_writable(output_dir)?;
+
+    let output = UnpackOutput {
+        dir: output_dir,
+        compression: options.chunk_compression,
+        compression_level: options.compression_level,
+        layout: options.chunk_layout,
+        skipped: SkippedChunks::new(&options.keep_going),
+    };
+    let retry = RetryPolicy::new(&options.retry);
+
+    let readers: Vec<Box<dyn io::Read + Send>> = if options.input_file.is_empty() {
+        if atty::is(Stream::Stdin) && !options.ignore_tty {
+            return Err(
+                Message::new(messages::TTY_INPUT_REFUSED).into_error(io::ErrorKind::InvalidInput)
+            );
+        }
+
+        vec![Box::new(io::stdin())]
+    } else {
+        options
+            .input_file
+            .iter()
+            .map(
+                |input_path| -> Result<Box<dyn io::Read + Send>, io::Error> {
+                    let input_path = Path::new(input_path);
+
+                    if !Path::exists(input_path) {
+                        return Err(Message::new(messages::INPUT_FILE_MISSING)
+                            .arg("path", input_path.display())
+                            .into_error(io::ErrorKind::NotFound));
+                    }
+
+                    let file = retry.run(|| File::open(input_path))?;
+
+                    Ok(Box::new(BufReader::new(retry.wrap(file))))
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let journal = match &options.journal {
+        Some(journal_path) => {
+            let (journal, records) = Journal::open(Path::new(journal_path), "unpack")?;
+
+            let completed_chunks = records
+                .iter()
+                .filter_map(|record| {
+                    let (archive_index, path) = record.strip_prefix("chunk ")?.split_once(' ')?;
+                    let (region, chunk) = parse_chunk_entry_path(Path::new(path))?;
+
+                    Some((archive_index.parse::<usize>().ok()?, region, chunk))
+                })
+                .collect();
+
+            Some(UnpackJournal {
+                journal,
+                completed_chunks,
+            })
+        }
+        None => None,
+    };
+
+    // Existing region files are opened rather than truncated, so that their other chunks are kept
+    let existing_regions = if options.update {
+        find_existing_regions(output_dir)?
+    } else {
+        HashSet::new()
+    };
+
+    observer.started(None, None);
+
+    let (unpacked_regions, profiles) = unpack_archives(
+        readers,
+        &output,
+        options,
+        &existing_regions,
+        journal,
+        &retry,
+        observer,
+    )?;
+
+    // Replaced chunks were written over the sectors of their old copies where they fit, so regions only need to be
+    // rewritten to apply the profile, in which case every existing region is rewritten rather than only those which
+    // chunks were unpacked into
+    let (transform, mut compacted_regions) = if options.reapply_profile {
+        let profile = single_profile(profiles)?;
+
+        (
+            Some(ProfileTransform::new(&profile)?),
+            existing_regions.clone(),
+        )
+    } else {
+        (None, HashSet::new())
+    };
+
+    // Chunks arrive in whatever order the archives and threads produce them, and a region evicted from the cache of
+    // open regions is appended to when it is reopened, so regions are only laid out in order once they are complete
+    if output.layout != ChunkLayout::Arrival {
+        compacted_regions.extend(unpacked_regions.iter().cloned());
+    }
+
+    for region in &compacted_regions {
+        let region_path = output_dir.join(format!("r.{}.{}.mca", region.x, region.z));
+        retry.run(|| compact_region_file(&region_path, transform.as_ref(), &output))?;
+    }
+
+    observer.finished();
+    retry.report(observer);
+
+    output.skipped.finish(observer)
+}
+
+fn find_existing_regions(output_dir: &Path) -> Result<HashSet<ChunkPos>, io::Error> {
+    let mut regions = HashSet::new();
+
+    if !output_dir.exists() {
+        return Ok(regions);
+    }
+
+    for entry in fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            regions.insert(RegionFile::parse_name(&name));
+        }
+    }
+
+    Ok(regions)
+}
+
+/// Returns the profile shared by every unpacked archive, failing if any of them has no profile or if they differ.
+fn single_profile(profiles: Vec<Option<ArchiveProfile>>) -> Result<ArchiveProfile, io::Error> {
+    let mut profiles = profiles.into_iter().map(|profile| {
+        profile.ok_or_else(|| {
+            Message::new(messages::PROFILE_MISSING).into_error(io::ErrorKind::InvalidData)
+        })
+    });
+
+    let profile = profiles.next().unwrap()?;
+
+    for other in profiles {
+        if other? != profile {
+            return Err(
+                Message::new(messages::PROFILE_CONFLICT).into_error(io::ErrorKind::InvalidData)
+            );
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Rewrites a region file with its chunks in the output's layout, applying the transformations of a profile to its
+/// chunks if one is given, in which case the changed chunks are compressed as the output asks. The new region is staged
+/// in the run's temporary directory and then moved over the old one, so the region is never left half-written.
+fn compact_region_file(
+    path: &Path,
+    transform: Option<&ProfileTransform>,
+    output: &UnpackOutput,
+) -> Result<(), io::Error> {
+    let temp_path = temp::path(&path.file_name().unwrap().to_string_lossy())?;
+
+    {
+        let region = RegionFile::open(path)?;
+        let mut writer = RegionFileWriter::create(&temp_path)?;
+        writer.set_compression(output.compression, output.compression_level);
+        writer.set_layout(output.layout);
+
+        for result in region.stream_raw_chunks() {
+            let raw_chunk = match result? {
+                Some(raw_chunk) => raw_chunk,
+                None => continue,
+            };
+
+            // Rewriting the region doesn't count as the game saving a chunk, so each one keeps its timestamp
+            let position = raw_chunk.position;
+            let timestamp = region.timestamp(position)?;
+
+            let transform = match transform {
+                Some(transform) => transform,
+                None => {
+                    writer.add_raw_chunk(&raw_chunk)?;
+                    writer.set_timestamp(position, timestamp);
+                    continue;
+                }
+            };
+
+            match transform.apply(raw_chunk)? {
+                Some(ProfiledChunk::Unchanged(raw_chunk)) => writer.add_raw_chunk(&raw_chunk)?,
+                Some(ProfiledChunk::Changed(chunk)) => writer.add_chunk(&chunk)?,
+                None => continue,
+            }
+
+            writer.set_timestamp(position, timestamp);
+        }
+
+        writer.finish()?;
+    }
+
+    temp::replace(&temp_path, path)
+}
+
+/// The journal of an unpack run, which records every chunk once it has been written to its region file. Chunks are
+/// identified by the index of the archive they came from, so that collisions between archives are still resolved
+/// correctly after resuming.
+struct UnpackJournal {
+    journal: Journal,
+    completed_chunks: HashSet<(usize, ChunkPos, ChunkPos)>,
+}
+
+struct ChunkEntry {
+    content: ChunkContent,
+    region: ChunkPos,
+    chunk: ChunkPos,
+    archive: usize,
+}
+
+enum ChunkContent {
+    /// The NBT data of the chunk, as stored in the archive.
+    Data(Box<[u8]>),
+    /// The SNBT text of the chunk, from an entry which was packed with `--entry-format snbt`. It is parsed by the
+    /// thread which writes its region, so that a chunk which can't be parsed can be skipped there.
+    Snbt(Box<[u8]>),
+    /// The chunk as it is stored in a region file, from an entry which was packed with `--raw`.
+    Raw(RawChunk),
+    /// The region and chunk positions of a chunk with identical contents, which has already been unpacked.
+    Reference(ChunkPos, ChunkPos),
+}
+
+/// Unpacks each archive on its own reader thread, while a single thread merges the chunks from every archive and
+/// shares them out between writer threads by region, so that each region file is only ever written by one thread.
+/// Returns the regions which chunks were written to, and the profile of each archive.
+fn unpack_archives(
+    readers: Vec<Box<dyn io::Read + Send>>,
+    output: &UnpackOutput,
+    options: &UnpackOptions,
+    existing_regions: &HashSet<ChunkPos>,
+    journal: Option<UnpackJournal>,
+    retry: &RetryPolicy,
+    observer: &dyn OperationObserver,
+) -> Result<(HashSet<ChunkPos>, Vec<Option<ArchiveProfile>>), io::Error> {
+    let collision_policy = options.on_collision;
+    let selection = &UnpackSelection {
+        labels: &options.label,
+        include: &options.include,
+        include_chunks: options.include_chunks.as_ref(),
+    };
+    let checks = ArchiveChecks {
+        require_profile: options.reapply_profile,
+        force: options.force,
+    };
+    let threads = options.threads.unwrap_or(1).max(1) as usize;
+
+    let (sender, receiver) = std::sync::mpsc::sync_channel(4 * readers.len());
+
+    let (journal, completed_chunks) = match journal {
+        Some(journal) => (Some(journal.journal), journal.completed_chunks),
+        None => (None, HashSet::new()),
+    };
+    let completed_chunks = &completed_chunks;
+
+    // Each archive stores its chunks grouped by region, so every archive can have a region open at once
+    let cache_size = NonZeroUsize::new(8.max(2 * readers.len())).unwrap();
+
+    std::thread::scope(|scope| {
+        let receive_thread = scope.spawn(
+            move || -> Result<(Option<Journal>, HashSet<ChunkPos>), io::Error> {
+                let mut created_regions = existing_regions.clone();
+                let mut unpacked_chunks: HashMap<(ChunkPos, ChunkPos), usize> = HashMap::new();
+
+                // Chunks written by an interrupted run are already in their region files, which must not be truncated
+                for &(archive_index, region, chunk) in completed_chunks {
+                    created_regions.insert(region);
+                    unpacked_chunks.insert((region, chunk), archive_index);
+                }
+
+                let journal = Mutex::new(journal);
+
+                // Duplicates are restored once every shard has finished, since their originals may be in regions
+                // which belong to other shards
+                let mut references: HashMap<(ChunkPos, ChunkPos), ChunkEntry> = HashMap::new();
+
+                std::thread::scope(|shard_scope| -> Result<(), io::Error> {
+                    let (shard_senders, shard_threads): (Vec<_>, Vec<_>) = (0..threads)
+                        .map(|_| {
+                            let (shard_sender, shard_receiver) = std::sync::mpsc::sync_channel(16);
+                            let mut created_regions = created_regions.clone();
+                            let journal = &journal;
+
+                            let shard_thread = shard_scope.spawn(move || {
+                                let region_cache: RegionFileCache =
+                                    Arc::new(Mutex::new(LruCache::new(cache_size)));
+
+                                shard_receiver.iter().try_for_each(|entry: ChunkEntry| {
+                                    unpack_journaled(
+                                        output,
+                                        &region_cache,
+                                        &mut created_regions,
+                                        journal,
+                                        retry,
+                                        observer,
+                                        entry,
+                                    )
+                                })?;
+
+                                finish_region_writers(&region_cache)?;
+
+                                Ok::<_, io::Error>(created_regions)
+                            });
+
+                            (shard_sender, shard_thread)
+                        })
+                        .unzip();
+
+                    let dispatch_result = receiver.iter().try_for_each(|entry: ChunkEntry| {
+                        if let Some(&previous) = unpacked_chunks.get(&(entry.region, entry.chunk)) {
+                            let replace = match collision_policy {
+                                CollisionPolicy::Error => {
+                                    return Err(Message::new(messages::UNPACK_COLLISION)
+                                        .arg("entry", chunk_entry_path(entry.region, entry.chunk))
+                                        .into_error(io::ErrorKind::AlreadyExists));
+                                }
+                                CollisionPolicy::First => entry.archive < previous,
+                                CollisionPolicy::Last => entry.archive >= previous,
+                            };
+
+                            if !replace {
+                                return Ok(());
+                            }
+                        }
+
+                        if let ChunkContent::Reference(original_region, original_chunk) =
+                            entry.content
+                        {
+                            if !unpacked_chunks.contains_key(&(original_region, original_chunk)) {
+                                return Err(Message::new(messages::UNPACK_MISSING_ORIGINAL)
+                                    .arg("entry", chunk_entry_path(entry.region, entry.chunk))
+                                    .arg(
+                                        "original",
+                                        chunk_entry_path(original_region, original_chunk),
+                                    )
+                                    .into_error(io::ErrorKind::InvalidData));
+                            }
+                        }
+
+                        unpacked_chunks.insert((entry.region, entry.chunk), entry.archive);
+
+                        match entry.content {
+                            ChunkContent::Reference(..) => {
+                                references.insert((entry.region, entry.chunk), entry);
+                                Ok(())
+                            }
+                            ChunkContent::Data(_)
+                            | ChunkContent::Snbt(_)
+                            | ChunkContent::Raw(_) => {
+                                // A duplicate which was replaced by a chunk from another archive must not be restored
+                                references.remove(&(entry.region, entry.chunk));
+
+                                shard_senders[shard_index(entry.region, threads)]
+                                    .send(entry)
+                                    .map_err(|_| {
+                                        Message::new(messages::UNPACK_SHARD_STOPPED)
+                                            .into_error(io::ErrorKind::Other)
+                                    })
+                            }
+                        }
+                    });
+
+                    drop(shard_senders);
+
+                    // If a shard failed, the dispatcher will have stopped early because its channel closed, so the
+                    // shard's error is the one worth reporting
+                    for shard_thread in shard_threads {
+                        created_regions.extend(shard_thread.join().unwrap()?);
+                    }
+
+                    dispatch_result
+                })?;
+
+                let region_cache: RegionFileCache = Arc::new(Mutex::new(LruCache::new(cache_size)));
+
+                for entry in references.into_values() {
+                    unpack_journaled(
+                        output,
+                        &region_cache,
+                        &mut created_regions,
+                        &journal,
+                        retry,
+                        observer,
+                        entry,
+                    )?;
+                }
+
+                finish_region_writers(&region_cache)?;
+
+                let unpacked_regions = unpacked_chunks.keys().map(|&(region, _)| region).collect();
+
+                Ok((journal.into_inner().unwrap(), unpacked_regions))
+            },
+        );
+
+        let read_threads: Vec<_> = readers
+            .into_iter()
+            .enumerate()
+            .map(|(archive_index, reader)| {
+                let sender = sender.clone();
+
+                scope.spawn(move || {
+                    read_archive(
+                        reader,
+                        archive_index,
+                        selection,
+                        checks,
+                        completed_chunks,
+                        sender,
+                        observer,
+                    )
+                })
+            })
+            .collect();
+
+        drop(sender);
+
+        let read_result = read_threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Result<Vec<_>, _>>();
+
+        // If the writer failed, the readers will have stopped early because the channel closed, so its error is the
+        // one worth reporting
+        let (journal, unpacked_regions) = receive_thread.join().unwrap()?;
+        let profiles = read_result?;
+
+        if let Some(journal) = journal {
+            journal.finish()?;
+        }
+
+        Ok((unpacked_regions, profiles))
+    })
+}
+
+/// What an archive must satisfy before its chunks are unpacked.
+#[derive(Copy, Clone)]
+struct ArchiveChecks {
+    /// The archive must have a profile, since it is going to be reapplied.
+    require_profile: bool,
+    /// Archives in a newer format version are restored anyway, rather than refused.
+    force: bool,
+}
+
+fn read_archive(
+    reader: Box<dyn io::Read + Send>,
+    archive_index: usize,
+    selection: &UnpackSelection,
+    checks: ArchiveChecks,
+    completed_chunks: &HashSet<(usize, ChunkPos, ChunkPos)>,
+    sender: SyncSender<ChunkEntry>,
+    observer: &dyn OperationObserver,
+) -> Result<Option<ArchiveProfile>, io::Error> {
+    let mut checksums = None;
+    let mut actual_checksums = Manifest::new();
+    let mut labels = None;
+    let mut profile = None;
+    let mut references = None;
+    let mut layout = None;
+
+    let completed = for_each_archive_entry(reader, |path, size, entry| {
+        // The layout is settled by the first entry: archives which have metadata start with it, so an incompatible
+        // archive is refused before anything is unpacked, and those which don't were packed before it was versioned
+        if layout.is_none() {
+            if path == Path::new(METADATA_ENTRY_PATH) {
+                let metadata = ArchiveMetadata::read_from(BufReader::new(entry))?;
+                layout = Some(metadata.layout(checks.force)?);
+
+                if !metadata.is_compatible() {
+                    observer.warning(&report!(
+                        warn,
+                        messages::UNPACK_FORCED,
+                        version = metadata.format_version
+                    ));
+                }
+
+                let tool_version = metadata.tool_version.as_deref().unwrap_or("unknown");
+
+                match &metadata.world_name {
+                    Some(world) => report!(
+                        info,
+                        messages::UNPACK_METADATA_WORLD,
+                        world = world.as_str(),
+                        tool_version = tool_version,
+                        version = metadata.format_version
+                    ),
+                    None => report!(
+                        info,
+                        messages::UNPACK_METADATA,
+                        tool_version = tool_version,
+                        version = metadata.format_version
+                    ),
+                };
+
+                return Ok(true);
+            }
+
+            layout = Some(ArchiveLayout::Unversioned);
+
+            report!(
+                debug,
+                messages::UNPACK_UNVERSIONED,
+                version = ArchiveLayout::Unversioned.version()
+            );
+        }
+
+        if path == Path::new(CHECKSUMS_ENTRY_PATH) {
+            checksums = Some(Manifest::read_from(BufReader::new(entry))?);
+            return Ok(true);
+        }
+
+        if path == Path::new(LABELS_ENTRY_PATH) {
+            labels = Some(RegionLabels::read_from(BufReader::new(entry))?);
+            return Ok(true);
+        }
+
+        if path == Path::new(PROFILE_ENTRY_PATH) {
+            profile = Some(ArchiveProfile::read_from(BufReader::new(entry))?);
+            return Ok(true);
+        }
+
+        if path == Path::new(DEDUP_ENTRY_PATH) {
+            references = Some(ChunkReferences::read_from(BufReader::new(entry))?);
+            return Ok(true);
+        }
+
+        let (region_position, chunk_position) = parse_chunk_entry_path(path).ok_or_else(|| {
+            Message::new(messages::UNPACK_MALFORMED_ENTRY)
+                .arg("entry", path.display())
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        // The profile precedes every chunk, so an archive without one is rejected before anything is unpacked
+        if checks.require_profile && profile.is_none() {
+            return Err(
+                Message::new(messages::PROFILE_MISSING).into_error(io::ErrorKind::InvalidData)
+            );
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        entry.read_to_end(&mut data)?;
+
+        actual_checksums.insert(region_position, chunk_position, hash_chunk_data(&data));
+
+        if !should_unpack(
+            archive_index,
+            region_position,
+            chunk_position,
+            selection,
+            labels.as_ref(),
+            completed_chunks,
+        )? {
+            return Ok(true);
+        }
+
+        let content = match raw_chunk_entry_compression(path) {
+            Some(compression) => {
+                ChunkContent::Raw(RawChunk::from_parts(chunk_position, compression, &data))
+            }
+            None if is_snbt_chunk_entry(path) => ChunkContent::Snbt(data.into_boxed_slice()),
+            None => ChunkContent::Data(data.into_boxed_slice()),
+        };
+
+        let sent = sender.send(ChunkEntry {
+            content,
+            region: region_position,
+            chunk: chunk_position,
+            archive: archive_index,
+        });
+
+        Ok(sent.is_ok())
+    })?;
+
+    if !completed {
+        // The writer thread has stopped and will report its own error
+        return Ok(profile);
+    }
+
+    if let Some(checksums) = checksums {
+        let mismatches = checksums.find_mismatches(&actual_checksums);
+
+        for (path, reason) in &mismatches {
+            observer.warning(&report!(
+                warn,
+                messages::UNPACK_CORRUPT_CHUNK,
+                entry = path.as_str(),
+                reason = Message::new(*reason).text()
+            ));
+        }
+
+        if let Some((path, reason)) = mismatches.first() {
+            return Err(Message::new(messages::UNPACK_CHECKSUMS_FAILED)
+                .arg("chunks", mismatches.len())
+                .arg("entry", path)
+                .arg("reason", Message::new(*reason))
+                .into_error(io::ErrorKind::InvalidData));
+        }
+    }
+
+    // Duplicated chunks are restored from their originals once every chunk stored in the archive has been unpacked
+    for ((region_position, chunk_position), (original_region, original_chunk)) in
+        references.iter().flat_map(ChunkReferences::iter)
+    {
+        if !should_unpack(
+            archive_index,
+            region_position,
+            chunk_position,
+            selection,
+            labels.as_ref(),
+            completed_chunks,
+        )? {
+            continue;
+        }
+
+        let sent = sender.send(ChunkEntry {
+            content: ChunkContent::Reference(original_region, original_chunk),
+            region: region_position,
+            chunk: chunk_position,
+            archive: archive_index,
+        });
+
+        if sent.is_err() {
+            break;
+        }
+    }
+
+    Ok(profile)
+}
+
+/// The regions and chunks which an unpack is limited to. Every chunk is unpacked if none of the filters are given.
+struct UnpackSelection<'a> {
+    labels: &'a [String],
+    include: &'a [String],
+    include_chunks: Option<&'a CoordinateBox>,
+}
+
+impl UnpackSelection<'_> {
+    /// Returns true if a chunk is within the regions and chunks selected by name and position.
+    fn includes(&self, region: ChunkPos, chunk: ChunkPos) -> bool {
+        if !self.include.is_empty() {
+            let name = format!("r.{}.{}", region.x, region.z);
+            let file_name = format!("{}.mca", name);
+
+            if !self.include.iter().any(|pattern| {
+                matches_pattern(pattern, &name) || matches_pattern(pattern, &file_name)
+            }) {
+                return false;
+            }
+        }
+
+        match self.include_chunks {
+            Some(bounds) => bounds.contains(ChunkPos {
+                x: (region.x * 32) + chunk.x,
+                z: (region.z * 32) + chunk.z,
+            }),
+            None => true,
+        }
+    }
+}
+
+/// Returns true if a chunk read from an archive should be unpacked, which is not the case if it was already unpacked by
+/// an interrupted run, if it isn't selected by name or position, or if its region doesn't have any of the labels being
+/// filtered by.
+fn should_unpack(
+    archive_index: usize,
+    region: ChunkPos,
+    chunk: ChunkPos,
+    selection: &UnpackSelection,
+    labels: Option<&RegionLabels>,
+    completed_chunks: &HashSet<(usize, ChunkPos, ChunkPos)>,
+) -> Result<bool, io::Error> {
+    if completed_chunks.contains(&(archive_index, region, chunk)) {
+        return Ok(false);
+    }
+
+    if !selection.includes(region, chunk) {
+        return Ok(false);
+    }
+
+    if selection.labels.is_empty() {
+        return Ok(true);
+    }
+
+    let labels = labels.ok_or_else(|| {
+        Message::new(messages::LABELS_MISSING).into_error(io::ErrorKind::InvalidData)
+    })?;
+
+    Ok(labels.has_any(region, selection.labels))
+}
+
+/// The signature at the start of the first local file header of a zip archive.
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+/// Calls `visit` with the path, size, and contents of every file in an archive, detecting whether it is a tar or zip
+/// archive, and whether it was compressed, from its first bytes. Zip archives are read sequentially through their local
+/// file headers, so they can be piped in the same way as tar archives. Returns false if `visit` stopped the iteration
+/// early by returning false.
+pub fn for_each_archive_entry<R, F>(reader: R, mut visit: F) -> Result<bool, io::Error>
+where
+    R: io::Read,
+    F: FnMut(&Path, u64, &mut dyn io::Read) -> Result<bool, io::Error>,
+{
+    for_each_archive_entry_with_type(reader, |path, _, size, entry| visit(path, size, entry))
+}
+
+/// Like `for_each_archive_entry`, but also tells `visit` whether each entry is a regular file, since tar archives can
+/// hold links and devices as well. Directories are skipped in either kind of archive.
+pub fn for_each_archive_entry_with_type<R, F>(reader: R, mut visit: F) -> Result<bool, io::Error>
+where
+    R: io::Read,
+    F: FnMut(&Path, bool, u64, &mut dyn io::Read) -> Result<bool, io::Error>,
+{
+    let mut reader = decompressed_reader(reader)?;
+
+    let mut signature = Vec::with_capacity(ZIP_SIGNATURE.len());
+    reader
+        .by_ref()
+        .take(ZIP_SIGNATURE.len() as u64)
+        .read_to_end(&mut signature)?;
+
+    let is_zip = signature == ZIP_SIGNATURE;
+    let mut reader = io::Cursor::new(signature).chain(reader);
+
+    if is_zip {
+        while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut reader)? {
+            if file.is_dir() {
+                continue;
+            }
+
+            let path = PathBuf::from(file.name());
+            let size = file.size();
+
+            if !visit(&path, true, size, &mut file)? {
+                return Ok(false);
+            }
+        }
+    } else {
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+
+            // Directory entries are harmless and are added by most other tools which create tarballs
+            if entry_type.is_dir() {
+                continue;
+            }
+
+            let path = entry.path()?.into_owned();
+            let size = entry.size();
+
+            let mut entry = CheckedEntry {
+                checksum: entry_checksum(&mut entry)?,
+                hasher: ChunkHasher::default(),
+                path: path.clone(),
+                inner: entry,
+            };
+
+            if !visit(&path, entry_type.is_file(), size, &mut entry)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Reads the checksum recorded for a tar entry in its PAX extended header, if it has one.
+fn entry_checksum<R>(entry: &mut tar::Entry<R>) -> Result<Option<u64>, io::Error>
+where
+    R: io::Read,
+{
+    let extensions = match entry.pax_extensions()? {
+        Some(extensions) => extensions,
+        None => return Ok(None),
+    };
+
+    for extension in extensions {
+        let extension = extension?;
+
+        if extension.key() == Ok(CHECKSUM_PAX_KEY) {
+            let checksum = extension
+                .value()
+                .ok()
+                .and_then(|value| u64::from_str_radix(value, 16).ok());
+
+            return Ok(checksum);
+        }
+    }
+
+    Ok(None)
+}
+
+/// The data of an archive entry, which is hashed as it is read and fails with an error at the end of the entry if it
+/// doesn't match the checksum recorded for it, so that a corrupt chunk is never used.
+struct CheckedEntry<R> {
+    inner: R,
+    checksum: Option<u64>,
+    hasher: ChunkHasher,
+    path: PathBuf,
+}
+
+impl<R> Read for CheckedEntry<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if let Some(checksum) = self.checksum {
+            if read == 0 && !buf.is_empty() && self.hasher.finish().1 != checksum {
+                return Err(Message::new(messages::ARCHIVE_ENTRY_CORRUPT)
+                    .arg("entry", self.path.display())
+                    .into_error(io::ErrorKind::InvalidData));
+            }
+
+            self.hasher.write_all(&buf[..read])?;
+        }
+
+        Ok(read)
+    }
+}
+
+use lru::LruCache;
+
+/// Chooses which writer thread handles a region.
+fn shard_index(region: ChunkPos, threads: usize) -> usize {
+    let hash =
+        (region.x as i64).wrapping_mul(73_856_093) ^ (region.z as i64).wrapping_mul(19_349_663);
+
+    hash.rem_euclid(threads as i64) as usize
+}
+
+/// Unpacks a chunk and records it in the journal, if there is one.
+fn unpack_journaled(
+    output: &UnpackOutput,
+    region_cache: &RegionFileCache,
+    created_regions: &mut HashSet<ChunkPos>,
+    journal: &Mutex<Option<Journal>>,
+    retry: &RetryPolicy,
+    observer: &dyn OperationObserver,
+    entry: ChunkEntry,
+) -> Result<(), io::Error> {
+    let (region, chunk) = (entry.region, entry.chunk);
+    let record = format!(
+        "chunk {} {}",
+        entry.archive,
+        chunk_entry_path(entry.region, entry.chunk)
+    );
+
+    unpack_file(
+        output,
+        region_cache.clone(),
+        created_regions,
+        retry,
+        observer,
+        entry,
+    )?;
+
+    observer.chunk_processed(region, chunk);
+
+    match journal.lock().unwrap().as_mut() {
+        Some(journal) => journal.record(&[record]),
+        None => Ok(()),
+    }
+}
+
+type RegionFileCache = Arc<Mutex<LruCache<ChunkPos, Arc<Mutex<RegionFileWriter>>>>>;
+
+fn unpack_file(
+    output: &UnpackOutput,
+    region_cache: RegionFileCache,
+    created_regions: &mut HashSet<ChunkPos>,
+    retry: &RetryPolicy,
+    observer: &dyn OperationObserver,
+    entry: ChunkEntry,
+) -> Result<(), io::Error> {
+    let region_writer =
+        open_region_writer(output, &region_cache, created_regions, retry, entry.region)?;
+
+    let (region, position) = (entry.region, entry.chunk);
+
+    let chunk = match entry.content {
+        ChunkContent::Data(data) => Ok(Chunk { position, data }),
+        ChunkContent::Snbt(text) => from_snbt(&text).map(|data| Chunk {
+            position,
+            data: data.into_boxed_slice(),
+        }),
+        ChunkContent::Raw(raw_chunk) => {
+            let mut region_writer = region_writer.lock().unwrap();
+            return retry.run(|| region_writer.add_raw_chunk(&raw_chunk));
+        }
+        ChunkContent::Reference(original_region, original_chunk) => {
+            let original_writer = open_region_writer(
+                output,
+                &region_cache,
+                created_regions,
+                retry,
+                original_region,
+            )?;
+
+            let original = original_writer
+                .lock()
+                .unwrap()
+                .read_raw_chunk(original_chunk)?;
+
+            // An original which was skipped with `--keep-going` takes its duplicates with it
+            original
+                .ok_or_else(|| {
+                    Message::new(messages::UNPACK_ORIGINAL_NOT_FOUND)
+                        .arg("entry", chunk_entry_path(original_region, original_chunk))
+                        .into_error(io::ErrorKind::NotFound)
+                })
+                .and_then(|original| {
+                    // Duplicates only have the same contents as their original apart from their position
+                    let mut chunk = original.decompress()?;
+                    chunk.position = position;
+
+                    relocate_chunk(
+                        &mut chunk.data,
+                        ChunkPos {
+                            x: (region.x * 32) + position.x,
+                            z: (region.z * 32) + position.z,
+                        },
+                    )?;
+
+                    Ok(chunk)
+                })
+        }
+    };
+
+    let chunk = match output.skipped.check(region, position, chunk, observer)? {
+        Some(chunk) => chunk,
+        None => return Ok(()),
+    };
+
+    let mut region_writer = region_writer.lock().unwrap();
+    retry.run(|| region_writer.add_chunk(&chunk))
+}
+
+fn open_region_writer(
+    output: &UnpackOutput,
+    region_cache: &RegionFileCache,
+    created_regions: &mut HashSet<ChunkPos>,
+    retry: &RetryPolicy,
+    region: ChunkPos,
+) -> Result<Arc<Mutex<RegionFileWriter>>, io::Error> {
+    let mut region_cache = region_cache.lock().unwrap();
+
+    match region_cache.get(&region) {
+        Some(r) => Ok(r.clone()),
+        None => {
+            let region_path = output.dir.join(format!("r.{}.{}.mca", region.x, region.z));
+
+            // A region which was evicted from the cache must be reopened rather than truncated
+            let mut writer = if created_regions.insert(region) {
+                retry.run(|| RegionFileWriter::create(&region_path))?
+            } else {
+                retry.run(|| RegionFileWriter::open(&region_path))?
+            };
+
+            writer.set_compression(output.compression, output.compression_level);
+
+            let writer = Arc::new(Mutex::new(writer));
+
+            // A region which falls out of the cache is finished now, and reopened if more of its chunks arrive
+            if let Some((_, evicted)) = region_cache.push(region, writer.clone()) {
+                finish_region_writer(evicted)?;
+            }
+
+            Ok(writer)
+        }
+    }
+}
+
+/// Finishes every region writer left in a cache, once nothing more will be unpacked into them.
+fn finish_region_writers(region_cache: &RegionFileCache) -> Result<(), io::Error> {
+    let mut region_cache = region_cache.lock().unwrap();
+
+    while let Some((_, writer)) = region_cache.pop_lru() {
+        finish_region_writer(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Finishes a writer which was taken out of a cache. A writer which is still being used to unpack a chunk is left to be
+/// finished when it is dropped, since the cache is large enough that this only happens when it holds a single region.
+fn finish_region_writer(writer: Arc<Mutex<RegionFileWriter>>) -> Result<(), io::Error> {
+    match Arc::try_unwrap(writer) {
+        Ok(writer) => writer.into_inner().unwrap().finish(),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Parses the value of `--since`, which is a timestamp if it's made up of digits and the path of a manifest otherwise.
+fn parse_since(value: &str) -> Result<Since, String> {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        return value
+            .parse()
+            .map(Since::Timestamp)
+            .map_err(|_| format!("Timestamp is too large: {}", value));
+    }
+
+    Ok(Since::Manifest(value.to_string()))
+}
+
+/// Parses a size in bytes such as `512M` or `2G`, using binary units. A bare number is interpreted as bytes.
+fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid size: {}", value))?;
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(&['B', 'I'][..]) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("Unknown size unit: {}", unit)),
+    };
+
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size is too large: {}", value))
+}
@@ -0,0 +1,148 @@
+use clap::{Parser, Subcommand};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use atty::Stream;
+
+use crate::commands::archive::{
+    for_each_archive_entry, parse_chunk_entry_path, PROFILE_ENTRY_PATH,
+};
+use crate::messages::{self, Message};
+use crate::profile::ArchiveProfile;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(subcommand)]
+    command: ArchiveCommand,
+}
+
+#[derive(Subcommand)]
+enum ArchiveCommand {
+    /// Show the profile of transformations an archive was packed with, which records the data it leaves out
+    ShowProfile(ShowProfileOptions),
+}
+
+#[derive(Parser)]
+struct ShowProfileOptions {
+    #[clap(
+        long,
+        help = "Path of the archive file to read (default is pipe from stdin)"
+    )]
+    input_file: Option<String>,
+
+    #[clap(long, help = "Allow binary data to be piped from a TTY")]
+    ignore_tty: bool,
+
+    #[clap(
+        long,
+        help = "Print the profile exactly as it is stored in the archive"
+    )]
+    raw: bool,
+}
+
+pub fn run(options: &Options) -> Result<(), io::Error> {
+    match &options.command {
+        ArchiveCommand::ShowProfile(options) => show_profile(options),
+    }
+}
+
+fn show_profile(options: &ShowProfileOptions) -> Result<(), io::Error> {
+    let profile = match &options.input_file {
+        Some(input_path) => {
+            let input_path = Path::new(input_path);
+
+            if !Path::exists(input_path) {
+                return Err(Message::new(messages::INPUT_FILE_MISSING)
+                    .arg("path", input_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            read_profile(BufReader::new(File::open(input_path)?))?
+        }
+        None => {
+            if atty::is(Stream::Stdin) && !options.ignore_tty {
+                return Err(Message::new(messages::TTY_INPUT_REFUSED)
+                    .into_error(io::ErrorKind::InvalidInput));
+            }
+
+            read_profile(io::stdin())?
+        }
+    };
+
+    let profile = profile.ok_or_else(|| {
+        Message::new(messages::PROFILE_MISSING).into_error(io::ErrorKind::InvalidData)
+    })?;
+
+    if options.raw {
+        return profile.write_to(&mut io::stdout());
+    }
+
+    if profile.is_empty() {
+        println!("{}", Message::new(messages::PROFILE_SHOW_EMPTY));
+    }
+
+    for path in &profile.removed_tags {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_REMOVED).arg("path", path)
+        );
+    }
+
+    if let Some(status) = &profile.min_status {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_MIN_STATUS).arg("status", status)
+        );
+    }
+
+    if !profile.status.is_empty() {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_STATUS).arg("statuses", profile.status.join(", "))
+        );
+    }
+
+    for area in &profile.include_regions {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_INCLUDE_REGIONS).arg("area", area)
+        );
+    }
+
+    for area in &profile.exclude_regions {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_EXCLUDE_REGIONS).arg("area", area)
+        );
+    }
+
+    if let Some(bounds) = &profile.chunk_bounds {
+        println!(
+            "{}",
+            Message::new(messages::PROFILE_SHOW_CHUNK_BOUNDS).arg("area", bounds)
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads the profile of an archive, which precedes its chunks, so reading stops at the first entry after it.
+fn read_profile<R>(reader: R) -> Result<Option<ArchiveProfile>, io::Error>
+where
+    R: io::Read,
+{
+    let mut profile = None;
+
+    for_each_archive_entry(reader, |path, _, entry| {
+        if path == Path::new(PROFILE_ENTRY_PATH) {
+            profile = Some(ArchiveProfile::read_from(BufReader::new(entry))?);
+            return Ok(false);
+        }
+
+        // Only the metadata and labels can come before the profile, so there is no need to look past the first chunk
+        Ok(parse_chunk_entry_path(path).is_none())
+    })?;
+
+    Ok(profile)
+}
@@ -0,0 +1,270 @@
+use clap::{Parser, ValueEnum};
+use fastnbt::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::data_version;
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::observer::NullObserver;
+use crate::read_only;
+use crate::region::{RegionFile, RegionFileWriter};
+use crate::retry::{RetryOptions, RetryPolicy};
+
+/// The data version of 1.18, which moved chunk data out of the `Level` compound and added `blending_data`.
+const DATA_VERSION_1_18: i32 = 2860;
+
+/// The data version of 1.19, which replaced the `old_noise` flag of `blending_data` with the range of sections to blend.
+const DATA_VERSION_1_19: i32 = 3105;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to change")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where the changed region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "add",
+        help = "Whether to add or remove blending data on the selected chunks"
+    )]
+    mode: BlendMode,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum BlendMode {
+    /// Mark chunks as old terrain, which new chunks generated next to them blend into
+    Add,
+    /// Stop new chunks from blending into the chunks
+    Remove,
+}
+
+pub fn blend_files(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let retry = RetryPolicy::new(&options.retry);
+    let mut report = BlendReport::default();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            blend_file(output_path, &path, options, &mut report, &retry)?;
+        }
+    }
+
+    if report.old_chunks > 0 {
+        report!(warn, messages::BLEND_OLD_CHUNKS, chunks = report.old_chunks);
+    }
+
+    let summary = match options.mode {
+        BlendMode::Add => messages::BLEND_ADDED_SUMMARY,
+        BlendMode::Remove => messages::BLEND_REMOVED_SUMMARY,
+    };
+
+    report!(
+        info,
+        summary,
+        changed_chunks = report.changed_chunks,
+        chunks = report.chunks
+    );
+
+    retry.report(&NullObserver);
+
+    Ok(())
+}
+
+/// Counts the chunks which were read and changed, and the selected chunks which were left alone because they were saved
+/// before 1.18.
+#[derive(Default)]
+struct BlendReport {
+    chunks: u64,
+    changed_chunks: u64,
+    old_chunks: u64,
+}
+
+/// Copies a region file, adding or removing the blending data of each selected chunk. Chunks which aren't selected or
+/// don't need to change are copied without recompressing them.
+fn blend_file(
+    output_dir: &Path,
+    path: &Path,
+    options: &Options,
+    report: &mut BlendReport,
+    retry: &RetryPolicy,
+) -> Result<(), io::Error> {
+    let name = path.file_name().unwrap();
+    let region_position = RegionFile::parse_name(&name.to_string_lossy());
+    let selected_region = options.filter.includes_region(region_position);
+
+    let in_region = retry.run(|| RegionFile::open(path))?;
+    let mut out_region = retry.run(|| RegionFileWriter::create(&Path::join(output_dir, name)))?;
+    let mut changed_chunks = 0;
+    let started = Instant::now();
+
+    for result in retry.stream_raw_chunks(&in_region) {
+        let raw_chunk = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        report.chunks += 1;
+
+        if !selected_region
+            || !options
+                .filter
+                .includes_chunk(region_position, raw_chunk.position)
+        {
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let chunk = raw_chunk.decompress()?;
+        let mut nbt = read_nbt(&chunk.data)?;
+
+        let version = data_version(&nbt);
+
+        // Minecraft decides how chunks from before 1.18 blend by itself when it upgrades them
+        if version < DATA_VERSION_1_18 {
+            report.old_chunks += 1;
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let root = match &mut nbt {
+            Value::Compound(root) => root,
+            _ => {
+                retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+                continue;
+            }
+        };
+
+        let changed = match options.mode {
+            BlendMode::Add => add_blending_data(root, version),
+            BlendMode::Remove => remove_blending_data(root),
+        };
+
+        if !changed {
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let blended_chunk = chunk.with_data(write_nbt(&nbt)?.into_boxed_slice());
+
+        retry.run(|| out_region.add_chunk(&blended_chunk))?;
+        changed_chunks += 1;
+    }
+
+    out_region.finish()?;
+
+    report.changed_chunks += changed_chunks;
+
+    report!(
+        debug,
+        messages::BLEND_REGION,
+        region = name.to_string_lossy().into_owned(),
+        changed_chunks = changed_chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(())
+}
+
+/// Marks a chunk as old terrain for new chunks to blend into, in the form used by the version which saved it. The
+/// chunk's heightmaps are removed, since blending reads the height of the terrain, which Minecraft recomputes from the
+/// blocks when a chunk has no heightmaps. Returns false if the chunk already has blending data.
+fn add_blending_data(root: &mut HashMap<String, Value>, version: i32) -> bool {
+    if root.contains_key("blending_data") {
+        return false;
+    }
+
+    let mut blending_data = HashMap::new();
+
+    if version < DATA_VERSION_1_19 {
+        blending_data.insert(String::from("old_noise"), Value::Byte(1));
+    } else {
+        let (min_section, max_section) = section_range(root).unwrap_or((-4, 20));
+
+        blending_data.insert(String::from("min_section"), Value::Int(min_section));
+        blending_data.insert(String::from("max_section"), Value::Int(max_section));
+    }
+
+    root.insert(
+        String::from("blending_data"),
+        Value::Compound(blending_data),
+    );
+    root.remove("Heightmaps");
+
+    true
+}
+
+/// Removes a chunk's blending data, along with any retrogeneration of the terrain below Y=0 which was pending from an
+/// upgrade, as that also blends the chunk into its neighbours. Returns false if the chunk had neither.
+fn remove_blending_data(root: &mut HashMap<String, Value>) -> bool {
+    let blending_data = root.remove("blending_data").is_some();
+    let retrogen = root.remove("below_zero_retrogen").is_some();
+
+    blending_data || retrogen
+}
+
+/// Returns the lowest section of a chunk and the section just above its highest, which bound the sections to blend.
+/// Sections which only store light, such as those just outside the world's height, are ignored.
+fn section_range(root: &HashMap<String, Value>) -> Option<(i32, i32)> {
+    let sections = match root.get("sections") {
+        Some(Value::List(sections)) => sections,
+        _ => return None,
+    };
+
+    let ys = sections.iter().filter_map(|section| match section {
+        Value::Compound(section) if section.contains_key("block_states") => {
+            match section.get("Y") {
+                Some(Value::Byte(y)) => Some(*y as i32),
+                Some(Value::Int(y)) => Some(*y),
+                _ => None,
+            }
+        }
+        _ => None,
+    });
+
+    let (min, max) = ys.fold(None, |range, y| match range {
+        Some((min, max)) => Some((i32::min(min, y), i32::max(max, y))),
+        None => Some((y, y)),
+    })?;
+
+    Some((min, max + 1))
+}
@@ -0,0 +1,651 @@
+use clap::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use atty::Stream;
+use fastnbt::Value;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::messages::{self, Message};
+use crate::nbt::read_nbt;
+use crate::region::{compression_name, AnyRegionFile, ChunkPos, RawChunk, RegionFile};
+
+/// The number of chunks along each side of a region.
+const REGION_SIZE: i32 = 32;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region files to browse")]
+    input_dir: String,
+}
+
+pub fn browse(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if !atty::is(Stream::Stdout) {
+        return Err(
+            Message::new(messages::BROWSE_NO_TERMINAL).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    let mut regions = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && AnyRegionFile::is_region_name(&name) {
+            regions.push(path);
+        }
+    }
+
+    if regions.is_empty() {
+        return Err(Message::new(messages::BROWSE_NO_REGIONS)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    // Regions are listed by position rather than by name, so that neighbouring regions are next to each other
+    regions.sort_by_key(|path| {
+        let position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+        (position.z, position.x)
+    });
+
+    let mut browser = Browser::new(regions);
+    let mut terminal = ratatui::try_init()?;
+    let result = browser.run(&mut terminal);
+
+    // The terminal is restored even if drawing failed, so that the error can be read
+    ratatui::restore();
+
+    result
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Pane {
+    Regions,
+    Grid,
+    Tree,
+}
+
+/// The state of the browser, which moves from the list of regions to the chunks of the selected region, and from there
+/// to the NBT of a chunk.
+struct Browser {
+    regions: Vec<PathBuf>,
+    region_list: ListState,
+    /// The region selected in the list, which is read when it is first selected.
+    region: Option<RegionView>,
+    /// The position of the selected chunk within the region.
+    cursor: ChunkPos,
+    tree: Option<ChunkTree>,
+    focus: Pane,
+    /// A message about the last action, such as a chunk which couldn't be opened, shown until the next key press.
+    status: Option<String>,
+}
+
+impl Browser {
+    fn new(regions: Vec<PathBuf>) -> Self {
+        let mut browser = Browser {
+            regions,
+            region_list: ListState::default(),
+            region: None,
+            cursor: ChunkPos { x: 0, z: 0 },
+            tree: None,
+            focus: Pane::Regions,
+            status: None,
+        };
+
+        browser.select_region(0);
+        browser
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), io::Error> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Handles a key press, returning whether the browser should keep running.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        self.status = None;
+
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Pane::Regions => Pane::Grid,
+                    Pane::Grid if self.tree.is_some() => Pane::Tree,
+                    Pane::Grid | Pane::Tree => Pane::Regions,
+                };
+
+                return true;
+            }
+            _ => {}
+        }
+
+        match self.focus {
+            Pane::Regions => self.handle_regions_key(key.code),
+            Pane::Grid => self.handle_grid_key(key.code),
+            Pane::Tree => self.handle_tree_key(key.code),
+        }
+
+        true
+    }
+
+    fn handle_regions_key(&mut self, code: KeyCode) {
+        let selected = self.region_list.selected().unwrap_or(0);
+
+        match code {
+            KeyCode::Up | KeyCode::Char('k') if selected > 0 => self.select_region(selected - 1),
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < self.regions.len() => {
+                self.select_region(selected + 1)
+            }
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.focus = Pane::Grid,
+            _ => {}
+        }
+    }
+
+    fn handle_grid_key(&mut self, code: KeyCode) {
+        let (dx, dz) = match code {
+            KeyCode::Up | KeyCode::Char('k') => (0, -1),
+            KeyCode::Down | KeyCode::Char('j') => (0, 1),
+            KeyCode::Left | KeyCode::Char('h') => (-1, 0),
+            KeyCode::Right | KeyCode::Char('l') => (1, 0),
+            KeyCode::Enter => {
+                self.open_chunk();
+                return;
+            }
+            KeyCode::Esc => {
+                self.focus = Pane::Regions;
+                return;
+            }
+            _ => return,
+        };
+
+        self.cursor = ChunkPos {
+            x: (self.cursor.x + dx).clamp(0, REGION_SIZE - 1),
+            z: (self.cursor.z + dz).clamp(0, REGION_SIZE - 1),
+        };
+    }
+
+    fn handle_tree_key(&mut self, code: KeyCode) {
+        let tree = match &mut self.tree {
+            Some(tree) => tree,
+            None => return,
+        };
+
+        let rows = tree.rows();
+        let selected = tree
+            .list
+            .selected()
+            .unwrap_or(0)
+            .min(rows.len().saturating_sub(1));
+
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => tree.list.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => tree
+                .list
+                .select(Some((selected + 1).min(rows.len().saturating_sub(1)))),
+            KeyCode::PageUp => tree.list.select(Some(selected.saturating_sub(20))),
+            KeyCode::PageDown => tree
+                .list
+                .select(Some((selected + 20).min(rows.len().saturating_sub(1)))),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                if let Some(row) = rows.get(selected).filter(|row| row.expandable) {
+                    if !tree.expanded.remove(&row.path) {
+                        tree.expanded.insert(row.path.clone());
+                    }
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                let row = match rows.get(selected) {
+                    Some(row) => row,
+                    None => return,
+                };
+
+                // Collapsing a tag which isn't expanded moves up to its parent instead
+                if !tree.expanded.remove(&row.path) {
+                    let parent = &row.path[..row.path.len() - 1];
+
+                    if let Some(index) = rows.iter().position(|row| row.path == parent) {
+                        tree.list.select(Some(index));
+                    }
+                }
+            }
+            KeyCode::Esc => self.focus = Pane::Grid,
+            _ => {}
+        }
+    }
+
+    fn select_region(&mut self, index: usize) {
+        self.region_list.select(Some(index));
+        self.region = Some(RegionView::read(&self.regions[index]));
+        self.tree = None;
+
+        if let Some(Err(error)) = self.region.as_ref().map(|region| &region.error) {
+            self.status = Some(error.clone());
+        }
+    }
+
+    fn open_chunk(&mut self) {
+        let region = match &self.region {
+            Some(region) => region,
+            None => return,
+        };
+
+        let position = region.absolute(self.cursor);
+
+        let raw_chunk = match region.chunks.get(&self.cursor) {
+            Some(Ok(raw_chunk)) => raw_chunk,
+            Some(Err(error)) => {
+                self.status = Some(error.clone());
+                return;
+            }
+            None => {
+                self.status = Some(
+                    Message::new(messages::BROWSE_CHUNK_EMPTY)
+                        .arg("x", position.x)
+                        .arg("z", position.z)
+                        .text(),
+                );
+                return;
+            }
+        };
+
+        match raw_chunk
+            .decompress()
+            .and_then(|chunk| read_nbt(&chunk.data))
+        {
+            Ok(root) => {
+                let mut tree = ChunkTree {
+                    position,
+                    root,
+                    expanded: HashSet::new(),
+                    list: ListState::default(),
+                };
+
+                tree.list.select(Some(0));
+                self.tree = Some(tree);
+                self.focus = Pane::Tree;
+            }
+            Err(err) => {
+                self.status = Some(
+                    Message::new(messages::BROWSE_CHUNK_UNREADABLE)
+                        .arg("x", position.x)
+                        .arg("z", position.z)
+                        .arg("error", messages::error_message(&err).text())
+                        .text(),
+                );
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [regions, grid, tree] = Layout::horizontal([
+            Constraint::Length(24),
+            Constraint::Length(REGION_SIZE as u16 * 2 + 2),
+            Constraint::Min(0),
+        ])
+        .areas(main);
+
+        self.draw_regions(frame, regions);
+        self.draw_grid(frame, grid);
+        self.draw_tree(frame, tree);
+
+        let status_text = match &self.status {
+            Some(status) => status.clone(),
+            None => self.chunk_info(),
+        };
+
+        frame.render_widget(Paragraph::new(status_text), status);
+    }
+
+    fn draw_regions(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .regions
+            .iter()
+            .map(|path| ListItem::new(path.file_name().unwrap().to_string_lossy().into_owned()))
+            .collect();
+
+        let list = List::new(items)
+            .block(pane_block(
+                Message::new(messages::BROWSE_REGIONS_TITLE).text(),
+                self.focus == Pane::Regions,
+            ))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(list, area, &mut self.region_list);
+    }
+
+    fn draw_grid(&self, frame: &mut Frame, area: Rect) {
+        let region = match &self.region {
+            Some(region) => region,
+            None => return,
+        };
+
+        let opened = self.tree.as_ref().map(|tree| tree.position);
+        let lines: Vec<Line> = (0..REGION_SIZE)
+            .map(|z| {
+                Line::from(
+                    (0..REGION_SIZE)
+                        .map(|x| {
+                            let position = ChunkPos { x, z };
+                            let (cell, color) = match region.chunks.get(&position) {
+                                Some(Ok(_)) if opened == Some(region.absolute(position)) => {
+                                    ("██", Color::Yellow)
+                                }
+                                Some(Ok(_)) => ("██", Color::Green),
+                                Some(Err(_)) => ("▒▒", Color::Red),
+                                None => ("··", Color::DarkGray),
+                            };
+
+                            let mut style = Style::default().fg(color);
+
+                            if position == self.cursor {
+                                style = style.add_modifier(Modifier::REVERSED);
+                            }
+
+                            Span::styled(cell, style)
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+
+        let title = Message::new(messages::BROWSE_GRID_TITLE)
+            .arg("region", &region.name)
+            .arg(
+                "chunks",
+                region.chunks.values().filter(|chunk| chunk.is_ok()).count(),
+            )
+            .text();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(pane_block(title, self.focus == Pane::Grid)),
+            area,
+        );
+    }
+
+    fn draw_tree(&mut self, frame: &mut Frame, area: Rect) {
+        let focused = self.focus == Pane::Tree;
+        let tree = match &mut self.tree {
+            Some(tree) => tree,
+            None => {
+                let block = pane_block(String::new(), focused);
+                frame.render_widget(
+                    Paragraph::new(Message::new(messages::BROWSE_HELP).text())
+                        .wrap(Wrap { trim: true })
+                        .block(block),
+                    area,
+                );
+                return;
+            }
+        };
+
+        let items: Vec<ListItem> = tree
+            .rows()
+            .into_iter()
+            .map(|row| {
+                let marker = match (row.expandable, tree.expanded.contains(&row.path)) {
+                    (true, true) => "▾ ",
+                    (true, false) => "▸ ",
+                    (false, _) => "  ",
+                };
+
+                ListItem::new(Line::from(vec![
+                    Span::raw("  ".repeat(row.path.len() - 1)),
+                    Span::raw(marker),
+                    Span::styled(
+                        row.path.last().cloned().unwrap_or_default(),
+                        Style::default().fg(Color::Cyan),
+                    ),
+                    Span::raw(": "),
+                    Span::raw(row.summary),
+                ]))
+            })
+            .collect();
+
+        let title = Message::new(messages::BROWSE_TREE_TITLE)
+            .arg("x", tree.position.x)
+            .arg("z", tree.position.z)
+            .text();
+
+        let list = List::new(items)
+            .block(pane_block(title, focused))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(list, area, &mut tree.list);
+    }
+
+    /// Describes the chunk under the cursor for the status line.
+    fn chunk_info(&self) -> String {
+        let region = match &self.region {
+            Some(region) => region,
+            None => return String::new(),
+        };
+
+        let position = region.absolute(self.cursor);
+
+        match region.chunks.get(&self.cursor) {
+            Some(Ok(raw_chunk)) => Message::new(messages::BROWSE_CHUNK_INFO)
+                .arg("x", position.x)
+                .arg("z", position.z)
+                .arg("bytes", raw_chunk.compressed_data().len())
+                .arg("compression", compression_name(raw_chunk.compression()))
+                .arg(
+                    "timestamp",
+                    region.timestamps.get(&self.cursor).copied().unwrap_or(0),
+                )
+                .text(),
+            Some(Err(error)) => error.clone(),
+            None => Message::new(messages::BROWSE_CHUNK_EMPTY)
+                .arg("x", position.x)
+                .arg("z", position.z)
+                .text(),
+        }
+    }
+}
+
+fn pane_block(title: String, focused: bool) -> Block<'static> {
+    let style = if focused {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(style)
+        .title(title)
+}
+
+/// The chunks of a region, read up front so that moving around the grid doesn't touch the file.
+struct RegionView {
+    name: String,
+    position: ChunkPos,
+    /// Each stored chunk by its position within the region, or the error of reading it.
+    chunks: HashMap<ChunkPos, Result<RawChunk, String>>,
+    timestamps: HashMap<ChunkPos, u32>,
+    /// Whether the region itself could be opened, which is shown in the status line when the region is selected.
+    error: Result<(), String>,
+}
+
+impl RegionView {
+    fn read(path: &Path) -> Self {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let mut view = RegionView {
+            position: RegionFile::parse_name(&name),
+            name,
+            chunks: HashMap::new(),
+            timestamps: HashMap::new(),
+            error: Ok(()),
+        };
+
+        let region = match AnyRegionFile::open(path) {
+            Ok(region) => region,
+            Err(err) => {
+                view.error = Err(Message::new(messages::BROWSE_REGION_UNREADABLE)
+                    .arg("region", &view.name)
+                    .arg("error", messages::error_message(&err).text())
+                    .text());
+                return view;
+            }
+        };
+
+        // Chunks are stored in the order of their positions, x first
+        for (index, result) in region.stream_raw_chunks().enumerate() {
+            let position = ChunkPos {
+                x: index as i32 % REGION_SIZE,
+                z: index as i32 / REGION_SIZE,
+            };
+
+            match result {
+                Ok(Some(raw_chunk)) => {
+                    view.chunks.insert(position, Ok(raw_chunk));
+                }
+                Ok(None) => continue,
+                Err(err) => {
+                    let absolute = view.absolute(position);
+                    view.chunks.insert(
+                        position,
+                        Err(Message::new(messages::BROWSE_CHUNK_UNREADABLE)
+                            .arg("x", absolute.x)
+                            .arg("z", absolute.z)
+                            .arg("error", messages::error_message(&err).text())
+                            .text()),
+                    );
+                }
+            }
+
+            if let Ok(timestamp) = region.timestamp(position) {
+                view.timestamps.insert(position, timestamp);
+            }
+        }
+
+        view
+    }
+
+    /// Returns the position of a chunk in the world from its position within the region.
+    fn absolute(&self, position: ChunkPos) -> ChunkPos {
+        ChunkPos {
+            x: self.position.x * REGION_SIZE + position.x,
+            z: self.position.z * REGION_SIZE + position.z,
+        }
+    }
+}
+
+/// The NBT of an opened chunk, shown as a tree in which compounds and lists can be expanded.
+struct ChunkTree {
+    position: ChunkPos,
+    root: Value,
+    /// The paths of the compounds and lists which have been expanded, as the names of the tags leading to them.
+    expanded: HashSet<Vec<String>>,
+    list: ListState,
+}
+
+/// A line of the tree, which is a tag below an expanded compound or list.
+struct TreeRow {
+    path: Vec<String>,
+    summary: String,
+    expandable: bool,
+}
+
+impl ChunkTree {
+    /// Lists the tags which are visible with the current compounds and lists expanded, in the order they are shown.
+    fn rows(&self) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+
+        if let Value::Compound(root) = &self.root {
+            push_compound_rows(root, &mut Vec::new(), &self.expanded, &mut rows);
+        }
+
+        rows
+    }
+}
+
+fn push_compound_rows(
+    compound: &HashMap<String, Value>,
+    path: &mut Vec<String>,
+    expanded: &HashSet<Vec<String>>,
+    rows: &mut Vec<TreeRow>,
+) {
+    let mut names: Vec<&String> = compound.keys().collect();
+    names.sort();
+
+    for name in names {
+        push_rows(&compound[name], name.clone(), path, expanded, rows);
+    }
+}
+
+fn push_rows(
+    value: &Value,
+    name: String,
+    path: &mut Vec<String>,
+    expanded: &HashSet<Vec<String>>,
+    rows: &mut Vec<TreeRow>,
+) {
+    path.push(name);
+    rows.push(TreeRow {
+        path: path.clone(),
+        summary: summarize(value),
+        expandable: matches!(value, Value::Compound(_) | Value::List(_)),
+    });
+
+    if expanded.contains(path) {
+        match value {
+            Value::Compound(compound) => push_compound_rows(compound, path, expanded, rows),
+            Value::List(list) => {
+                for (index, element) in list.iter().enumerate() {
+                    push_rows(element, format!("[{}]", index), path, expanded, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    path.pop();
+}
+
+/// Describes a tag on a single line, writing values as in SNBT and giving the size of compounds, lists and arrays.
+fn summarize(value: &Value) -> String {
+    match value {
+        Value::Byte(value) => format!("{}b", value),
+        Value::Short(value) => format!("{}s", value),
+        Value::Int(value) => format!("{}", value),
+        Value::Long(value) => format!("{}L", value),
+        Value::Float(value) => format!("{}f", value),
+        Value::Double(value) => format!("{}d", value),
+        Value::String(value) => format!("{:?}", value),
+        Value::ByteArray(array) => format!("[B; {}]", array.len()),
+        Value::IntArray(array) => format!("[I; {}]", array.len()),
+        Value::LongArray(array) => format!("[L; {}]", array.len()),
+        Value::List(list) => Message::new(messages::BROWSE_LIST)
+            .arg("items", list.len())
+            .text(),
+        Value::Compound(compound) => Message::new(messages::BROWSE_COMPOUND)
+            .arg("entries", compound.len())
+            .text(),
+    }
+}
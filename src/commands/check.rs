@@ -0,0 +1,587 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::messages::{self, report, Message};
+use crate::nbt::{chunk_position, read_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionEntry, RegionFile, RegionFileWriter, HEADER_SECTORS};
+use crate::temp;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to check")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the report written to stdout",
+        default_value = "text"
+    )]
+    format: CheckFormat,
+
+    #[clap(
+        long,
+        help = "Rewrite region files with problems in place, discarding the chunks which can't be kept"
+    )]
+    fix: bool,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum CheckFormat {
+    /// One human-readable line per problem, followed by a line for each region file
+    Text,
+    /// One JSON object per line for each problem and region file
+    Json,
+}
+
+/// A problem with where a chunk is stored, which can make the game read the wrong data for it.
+enum SectorProblem {
+    /// The file is too short to hold the header, so none of its chunks can be found.
+    TruncatedHeader,
+    /// The chunk's sectors extend past the end of the file, so some of its data is missing.
+    PastEnd { entry: RegionEntry },
+    /// The chunk's sectors overlap the header of the region file.
+    InHeader { entry: RegionEntry },
+    /// The chunk has a location but no sectors.
+    Empty { entry: RegionEntry },
+    /// Two chunks share some of their sectors, so at least one of them has been overwritten by the other.
+    Overlap {
+        entry: RegionEntry,
+        other: ChunkPos,
+        first: u32,
+        count: u32,
+    },
+}
+
+/// The result of checking the layout of a region file.
+struct RegionCheck {
+    name: String,
+    sectors: u32,
+    chunks: usize,
+    problems: Vec<SectorProblem>,
+    /// Ranges of sectors after the header which no chunk uses, as their first sector and number of sectors.
+    unused: Vec<(u32, u32)>,
+    /// The chunks which were discarded along with why, if the region was rewritten by `--fix`.
+    discarded: Option<Vec<(ChunkPos, Message)>>,
+}
+
+impl RegionCheck {
+    fn unused_sectors(&self) -> u32 {
+        self.unused.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// The number of problems which remain in the region file.
+    fn remaining_problems(&self) -> usize {
+        match self.discarded {
+            Some(_) => 0,
+            None => self.problems.len(),
+        }
+    }
+
+    /// Whether `--fix` can repair the region file, which is only impossible once its header is lost.
+    fn is_fixable(&self) -> bool {
+        !self.problems.is_empty()
+            && !self
+                .problems
+                .iter()
+                .any(|problem| matches!(problem, SectorProblem::TruncatedHeader))
+    }
+}
+
+pub fn check_regions(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if options.fix {
+        read_only::ensure_writable(input_path)?;
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let checks = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| {
+                let mut check = check_region(path)?;
+
+                if options.fix && check.is_fixable() {
+                    check.discarded = Some(fix_region(path, &check)?);
+                }
+
+                Ok(check)
+            })
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    for check in &checks {
+        print_check(&mut out, check, options.format)?;
+    }
+
+    out.flush()?;
+
+    let problems: usize = checks.iter().map(|check| check.problems.len()).sum();
+    let remaining_problems: usize = checks.iter().map(RegionCheck::remaining_problems).sum();
+
+    report!(
+        info,
+        messages::CHECK_SUMMARY,
+        regions = checks.len(),
+        problems = problems,
+        unused_sectors = checks.iter().map(RegionCheck::unused_sectors).sum::<u32>()
+    );
+
+    if options.fix {
+        report!(
+            info,
+            messages::CHECK_FIX_SUMMARY,
+            regions = checks
+                .iter()
+                .filter(|check| check.discarded.is_some())
+                .count(),
+            chunks = checks
+                .iter()
+                .filter_map(|check| check.discarded.as_ref())
+                .map(Vec::len)
+                .sum::<usize>()
+        );
+    }
+
+    if remaining_problems > 0 {
+        let problems = remaining_problems;
+
+        return Err(Message::new(messages::CHECK_PROBLEMS)
+            .arg("problems", problems)
+            .into_error(io::ErrorKind::InvalidData));
+    }
+
+    Ok(())
+}
+
+/// Checks the location table of a region file against the sectors of the file, without reading any chunks.
+fn check_region(path: &Path) -> Result<RegionCheck, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+    let region = match RegionFile::open(path) {
+        Ok(region) => region,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(RegionCheck {
+                name,
+                sectors: 0,
+                chunks: 0,
+                problems: vec![SectorProblem::TruncatedHeader],
+                unused: Vec::new(),
+                discarded: None,
+            });
+        }
+        Err(err) => return Err(err),
+    };
+
+    let sectors = region.sector_len()?;
+    let mut entries = region.entries()?;
+
+    let mut problems = Vec::new();
+
+    for entry in &entries {
+        let end = entry.sector_index + entry.sector_count;
+
+        if entry.sector_count == 0 {
+            problems.push(SectorProblem::Empty { entry: *entry });
+        } else if entry.sector_index < HEADER_SECTORS {
+            problems.push(SectorProblem::InHeader { entry: *entry });
+        } else if end > sectors {
+            problems.push(SectorProblem::PastEnd { entry: *entry });
+        }
+    }
+
+    // Sorting the entries by their first sector means that each entry can only overlap the entries which follow it,
+    // up until the first one which starts after it ends
+    entries.sort_by_key(|entry| entry.sector_index);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let end = entry.sector_index + entry.sector_count;
+
+        for other in &entries[index + 1..] {
+            if other.sector_index >= end {
+                break;
+            }
+
+            let shared_end = end.min(other.sector_index + other.sector_count);
+
+            if shared_end > other.sector_index {
+                problems.push(SectorProblem::Overlap {
+                    entry: *entry,
+                    other: other.position,
+                    first: other.sector_index,
+                    count: shared_end - other.sector_index,
+                });
+            }
+        }
+    }
+
+    let mut used = vec![false; sectors as usize];
+
+    for entry in &entries {
+        let start = entry.sector_index.min(sectors) as usize;
+        let end = (entry.sector_index + entry.sector_count).min(sectors) as usize;
+
+        used[start..end]
+            .iter_mut()
+            .for_each(|sector| *sector = true);
+    }
+
+    let mut unused = Vec::new();
+    let mut sector = HEADER_SECTORS;
+
+    while sector < sectors {
+        if used[sector as usize] {
+            sector += 1;
+            continue;
+        }
+
+        let first = sector;
+
+        while sector < sectors && !used[sector as usize] {
+            sector += 1;
+        }
+
+        unused.push((first, sector - first));
+    }
+
+    Ok(RegionCheck {
+        name,
+        sectors,
+        chunks: entries.len(),
+        problems,
+        unused,
+        discarded: None,
+    })
+}
+
+/// Rewrites a region file without the chunks behind its problems, returning the chunks which were discarded along
+/// with why. Where two chunks share sectors, the chunk whose data holds its own position is kept, or the one saved
+/// most recently if that doesn't tell them apart. The new region is staged in the run's temporary directory and then
+/// moved over the old one, so the region is never left half-written.
+fn fix_region(path: &Path, check: &RegionCheck) -> Result<Vec<(ChunkPos, Message)>, io::Error> {
+    let region_position = RegionFile::parse_name(&check.name);
+    let mut discarded: HashMap<ChunkPos, Message> = HashMap::new();
+
+    let temp_path = temp::path(&check.name)?;
+
+    {
+        let region = RegionFile::open(path)?;
+
+        // Whether a chunk's data can be read and holds the chunk's own position, or why it can't be read
+        let holds_own_position = |entry: &RegionEntry| -> Result<bool, io::Error> {
+            let chunk = region.get_raw_chunk_from_entry(*entry)?.decompress()?;
+            let expected = ChunkPos {
+                x: (region_position.x * 32) + entry.position.x,
+                z: (region_position.z * 32) + entry.position.z,
+            };
+
+            Ok(chunk_position(&read_nbt(&chunk.data)?) == Some(expected))
+        };
+
+        for problem in &check.problems {
+            match problem {
+                SectorProblem::InHeader { entry } => {
+                    discarded.insert(
+                        entry.position,
+                        Message::new(messages::CHECK_REASON_IN_HEADER),
+                    );
+                }
+                SectorProblem::Empty { entry } => {
+                    discarded.insert(entry.position, Message::new(messages::CHECK_REASON_EMPTY));
+                }
+                SectorProblem::PastEnd { entry } => {
+                    // The end of the chunk's data may still be within the file, in which case it can be kept
+                    if let Err(err) = holds_own_position(entry) {
+                        discarded.insert(
+                            entry.position,
+                            Message::new(messages::CHECK_REASON_UNREADABLE)
+                                .arg("error", messages::error_message(&err)),
+                        );
+                    }
+                }
+                SectorProblem::Overlap { .. } | SectorProblem::TruncatedHeader => {}
+            }
+        }
+
+        // Overlaps are resolved last, since an overlap with a chunk which is already discarded needs no resolving
+        for problem in &check.problems {
+            let (entry, other_position) = match problem {
+                SectorProblem::Overlap { entry, other, .. } => (entry, *other),
+                _ => continue,
+            };
+
+            if discarded.contains_key(&entry.position) || discarded.contains_key(&other_position) {
+                continue;
+            }
+
+            let other = region
+                .entries()?
+                .into_iter()
+                .find(|other| other.position == other_position)
+                .unwrap();
+
+            let entry_matches = holds_own_position(entry).unwrap_or(false);
+            let other_matches = holds_own_position(&other).unwrap_or(false);
+
+            let (kept, lost, reason) = if entry_matches != other_matches {
+                let (kept, lost) = if entry_matches {
+                    (entry.position, other.position)
+                } else {
+                    (other.position, entry.position)
+                };
+
+                (kept, lost, messages::CHECK_REASON_OVERLAP_POSITION)
+            } else {
+                let entry_timestamp = region.timestamp(entry.position)?;
+                let other_timestamp = region.timestamp(other.position)?;
+
+                let (kept, lost) = if entry_timestamp > other_timestamp {
+                    (entry.position, other.position)
+                } else {
+                    (other.position, entry.position)
+                };
+
+                (kept, lost, messages::CHECK_REASON_OVERLAP_OLDER)
+            };
+
+            discarded.insert(
+                lost,
+                Message::new(reason)
+                    .arg("other_x", kept.x)
+                    .arg("other_z", kept.z),
+            );
+        }
+
+        let mut writer = RegionFileWriter::create(&temp_path)?;
+
+        for entry in region.entries()? {
+            if discarded.contains_key(&entry.position) {
+                continue;
+            }
+
+            writer.add_raw_chunk(&region.get_raw_chunk_from_entry(entry)?)?;
+            writer.set_timestamp(entry.position, region.timestamp(entry.position)?);
+        }
+
+        writer.finish()?;
+    }
+
+    temp::replace(&temp_path, path)?;
+
+    let mut discarded: Vec<(ChunkPos, Message)> = discarded.into_iter().collect();
+    discarded.sort_by_key(|(position, _)| (position.z, position.x));
+
+    Ok(discarded)
+}
+
+fn print_check<W>(out: &mut W, check: &RegionCheck, format: CheckFormat) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    for problem in &check.problems {
+        print_problem(out, &check.name, check.sectors, problem, format)?;
+    }
+
+    for &(first, count) in &check.unused {
+        match format {
+            CheckFormat::Text => writeln!(
+                out,
+                "{}",
+                Message::new(messages::CHECK_UNUSED)
+                    .arg("region", &check.name)
+                    .arg("first", first)
+                    .arg("last", first + count - 1)
+            )?,
+            CheckFormat::Json => writeln!(
+                out,
+                "{}",
+                serde_json::json!({
+                    "kind": "unused",
+                    "region": check.name,
+                    "sector": first,
+                    "sectors": count,
+                })
+            )?,
+        }
+    }
+
+    for (position, reason) in check.discarded.iter().flatten() {
+        match format {
+            CheckFormat::Text => writeln!(
+                out,
+                "{}",
+                Message::new(messages::CHECK_DISCARDED)
+                    .arg("region", &check.name)
+                    .arg("x", position.x)
+                    .arg("z", position.z)
+                    .arg("reason", reason)
+            )?,
+            CheckFormat::Json => writeln!(
+                out,
+                "{}",
+                serde_json::json!({
+                    "kind": "discarded",
+                    "region": check.name,
+                    "chunk_x": position.x,
+                    "chunk_z": position.z,
+                    "reason": reason.to_string(),
+                })
+            )?,
+        }
+    }
+
+    match format {
+        CheckFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(messages::CHECK_REGION)
+                .arg("region", &check.name)
+                .arg("chunks", check.chunks)
+                .arg("sectors", check.sectors)
+                .arg("problems", check.problems.len())
+                .arg("unused_sectors", check.unused_sectors())
+        ),
+        CheckFormat::Json => writeln!(
+            out,
+            "{}",
+            serde_json::json!({
+                "kind": "region",
+                "region": check.name,
+                "chunks": check.chunks,
+                "sectors": check.sectors,
+                "problems": check.problems.len(),
+                "unused_sectors": check.unused_sectors(),
+                "fixed": check.discarded.is_some(),
+            })
+        ),
+    }
+}
+
+fn print_problem<W>(
+    out: &mut W,
+    region: &str,
+    sectors: u32,
+    problem: &SectorProblem,
+    format: CheckFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let (kind, entry, message) = match problem {
+        SectorProblem::TruncatedHeader => (
+            "truncated_header",
+            None,
+            Message::new(messages::CHECK_TRUNCATED_HEADER),
+        ),
+        SectorProblem::PastEnd { entry } => (
+            "past_end",
+            Some(entry),
+            Message::new(messages::CHECK_PAST_END).arg("sectors", sectors),
+        ),
+        SectorProblem::InHeader { entry } => (
+            "in_header",
+            Some(entry),
+            Message::new(messages::CHECK_IN_HEADER),
+        ),
+        SectorProblem::Empty { entry } => {
+            ("empty", Some(entry), Message::new(messages::CHECK_EMPTY))
+        }
+        SectorProblem::Overlap {
+            entry,
+            other,
+            first,
+            count,
+        } => (
+            "overlap",
+            Some(entry),
+            Message::new(messages::CHECK_OVERLAP)
+                .arg("other_x", other.x)
+                .arg("other_z", other.z)
+                .arg("shared_first", first)
+                .arg("shared_last", first + count - 1),
+        ),
+    };
+
+    match format {
+        CheckFormat::Text => {
+            let mut message = message.arg("region", region);
+
+            if let Some(entry) = entry {
+                message = message
+                    .arg("x", entry.position.x)
+                    .arg("z", entry.position.z)
+                    .arg("first", entry.sector_index)
+                    .arg("last", entry.sector_index + entry.sector_count - 1);
+            }
+
+            writeln!(out, "{}", message)
+        }
+        CheckFormat::Json => {
+            let mut object = serde_json::json!({
+                "kind": kind,
+                "region": region,
+            });
+
+            if let Some(entry) = entry {
+                object["chunk_x"] = serde_json::json!(entry.position.x);
+                object["chunk_z"] = serde_json::json!(entry.position.z);
+                object["sector"] = serde_json::json!(entry.sector_index);
+                object["sectors"] = serde_json::json!(entry.sector_count);
+            }
+
+            if let SectorProblem::Overlap {
+                other,
+                first,
+                count,
+                ..
+            } = problem
+            {
+                object["other_chunk_x"] = serde_json::json!(other.x);
+                object["other_chunk_z"] = serde_json::json!(other.z);
+                object["shared_sector"] = serde_json::json!(first);
+                object["shared_sectors"] = serde_json::json!(count);
+            }
+
+            if let SectorProblem::PastEnd { .. } = problem {
+                object["file_sectors"] = serde_json::json!(sectors);
+            }
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use clap::Parser;
+use std::fs;
+use std::io;
+
+use crate::messages::{self, Message};
+use crate::read_only;
+use crate::temp;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Print the directories which would be removed without removing them"
+    )]
+    dry_run: bool,
+}
+
+pub fn cleanup_stale(options: &Options) -> Result<(), io::Error> {
+    let stale_dirs = temp::find_stale_run_dirs()?;
+
+    if stale_dirs.is_empty() {
+        println!(
+            "{}",
+            Message::new(messages::CLEANUP_NONE).arg("path", temp::base_dir().display())
+        );
+        return Ok(());
+    }
+
+    for path in &stale_dirs {
+        if options.dry_run {
+            println!(
+                "{}",
+                Message::new(messages::CLEANUP_WOULD_REMOVE).arg("path", path.display())
+            );
+            continue;
+        }
+
+        read_only::ensure_writable(path)?;
+        fs::remove_dir_all(path)?;
+
+        println!(
+            "{}",
+            Message::new(messages::CLEANUP_REMOVED).arg("path", path.display())
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,410 @@
+use clap::{Parser, ValueEnum};
+use fastnbt::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blocks::clear_columns;
+use crate::commands::delete_chunks::{delete_from_region, Coordinates};
+use crate::filter::{parse_coordinate_box, CoordinateBox};
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile, RegionFileWriter};
+use crate::temp;
+
+/// Lists of a chunk which hold things placed at integer block coordinates, either at the root since 1.18 or within the
+/// `Level` compound before.
+const BLOCK_ENTITY_LISTS: &[&str] = &["block_entities", "TileEntities"];
+const TICK_LISTS: &[&str] = &["block_ticks", "fluid_ticks", "TileTicks", "LiquidTicks"];
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Directory of region (.mca) files to clear, which are rewritten in place"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Directory of entity region files, added in 1.17, whose entities within the area are also cleared"
+    )]
+    entities_dir: Option<String>,
+
+    #[clap(
+        long,
+        required = true,
+        help = "Area to clear, given as X,Z or ranges such as -10..10,-10..10 (may be repeated)",
+        value_parser = parse_coordinate_box
+    )]
+    area: Vec<CoordinateBox>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "chunk",
+        help = "Whether the areas are given in chunk or block coordinates"
+    )]
+    coordinates: Coordinates,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "air",
+        help = "Whether to fill the area with air or to delete every chunk it touches"
+    )]
+    mode: ClearMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ClearMode {
+    /// Replace the blocks within the area with air, and remove its block entities, entities and scheduled ticks
+    Air,
+    /// Delete the chunks within the area, so that the game generates them again
+    Delete,
+}
+
+/// Counts the chunks and the things within them which were cleared.
+#[derive(Default)]
+struct ClearReport {
+    chunks: u64,
+    regions: u64,
+    removed_regions: u64,
+    blocks: u64,
+    block_entities: u64,
+    entities: u64,
+    ticks: u64,
+}
+
+pub fn clear(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let mut dirs = vec![input_path];
+
+    if let Some(entities_dir) = &options.entities_dir {
+        let entities_path = Path::new(entities_dir);
+
+        if !Path::is_dir(entities_path) {
+            return Err(Message::new(messages::INPUT_DIR_MISSING)
+                .arg("path", entities_path.display())
+                .into_error(io::ErrorKind::NotFound));
+        }
+
+        dirs.push(entities_path);
+    }
+
+    for dir in &dirs {
+        read_only::ensure_writable(dir)?;
+    }
+
+    // Blocks are cleared by their own coordinates, so areas of chunks are widened to every block within them
+    let block_areas: Vec<CoordinateBox> = options
+        .area
+        .iter()
+        .map(|area| match options.coordinates {
+            Coordinates::Chunk => area.chunk_area_blocks(),
+            Coordinates::Block => area.clone(),
+        })
+        .collect();
+    let chunk_areas: Vec<CoordinateBox> = block_areas
+        .iter()
+        .map(CoordinateBox::block_area_chunks)
+        .collect();
+
+    let mut report = ClearReport::default();
+
+    for dir in dirs {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+            if !path.is_file() || !RegionFile::is_region_name(&name) {
+                continue;
+            }
+
+            let region_position = RegionFile::parse_name(&name);
+
+            if !chunk_areas
+                .iter()
+                .any(|area| area.intersects_region(region_position))
+            {
+                continue;
+            }
+
+            let selected: HashSet<ChunkPos> = RegionFile::open(&path)?
+                .entries()?
+                .into_iter()
+                .map(|entry| entry.position)
+                .filter(|position| {
+                    let absolute = absolute_position(region_position, *position);
+
+                    chunk_areas.iter().any(|area| area.contains(absolute))
+                })
+                .collect();
+
+            if selected.is_empty() {
+                continue;
+            }
+
+            match options.mode {
+                ClearMode::Air => {
+                    let chunks = clear_region(&path, &selected, &block_areas, &mut report)?;
+
+                    report!(
+                        debug,
+                        messages::CLEAR_REGION,
+                        region = name,
+                        chunks = chunks
+                    );
+                }
+                ClearMode::Delete => {
+                    let (deleted_chunks, remaining_chunks) = delete_from_region(&path, &selected)?;
+
+                    report.chunks += deleted_chunks as u64;
+
+                    if remaining_chunks == 0 {
+                        fs::remove_file(&path)?;
+                        report.removed_regions += 1;
+
+                        report!(debug, messages::DELETE_CHUNKS_REGION_REMOVED, region = name);
+                    } else {
+                        report!(
+                            debug,
+                            messages::DELETE_CHUNKS_REGION,
+                            region = name,
+                            chunks = deleted_chunks
+                        );
+                    }
+                }
+            }
+
+            report.regions += 1;
+        }
+    }
+
+    match options.mode {
+        ClearMode::Air => {
+            report!(
+                info,
+                messages::CLEAR_SUMMARY,
+                blocks = report.blocks,
+                chunks = report.chunks,
+                regions = report.regions,
+                block_entities = report.block_entities,
+                entities = report.entities,
+                ticks = report.ticks
+            );
+        }
+        ClearMode::Delete => {
+            report!(
+                info,
+                messages::CLEAR_DELETED,
+                chunks = report.chunks,
+                regions = report.regions,
+                removed = report.removed_regions
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Clears the area from the selected chunks of a region, returning the number of chunks which changed. The new region
+/// is staged in the run's temporary directory and then moved over the old one, and is only written if a chunk changed.
+/// Chunks which changed are dated to now, as if the game had saved them, while the rest keep their timestamps.
+fn clear_region(
+    path: &Path,
+    selected: &HashSet<ChunkPos>,
+    areas: &[CoordinateBox],
+    report: &mut ClearReport,
+) -> Result<u64, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region_position = RegionFile::parse_name(&name);
+
+    let region = RegionFile::open(path)?;
+    let temp_path = temp::path(&name)?;
+    let mut changed_chunks = 0;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or(0);
+
+    {
+        let mut writer = RegionFileWriter::create(&temp_path)?;
+
+        for result in region.stream_raw_chunks() {
+            let raw_chunk = match result? {
+                Some(raw_chunk) => raw_chunk,
+                None => continue,
+            };
+
+            if !selected.contains(&raw_chunk.position) {
+                writer.add_raw_chunk(&raw_chunk)?;
+                writer.set_timestamp(raw_chunk.position, region.timestamp(raw_chunk.position)?);
+                continue;
+            }
+
+            let chunk = raw_chunk.decompress()?;
+            let mut nbt = read_nbt(&chunk.data)?;
+            let absolute = absolute_position(region_position, chunk.position);
+
+            let changed = clear_chunk(&mut nbt, absolute, areas, report).map_err(|reason| {
+                Message::new(messages::CLEAR_INVALID_CHUNK)
+                    .arg("region", &name)
+                    .arg("x", chunk.position.x)
+                    .arg("z", chunk.position.z)
+                    .arg("reason", reason)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+            // Chunks which held nothing within the area can be copied without recompressing them
+            if !changed {
+                writer.add_raw_chunk(&raw_chunk)?;
+                writer.set_timestamp(raw_chunk.position, region.timestamp(raw_chunk.position)?);
+                continue;
+            }
+
+            writer.add_chunk(&chunk.with_data(write_nbt(&nbt)?.into_boxed_slice()))?;
+            writer.set_timestamp(chunk.position, now);
+            changed_chunks += 1;
+        }
+
+        writer.finish()?;
+    }
+
+    drop(region);
+
+    if changed_chunks > 0 {
+        temp::replace(&temp_path, path)?;
+    } else {
+        fs::remove_file(&temp_path)?;
+    }
+
+    report.chunks += changed_chunks;
+
+    Ok(changed_chunks)
+}
+
+/// Fills the columns of a chunk within the area with air, and removes the block entities, entities and scheduled ticks
+/// within it. Entity files added in 1.17 have no blocks, and keep their entities in a list at the root. Returns true if
+/// anything was cleared.
+fn clear_chunk(
+    nbt: &mut Value,
+    chunk: ChunkPos,
+    areas: &[CoordinateBox],
+    report: &mut ClearReport,
+) -> Result<bool, String> {
+    let in_area = |x: i32, z: i32| areas.iter().any(|area| area.contains_coordinates(x, z));
+
+    let mut columns = [false; 256];
+
+    for (index, column) in columns.iter_mut().enumerate() {
+        *column = in_area(
+            (chunk.x * 16) + (index % 16) as i32,
+            (chunk.z * 16) + (index / 16) as i32,
+        );
+    }
+
+    let blocks = clear_columns(nbt, &columns)?;
+
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return Err(String::from("Chunk is not a compound")),
+    };
+
+    let container = if root.contains_key("Level") {
+        match root.get_mut("Level") {
+            Some(Value::Compound(level)) => level,
+            _ => return Err(String::from("Chunk's Level is not a compound")),
+        }
+    } else {
+        root
+    };
+
+    let block_entities = remove_in_area(container, BLOCK_ENTITY_LISTS, block_position, &in_area);
+    let ticks = remove_in_area(container, TICK_LISTS, block_position, &in_area);
+    let entities = remove_in_area(container, &["Entities"], entity_position, &in_area);
+
+    report.blocks += blocks;
+    report.block_entities += block_entities;
+    report.entities += entities;
+    report.ticks += ticks;
+
+    Ok(blocks + block_entities + entities + ticks > 0)
+}
+
+/// Removes the elements of the lists in a compound whose position is within the area, returning how many were removed.
+fn remove_in_area<P, A>(
+    container: &mut HashMap<String, Value>,
+    keys: &[&str],
+    position: P,
+    in_area: &A,
+) -> u64
+where
+    P: Fn(&Value) -> Option<(i32, i32)>,
+    A: Fn(i32, i32) -> bool,
+{
+    let mut removed = 0;
+
+    for key in keys {
+        if let Some(Value::List(list)) = container.get_mut(*key) {
+            let before = list.len();
+
+            list.retain(|element| match position(element) {
+                Some((x, z)) => !in_area(x, z),
+                None => true,
+            });
+
+            removed += (before - list.len()) as u64;
+        }
+    }
+
+    removed
+}
+
+/// Returns the horizontal position of a block entity or scheduled tick, which is stored as integer coordinates.
+fn block_position(element: &Value) -> Option<(i32, i32)> {
+    match element {
+        Value::Compound(element) => match (element.get("x"), element.get("z")) {
+            (Some(Value::Int(x)), Some(Value::Int(z))) => Some((*x, *z)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the horizontal position of the block an entity is in, from the list of doubles in its `Pos` tag.
+fn entity_position(entity: &Value) -> Option<(i32, i32)> {
+    let position = match entity {
+        Value::Compound(entity) => match entity.get("Pos") {
+            Some(Value::List(position)) => position,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    match (position.first(), position.get(2)) {
+        (Some(Value::Double(x)), Some(Value::Double(z))) => {
+            Some((x.floor() as i32, z.floor() as i32))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the absolute position of a chunk, given its region and its position within that region.
+fn absolute_position(region: ChunkPos, chunk: ChunkPos) -> ChunkPos {
+    ChunkPos {
+        x: (region.x * 32) + chunk.x,
+        z: (region.z * 32) + chunk.z,
+    }
+}
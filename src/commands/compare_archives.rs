@@ -0,0 +1,280 @@
+use clap::{Parser, ValueEnum};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use crate::commands::archive::{
+    chunk_entry_nbt, chunk_entry_path, for_each_archive_entry, parse_chunk_entry_path,
+    DEDUP_ENTRY_PATH,
+};
+use crate::dedup::{dedup_key, ChunkReferences};
+use crate::messages::{self, report, Message, MessageId};
+use crate::region::ChunkPos;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Path of the older archive")]
+    a: String,
+
+    #[clap(
+        long,
+        help = "Path of the newer archive, which is compared against the older one"
+    )]
+    b: String,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the differences written to stdout",
+        default_value = "text"
+    )]
+    format: CompareFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum CompareFormat {
+    /// One human-readable line per difference
+    Text,
+    /// One JSON object per line for each difference
+    Json,
+}
+
+/// The chunks of an archive by region, each with a hash of its NBT which ignores the chunk's position. Positions are
+/// left out so that a duplicate stored with `--dedup` has the same hash as its original, without having to relocate it.
+type ArchiveChunks = HashMap<ChunkPos, HashMap<ChunkPos, u64>>;
+
+pub fn compare_archives(options: &Options) -> Result<(), io::Error> {
+    for input_path in [&options.a, &options.b] {
+        let input_path = Path::new(input_path);
+
+        if !Path::exists(input_path) {
+            return Err(Message::new(messages::INPUT_FILE_MISSING)
+                .arg("path", input_path.display())
+                .into_error(io::ErrorKind::NotFound));
+        }
+    }
+
+    // Both archives are streamed at the same time, since reading them is mostly spent decompressing
+    let (old, new) = rayon::join(
+        || read_archive_chunks(Path::new(&options.a)),
+        || read_archive_chunks(Path::new(&options.b)),
+    );
+    let (old, new) = (old?, new?);
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    let mut regions_added = 0;
+    let mut regions_removed = 0;
+    let mut chunks_added = 0;
+    let mut chunks_removed = 0;
+    let mut chunks_changed = 0;
+    let mut chunks_unchanged = 0;
+
+    let mut regions: Vec<ChunkPos> = old
+        .keys()
+        .chain(new.keys())
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    regions.sort_by_key(|region| (region.x, region.z));
+
+    for region in regions {
+        let (old_chunks, new_chunks) = match (old.get(&region), new.get(&region)) {
+            (Some(old_chunks), Some(new_chunks)) => (old_chunks, new_chunks),
+            (None, Some(new_chunks)) => {
+                print_region(&mut out, region, new_chunks.len(), true, options.format)?;
+                regions_added += 1;
+                chunks_added += new_chunks.len();
+                continue;
+            }
+            (Some(old_chunks), None) => {
+                print_region(&mut out, region, old_chunks.len(), false, options.format)?;
+                regions_removed += 1;
+                chunks_removed += old_chunks.len();
+                continue;
+            }
+            (None, None) => unreachable!(),
+        };
+
+        let mut chunks: Vec<ChunkPos> = old_chunks
+            .keys()
+            .chain(new_chunks.keys())
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        chunks.sort_by_key(|chunk| (chunk.z, chunk.x));
+
+        for chunk in chunks {
+            let change = match (old_chunks.get(&chunk), new_chunks.get(&chunk)) {
+                (Some(old_hash), Some(new_hash)) if old_hash == new_hash => {
+                    chunks_unchanged += 1;
+                    continue;
+                }
+                (Some(_), Some(_)) => {
+                    chunks_changed += 1;
+                    messages::COMPARE_CHUNK_CHANGED
+                }
+                (None, Some(_)) => {
+                    chunks_added += 1;
+                    messages::COMPARE_CHUNK_ADDED
+                }
+                (Some(_), None) => {
+                    chunks_removed += 1;
+                    messages::COMPARE_CHUNK_REMOVED
+                }
+                (None, None) => unreachable!(),
+            };
+
+            print_chunk(&mut out, region, chunk, change, options.format)?;
+        }
+    }
+
+    out.flush()?;
+
+    report!(
+        info,
+        messages::COMPARE_SUMMARY,
+        regions_added = regions_added,
+        regions_removed = regions_removed,
+        chunks_added = chunks_added,
+        chunks_removed = chunks_removed,
+        chunks_changed = chunks_changed,
+        chunks_unchanged = chunks_unchanged
+    );
+
+    Ok(())
+}
+
+/// Streams an archive, hashing the NBT of every chunk it stores. Chunks packed with `--raw` or as SNBT are converted
+/// to NBT first, so archives packed in different ways can be compared.
+fn read_archive_chunks(archive_path: &Path) -> Result<ArchiveChunks, io::Error> {
+    let mut chunks = ArchiveChunks::new();
+    let mut references = None;
+
+    for_each_archive_entry(
+        BufReader::new(File::open(archive_path)?),
+        |path, size, reader| {
+            let mut data = Vec::with_capacity(size as usize);
+            reader.read_to_end(&mut data)?;
+
+            if path == Path::new(DEDUP_ENTRY_PATH) {
+                references = Some(ChunkReferences::read_from(&data[..])?);
+                return Ok(true);
+            }
+
+            if let Some((region, chunk)) = parse_chunk_entry_path(path) {
+                let hash = dedup_key(&chunk_entry_nbt(path, &data)?)?.hash;
+                chunks.entry(region).or_default().insert(chunk, hash);
+            }
+
+            Ok(true)
+        },
+    )?;
+
+    // Duplicates are only listed once every chunk has been stored, so they're resolved at the end
+    for ((region, chunk), (original_region, original_chunk)) in
+        references.iter().flat_map(ChunkReferences::iter)
+    {
+        let hash = chunks
+            .get(&original_region)
+            .and_then(|original_chunks| original_chunks.get(&original_chunk))
+            .copied()
+            .ok_or_else(|| {
+                Message::new(messages::COMPARE_MISSING_ORIGINAL)
+                    .arg("entry", chunk_entry_path(region, chunk))
+                    .arg(
+                        "original",
+                        chunk_entry_path(original_region, original_chunk),
+                    )
+                    .arg("archive", archive_path.display())
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+        chunks.entry(region).or_default().insert(chunk, hash);
+    }
+
+    Ok(chunks)
+}
+
+fn print_region<W>(
+    out: &mut W,
+    region: ChunkPos,
+    chunks: usize,
+    added: bool,
+    format: CompareFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let name = format!("r.{}.{}", region.x, region.z);
+    let change = if added {
+        messages::COMPARE_REGION_ADDED
+    } else {
+        messages::COMPARE_REGION_REMOVED
+    };
+
+    match format {
+        CompareFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(change)
+                .arg("region", &name)
+                .arg("chunks", chunks)
+        ),
+        CompareFormat::Json => {
+            let object = serde_json::json!({
+                "kind": "region",
+                "change": if added { "added" } else { "removed" },
+                "region": name,
+                "region_x": region.x,
+                "region_z": region.z,
+                "chunks": chunks,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+fn print_chunk<W>(
+    out: &mut W,
+    region: ChunkPos,
+    chunk: ChunkPos,
+    change: MessageId,
+    format: CompareFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    match format {
+        CompareFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(change).arg("entry", chunk_entry_path(region, chunk))
+        ),
+        CompareFormat::Json => {
+            let change = if change == messages::COMPARE_CHUNK_ADDED {
+                "added"
+            } else if change == messages::COMPARE_CHUNK_REMOVED {
+                "removed"
+            } else {
+                "changed"
+            };
+
+            let object = serde_json::json!({
+                "kind": "chunk",
+                "change": change,
+                "region": format!("r.{}.{}", region.x, region.z),
+                "x": (region.x * 32) + chunk.x,
+                "z": (region.z * 32) + chunk.z,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
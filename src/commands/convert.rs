@@ -0,0 +1,188 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::linear::LinearRegionFileWriter;
+use crate::messages::{self, report, Message};
+use crate::read_only;
+use crate::region::{AnyRegionFile, RegionFile, RegionFileWriter};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Input directory of region files to convert, in the Anvil (.mca), Linear (.linear) or MCRegion (.mcr) format"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where the converted region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(long, value_enum, help = "Format to convert the region files to")]
+    to: RegionFormat,
+
+    #[clap(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(i32).range(1..=22),
+        help = "Zstandard level used when writing Linear region files, from 1 to 22"
+    )]
+    compression_level: i32,
+
+    #[clap(long, help = "Threads used for converting region files")]
+    threads: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RegionFormat {
+    /// Anvil region files, as written by Minecraft
+    Anvil,
+    /// Linear region files, as written by some server forks
+    Linear,
+}
+
+impl RegionFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RegionFormat::Anvil => "mca",
+            RegionFormat::Linear => "linear",
+        }
+    }
+}
+
+pub fn convert_world(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file()
+            && AnyRegionFile::is_region_name(&name)
+            && !AnyRegionFile::is_superseded(&path)
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let converted = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| convert_file(output_path, path, options))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    report!(
+        info,
+        messages::CONVERT_SUMMARY,
+        chunks = converted.iter().sum::<usize>(),
+        regions = converted.len(),
+        format = options.to.extension()
+    );
+
+    Ok(())
+}
+
+/// Converts a single region file into the target format, keeping the time each chunk was last saved. Returns the number
+/// of chunks which were converted.
+fn convert_file(output_dir: &Path, path: &Path, options: &Options) -> Result<usize, io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let name = format!(
+        "r.{}.{}.{}",
+        region_position.x,
+        region_position.z,
+        options.to.extension()
+    );
+    let output_path = output_dir.join(&name);
+
+    let in_region = AnyRegionFile::open(path)?;
+    let mut chunks = 0;
+    let started = Instant::now();
+
+    match options.to {
+        RegionFormat::Anvil => {
+            let mut out_region = RegionFileWriter::create(&output_path)?;
+
+            for result in in_region.stream_raw_chunks() {
+                let raw_chunk = match result? {
+                    Some(raw_chunk) => raw_chunk,
+                    None => continue,
+                };
+
+                // Chunks read from a Linear region are uncompressed, so they need to be compressed for the game
+                if in_region.has_compressed_chunks() {
+                    out_region.add_raw_chunk(&raw_chunk)?;
+                } else {
+                    out_region.add_chunk(&raw_chunk.decompress()?)?;
+                }
+
+                out_region
+                    .set_timestamp(raw_chunk.position, in_region.timestamp(raw_chunk.position)?);
+                chunks += 1;
+            }
+
+            out_region.finish()?;
+        }
+        RegionFormat::Linear => {
+            let mut out_region =
+                LinearRegionFileWriter::create(&output_path, options.compression_level)?;
+
+            for result in in_region.stream_raw_chunks() {
+                let raw_chunk = match result? {
+                    Some(raw_chunk) => raw_chunk,
+                    None => continue,
+                };
+
+                out_region.add_chunk(
+                    &raw_chunk.decompress()?,
+                    in_region.timestamp(raw_chunk.position)?,
+                );
+                chunks += 1;
+            }
+
+            out_region.finish()?;
+        }
+    }
+
+    report!(
+        debug,
+        messages::CONVERT_REGION,
+        region = name,
+        chunks = chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(chunks)
+}
@@ -0,0 +1,247 @@
+use clap::Parser;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::commands::shift::{parse_offset, shift_chunk, target_region, target_regions};
+use crate::filter::{parse_coordinate_box, CoordinateBox};
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::read_only;
+use crate::region::{Chunk, ChunkPos, RegionFile, RegionFileWriter};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Input directory of region (.mca) files to copy chunks from"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory of region files to copy chunks into, which may already contain a world"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        help = "Box of absolute chunk coordinates to copy, such as -8..8,-8..8",
+        value_parser = parse_coordinate_box,
+        allow_hyphen_values = true
+    )]
+    chunks: CoordinateBox,
+
+    #[clap(
+        long,
+        help = "Offset to move the copied chunks by, in chunks, such as 64,-32",
+        value_parser = parse_offset,
+        allow_hyphen_values = true
+    )]
+    offset: Option<ChunkPos>,
+
+    #[clap(
+        long,
+        help = "Leave chunks which already exist in the output alone, rather than replacing them"
+    )]
+    skip_existing: bool,
+
+    #[clap(long, help = "Threads used for copying region files")]
+    threads: Option<u32>,
+}
+
+/// The number of chunks which were copied into a region, and which were skipped as they already existed there.
+#[derive(Default)]
+struct CopyCounts {
+    copied: usize,
+    skipped: usize,
+}
+
+pub fn copy_chunks(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    // Copying within a directory would read regions while chunks are being written into them
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let offset = options.offset.unwrap_or(ChunkPos { x: 0, z: 0 });
+
+    let mut sources: HashMap<ChunkPos, PathBuf> = HashMap::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            let region = RegionFile::parse_name(&name);
+
+            if options.chunks.intersects_region(region) {
+                sources.insert(region, path);
+            }
+        }
+    }
+
+    let mut targets: BTreeMap<(i32, i32), BTreeSet<(i32, i32)>> = BTreeMap::new();
+
+    for region in sources.keys() {
+        for target in target_regions(*region, offset) {
+            targets
+                .entry((target.x, target.z))
+                .or_default()
+                .insert((region.x, region.z));
+        }
+    }
+
+    let targets: Vec<_> = targets.into_iter().collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let copied = pool.install(|| {
+        targets
+            .par_iter()
+            .map(|((x, z), target_sources)| {
+                let source_paths: Vec<&PathBuf> = target_sources
+                    .iter()
+                    .map(|&(x, z)| &sources[&ChunkPos { x, z }])
+                    .collect();
+
+                copy_into_region(
+                    output_path,
+                    ChunkPos { x: *x, z: *z },
+                    &source_paths,
+                    offset,
+                    options,
+                )
+            })
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let chunks: usize = copied.iter().map(|counts| counts.copied).sum();
+    let skipped: usize = copied.iter().map(|counts| counts.skipped).sum();
+    let regions = copied.iter().filter(|counts| counts.copied > 0).count();
+
+    report!(
+        info,
+        messages::COPY_SUMMARY,
+        chunks = chunks,
+        regions = regions,
+        skipped = skipped,
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Copies the selected chunks which move into the target region, from each of the regions they can come from. The
+/// target region is created if it doesn't exist yet, and otherwise the copied chunks are added to it, replacing any
+/// chunks at the same positions unless existing chunks are skipped.
+fn copy_into_region(
+    output_dir: &Path,
+    target: ChunkPos,
+    sources: &[&PathBuf],
+    offset: ChunkPos,
+    options: &Options,
+) -> Result<CopyCounts, io::Error> {
+    let name = format!("r.{}.{}.mca", target.x, target.z);
+    let started = Instant::now();
+
+    let mut chunks = Vec::new();
+
+    for path in sources {
+        let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+        let region = RegionFile::open(path)?;
+
+        for result in region.stream_chunks() {
+            let chunk = match result? {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+
+            let position = ChunkPos {
+                x: region_position.x * 32 + chunk.position.x,
+                z: region_position.z * 32 + chunk.position.z,
+            };
+
+            if !options.chunks.contains(position)
+                || target_region(region_position, chunk.position, offset) != target
+            {
+                continue;
+            }
+
+            let mut nbt = read_nbt(&chunk.data)?;
+            shift_chunk(&mut nbt, offset);
+
+            // The chunk keeps the time it was last saved, rather than that of any chunk it replaces
+            chunks.push((
+                Chunk {
+                    data: write_nbt(&nbt)?.into_boxed_slice(),
+                    position: ChunkPos {
+                        x: (position.x + offset.x).rem_euclid(32),
+                        z: (position.z + offset.z).rem_euclid(32),
+                    },
+                },
+                region.timestamp(chunk.position)?,
+            ));
+        }
+    }
+
+    let mut counts = CopyCounts::default();
+
+    if chunks.is_empty() {
+        return Ok(counts);
+    }
+
+    let output_path = output_dir.join(&name);
+
+    let mut writer = if output_path.exists() {
+        RegionFileWriter::open(&output_path)?
+    } else {
+        RegionFileWriter::create(&output_path)?
+    };
+
+    for (chunk, timestamp) in &chunks {
+        if options.skip_existing && writer.read_raw_chunk(chunk.position)?.is_some() {
+            counts.skipped += 1;
+            continue;
+        }
+
+        writer.add_chunk(chunk)?;
+        writer.set_timestamp(chunk.position, *timestamp);
+        counts.copied += 1;
+    }
+
+    writer.finish()?;
+
+    report!(
+        debug,
+        messages::COPY_REGION,
+        region = name,
+        chunks = counts.copied,
+        skipped = counts.skipped,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(counts)
+}
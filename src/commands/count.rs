@@ -0,0 +1,374 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::block_index::{BlockIndex, ChunkBlockCounts};
+use crate::blocks::{block_state_matches, count_block_states, qualify};
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to count")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "world",
+        help = "Whether to tally blocks across the whole world, or separately for each region or chunk"
+    )]
+    group_by: CountGroup,
+
+    #[clap(long, value_enum, default_value = "csv", help = "Format of the counts")]
+    format: CountFormat,
+
+    #[clap(long, help = "Output file for the counts (defaults to stdout)")]
+    output: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only count these blocks, either by name (diamond_ore) or exact state (comma-separated)",
+        value_delimiter = ','
+    )]
+    blocks: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Count blocks by name alone, combining all of their states"
+    )]
+    by_name: bool,
+
+    #[clap(
+        long,
+        help = "Block index to take the counts of regions from if they haven't changed since it was built, instead of decoding their chunks"
+    )]
+    index: Option<String>,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CountGroup {
+    /// One tally for the whole world
+    World,
+    /// One tally for each region file
+    Region,
+    /// One tally for each chunk
+    Chunk,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CountFormat {
+    /// One `block,count,fraction` row for each block in each group, preceded by the coordinates of the group
+    Csv,
+    /// One JSON object per line for each block in each group
+    Json,
+}
+
+/// The number of blocks of each state in a group, along with the total number of blocks in the group.
+#[derive(Default)]
+struct Tally {
+    blocks: HashMap<String, u64>,
+    total: u64,
+}
+
+impl Tally {
+    /// Returns the blocks of the tally from the most to least common, with ties broken by name so that output is stable.
+    fn sorted(&self) -> Vec<(&String, u64)> {
+        let mut blocks: Vec<(&String, u64)> = self
+            .blocks
+            .iter()
+            .map(|(block, count)| (block, *count))
+            .collect();
+        blocks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        blocks
+    }
+}
+
+pub fn count_blocks(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if let Some(output) = &options.output {
+        read_only::ensure_writable(Path::new(output))?;
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let index = match &options.index {
+        Some(index) => {
+            let index_path = Path::new(index);
+
+            if !Path::exists(index_path) {
+                return Err(Message::new(messages::INDEX_FILE_MISSING)
+                    .arg("path", index_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            Some(BlockIndex::load(index_path)?)
+        }
+        None => None,
+    };
+
+    let queries: Vec<String> = options.blocks.iter().map(|id| qualify(id)).collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let counted = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| count_region(path, index.as_ref(), options))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    // Groups are keyed by the coordinates of their region or chunk, which the world as a whole doesn't have
+    let mut groups: BTreeMap<(i32, i32), Tally> = BTreeMap::new();
+    let mut chunks = 0;
+
+    for (region_position, region_chunks) in counted {
+        for (chunk_position, counts) in region_chunks {
+            let key = match options.group_by {
+                CountGroup::World => (0, 0),
+                CountGroup::Region => (region_position.x, region_position.z),
+                CountGroup::Chunk => (chunk_position.x, chunk_position.z),
+            };
+
+            let group = groups.entry(key).or_default();
+
+            for (block, count) in counts {
+                group.total += count;
+                *group.blocks.entry(block).or_default() += count;
+            }
+
+            chunks += 1;
+        }
+    }
+
+    // Fractions are relative to every block in the group, so blocks are only left out after the totals are known
+    if !queries.is_empty() {
+        for group in groups.values_mut() {
+            group.blocks.retain(|block, _| {
+                queries
+                    .iter()
+                    .any(|query| block_state_matches(block, query))
+            });
+        }
+    }
+
+    match &options.output {
+        Some(output) => write_counts(
+            &groups,
+            options.group_by,
+            options.format,
+            BufWriter::new(File::create(output)?),
+        )?,
+        None => write_counts(
+            &groups,
+            options.group_by,
+            options.format,
+            io::stdout().lock(),
+        )?,
+    }
+
+    let blocks: u64 = groups
+        .values()
+        .flat_map(|group| group.blocks.values())
+        .sum();
+
+    report!(
+        info,
+        messages::COUNT_SUMMARY,
+        blocks = blocks,
+        chunks = chunks,
+        regions = files.len()
+    );
+
+    Ok(())
+}
+
+/// Counts the blocks of each chunk in a region file, returning the position of the region and the absolute position
+/// of each chunk with its counts. Regions which haven't changed since the index was built are counted from it instead.
+fn count_region(
+    path: &Path,
+    index: Option<&BlockIndex>,
+    options: &Options,
+) -> Result<(ChunkPos, Vec<ChunkBlockCounts>), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+
+    if let Some(index) = index {
+        if let Some(region_index) = index.current_region(path)? {
+            let chunks = region_index
+                .chunks
+                .iter()
+                .filter(|chunk| {
+                    options.filter.includes_chunk(
+                        region_position,
+                        ChunkPos {
+                            x: chunk.position.x.rem_euclid(32),
+                            z: chunk.position.z.rem_euclid(32),
+                        },
+                    )
+                })
+                .map(|chunk| {
+                    let counts = index.chunk_states(chunk);
+                    (chunk.position, group_counts(counts, options.by_name))
+                })
+                .collect();
+
+            return Ok((region_position, chunks));
+        }
+    }
+
+    let region = RegionFile::open(path)?;
+    let mut chunks = Vec::new();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !options
+            .filter
+            .includes_chunk(region_position, chunk.position)
+        {
+            continue;
+        }
+
+        let nbt = read_nbt(&chunk.data)?;
+        let counts = count_block_states(&nbt).map_err(|err| {
+            Message::new(messages::BLOCK_COUNT_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        let position = ChunkPos {
+            x: (region_position.x * 32) + chunk.position.x,
+            z: (region_position.z * 32) + chunk.position.z,
+        };
+
+        chunks.push((position, group_counts(counts, options.by_name)));
+    }
+
+    Ok((region_position, chunks))
+}
+
+/// Combines the counts of each state of a block if counting by name, or otherwise returns the counts as they are.
+fn group_counts(counts: HashMap<String, u64>, by_name: bool) -> HashMap<String, u64> {
+    if !by_name {
+        return counts;
+    }
+
+    let mut names: HashMap<String, u64> = HashMap::new();
+
+    for (state, count) in counts {
+        let name = state.split('[').next().unwrap_or(&state);
+        *names.entry(String::from(name)).or_default() += count;
+    }
+
+    names
+}
+
+fn write_counts<W>(
+    groups: &BTreeMap<(i32, i32), Tally>,
+    group_by: CountGroup,
+    format: CountFormat,
+    mut writer: W,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    if let CountFormat::Csv = format {
+        match group_by {
+            CountGroup::World => writeln!(writer, "block,count,fraction")?,
+            CountGroup::Region => writeln!(writer, "region_x,region_z,block,count,fraction")?,
+            CountGroup::Chunk => writeln!(writer, "chunk_x,chunk_z,block,count,fraction")?,
+        }
+    }
+
+    for (&(x, z), group) in groups {
+        for (block, count) in group.sorted() {
+            let fraction = count as f64 / group.total as f64;
+
+            match format {
+                CountFormat::Csv => {
+                    if group_by != CountGroup::World {
+                        write!(writer, "{},{},", x, z)?;
+                    }
+
+                    writeln!(writer, "{},{},{:.8}", csv_field(block), count, fraction)?;
+                }
+                CountFormat::Json => {
+                    let mut object = serde_json::json!({
+                        "block": block,
+                        "count": count,
+                        "fraction": fraction,
+                    });
+
+                    match group_by {
+                        CountGroup::World => {}
+                        CountGroup::Region => {
+                            object["region_x"] = serde_json::json!(x);
+                            object["region_z"] = serde_json::json!(z);
+                        }
+                        CountGroup::Chunk => {
+                            object["chunk_x"] = serde_json::json!(x);
+                            object["chunk_z"] = serde_json::json!(z);
+                        }
+                    }
+
+                    writeln!(writer, "{}", object)?;
+                }
+            }
+        }
+    }
+
+    writer.flush()
+}
+
+/// Quotes a CSV field if needed, since the properties of a block state are separated by commas.
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
@@ -0,0 +1,652 @@
+use atty::Stream;
+use clap::{Parser, ValueEnum};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::blocks::{palette_block_names, qualify};
+use crate::filter::{matches_pattern, parse_coordinate_box, CoordinateBox};
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile, RegionFileWriter};
+use crate::temp;
+
+/// Blocks which are rarely generated by the game, and so suggest that a player has built in a chunk. Names may contain
+/// `*` to match any text.
+const PLAYER_BLOCKS: &[&str] = &[
+    "chest",
+    "trapped_chest",
+    "barrel",
+    "ender_chest",
+    "*shulker_box",
+    "crafting_table",
+    "furnace",
+    "blast_furnace",
+    "smoker",
+    "torch",
+    "wall_torch",
+    "soul_torch",
+    "soul_wall_torch",
+    "*_bed",
+    "anvil",
+    "enchanting_table",
+    "brewing_stand",
+    "beacon",
+    "hopper",
+    "redstone_wire",
+    "repeater",
+    "comparator",
+    "piston",
+    "sticky_piston",
+    "observer",
+    "*_concrete",
+    "*_glazed_terracotta",
+];
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Directory of region (.mca) files to delete chunks from, which are rewritten in place"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "File listing the chunks to delete, with one X,Z coordinate per line"
+    )]
+    list: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "chunk",
+        help = "Whether the listed coordinates are chunk or block coordinates"
+    )]
+    coordinates: Coordinates,
+
+    #[clap(
+        long,
+        help = "File of chunks which are never deleted, even if they are listed, given as coordinates, boxes such as -10..10,-10..10, or exported claims in CSV or JSON (may be repeated)"
+    )]
+    protect_file: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Keep listed chunks whose palettes contain blocks which players place, such as chests, crafting tables and torches"
+    )]
+    protect_player_blocks: bool,
+
+    #[clap(
+        long,
+        requires = "protect_player_blocks",
+        value_delimiter = ',',
+        help = "Blocks which mark a chunk as built by players, replacing the default list (comma-separated, * matches any text)"
+    )]
+    player_blocks: Vec<String>,
+
+    #[clap(
+        long,
+        requires = "protect_player_blocks",
+        default_value_t = 0,
+        help = "Number of chunks around each chunk built by players which are also kept"
+    )]
+    protect_margin: u32,
+
+    #[clap(
+        long,
+        help = "Count the chunks which would be deleted without rewriting any region files"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        requires = "dry_run",
+        help = "Print a map of each affected region during a dry run, marking the chunks which would be kept and deleted"
+    )]
+    show_map: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Coordinates {
+    /// Absolute chunk coordinates
+    Chunk,
+    /// Block coordinates, selecting the chunk which contains each block
+    Block,
+}
+
+pub fn delete_chunks(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if !options.dry_run {
+        read_only::ensure_writable(input_path)?;
+    }
+
+    let mut listed = read_chunk_list(Path::new(&options.list), options.coordinates)?;
+    let listed_count: usize = listed.values().map(HashSet::len).sum();
+
+    let mut protected_areas = Vec::new();
+
+    for protect_file in &options.protect_file {
+        protected_areas.extend(read_protect_file(
+            Path::new(protect_file),
+            options.coordinates,
+        )?);
+    }
+
+    if !protected_areas.is_empty() {
+        let mut protected = 0;
+
+        for (&(x, z), chunks) in listed.iter_mut() {
+            chunks.retain(|chunk| {
+                let position = ChunkPos {
+                    x: (x * 32) + chunk.x,
+                    z: (z * 32) + chunk.z,
+                };
+
+                if protected_areas.iter().any(|area| area.contains(position)) {
+                    protected += 1;
+                    return false;
+                }
+
+                true
+            });
+        }
+
+        report!(
+            info,
+            messages::DELETE_CHUNKS_PROTECTED,
+            chunks = protected,
+            areas = protected_areas.len()
+        );
+    }
+
+    if options.protect_player_blocks {
+        let patterns: Vec<String> = if options.player_blocks.is_empty() {
+            PLAYER_BLOCKS.iter().map(|name| qualify(name)).collect()
+        } else {
+            options
+                .player_blocks
+                .iter()
+                .map(|name| qualify(name))
+                .collect()
+        };
+
+        let margin = options.protect_margin as i32;
+        let built = find_built_chunks(input_path, &listed, margin, &patterns)?;
+        let mut protected = 0;
+
+        for (&(x, z), chunks) in listed.iter_mut() {
+            chunks.retain(|chunk| {
+                let (chunk_x, chunk_z) = ((x * 32) + chunk.x, (z * 32) + chunk.z);
+
+                let near_built = (-margin..=margin).any(|dz| {
+                    (-margin..=margin).any(|dx| {
+                        built.contains(&ChunkPos {
+                            x: chunk_x + dx,
+                            z: chunk_z + dz,
+                        })
+                    })
+                });
+
+                if near_built {
+                    protected += 1;
+                }
+
+                !near_built
+            });
+        }
+
+        report!(
+            info,
+            messages::DELETE_CHUNKS_PLAYER_PROTECTED,
+            chunks = protected,
+            built = built.len()
+        );
+    }
+
+    let mut deleted = 0;
+    let mut regions = 0;
+    let mut removed_regions = 0;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let colored = atty::is(Stream::Stdout);
+
+    for (&(x, z), chunks) in &listed {
+        let name = format!("r.{}.{}.mca", x, z);
+        let path = input_path.join(&name);
+
+        if !path.is_file() {
+            continue;
+        }
+
+        if options.dry_run {
+            let present = region_chunks(&RegionFile::open(&path)?)?;
+            let deleted_chunks = present.iter().filter(|pos| chunks.contains(pos)).count();
+
+            if deleted_chunks == 0 {
+                continue;
+            }
+
+            if options.show_map {
+                print_map(&mut out, &name, &present, chunks, colored)?;
+            }
+
+            deleted += deleted_chunks;
+            regions += 1;
+
+            if deleted_chunks == present.len() {
+                removed_regions += 1;
+            }
+
+            continue;
+        }
+
+        let (deleted_chunks, remaining_chunks) = delete_from_region(&path, chunks)?;
+
+        if deleted_chunks == 0 {
+            continue;
+        }
+
+        deleted += deleted_chunks;
+        regions += 1;
+
+        if remaining_chunks == 0 {
+            fs::remove_file(&path)?;
+            removed_regions += 1;
+
+            report!(debug, messages::DELETE_CHUNKS_REGION_REMOVED, region = name);
+        } else {
+            report!(
+                debug,
+                messages::DELETE_CHUNKS_REGION,
+                region = name,
+                chunks = deleted_chunks
+            );
+        }
+    }
+
+    let summary = if options.dry_run {
+        messages::DELETE_CHUNKS_DRY_RUN
+    } else {
+        messages::DELETE_CHUNKS_SUMMARY
+    };
+
+    report!(
+        info,
+        summary,
+        chunks = deleted,
+        listed = listed_count,
+        regions = regions,
+        removed = removed_regions
+    );
+
+    Ok(())
+}
+
+/// Reads a list of chunks, grouped by region and given by their position within that region. Blank lines and lines
+/// starting with `#` are ignored.
+fn read_chunk_list(
+    path: &Path,
+    coordinates: Coordinates,
+) -> Result<BTreeMap<(i32, i32), HashSet<ChunkPos>>, io::Error> {
+    let contents = fs::read_to_string(path)?;
+    let mut chunks: BTreeMap<(i32, i32), HashSet<ChunkPos>> = BTreeMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let position = parse_position(line).map_err(|reason| {
+            Message::new(messages::DELETE_CHUNKS_LIST_INVALID)
+                .arg("path", path.display())
+                .arg("line", index + 1)
+                .arg("reason", reason)
+                .into_error(io::ErrorKind::InvalidInput)
+        })?;
+
+        let position = match coordinates {
+            Coordinates::Chunk => position,
+            Coordinates::Block => ChunkPos {
+                x: position.x.div_euclid(16),
+                z: position.z.div_euclid(16),
+            },
+        };
+
+        chunks
+            .entry((position.x.div_euclid(32), position.z.div_euclid(32)))
+            .or_default()
+            .insert(ChunkPos {
+                x: position.x.rem_euclid(32),
+                z: position.z.rem_euclid(32),
+            });
+    }
+
+    Ok(chunks)
+}
+
+/// Finds the chunks within `margin` of a listed chunk whose palettes contain a block matching one of the patterns,
+/// returning their absolute positions. Only these chunks are read, so that chunks which would be kept anyway aren't
+/// decompressed.
+fn find_built_chunks(
+    input_path: &Path,
+    listed: &BTreeMap<(i32, i32), HashSet<ChunkPos>>,
+    margin: i32,
+    patterns: &[String],
+) -> Result<HashSet<ChunkPos>, io::Error> {
+    let mut candidates: HashMap<(i32, i32), HashSet<ChunkPos>> = HashMap::new();
+
+    for (&(x, z), chunks) in listed {
+        for chunk in chunks {
+            for dz in -margin..=margin {
+                for dx in -margin..=margin {
+                    let (chunk_x, chunk_z) = ((x * 32) + chunk.x + dx, (z * 32) + chunk.z + dz);
+
+                    candidates
+                        .entry((chunk_x.div_euclid(32), chunk_z.div_euclid(32)))
+                        .or_default()
+                        .insert(ChunkPos {
+                            x: chunk_x.rem_euclid(32),
+                            z: chunk_z.rem_euclid(32),
+                        });
+                }
+            }
+        }
+    }
+
+    let mut built = HashSet::new();
+
+    for (&(x, z), chunks) in &candidates {
+        let path = input_path.join(format!("r.{}.{}.mca", x, z));
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let region = RegionFile::open(&path)?;
+
+        for position in chunks {
+            let chunk = match region.get_raw_chunk(*position)? {
+                Some(raw_chunk) => raw_chunk.decompress()?,
+                None => continue,
+            };
+
+            let nbt = read_nbt(&chunk.data)?;
+
+            if palette_block_names(&nbt).iter().any(|name| {
+                patterns
+                    .iter()
+                    .any(|pattern| matches_pattern(pattern, name))
+            }) {
+                built.insert(ChunkPos {
+                    x: (x * 32) + position.x,
+                    z: (z * 32) + position.z,
+                });
+            }
+        }
+    }
+
+    Ok(built)
+}
+
+/// Reads the areas of a protect file in absolute chunk coordinates. Claims exported by protection plugins are given in
+/// block coordinates, either as a JSON array of objects with `min_x`, `min_z`, `max_x` and `max_z` keys (or `min` and
+/// `max` objects with `x` and `z` keys, as WorldGuard writes them), or as CSV with a header naming those columns. Any
+/// other file lists a chunk or box of chunks per line, in the same form as `--chunk-bounds`, which follows
+/// `--coordinates` like the chunk list does.
+fn read_protect_file(
+    path: &Path,
+    coordinates: Coordinates,
+) -> Result<Vec<CoordinateBox>, io::Error> {
+    let contents = fs::read_to_string(path)?;
+
+    let invalid = |reason: String| {
+        Message::new(messages::DELETE_CHUNKS_PROTECT_INVALID)
+            .arg("path", path.display())
+            .arg("reason", reason)
+            .into_error(io::ErrorKind::InvalidInput)
+    };
+
+    if contents.trim_start().starts_with('[') {
+        return read_protect_json(&contents).map_err(invalid);
+    }
+
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    let header = match lines.peek() {
+        Some((_, line)) if line.to_ascii_lowercase().contains("min_x") => Some(
+            line.split(',')
+                .map(|column| column.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        ),
+        _ => None,
+    };
+
+    let mut areas = Vec::new();
+
+    match header {
+        Some(header) => {
+            let column = |name: &str| {
+                header
+                    .iter()
+                    .position(|column| column == name)
+                    .ok_or_else(|| invalid(format!("CSV header has no {} column", name)))
+            };
+
+            let columns = [
+                column("min_x")?,
+                column("min_z")?,
+                column("max_x")?,
+                column("max_z")?,
+            ];
+
+            for (line_number, line) in lines.skip(1) {
+                let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+                let mut bounds = [0; 4];
+
+                for (bound, &index) in bounds.iter_mut().zip(&columns) {
+                    let field = fields.get(index).copied().unwrap_or("");
+
+                    *bound = field.parse::<i32>().map_err(|_| {
+                        invalid(format!(
+                            "line {}: Invalid coordinate: {}",
+                            line_number, field
+                        ))
+                    })?;
+                }
+
+                areas.push(claim_area(bounds));
+            }
+        }
+        None => {
+            for (line_number, line) in lines {
+                let area = parse_coordinate_box(line)
+                    .map_err(|reason| invalid(format!("line {}: {}", line_number, reason)))?;
+
+                areas.push(match coordinates {
+                    Coordinates::Chunk => area,
+                    Coordinates::Block => area.block_area_chunks(),
+                });
+            }
+        }
+    }
+
+    Ok(areas)
+}
+
+/// Reads claims exported as a JSON array, in block coordinates.
+fn read_protect_json(contents: &str) -> Result<Vec<CoordinateBox>, String> {
+    let claims: Vec<serde_json::Value> =
+        serde_json::from_str(contents).map_err(|err| err.to_string())?;
+
+    claims
+        .iter()
+        .enumerate()
+        .map(|(index, claim)| {
+            let coordinate = |flat: &str, nested: &str, axis: &str| {
+                claim
+                    .get(flat)
+                    .or_else(|| claim.get(nested).and_then(|corner| corner.get(axis)))
+                    .and_then(serde_json::Value::as_f64)
+                    .map(|value| value.floor() as i32)
+                    .ok_or_else(|| format!("claim {} has no {}", index + 1, flat))
+            };
+
+            Ok(claim_area([
+                coordinate("min_x", "min", "x")?,
+                coordinate("min_z", "min", "z")?,
+                coordinate("max_x", "max", "x")?,
+                coordinate("max_z", "max", "z")?,
+            ]))
+        })
+        .collect()
+}
+
+/// Returns the chunks covered by a claim given by the block coordinates `[min_x, min_z, max_x, max_z]`, in case its
+/// corners were exported in either order.
+fn claim_area([min_x, min_z, max_x, max_z]: [i32; 4]) -> CoordinateBox {
+    CoordinateBox::new(
+        min_x.min(max_x)..=min_x.max(max_x),
+        min_z.min(max_z)..=min_z.max(max_z),
+    )
+    .block_area_chunks()
+}
+
+/// Parses a chunk position written as `X,Z`.
+pub fn parse_position(line: &str) -> Result<ChunkPos, String> {
+    let (x, z) = line
+        .split_once(',')
+        .ok_or_else(|| format!("Expected coordinates in the form X,Z: {}", line))?;
+
+    let parse = |coordinate: &str| {
+        coordinate
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid coordinate: {}", coordinate.trim()))
+    };
+
+    Ok(ChunkPos {
+        x: parse(x)?,
+        z: parse(z)?,
+    })
+}
+
+/// Rewrites a region file without the given chunks, returning the number of chunks which were deleted and the number
+/// which remain. The region is left untouched if none of the chunks are in it. The new region is staged in the run's
+/// temporary directory and then moved over the old one, so the region is never left half-written.
+pub fn delete_from_region(
+    path: &Path,
+    chunks: &HashSet<ChunkPos>,
+) -> Result<(usize, usize), io::Error> {
+    let region = RegionFile::open(path)?;
+    let present = region_chunks(&region)?;
+
+    let deleted = present.iter().filter(|pos| chunks.contains(pos)).count();
+    let remaining = present.len() - deleted;
+
+    if deleted == 0 || remaining == 0 {
+        return Ok((deleted, remaining));
+    }
+
+    let temp_path = temp::path(&path.file_name().unwrap().to_string_lossy())?;
+
+    {
+        let mut writer = RegionFileWriter::create(&temp_path)?;
+
+        for result in region.stream_raw_chunks() {
+            let raw_chunk = match result? {
+                Some(raw_chunk) => raw_chunk,
+                None => continue,
+            };
+
+            if !chunks.contains(&raw_chunk.position) {
+                writer.add_raw_chunk(&raw_chunk)?;
+                writer.set_timestamp(raw_chunk.position, region.timestamp(raw_chunk.position)?);
+            }
+        }
+
+        writer.finish()?;
+    }
+
+    drop(region);
+    temp::replace(&temp_path, path)?;
+
+    Ok((deleted, remaining))
+}
+
+/// Lists the positions of the chunks stored in a region, without decompressing them.
+fn region_chunks(region: &RegionFile) -> Result<Vec<ChunkPos>, io::Error> {
+    region
+        .stream_raw_chunks()
+        .filter_map(|result| match result {
+            Ok(Some(raw_chunk)) => Some(Ok(raw_chunk.position)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect()
+}
+
+/// Prints a 32x32 grid of a region with one character per chunk, north at the top: `#` for chunks which would be kept,
+/// `x` for chunks which would be deleted, and `.` where the region has no chunk. Kept and deleted chunks are colored
+/// green and red when writing to a terminal.
+fn print_map<W: Write>(
+    out: &mut W,
+    name: &str,
+    present: &[ChunkPos],
+    deleted: &HashSet<ChunkPos>,
+    colored: bool,
+) -> Result<(), io::Error> {
+    let present: HashSet<ChunkPos> = present.iter().copied().collect();
+    let deleted_count = present.iter().filter(|pos| deleted.contains(pos)).count();
+
+    writeln!(
+        out,
+        "{}",
+        Message::new(messages::DELETE_CHUNKS_MAP)
+            .arg("region", name)
+            .arg("kept", present.len() - deleted_count)
+            .arg("deleted", deleted_count)
+    )?;
+
+    for z in 0..32 {
+        let mut line = String::with_capacity(32);
+
+        for x in 0..32 {
+            let pos = ChunkPos { x, z };
+
+            let (mark, color) = if !present.contains(&pos) {
+                ('.', None)
+            } else if deleted.contains(&pos) {
+                ('x', Some("31"))
+            } else {
+                ('#', Some("32"))
+            };
+
+            match color {
+                Some(color) if colored => line.push_str(&format!("\x1b[{}m{}\x1b[0m", color, mark)),
+                _ => line.push(mark),
+            }
+        }
+
+        writeln!(out, "{}", line)?;
+    }
+
+    writeln!(out)
+}
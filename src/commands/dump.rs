@@ -0,0 +1,214 @@
+use clap::Parser;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::commands::delete_chunks::parse_position;
+use crate::messages::{self, Message};
+use crate::region::{compression_name, ChunkPos, RegionEntry, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Path of the region (.mca) file to dump")]
+    region: String,
+
+    #[clap(
+        long,
+        help = "Dump the entry of this chunk instead of the whole header, given as X,Z either within the region or in the world",
+        value_parser = parse_position
+    )]
+    chunk: Option<ChunkPos>,
+
+    #[clap(
+        long,
+        requires = "chunk",
+        help = "Also write a hexdump of the chunk's payload as it is stored"
+    )]
+    hex: bool,
+}
+
+pub fn dump(options: &Options) -> Result<(), io::Error> {
+    let path = Path::new(&options.region);
+
+    if !Path::exists(path) {
+        return Err(Message::new(messages::INPUT_FILE_MISSING)
+            .arg("path", path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region = RegionFile::open(path)?;
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    match options.chunk {
+        Some(position) => {
+            let position = ChunkPos {
+                x: position.x.rem_euclid(32),
+                z: position.z.rem_euclid(32),
+            };
+
+            dump_chunk(&mut out, &region, position, options.hex)?;
+        }
+        None => dump_header(&mut out, &region, &name)?,
+    }
+
+    out.flush()
+}
+
+/// Writes the location and timestamp of every chunk which the header records, in the order of their entries.
+fn dump_header<W: Write>(out: &mut W, region: &RegionFile, name: &str) -> Result<(), io::Error> {
+    let entries = region.entries()?;
+
+    writeln!(
+        out,
+        "{}",
+        Message::new(messages::DUMP_REGION)
+            .arg("region", name)
+            .arg("chunks", entries.len())
+            .arg("sectors", region.sector_len()?)
+            .text()
+    )?;
+
+    for entry in &entries {
+        writeln!(out, "{}", entry_message(region, entry)?.text())?;
+    }
+
+    Ok(())
+}
+
+/// Writes everything the region records about a single chunk. The payload is read from the chunk's sectors as it is,
+/// so that a chunk which can't be decoded can still be looked at.
+fn dump_chunk<W: Write>(
+    out: &mut W,
+    region: &RegionFile,
+    position: ChunkPos,
+    hex: bool,
+) -> Result<(), io::Error> {
+    let entry = region
+        .entries()?
+        .into_iter()
+        .find(|entry| entry.position == position);
+
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            writeln!(
+                out,
+                "{}",
+                Message::new(messages::DUMP_CHUNK_MISSING)
+                    .arg("x", position.x)
+                    .arg("z", position.z)
+                    .arg("index", entry_index(position))
+                    .arg("timestamp", region.timestamp(position)?)
+                    .text()
+            )?;
+
+            return Ok(());
+        }
+    };
+
+    writeln!(out, "{}", entry_message(region, &entry)?.text())?;
+
+    let (length, compression) = match region.read_chunk_header(entry) {
+        Ok(header) => header,
+        Err(err) => {
+            writeln!(
+                out,
+                "{}",
+                Message::new(messages::DUMP_CHUNK_UNREADABLE)
+                    .arg("error", messages::error_message(&err).text())
+                    .text()
+            )?;
+
+            return Ok(());
+        }
+    };
+
+    writeln!(
+        out,
+        "{}",
+        Message::new(messages::DUMP_CHUNK_HEADER)
+            .arg("length", length)
+            .arg("compression", compression)
+            .arg("compression_name", compression_name(compression))
+            .text()
+    )?;
+
+    let raw_chunk = match region.get_raw_chunk_from_entry(entry) {
+        Ok(raw_chunk) => raw_chunk,
+        Err(err) => {
+            writeln!(
+                out,
+                "{}",
+                Message::new(messages::DUMP_CHUNK_UNREADABLE)
+                    .arg("error", messages::error_message(&err).text())
+                    .text()
+            )?;
+
+            return Ok(());
+        }
+    };
+
+    writeln!(
+        out,
+        "{}",
+        Message::new(messages::DUMP_CHUNK_PAYLOAD)
+            .arg("bytes", raw_chunk.payload.len())
+            .text()
+    )?;
+
+    if hex {
+        write_hexdump(out, &raw_chunk.payload)?;
+    }
+
+    Ok(())
+}
+
+fn entry_message(region: &RegionFile, entry: &RegionEntry) -> Result<Message, io::Error> {
+    Ok(Message::new(messages::DUMP_ENTRY)
+        .arg("x", entry.position.x)
+        .arg("z", entry.position.z)
+        .arg("index", entry_index(entry.position))
+        .arg("first", entry.sector_index)
+        .arg("count", entry.sector_count)
+        .arg("timestamp", region.timestamp(entry.position)?))
+}
+
+fn entry_index(position: ChunkPos) -> i32 {
+    position.x + position.z * 32
+}
+
+/// Writes data as lines of 16 bytes, each starting with its offset and ending with the bytes which are printable.
+fn write_hexdump<W: Write>(out: &mut W, data: &[u8]) -> Result<(), io::Error> {
+    for (line, bytes) in data.chunks(16).enumerate() {
+        write!(out, "{:08x} ", line * 16)?;
+
+        for column in 0..16 {
+            // An extra space splits each line into two halves of eight bytes
+            if column == 8 {
+                write!(out, " ")?;
+            }
+
+            match bytes.get(column) {
+                Some(byte) => write!(out, " {:02x}", byte)?,
+                None => write!(out, "   ")?,
+            }
+        }
+
+        let text: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        writeln!(out, "  |{}|", text)?;
+    }
+
+    Ok(())
+}
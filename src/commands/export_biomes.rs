@@ -0,0 +1,393 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::blocks::decode_biome_sections;
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::RegionFile;
+
+/// The width and height of the grid of each region, in 4x4 biome cells.
+const REGION_CELLS: usize = 32 * 4;
+
+/// The colors of common biomes, by name without the `minecraft:` namespace. Any other biome is given a color derived
+/// from its name.
+const BIOME_COLORS: &[(&str, [u8; 3])] = &[
+    ("ocean", [0, 0, 112]),
+    ("deep_ocean", [0, 0, 48]),
+    ("warm_ocean", [0, 0, 172]),
+    ("lukewarm_ocean", [0, 0, 144]),
+    ("cold_ocean", [32, 32, 112]),
+    ("frozen_ocean", [112, 112, 214]),
+    ("river", [0, 0, 255]),
+    ("frozen_river", [160, 160, 255]),
+    ("beach", [250, 222, 85]),
+    ("snowy_beach", [250, 240, 192]),
+    ("stony_shore", [162, 162, 132]),
+    ("plains", [141, 179, 96]),
+    ("sunflower_plains", [181, 219, 136]),
+    ("snowy_plains", [255, 255, 255]),
+    ("ice_spikes", [180, 220, 220]),
+    ("desert", [250, 148, 24]),
+    ("swamp", [7, 249, 178]),
+    ("mangrove_swamp", [44, 204, 142]),
+    ("forest", [5, 102, 33]),
+    ("flower_forest", [45, 142, 73]),
+    ("birch_forest", [48, 116, 68]),
+    ("dark_forest", [64, 81, 26]),
+    ("old_growth_birch_forest", [88, 156, 108]),
+    ("old_growth_pine_taiga", [89, 102, 81]),
+    ("old_growth_spruce_taiga", [129, 142, 121]),
+    ("taiga", [11, 102, 89]),
+    ("snowy_taiga", [49, 85, 74]),
+    ("savanna", [189, 178, 95]),
+    ("savanna_plateau", [167, 157, 100]),
+    ("windswept_hills", [96, 96, 96]),
+    ("windswept_forest", [80, 112, 80]),
+    ("windswept_savanna", [229, 218, 135]),
+    ("jungle", [83, 123, 9]),
+    ("sparse_jungle", [98, 139, 23]),
+    ("bamboo_jungle", [118, 142, 20]),
+    ("badlands", [217, 69, 21]),
+    ("eroded_badlands", [255, 109, 61]),
+    ("wooded_badlands", [176, 151, 101]),
+    ("meadow", [96, 164, 69]),
+    ("cherry_grove", [255, 145, 200]),
+    ("grove", [71, 114, 108]),
+    ("snowy_slopes", [196, 196, 196]),
+    ("frozen_peaks", [160, 160, 160]),
+    ("jagged_peaks", [220, 220, 200]),
+    ("stony_peaks", [123, 143, 116]),
+    ("mushroom_fields", [255, 0, 255]),
+    ("dripstone_caves", [78, 48, 18]),
+    ("lush_caves", [40, 60, 0]),
+    ("deep_dark", [10, 20, 30]),
+    ("nether_wastes", [191, 59, 59]),
+    ("soul_sand_valley", [94, 56, 48]),
+    ("crimson_forest", [221, 8, 8]),
+    ("warped_forest", [73, 144, 123]),
+    ("basalt_deltas", [64, 54, 54]),
+    ("the_end", [128, 128, 255]),
+    ("end_highlands", [181, 181, 54]),
+    ("end_midlands", [255, 255, 160]),
+    ("small_end_islands", [96, 96, 223]),
+    ("end_barrens", [112, 112, 180]),
+    ("the_void", [0, 0, 0]),
+];
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to export")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where a file will be written for each region, named after its region file"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "png",
+        help = "Format of the biome map of each region"
+    )]
+    format: BiomeFormat,
+
+    #[clap(
+        long,
+        default_value = "64",
+        allow_hyphen_values = true,
+        help = "Y level to take the biomes from, since biomes can change with depth"
+    )]
+    y: i32,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BiomeFormat {
+    /// A 128x128 image with one pixel for each 4x4 column of blocks
+    Png,
+    /// An object with the palette of biomes in the region and a 128x128 grid of indices into it
+    Json,
+    /// 128 rows of 128 biome names, one for each 4x4 column of blocks
+    Csv,
+}
+
+/// The biomes of a region, as indices into its palette for each 4x4 cell in ZX order. Cells of chunks which are missing
+/// or have no biomes at the Y level are `None`.
+struct BiomeGrid {
+    palette: Vec<String>,
+    cells: Vec<Option<u16>>,
+}
+
+/// The number of chunks in a region which were exported, and which had no biomes at the Y level.
+#[derive(Default)]
+struct ExportCounts {
+    chunks: usize,
+    missing_chunks: usize,
+}
+
+pub fn export_biomes(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let exported = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| export_region(path, output_path, options))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let chunks: usize = exported.iter().map(|counts| counts.chunks).sum();
+    let missing_chunks: usize = exported.iter().map(|counts| counts.missing_chunks).sum();
+
+    if missing_chunks > 0 {
+        report!(
+            warn,
+            messages::EXPORT_BIOMES_MISSING,
+            missing_chunks = missing_chunks,
+            y = options.y
+        );
+    }
+
+    report!(
+        info,
+        messages::EXPORT_BIOMES_SUMMARY,
+        chunks = chunks,
+        regions = files.len(),
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+fn export_region(
+    path: &Path,
+    output_dir: &Path,
+    options: &Options,
+) -> Result<ExportCounts, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region_position = RegionFile::parse_name(&name);
+    let region = RegionFile::open(path)?;
+    let started = Instant::now();
+
+    let section_y = options.y.div_euclid(16);
+    let cell_y = (options.y.rem_euclid(16) / 4) as usize;
+
+    let mut grid = BiomeGrid {
+        palette: Vec::new(),
+        cells: vec![None; REGION_CELLS * REGION_CELLS],
+    };
+    let mut palette_indices: HashMap<String, u16> = HashMap::new();
+    let mut counts = ExportCounts::default();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !options
+            .filter
+            .includes_chunk(region_position, chunk.position)
+        {
+            continue;
+        }
+
+        let nbt = read_nbt(&chunk.data)?;
+        let sections = decode_biome_sections(&nbt).map_err(|err| {
+            Message::new(messages::EXPORT_BIOMES_DECODE_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        let section = match sections.into_iter().find(|section| section.y == section_y) {
+            Some(section) => section,
+            None => {
+                counts.missing_chunks += 1;
+                continue;
+            }
+        };
+
+        // Biomes are added to the region's palette as they are found, so each section's palette is mapped onto it
+        let indices: Vec<u16> = section
+            .palette
+            .into_iter()
+            .map(|biome| {
+                *palette_indices.entry(biome.clone()).or_insert_with(|| {
+                    grid.palette.push(biome);
+                    (grid.palette.len() - 1) as u16
+                })
+            })
+            .collect();
+
+        let (chunk_x, chunk_z) = (chunk.position.x as usize, chunk.position.z as usize);
+
+        for z in 0..4 {
+            for x in 0..4 {
+                let index = section.indices[(((cell_y * 4) + z) * 4) + x];
+                let cell = ((chunk_z * 4) + z) * REGION_CELLS + (chunk_x * 4) + x;
+
+                grid.cells[cell] = Some(indices[index as usize]);
+            }
+        }
+
+        counts.chunks += 1;
+    }
+
+    let extension = match options.format {
+        BiomeFormat::Png => "png",
+        BiomeFormat::Json => "json",
+        BiomeFormat::Csv => "csv",
+    };
+
+    let output_path = output_dir.join(Path::new(&name).with_extension(extension));
+    let writer = BufWriter::new(File::create(output_path)?);
+
+    match options.format {
+        BiomeFormat::Png => write_png(&grid, writer)?,
+        BiomeFormat::Json => write_json(&grid, writer)?,
+        BiomeFormat::Csv => write_csv(&grid, writer)?,
+    }
+
+    report!(
+        debug,
+        messages::EXPORT_BIOMES_REGION,
+        region = name,
+        chunks = counts.chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(counts)
+}
+
+/// Writes the grid as an image, leaving cells without biomes transparent.
+fn write_png<W>(grid: &BiomeGrid, writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let colors: Vec<[u8; 3]> = grid
+        .palette
+        .iter()
+        .map(|biome| biome_color(biome))
+        .collect();
+    let mut pixels = Vec::with_capacity(grid.cells.len() * 4);
+
+    for cell in &grid.cells {
+        match cell {
+            Some(index) => {
+                pixels.extend_from_slice(&colors[*index as usize]);
+                pixels.push(255);
+            }
+            None => pixels.extend_from_slice(&[0, 0, 0, 0]),
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, REGION_CELLS as u32, REGION_CELLS as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    Ok(())
+}
+
+/// Writes the grid as an object with its palette and rows of indices into it, where cells without biomes are null.
+fn write_json<W>(grid: &BiomeGrid, mut writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let rows: Vec<&[Option<u16>]> = grid.cells.chunks(REGION_CELLS).collect();
+    let object = serde_json::json!({
+        "palette": grid.palette,
+        "cells": rows,
+    });
+
+    writeln!(writer, "{}", object)?;
+    writer.flush()
+}
+
+/// Writes the grid as rows of biome names, where cells without biomes are empty.
+fn write_csv<W>(grid: &BiomeGrid, mut writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    for row in grid.cells.chunks(REGION_CELLS) {
+        let names: Vec<&str> = row
+            .iter()
+            .map(|cell| match cell {
+                Some(index) => grid.palette[*index as usize].as_str(),
+                None => "",
+            })
+            .collect();
+
+        writeln!(writer, "{}", names.join(","))?;
+    }
+
+    writer.flush()
+}
+
+fn biome_color(biome: &str) -> [u8; 3] {
+    let name = biome.strip_prefix("minecraft:").unwrap_or(biome);
+
+    match BIOME_COLORS.iter().find(|(known, _)| *known == name) {
+        Some((_, color)) => *color,
+        None => {
+            let hash = xxh64(name.as_bytes(), 0).to_le_bytes();
+            [hash[0], hash[1], hash[2]]
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::decode_heightmap;
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::RegionFile;
+
+/// The width and height of the image of each region, in pixels.
+const REGION_SIZE: usize = 32 * 16;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to export")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where a PNG will be written for each region, named after its region file"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long = "type",
+        value_enum,
+        default_value = "MOTION_BLOCKING",
+        help = "Heightmap to export"
+    )]
+    heightmap: HeightmapType,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+#[value(rename_all = "SCREAMING_SNAKE_CASE")]
+enum HeightmapType {
+    /// The highest block which blocks motion or contains a fluid
+    MotionBlocking,
+    /// As with MOTION_BLOCKING, but ignoring leaves
+    MotionBlockingNoLeaves,
+    /// The highest block which isn't air
+    WorldSurface,
+    /// The highest block which blocks motion, ignoring fluids
+    OceanFloor,
+}
+
+impl HeightmapType {
+    fn tag_name(self) -> &'static str {
+        match self {
+            HeightmapType::MotionBlocking => "MOTION_BLOCKING",
+            HeightmapType::MotionBlockingNoLeaves => "MOTION_BLOCKING_NO_LEAVES",
+            HeightmapType::WorldSurface => "WORLD_SURFACE",
+            HeightmapType::OceanFloor => "OCEAN_FLOOR",
+        }
+    }
+}
+
+/// The number of chunks in a region which were exported, and which had no heightmap of the requested type.
+#[derive(Default)]
+struct ExportCounts {
+    chunks: usize,
+    missing_chunks: usize,
+}
+
+pub fn export_heightmap(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let exported = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| export_region(path, output_path, options))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let chunks: usize = exported.iter().map(|counts| counts.chunks).sum();
+    let missing_chunks: usize = exported.iter().map(|counts| counts.missing_chunks).sum();
+
+    if missing_chunks > 0 {
+        report!(
+            warn,
+            messages::EXPORT_HEIGHTMAP_MISSING,
+            missing_chunks = missing_chunks,
+            heightmap = options.heightmap.tag_name()
+        );
+    }
+
+    report!(
+        info,
+        messages::EXPORT_HEIGHTMAP_SUMMARY,
+        chunks = chunks,
+        regions = files.len(),
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Writes the heightmap of every chunk in a region to a 16-bit grayscale PNG of the same name, where each pixel is the
+/// height of a column counted from the bottom of the world. Columns of chunks which are missing or have no heightmap
+/// are left at zero.
+fn export_region(
+    path: &Path,
+    output_dir: &Path,
+    options: &Options,
+) -> Result<ExportCounts, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region_position = RegionFile::parse_name(&name);
+    let region = RegionFile::open(path)?;
+    let started = Instant::now();
+
+    let mut heights = vec![0u16; REGION_SIZE * REGION_SIZE];
+    let mut counts = ExportCounts::default();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !options
+            .filter
+            .includes_chunk(region_position, chunk.position)
+        {
+            continue;
+        }
+
+        let nbt = read_nbt(&chunk.data)?;
+        let heightmap = decode_heightmap(&nbt, options.heightmap.tag_name()).map_err(|err| {
+            Message::new(messages::EXPORT_HEIGHTMAP_DECODE_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        let heightmap = match heightmap {
+            Some(heightmap) => heightmap,
+            None => {
+                counts.missing_chunks += 1;
+                continue;
+            }
+        };
+
+        let (chunk_x, chunk_z) = (chunk.position.x as usize, chunk.position.z as usize);
+
+        for (z, row) in heightmap.chunks(16).enumerate() {
+            let start = ((chunk_z * 16) + z) * REGION_SIZE + (chunk_x * 16);
+            heights[start..start + 16].copy_from_slice(row);
+        }
+
+        counts.chunks += 1;
+    }
+
+    let output_path = output_dir.join(Path::new(&name).with_extension("png"));
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(output_path)?),
+        REGION_SIZE as u32,
+        REGION_SIZE as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+
+    // 16-bit samples are stored in big-endian order
+    let pixels: Vec<u8> = heights
+        .iter()
+        .flat_map(|height| height.to_be_bytes())
+        .collect();
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    report!(
+        debug,
+        messages::EXPORT_HEIGHTMAP_REGION,
+        region = name,
+        chunks = counts.chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(counts)
+}
@@ -0,0 +1,322 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::blocks::{block_name, decode_sections};
+use crate::filter::parse_coordinate_range;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::RegionFile;
+
+/// The size of each cell of the PNG heatmap, in pixels.
+const CELL_WIDTH: u32 = 16;
+const CELL_HEIGHT: u32 = 4;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to analyze")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Range of Y levels to export, such as -64..0 (exclusive) or -64..=0 (inclusive)",
+        value_parser = parse_coordinate_range,
+        allow_hyphen_values = true
+    )]
+    y_range: RangeInclusive<i32>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "Format of the exported layers"
+    )]
+    format: LayerFormat,
+
+    #[clap(
+        long,
+        help = "Output file for the exported layers (defaults to stdout for CSV, and is required for PNG)"
+    )]
+    output: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only export these blocks, such as minecraft:diamond_ore (comma-separated)",
+        value_delimiter = ','
+    )]
+    blocks: Vec<String>,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LayerFormat {
+    /// One `y,block,count,fraction` row for each block in each layer
+    Csv,
+    /// A heatmap with a column for each block and a row for each layer, brightest where that block is most common
+    Png,
+}
+
+/// The number of blocks of each name found in a layer, along with the total number of blocks in that layer.
+#[derive(Default)]
+struct LayerCounts {
+    blocks: HashMap<String, u64>,
+    total: u64,
+}
+
+pub fn export_layers(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if let LayerFormat::Png = options.format {
+        if options.output.is_none() {
+            return Err(Message::new(messages::EXPORT_LAYERS_PNG_REQUIRES_OUTPUT)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
+    }
+
+    if let Some(output) = &options.output {
+        read_only::ensure_writable(Path::new(output))?;
+    }
+
+    let mut regions: Vec<PathBuf> = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            regions.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let counted = pool.install(|| {
+        regions
+            .into_par_iter()
+            .map(|path| count_region_layers(&path, &options.y_range))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let mut layers: BTreeMap<i32, LayerCounts> = BTreeMap::new();
+
+    for region_layers in counted {
+        for (y, counts) in region_layers {
+            let layer = layers.entry(y).or_default();
+            layer.total += counts.total;
+
+            for (block, count) in counts.blocks {
+                *layer.blocks.entry(block).or_default() += count;
+            }
+        }
+    }
+
+    if !options.blocks.is_empty() {
+        for layer in layers.values_mut() {
+            layer
+                .blocks
+                .retain(|block, _| options.blocks.contains(block));
+        }
+    }
+
+    match options.format {
+        LayerFormat::Csv => match &options.output {
+            Some(output) => write_csv(&layers, BufWriter::new(File::create(output)?)),
+            None => write_csv(&layers, io::stdout().lock()),
+        },
+        LayerFormat::Png => write_png(
+            &layers,
+            &options.blocks,
+            File::create(options.output.as_ref().unwrap())?,
+        ),
+    }
+}
+
+/// Counts the blocks in each layer of a region file which falls within the Y range, keyed by block name.
+fn count_region_layers(
+    path: &Path,
+    y_range: &RangeInclusive<i32>,
+) -> Result<BTreeMap<i32, LayerCounts>, io::Error> {
+    let region = RegionFile::open(path)?;
+    let mut layers: BTreeMap<i32, LayerCounts> = BTreeMap::new();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        let nbt = read_nbt(&chunk.data)?;
+        let sections = decode_sections(&nbt).map_err(|err| {
+            Message::new(messages::EXPORT_LAYERS_DECODE_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        for section in sections {
+            for (layer_y, palette_counts) in section.layer_palette_counts().into_iter().enumerate()
+            {
+                let y = (section.y * 16) + layer_y as i32;
+
+                if !y_range.contains(&y) {
+                    continue;
+                }
+
+                let layer = layers.entry(y).or_default();
+
+                for (entry, count) in section.palette.iter().zip(palette_counts) {
+                    if count > 0 {
+                        let name = block_name(entry).unwrap_or("minecraft:air");
+
+                        *layer.blocks.entry(String::from(name)).or_default() += count as u64;
+                        layer.total += count as u64;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(layers)
+}
+
+fn write_csv<W>(layers: &BTreeMap<i32, LayerCounts>, mut writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    writeln!(writer, "y,block,count,fraction")?;
+
+    for (y, layer) in layers {
+        let mut blocks: Vec<(&String, &u64)> = layer.blocks.iter().collect();
+        blocks.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        for (block, count) in blocks {
+            writeln!(
+                writer,
+                "{},{},{},{:.8}",
+                y,
+                block,
+                count,
+                *count as f64 / layer.total as f64
+            )?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Writes a grayscale heatmap with a column for each block and a row for each layer, with the highest layer at the
+/// top. Each column is scaled to the layer where that block is most common, so that the shape of its distribution is
+/// visible regardless of how rare it is overall.
+fn write_png<W>(
+    layers: &BTreeMap<i32, LayerCounts>,
+    blocks: &[String],
+    writer: W,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    // Without an explicit list of blocks, every block found is given a column, from the most to least common
+    let columns: Vec<String> = if blocks.is_empty() {
+        let mut totals: HashMap<&String, u64> = HashMap::new();
+
+        for layer in layers.values() {
+            for (block, count) in &layer.blocks {
+                *totals.entry(block).or_default() += count;
+            }
+        }
+
+        let mut totals: Vec<(&String, u64)> = totals.into_iter().collect();
+        totals.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        totals.into_iter().map(|(block, _)| block.clone()).collect()
+    } else {
+        blocks.to_vec()
+    };
+
+    if columns.is_empty() || layers.is_empty() {
+        return Err(
+            Message::new(messages::EXPORT_LAYERS_EMPTY).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    let fractions: Vec<Vec<f64>> = layers
+        .values()
+        .rev()
+        .map(|layer| {
+            columns
+                .iter()
+                .map(|block| match layer.blocks.get(block) {
+                    Some(count) if layer.total > 0 => *count as f64 / layer.total as f64,
+                    _ => 0.0,
+                })
+                .collect()
+        })
+        .collect();
+
+    let maximums: Vec<f64> = (0..columns.len())
+        .map(|column| fractions.iter().map(|row| row[column]).fold(0.0, f64::max))
+        .collect();
+
+    let width = columns.len() as u32 * CELL_WIDTH;
+    let height = fractions.len() as u32 * CELL_HEIGHT;
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+
+    for row in &fractions {
+        let mut line = Vec::with_capacity(width as usize);
+
+        for (fraction, maximum) in row.iter().zip(&maximums) {
+            let intensity = if *maximum > 0.0 {
+                ((fraction / maximum) * 255.0).round() as u8
+            } else {
+                0
+            };
+
+            line.resize(line.len() + CELL_WIDTH as usize, intensity);
+        }
+
+        for _ in 0..CELL_HEIGHT {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(BufWriter::new(writer), width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    let min_y = *layers.keys().next().unwrap();
+    let max_y = *layers.keys().next_back().unwrap();
+
+    report!(
+        info,
+        messages::EXPORT_LAYERS_WRITTEN,
+        layers = fractions.len(),
+        min_y = min_y,
+        max_y = max_y,
+        columns = columns.join(", ")
+    );
+
+    Ok(())
+}
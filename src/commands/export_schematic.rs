@@ -0,0 +1,688 @@
+use clap::{Parser, ValueEnum};
+use fastnbt::{ByteArray, IntArray, LongArray, Value};
+use flate2::write::GzEncoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::blocks::{block_state_string, data_version, decode_sections, pack_values};
+use crate::messages::{self, report, Message};
+use crate::nbt::{chunk_level, read_nbt, write_named_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+/// The largest size of a Sponge schematic on each axis, which is stored as an unsigned short.
+const SPONGE_MAX_SIZE: i32 = u16::MAX as i32;
+
+/// The versions of each format which are written.
+const SPONGE_VERSION: i32 = 2;
+const LITEMATIC_VERSION: i32 = 6;
+const LITEMATIC_SUB_VERSION: i32 = 1;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to export from")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Directory of entity region files, added in 1.17, whose entities within the selection are also exported"
+    )]
+    entities_dir: Option<String>,
+
+    #[clap(
+        long,
+        help = "Opposite corners of the selection in block coordinates, both inclusive, given as X1,Y1,Z1,X2,Y2,Z2",
+        value_parser = parse_bounds,
+        allow_hyphen_values = true
+    )]
+    bounds: Bounds,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "sponge",
+        help = "Format of the schematic"
+    )]
+    format: SchematicFormat,
+
+    #[clap(long, help = "Output path for the schematic")]
+    output: String,
+
+    #[clap(
+        long,
+        help = "Name recorded in the schematic's metadata (defaults to the name of the output file)"
+    )]
+    name: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SchematicFormat {
+    /// A Sponge schematic (.schem) of version 2, as read by WorldEdit and most other editors
+    Sponge,
+    /// A Litematica schematic (.litematic) with a single region
+    Litematic,
+}
+
+/// A box of blocks, with inclusive bounds on each axis.
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: [i32; 3],
+    max: [i32; 3],
+}
+
+impl Bounds {
+    fn size(&self) -> [i32; 3] {
+        [
+            self.max[0] - self.min[0] + 1,
+            self.max[1] - self.min[1] + 1,
+            self.max[2] - self.min[2] + 1,
+        ]
+    }
+
+    fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        (self.min[0]..=self.max[0]).contains(&x)
+            && (self.min[1]..=self.max[1]).contains(&y)
+            && (self.min[2]..=self.max[2]).contains(&z)
+    }
+}
+
+fn parse_bounds(value: &str) -> Result<Bounds, String> {
+    let coordinates = value
+        .split(',')
+        .map(|coordinate| {
+            coordinate
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid coordinate: {}", coordinate.trim()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if coordinates.len() != 6 {
+        return Err(format!(
+            "Expected bounds in the form X1,Y1,Z1,X2,Y2,Z2: {}",
+            value
+        ));
+    }
+
+    // The corners can be given in either order, as they would be picked in game
+    Ok(Bounds {
+        min: [
+            coordinates[0].min(coordinates[3]),
+            coordinates[1].min(coordinates[4]),
+            coordinates[2].min(coordinates[5]),
+        ],
+        max: [
+            coordinates[0].max(coordinates[3]),
+            coordinates[1].max(coordinates[4]),
+            coordinates[2].max(coordinates[5]),
+        ],
+    })
+}
+
+/// The blocks within the selection, as indices into a palette of the block states found in it, along with the block
+/// entities and entities within it at their absolute positions.
+struct Selection {
+    bounds: Bounds,
+    blocks: Vec<u16>,
+    palette: Vec<Value>,
+    palette_indices: HashMap<String, u16>,
+    block_entities: Vec<HashMap<String, Value>>,
+    entities: Vec<HashMap<String, Value>>,
+    data_version: i32,
+    chunks: usize,
+}
+
+impl Selection {
+    fn new(bounds: Bounds) -> Self {
+        let [width, height, length] = bounds.size();
+
+        let mut air = HashMap::new();
+        air.insert(
+            String::from("Name"),
+            Value::String(String::from("minecraft:air")),
+        );
+
+        // Blocks in chunks or sections which were never saved are air, which both formats expect first in the palette
+        let mut selection = Selection {
+            bounds,
+            blocks: vec![0; width as usize * height as usize * length as usize],
+            palette: Vec::new(),
+            palette_indices: HashMap::new(),
+            block_entities: Vec::new(),
+            entities: Vec::new(),
+            data_version: 0,
+            chunks: 0,
+        };
+        selection.intern(&Value::Compound(air)).unwrap();
+
+        selection
+    }
+
+    /// Returns the index of a block state in the selection's palette, adding it if it isn't there yet.
+    fn intern(&mut self, entry: &Value) -> Result<u16, String> {
+        let state = block_state_string(entry);
+
+        if let Some(&index) = self.palette_indices.get(&state) {
+            return Ok(index);
+        }
+
+        if self.palette.len() > u16::MAX as usize {
+            return Err(String::from("Selection has too many block states"));
+        }
+
+        let index = self.palette.len() as u16;
+        self.palette.push(entry.clone());
+        self.palette_indices.insert(state, index);
+
+        Ok(index)
+    }
+
+    /// Returns the index of a block within the selection in YZX order, which both formats use.
+    fn block_index(&self, x: i32, y: i32, z: i32) -> usize {
+        let [width, _, length] = self.bounds.size();
+        let [min_x, min_y, min_z] = self.bounds.min;
+
+        (((y - min_y) as usize * length as usize) + (z - min_z) as usize) * width as usize
+            + (x - min_x) as usize
+    }
+
+    /// Copies the blocks, block entities and entities of a chunk which lie within the selection.
+    fn add_chunk(&mut self, nbt: &Value, chunk: ChunkPos) -> Result<(), String> {
+        self.data_version = self.data_version.max(data_version(nbt));
+        self.chunks += 1;
+
+        let bounds = self.bounds;
+        let min_x = (chunk.x * 16).max(bounds.min[0]);
+        let max_x = ((chunk.x * 16) + 15).min(bounds.max[0]);
+        let min_z = (chunk.z * 16).max(bounds.min[2]);
+        let max_z = ((chunk.z * 16) + 15).min(bounds.max[2]);
+
+        for section in decode_sections(nbt)? {
+            let min_y = (section.y * 16).max(bounds.min[1]);
+            let max_y = ((section.y * 16) + 15).min(bounds.max[1]);
+
+            if min_y > max_y {
+                continue;
+            }
+
+            // Entries are only added to the selection's palette once a block within the selection uses them
+            let mut indices: Vec<Option<u16>> = vec![None; section.palette.len()];
+
+            for y in min_y..=max_y {
+                for z in min_z..=max_z {
+                    for x in min_x..=max_x {
+                        let local = ((((y - (section.y * 16)) * 16) + (z - (chunk.z * 16))) * 16)
+                            + (x - (chunk.x * 16));
+                        let entry = section.indices[local as usize] as usize;
+
+                        let block = match indices[entry] {
+                            Some(block) => block,
+                            None => {
+                                let block = self.intern(&section.palette[entry])?;
+                                indices[entry] = Some(block);
+                                block
+                            }
+                        };
+
+                        let index = self.block_index(x, y, z);
+                        self.blocks[index] = block;
+                    }
+                }
+            }
+        }
+
+        if let Some(level) = chunk_level(nbt) {
+            for key in &["block_entities", "TileEntities"] {
+                self.add_block_entities(level.get(*key));
+            }
+
+            // Entities were saved with their chunk before 1.17
+            self.add_entities(level.get("Entities"));
+        }
+
+        Ok(())
+    }
+
+    fn add_block_entities(&mut self, list: Option<&Value>) {
+        let list = match list {
+            Some(Value::List(list)) => list,
+            _ => return,
+        };
+
+        for block_entity in list {
+            if let Value::Compound(block_entity) = block_entity {
+                if let (Some(Value::Int(x)), Some(Value::Int(y)), Some(Value::Int(z))) = (
+                    block_entity.get("x"),
+                    block_entity.get("y"),
+                    block_entity.get("z"),
+                ) {
+                    if self.bounds.contains(*x, *y, *z) {
+                        self.block_entities.push(block_entity.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_entities(&mut self, list: Option<&Value>) {
+        let list = match list {
+            Some(Value::List(list)) => list,
+            _ => return,
+        };
+
+        for entity in list {
+            if let Value::Compound(entity) = entity {
+                if let Some([x, y, z]) = entity_position(entity) {
+                    if self
+                        .bounds
+                        .contains(x.floor() as i32, y.floor() as i32, z.floor() as i32)
+                    {
+                        self.entities.push(entity.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn export_schematic(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    let bounds = options.bounds;
+    let [width, height, length] = bounds.size();
+
+    if options.format == SchematicFormat::Sponge
+        && (width > SPONGE_MAX_SIZE || height > SPONGE_MAX_SIZE || length > SPONGE_MAX_SIZE)
+    {
+        return Err(Message::new(messages::EXPORT_SCHEMATIC_TOO_LARGE)
+            .arg("width", width)
+            .arg("height", height)
+            .arg("length", length)
+            .arg("max", SPONGE_MAX_SIZE)
+            .into_error(io::ErrorKind::InvalidInput));
+    }
+
+    let mut selection = Selection::new(bounds);
+
+    for ((region_x, region_z), chunks) in selected_chunks(&bounds) {
+        let path = input_path.join(format!("r.{}.{}.mca", region_x, region_z));
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let region = RegionFile::open(&path)?;
+
+        for (chunk, absolute) in chunks {
+            let chunk = match region.get_raw_chunk(chunk)? {
+                Some(raw_chunk) => raw_chunk.decompress()?,
+                None => continue,
+            };
+
+            let nbt = read_nbt(&chunk.data)?;
+
+            selection.add_chunk(&nbt, absolute).map_err(|reason| {
+                Message::new(messages::EXPORT_SCHEMATIC_DECODE_FAILED)
+                    .arg("x", absolute.x)
+                    .arg("z", absolute.z)
+                    .arg("path", path.display())
+                    .arg("reason", reason)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+        }
+    }
+
+    if selection.chunks == 0 {
+        report!(warn, messages::EXPORT_SCHEMATIC_NO_CHUNKS);
+    }
+
+    if let Some(entities_dir) = &options.entities_dir {
+        read_entity_files(Path::new(entities_dir), &mut selection)?;
+    }
+
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => output_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+
+    let (schematic, root_name) = match options.format {
+        SchematicFormat::Sponge => (sponge_schematic(&selection, &name), "Schematic"),
+        SchematicFormat::Litematic => (litematic_schematic(&selection, &name), ""),
+    };
+
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(output_path)?),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&write_named_nbt(&schematic, root_name)?)?;
+    encoder.finish()?.flush()?;
+
+    report!(
+        info,
+        messages::EXPORT_SCHEMATIC_SUMMARY,
+        width = width,
+        height = height,
+        length = length,
+        chunks = selection.chunks,
+        palette = selection.palette.len(),
+        block_entities = selection.block_entities.len(),
+        entities = selection.entities.len(),
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Groups the chunks which the selection covers by their region, giving each chunk's position within its region along
+/// with its absolute position.
+fn selected_chunks(bounds: &Bounds) -> BTreeMap<(i32, i32), Vec<(ChunkPos, ChunkPos)>> {
+    let mut regions: BTreeMap<(i32, i32), Vec<(ChunkPos, ChunkPos)>> = BTreeMap::new();
+
+    for z in bounds.min[2].div_euclid(16)..=bounds.max[2].div_euclid(16) {
+        for x in bounds.min[0].div_euclid(16)..=bounds.max[0].div_euclid(16) {
+            regions
+                .entry((x.div_euclid(32), z.div_euclid(32)))
+                .or_default()
+                .push((
+                    ChunkPos {
+                        x: x.rem_euclid(32),
+                        z: z.rem_euclid(32),
+                    },
+                    ChunkPos { x, z },
+                ));
+        }
+    }
+
+    regions
+}
+
+/// Adds the entities within the selection from the entity files saved since 1.17, which keep a list of entities at
+/// their root.
+fn read_entity_files(entities_path: &Path, selection: &mut Selection) -> Result<(), io::Error> {
+    if !Path::is_dir(entities_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", entities_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    for ((region_x, region_z), chunks) in selected_chunks(&selection.bounds) {
+        let path = entities_path.join(format!("r.{}.{}.mca", region_x, region_z));
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let region = RegionFile::open(&path)?;
+
+        for (chunk, _) in chunks {
+            let chunk = match region.get_raw_chunk(chunk)? {
+                Some(raw_chunk) => raw_chunk.decompress()?,
+                None => continue,
+            };
+
+            if let Value::Compound(root) = read_nbt(&chunk.data)? {
+                selection.add_entities(root.get("Entities"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a Sponge schematic of version 2, whose palette maps block states to indices, and whose blocks are written as
+/// varints. Block entities and entities are positioned relative to the schematic's offset, which is its minimum corner.
+fn sponge_schematic(selection: &Selection, name: &str) -> Value {
+    let bounds = &selection.bounds;
+    let [width, height, length] = bounds.size();
+
+    let mut palette = HashMap::new();
+
+    for (index, entry) in selection.palette.iter().enumerate() {
+        palette.insert(block_state_string(entry), Value::Int(index as i32));
+    }
+
+    let mut block_data = Vec::with_capacity(selection.blocks.len());
+
+    for &block in &selection.blocks {
+        let mut value = block as u32;
+
+        loop {
+            if value < 0x80 {
+                block_data.push(value as i8);
+                break;
+            }
+
+            block_data.push(((value & 0x7f) | 0x80) as u8 as i8);
+            value >>= 7;
+        }
+    }
+
+    let block_entities = selection
+        .block_entities
+        .iter()
+        .map(|block_entity| {
+            let mut block_entity = block_entity.clone();
+            let position = relative_block_position(&mut block_entity, bounds);
+
+            block_entity.remove("keepPacked");
+            block_entity.insert(
+                String::from("Pos"),
+                Value::IntArray(IntArray::new(position.to_vec())),
+            );
+
+            if let Some(id) = block_entity.remove("id") {
+                block_entity.insert(String::from("Id"), id);
+            }
+
+            Value::Compound(block_entity)
+        })
+        .collect();
+
+    let entities = selection
+        .entities
+        .iter()
+        .map(|entity| {
+            let mut entity = entity.clone();
+            relative_entity_position(&mut entity, bounds);
+
+            if let Some(id) = entity.remove("id") {
+                entity.insert(String::from("Id"), id);
+            }
+
+            Value::Compound(entity)
+        })
+        .collect();
+
+    let mut metadata = HashMap::new();
+    metadata.insert(String::from("Name"), Value::String(String::from(name)));
+    metadata.insert(String::from("Date"), Value::Long(now_millis()));
+
+    let mut schematic = HashMap::new();
+    schematic.insert(String::from("Version"), Value::Int(SPONGE_VERSION));
+    schematic.insert(
+        String::from("DataVersion"),
+        Value::Int(selection.data_version),
+    );
+    schematic.insert(String::from("Metadata"), Value::Compound(metadata));
+
+    // Sizes are unsigned shorts, which NBT only has signed shorts to store
+    schematic.insert(String::from("Width"), Value::Short(width as u16 as i16));
+    schematic.insert(String::from("Height"), Value::Short(height as u16 as i16));
+    schematic.insert(String::from("Length"), Value::Short(length as u16 as i16));
+    schematic.insert(
+        String::from("Offset"),
+        Value::IntArray(IntArray::new(bounds.min.to_vec())),
+    );
+    schematic.insert(
+        String::from("PaletteMax"),
+        Value::Int(selection.palette.len() as i32),
+    );
+    schematic.insert(String::from("Palette"), Value::Compound(palette));
+    schematic.insert(
+        String::from("BlockData"),
+        Value::ByteArray(ByteArray::new(block_data)),
+    );
+    schematic.insert(String::from("BlockEntities"), Value::List(block_entities));
+    schematic.insert(String::from("Entities"), Value::List(entities));
+
+    Value::Compound(schematic)
+}
+
+/// Builds a Litematica schematic with a single region covering the selection, whose blocks are packed tightly into
+/// longs of at least two bits each, spanning the boundaries between longs. Block entities and entities are positioned
+/// relative to the region.
+fn litematic_schematic(selection: &Selection, name: &str) -> Value {
+    let bounds = &selection.bounds;
+    let [width, height, length] = bounds.size();
+
+    let bits = (usize::BITS - (selection.palette.len() - 1).leading_zeros()).max(2) as usize;
+    let block_states = pack_values(&selection.blocks, bits, true);
+
+    let block_entities = selection
+        .block_entities
+        .iter()
+        .map(|block_entity| {
+            let mut block_entity = block_entity.clone();
+            let [x, y, z] = relative_block_position(&mut block_entity, bounds);
+
+            block_entity.insert(String::from("x"), Value::Int(x));
+            block_entity.insert(String::from("y"), Value::Int(y));
+            block_entity.insert(String::from("z"), Value::Int(z));
+
+            Value::Compound(block_entity)
+        })
+        .collect();
+
+    let entities = selection
+        .entities
+        .iter()
+        .map(|entity| {
+            let mut entity = entity.clone();
+            relative_entity_position(&mut entity, bounds);
+
+            Value::Compound(entity)
+        })
+        .collect();
+
+    let total_blocks = selection.blocks.iter().filter(|&&block| block != 0).count();
+    let now = now_millis();
+
+    let mut region = HashMap::new();
+    region.insert(String::from("Position"), vector(0, 0, 0));
+    region.insert(String::from("Size"), vector(width, height, length));
+    region.insert(
+        String::from("BlockStatePalette"),
+        Value::List(selection.palette.clone()),
+    );
+    region.insert(
+        String::from("BlockStates"),
+        Value::LongArray(LongArray::new(block_states)),
+    );
+    region.insert(String::from("TileEntities"), Value::List(block_entities));
+    region.insert(String::from("Entities"), Value::List(entities));
+    region.insert(String::from("PendingBlockTicks"), Value::List(Vec::new()));
+    region.insert(String::from("PendingFluidTicks"), Value::List(Vec::new()));
+
+    let mut regions = HashMap::new();
+    regions.insert(String::from(name), Value::Compound(region));
+
+    let mut metadata = HashMap::new();
+    metadata.insert(String::from("Name"), Value::String(String::from(name)));
+    metadata.insert(String::from("Author"), Value::String(String::new()));
+    metadata.insert(String::from("Description"), Value::String(String::new()));
+    metadata.insert(String::from("RegionCount"), Value::Int(1));
+    metadata.insert(
+        String::from("TotalVolume"),
+        Value::Int(selection.blocks.len() as i32),
+    );
+    metadata.insert(String::from("TotalBlocks"), Value::Int(total_blocks as i32));
+    metadata.insert(String::from("TimeCreated"), Value::Long(now));
+    metadata.insert(String::from("TimeModified"), Value::Long(now));
+    metadata.insert(String::from("EnclosingSize"), vector(width, height, length));
+
+    let mut schematic = HashMap::new();
+    schematic.insert(String::from("Version"), Value::Int(LITEMATIC_VERSION));
+    schematic.insert(
+        String::from("SubVersion"),
+        Value::Int(LITEMATIC_SUB_VERSION),
+    );
+    schematic.insert(
+        String::from("MinecraftDataVersion"),
+        Value::Int(selection.data_version),
+    );
+    schematic.insert(String::from("Metadata"), Value::Compound(metadata));
+    schematic.insert(String::from("Regions"), Value::Compound(regions));
+
+    Value::Compound(schematic)
+}
+
+/// Removes the absolute position of a block entity, returning its position relative to the selection.
+fn relative_block_position(block_entity: &mut HashMap<String, Value>, bounds: &Bounds) -> [i32; 3] {
+    let mut position = [0; 3];
+
+    for ((axis, min), relative) in ["x", "y", "z"].iter().zip(&bounds.min).zip(&mut position) {
+        if let Some(Value::Int(value)) = block_entity.remove(*axis) {
+            *relative = value - min;
+        }
+    }
+
+    position
+}
+
+/// Moves an entity's position from absolute coordinates to coordinates relative to the selection.
+fn relative_entity_position(entity: &mut HashMap<String, Value>, bounds: &Bounds) {
+    if let Some([x, y, z]) = entity_position(entity) {
+        entity.insert(
+            String::from("Pos"),
+            Value::List(vec![
+                Value::Double(x - bounds.min[0] as f64),
+                Value::Double(y - bounds.min[1] as f64),
+                Value::Double(z - bounds.min[2] as f64),
+            ]),
+        );
+    }
+}
+
+/// Returns an entity's position from the list of doubles in its `Pos` tag.
+fn entity_position(entity: &HashMap<String, Value>) -> Option<[f64; 3]> {
+    match entity.get("Pos") {
+        Some(Value::List(position)) => match position.as_slice() {
+            [Value::Double(x), Value::Double(y), Value::Double(z)] => Some([*x, *y, *z]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn vector(x: i32, y: i32, z: i32) -> Value {
+    let mut vector = HashMap::new();
+    vector.insert(String::from("x"), Value::Int(x));
+    vector.insert(String::from("y"), Value::Int(y));
+    vector.insert(String::from("z"), Value::Int(z));
+
+    Value::Compound(vector)
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
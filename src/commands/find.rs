@@ -0,0 +1,441 @@
+use clap::{ArgGroup, Parser, ValueEnum};
+use fastnbt::Value;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::block_index::BlockIndex;
+use crate::blocks::{block_state_matches, block_state_string, decode_sections, qualify};
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message, MessageId};
+use crate::nbt::{chunk_level, read_nbt};
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+#[clap(group(ArgGroup::new("targets").required(true).multiple(true).args(&["block", "entity", "block_entity"])))]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Input directory of region (.mca) files to search, or of entity files when searching for entities saved since 1.17"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Block to find, either by name (minecraft:spawner) or exact state (minecraft:chest[facing=north,...]) (may be repeated)"
+    )]
+    block: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Entity to find by its ID, such as minecraft:villager (may be repeated)"
+    )]
+    entity: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Block entity to find by its ID, such as minecraft:chest (may be repeated)"
+    )]
+    block_entity: Vec<String>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the matches written to stdout",
+        default_value = "text"
+    )]
+    format: FindFormat,
+
+    #[clap(
+        long,
+        help = "Block index used to skip the chunks of unchanged regions which hold none of the blocks being searched for"
+    )]
+    index: Option<String>,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum FindFormat {
+    /// One human-readable line per match
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum MatchKind {
+    Block,
+    Entity,
+    BlockEntity,
+}
+
+impl MatchKind {
+    fn name(self) -> &'static str {
+        match self {
+            MatchKind::Block => "block",
+            MatchKind::Entity => "entity",
+            MatchKind::BlockEntity => "block_entity",
+        }
+    }
+
+    fn message(self) -> MessageId {
+        match self {
+            MatchKind::Block => messages::FIND_BLOCK,
+            MatchKind::Entity => messages::FIND_ENTITY,
+            MatchKind::BlockEntity => messages::FIND_BLOCK_ENTITY,
+        }
+    }
+}
+
+/// Something found in a chunk, at world coordinates. Entities can be anywhere within a block, so their coordinates
+/// aren't rounded.
+struct Match {
+    kind: MatchKind,
+    id: String,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+/// The IDs being searched for, with the `minecraft:` namespace added to any which didn't give one.
+struct Queries {
+    blocks: Vec<String>,
+    entities: Vec<String>,
+    block_entities: Vec<String>,
+}
+
+/// A block index, along with the ids of its states which match any of the blocks being searched for.
+struct IndexedBlocks {
+    index: BlockIndex,
+    states: Vec<u32>,
+}
+
+pub fn find(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let queries = Queries {
+        blocks: options.block.iter().map(|id| qualify(id)).collect(),
+        entities: options.entity.iter().map(|id| qualify(id)).collect(),
+        block_entities: options.block_entity.iter().map(|id| qualify(id)).collect(),
+    };
+
+    let indexed = match &options.index {
+        Some(index) => {
+            let index_path = Path::new(index);
+
+            if !Path::exists(index_path) {
+                return Err(Message::new(messages::INDEX_FILE_MISSING)
+                    .arg("path", index_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            let index = BlockIndex::load(index_path)?;
+            let states = queries
+                .blocks
+                .iter()
+                .flat_map(|query| index.matching_states(query))
+                .collect();
+
+            Some(IndexedBlocks { index, states })
+        }
+        None => None,
+    };
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    // Matches are printed in a stable order, regardless of which thread found them
+    files.sort();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let results = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| search_region(path, &queries, indexed.as_ref(), &options.filter))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut matches = 0;
+    let mut chunks = 0;
+
+    for (region_matches, region_chunks) in results {
+        chunks += region_chunks;
+
+        for found in region_matches {
+            print_match(&mut out, &found, options.format)?;
+            matches += 1;
+        }
+    }
+
+    out.flush()?;
+
+    report!(
+        info,
+        messages::FIND_SUMMARY,
+        matches = matches,
+        chunks = chunks,
+        regions = files.len()
+    );
+
+    Ok(())
+}
+
+fn print_match<W>(out: &mut W, found: &Match, format: FindFormat) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    match format {
+        FindFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(found.kind.message())
+                .arg("id", &found.id)
+                .arg("x", found.x)
+                .arg("y", found.y)
+                .arg("z", found.z)
+        ),
+        FindFormat::Json => {
+            // Only entities have fractional coordinates, so blocks are written as integers
+            let coordinate = |value: f64| match found.kind {
+                MatchKind::Entity => serde_json::json!(value),
+                _ => serde_json::json!(value as i64),
+            };
+
+            let object = serde_json::json!({
+                "kind": found.kind.name(),
+                "id": found.id,
+                "x": coordinate(found.x),
+                "y": coordinate(found.y),
+                "z": coordinate(found.z),
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+/// Searches every chunk of a region file, returning the matches and the number of chunks searched. If the region hasn't
+/// changed since the index was built, chunks which the index shows to hold none of the blocks aren't searched for them.
+fn search_region(
+    path: &Path,
+    queries: &Queries,
+    indexed: Option<&IndexedBlocks>,
+    filter: &FilterOptions,
+) -> Result<(Vec<Match>, usize), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let region_index = match indexed {
+        Some(indexed) => indexed
+            .index
+            .current_region(path)?
+            .map(|region_index| (region_index, &indexed.states)),
+        None => None,
+    };
+
+    let mut matches = Vec::new();
+    let mut chunks = 0;
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !filter.includes_chunk(region_position, chunk.position) {
+            continue;
+        }
+
+        let position = ChunkPos {
+            x: (region_position.x * 32) + chunk.position.x,
+            z: (region_position.z * 32) + chunk.position.z,
+        };
+
+        let has_blocks = match region_index {
+            Some((region_index, states)) => region_index
+                .chunk(position)
+                .is_none_or(|counts| counts.count_of(states) > 0),
+            None => true,
+        };
+
+        // Chunks without any of the blocks don't need to be decoded at all unless something else is being searched for
+        if !has_blocks && queries.entities.is_empty() && queries.block_entities.is_empty() {
+            chunks += 1;
+            continue;
+        }
+
+        let nbt = read_nbt(&chunk.data)?;
+
+        if !queries.blocks.is_empty() && has_blocks {
+            find_blocks(&nbt, position, &queries.blocks, &mut matches).map_err(|err| {
+                Message::new(messages::FIND_DECODE_FAILED)
+                    .arg("x", chunk.position.x)
+                    .arg("z", chunk.position.z)
+                    .arg("path", path.display())
+                    .arg("reason", err)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+        }
+
+        if !queries.entities.is_empty() {
+            find_entities(&nbt, &queries.entities, &mut matches);
+        }
+
+        if !queries.block_entities.is_empty() {
+            find_block_entities(&nbt, &queries.block_entities, &mut matches);
+        }
+
+        chunks += 1;
+    }
+
+    Ok((matches, chunks))
+}
+
+fn find_blocks(
+    nbt: &Value,
+    position: ChunkPos,
+    queries: &[String],
+    matches: &mut Vec<Match>,
+) -> Result<(), String> {
+    for section in decode_sections(nbt)? {
+        // Most palettes contain none of the blocks being searched for, so their sections can be skipped entirely
+        let found: Vec<Option<String>> = section
+            .palette
+            .iter()
+            .map(|entry| {
+                let state = block_state_string(entry);
+
+                if queries
+                    .iter()
+                    .any(|query| block_state_matches(&state, query))
+                {
+                    Some(state)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        if found.iter().all(Option::is_none) {
+            continue;
+        }
+
+        for (index, &palette_index) in section.indices.iter().enumerate() {
+            if let Some(state) = &found[palette_index as usize] {
+                matches.push(Match {
+                    kind: MatchKind::Block,
+                    id: state.clone(),
+                    x: ((position.x * 16) + (index % 16) as i32) as f64,
+                    y: ((section.y * 16) + (index / 256) as i32) as f64,
+                    z: ((position.z * 16) + ((index / 16) % 16) as i32) as f64,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds entities in a chunk, which are stored in the chunk itself before 1.17 and in a separate entity file since.
+/// Both keep them in an `Entities` list, with the position of each entity in its `Pos` tag.
+fn find_entities(nbt: &Value, queries: &[String], matches: &mut Vec<Match>) {
+    let entities = match chunk_level(nbt).and_then(|level| level.get("Entities")) {
+        Some(Value::List(entities)) => entities,
+        _ => return,
+    };
+
+    for entity in entities {
+        let entity = match entity {
+            Value::Compound(entity) => entity,
+            _ => continue,
+        };
+
+        let id = match entity.get("id") {
+            Some(Value::String(id)) if queries.contains(id) => id,
+            _ => continue,
+        };
+
+        if let Some(Value::List(pos)) = entity.get("Pos") {
+            if let [Value::Double(x), Value::Double(y), Value::Double(z)] = pos[..] {
+                matches.push(Match {
+                    kind: MatchKind::Entity,
+                    id: id.clone(),
+                    x,
+                    y,
+                    z,
+                });
+            }
+        }
+    }
+}
+
+/// Finds block entities in a chunk, which 1.18 moved from `TileEntities` to `block_entities`.
+fn find_block_entities(nbt: &Value, queries: &[String], matches: &mut Vec<Match>) {
+    let block_entities = match chunk_level(nbt).and_then(|level| {
+        level
+            .get("block_entities")
+            .or_else(|| level.get("TileEntities"))
+    }) {
+        Some(Value::List(block_entities)) => block_entities,
+        _ => return,
+    };
+
+    for block_entity in block_entities {
+        let block_entity = match block_entity {
+            Value::Compound(block_entity) => block_entity,
+            _ => continue,
+        };
+
+        let id = match block_entity.get("id") {
+            Some(Value::String(id)) if queries.contains(id) => id,
+            _ => continue,
+        };
+
+        let coordinate = |name: &str| match block_entity.get(name) {
+            Some(Value::Int(value)) => Some(*value as f64),
+            _ => None,
+        };
+
+        if let (Some(x), Some(y), Some(z)) = (coordinate("x"), coordinate("y"), coordinate("z")) {
+            matches.push(Match {
+                kind: MatchKind::BlockEntity,
+                id: id.clone(),
+                x,
+                y,
+                z,
+            });
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use clap::Parser;
+use fastnbt::Value;
+use flate2::read::GzDecoder;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::blocks::{add_missing_sections, is_air, parse_block_state, set_blocks};
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile, RegionFileWriter};
+use crate::stripping::{force_relight, replace_heightmaps};
+use crate::temp;
+
+/// The highest section of worlds before 1.18, which are 256 blocks tall.
+const LEGACY_MAX_SECTION: i32 = 15;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Directory of region (.mca) files to paste into, which are rewritten in place"
+    )]
+    input_dir: String,
+
+    #[clap(long, help = "Sponge schematic (.schem) to paste")]
+    schematic: String,
+
+    #[clap(
+        long,
+        help = "Block coordinates of the schematic's minimum corner in the world, given as X,Y,Z",
+        value_parser = parse_block_position,
+        allow_hyphen_values = true
+    )]
+    at: [i32; 3],
+
+    #[clap(
+        long,
+        help = "Keep the world's blocks where the schematic has air, rather than replacing them with air"
+    )]
+    skip_air: bool,
+}
+
+fn parse_block_position(value: &str) -> Result<[i32; 3], String> {
+    let coordinates = value
+        .split(',')
+        .map(|coordinate| {
+            coordinate
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid coordinate: {}", coordinate.trim()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    match coordinates.as_slice() {
+        &[x, y, z] => Ok([x, y, z]),
+        _ => Err(format!("Expected a position in the form X,Y,Z: {}", value)),
+    }
+}
+
+/// The blocks of a schematic as indices into its palette in YZX order, along with its block entities at positions
+/// relative to its minimum corner.
+struct Schematic {
+    size: [i32; 3],
+    palette: Vec<Value>,
+    blocks: Vec<usize>,
+    block_entities: Vec<([i32; 3], HashMap<String, Value>)>,
+}
+
+impl Schematic {
+    fn block(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+        let [width, height, length] = self.size;
+
+        if !(0..width).contains(&x) || !(0..height).contains(&y) || !(0..length).contains(&z) {
+            return None;
+        }
+
+        Some(self.blocks[(((y * length) + z) * width + x) as usize])
+    }
+}
+
+/// Counts the chunks and blocks which were pasted into, and the chunks of the schematic's area which don't exist.
+#[derive(Default)]
+struct ImportReport {
+    chunks: u64,
+    missing_chunks: u64,
+    blocks: u64,
+    block_entities: u64,
+}
+
+pub fn import_schematic(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let schematic_path = Path::new(&options.schematic);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(input_path)?;
+
+    let schematic = read_schematic(schematic_path).map_err(|reason| {
+        Message::new(messages::IMPORT_SCHEMATIC_INVALID)
+            .arg("path", schematic_path.display())
+            .arg("reason", reason)
+            .into_error(io::ErrorKind::InvalidData)
+    })?;
+
+    let at = options.at;
+    let [width, _, length] = schematic.size;
+
+    // Chunks are grouped by region, so that each region is rewritten once
+    let mut regions: BTreeMap<(i32, i32), HashSet<ChunkPos>> = BTreeMap::new();
+
+    for z in at[2].div_euclid(16)..=(at[2] + length - 1).div_euclid(16) {
+        for x in at[0].div_euclid(16)..=(at[0] + width - 1).div_euclid(16) {
+            regions
+                .entry((x.div_euclid(32), z.div_euclid(32)))
+                .or_default()
+                .insert(ChunkPos {
+                    x: x.rem_euclid(32),
+                    z: z.rem_euclid(32),
+                });
+        }
+    }
+
+    let mut report = ImportReport::default();
+
+    for (&(x, z), chunks) in &regions {
+        let name = format!("r.{}.{}.mca", x, z);
+        let path = input_path.join(&name);
+
+        if !path.is_file() {
+            report.missing_chunks += chunks.len() as u64;
+            continue;
+        }
+
+        let pasted = paste_region(
+            &path,
+            ChunkPos { x, z },
+            chunks,
+            &schematic,
+            options,
+            &mut report,
+        )?;
+
+        report!(
+            debug,
+            messages::IMPORT_SCHEMATIC_REGION,
+            region = name,
+            chunks = pasted
+        );
+    }
+
+    if report.missing_chunks > 0 {
+        report!(
+            warn,
+            messages::IMPORT_SCHEMATIC_MISSING,
+            missing_chunks = report.missing_chunks
+        );
+    }
+
+    report!(
+        info,
+        messages::IMPORT_SCHEMATIC_SUMMARY,
+        blocks = report.blocks,
+        block_entities = report.block_entities,
+        chunks = report.chunks,
+        x = at[0],
+        y = at[1],
+        z = at[2]
+    );
+
+    Ok(())
+}
+
+/// Pastes the schematic into the given chunks of a region, returning the number of chunks which changed. The new region
+/// is staged in the run's temporary directory and then moved over the old one.
+fn paste_region(
+    path: &Path,
+    region_position: ChunkPos,
+    chunks: &HashSet<ChunkPos>,
+    schematic: &Schematic,
+    options: &Options,
+    report: &mut ImportReport,
+) -> Result<u64, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region = RegionFile::open(path)?;
+    let temp_path = temp::path(&name)?;
+    let mut pasted_chunks = 0;
+    let mut found_chunks = 0;
+
+    {
+        let mut writer = RegionFileWriter::create(&temp_path)?;
+
+        for result in region.stream_raw_chunks() {
+            let raw_chunk = match result? {
+                Some(raw_chunk) => raw_chunk,
+                None => continue,
+            };
+
+            if !chunks.contains(&raw_chunk.position) {
+                writer.add_raw_chunk(&raw_chunk)?;
+                continue;
+            }
+
+            found_chunks += 1;
+
+            let chunk = raw_chunk.decompress()?;
+            let mut nbt = read_nbt(&chunk.data)?;
+            let absolute = ChunkPos {
+                x: (region_position.x * 32) + chunk.position.x,
+                z: (region_position.z * 32) + chunk.position.z,
+            };
+
+            let pasted =
+                paste_chunk(&mut nbt, absolute, schematic, options, report).map_err(|reason| {
+                    Message::new(messages::IMPORT_SCHEMATIC_INVALID_CHUNK)
+                        .arg("region", &name)
+                        .arg("x", chunk.position.x)
+                        .arg("z", chunk.position.z)
+                        .arg("reason", reason)
+                        .into_error(io::ErrorKind::InvalidData)
+                })?;
+
+            if !pasted {
+                writer.add_raw_chunk(&raw_chunk)?;
+                continue;
+            }
+
+            writer.add_chunk(&chunk.with_data(write_nbt(&nbt)?.into_boxed_slice()))?;
+            pasted_chunks += 1;
+        }
+
+        writer.finish()?;
+    }
+
+    drop(region);
+
+    if pasted_chunks > 0 {
+        temp::replace(&temp_path, path)?;
+    } else {
+        fs::remove_file(&temp_path)?;
+    }
+
+    report.chunks += pasted_chunks;
+    report.missing_chunks += (chunks.len() - found_chunks) as u64;
+
+    Ok(pasted_chunks)
+}
+
+/// Pastes the part of the schematic which falls within a chunk, replacing the block entities of the blocks it replaces
+/// with the schematic's own. The chunk's heightmaps are recomputed and it is marked to be relit, since both depend on
+/// its blocks. Returns true if any block was set.
+fn paste_chunk(
+    nbt: &mut Value,
+    chunk: ChunkPos,
+    schematic: &Schematic,
+    options: &Options,
+    report: &mut ImportReport,
+) -> Result<bool, String> {
+    let at = options.at;
+    let (chunk_x, chunk_z) = (chunk.x * 16, chunk.z * 16);
+
+    let block_at = |x: i32, y: i32, z: i32| {
+        let block = schematic.block(x - at[0], y - at[1], z - at[2])?;
+
+        if options.skip_air && is_air(&schematic.palette[block]) {
+            return None;
+        }
+
+        Some(block)
+    };
+
+    // Chunks from before 1.18 leave out sections of nothing but air, which the schematic may need to fill
+    let min_section = at[1].div_euclid(16).max(0);
+    let max_section = (at[1] + schematic.size[1] - 1)
+        .div_euclid(16)
+        .min(LEGACY_MAX_SECTION);
+    add_missing_sections(nbt, min_section..=max_section);
+
+    let blocks = set_blocks(nbt, &schematic.palette, |x, y, z| {
+        block_at(chunk_x + x, y, chunk_z + z)
+    })?;
+
+    if blocks == 0 {
+        return Ok(false);
+    }
+
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return Err(String::from("Chunk is not a compound")),
+    };
+
+    let (container, key) = if root.contains_key("Level") {
+        match root.get_mut("Level") {
+            Some(Value::Compound(level)) => (level, "TileEntities"),
+            _ => return Err(String::from("Chunk's Level is not a compound")),
+        }
+    } else {
+        (root, "block_entities")
+    };
+
+    let block_entities = match container
+        .entry(String::from(key))
+        .or_insert_with(|| Value::List(Vec::new()))
+    {
+        Value::List(block_entities) => block_entities,
+        _ => return Err(format!("{} is not a list", key)),
+    };
+
+    block_entities.retain(|block_entity| match block_entity {
+        Value::Compound(block_entity) => {
+            match (
+                block_entity.get("x"),
+                block_entity.get("y"),
+                block_entity.get("z"),
+            ) {
+                (Some(Value::Int(x)), Some(Value::Int(y)), Some(Value::Int(z))) => {
+                    block_at(*x, *y, *z).is_none()
+                }
+                _ => true,
+            }
+        }
+        _ => true,
+    });
+
+    for ([x, y, z], block_entity) in &schematic.block_entities {
+        let (x, y, z) = (at[0] + x, at[1] + y, at[2] + z);
+
+        if x.div_euclid(16) != chunk.x || z.div_euclid(16) != chunk.z || block_at(x, y, z).is_none()
+        {
+            continue;
+        }
+
+        let mut block_entity = block_entity.clone();
+        block_entity.insert(String::from("x"), Value::Int(x));
+        block_entity.insert(String::from("y"), Value::Int(y));
+        block_entity.insert(String::from("z"), Value::Int(z));
+
+        block_entities.push(Value::Compound(block_entity));
+        report.block_entities += 1;
+    }
+
+    replace_heightmaps(nbt)?;
+    force_relight(nbt);
+
+    report.blocks += blocks;
+
+    Ok(true)
+}
+
+/// Reads a Sponge schematic of any version. Versions 1 and 2 keep the palette and blocks at the root, which is named
+/// `Schematic`, while version 3 moves the root into a compound of that name and the blocks into a `Blocks` compound,
+/// and nests the data of each block entity in a `Data` compound.
+fn read_schematic(path: &Path) -> Result<Schematic, String> {
+    let compressed = fs::read(path).map_err(|err| err.to_string())?;
+
+    // Schematics are almost always compressed, but nothing requires it
+    let data = if compressed.starts_with(&[0x1f, 0x8b]) {
+        let mut data = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut data)
+            .map_err(|err| err.to_string())?;
+        data
+    } else {
+        compressed
+    };
+
+    let root = match read_nbt(&data).map_err(|err| err.to_string())? {
+        Value::Compound(root) => root,
+        _ => return Err(String::from("Schematic is not a compound")),
+    };
+
+    let root = match root.get("Schematic") {
+        Some(Value::Compound(schematic)) => schematic,
+        _ => &root,
+    };
+
+    let version = match root.get("Version") {
+        Some(Value::Int(version)) => *version,
+        _ => return Err(String::from("Schematic has no version")),
+    };
+
+    let blocks = if version >= 3 {
+        match root.get("Blocks") {
+            Some(Value::Compound(blocks)) => blocks,
+            _ => return Err(String::from("Schematic has no blocks")),
+        }
+    } else {
+        root
+    };
+
+    let mut size = [0; 3];
+
+    for (size, axis) in size.iter_mut().zip(&["Width", "Height", "Length"]) {
+        *size = match root.get(*axis) {
+            Some(Value::Short(value)) => *value as u16 as i32,
+            _ => return Err(format!("Schematic has no {}", axis)),
+        };
+    }
+
+    let palette_states = match blocks.get("Palette") {
+        Some(Value::Compound(palette)) => palette,
+        _ => return Err(String::from("Schematic has no palette")),
+    };
+
+    let mut palette = vec![Value::Compound(HashMap::new()); palette_states.len()];
+
+    for (state, index) in palette_states {
+        let index = match index {
+            Value::Int(index) if (*index as usize) < palette.len() => *index as usize,
+            _ => return Err(format!("Invalid palette index for {}", state)),
+        };
+
+        palette[index] = parse_block_state(state)?;
+    }
+
+    let data = match blocks.get(if version >= 3 { "Data" } else { "BlockData" }) {
+        Some(Value::ByteArray(data)) => data,
+        _ => return Err(String::from("Schematic has no block data")),
+    };
+
+    let volume = size.iter().map(|&size| size as usize).product();
+    let mut block_indices = Vec::with_capacity(volume);
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    // Blocks are written as varints, in which each byte holds seven bits and whether another byte follows
+    for &byte in data.iter() {
+        value |= ((byte as u8 & 0x7f) as usize) << shift;
+
+        if byte as u8 & 0x80 == 0 {
+            if value >= palette.len() {
+                return Err(format!("Block refers to palette index {}", value));
+            }
+
+            block_indices.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+        }
+    }
+
+    if block_indices.len() != volume {
+        return Err(format!(
+            "Schematic has {} blocks, but is {}x{}x{}",
+            block_indices.len(),
+            size[0],
+            size[1],
+            size[2]
+        ));
+    }
+
+    let mut block_entities = Vec::new();
+    let list_key = if version == 1 {
+        "TileEntities"
+    } else {
+        "BlockEntities"
+    };
+
+    if let Some(Value::List(list)) = blocks.get(list_key) {
+        for block_entity in list {
+            let mut block_entity = match block_entity {
+                Value::Compound(block_entity) => block_entity.clone(),
+                _ => continue,
+            };
+
+            let position = match block_entity.remove("Pos") {
+                Some(Value::IntArray(position)) if position.len() == 3 => {
+                    [position[0], position[1], position[2]]
+                }
+                _ => return Err(String::from("Block entity has no position")),
+            };
+
+            let id = block_entity.remove("Id");
+
+            let mut data = if version >= 3 {
+                match block_entity.remove("Data") {
+                    Some(Value::Compound(data)) => data,
+                    _ => HashMap::new(),
+                }
+            } else {
+                block_entity
+            };
+
+            if let Some(id) = id {
+                data.insert(String::from("id"), id);
+            }
+
+            block_entities.push((position, data));
+        }
+    }
+
+    Ok(Schematic {
+        size,
+        palette,
+        blocks: block_indices,
+        block_entities,
+    })
+}
@@ -0,0 +1,248 @@
+use clap::{Parser, Subcommand};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::block_index::{count_region_blocks, BlockIndex, FileStamp};
+use crate::blocks::block_state_matches;
+use crate::messages::{self, report, Message};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Precompute the block state counts of every chunk into an index file
+    Build(BuildOptions),
+    /// Count blocks using a previously built index
+    Query(QueryOptions),
+}
+
+#[derive(Parser)]
+struct BuildOptions {
+    #[clap(long, help = "Input directory of region (.mca) files to index")]
+    input_dir: String,
+
+    #[clap(long, help = "Output path for the block index file")]
+    output: String,
+
+    #[clap(
+        long,
+        help = "Reuse the entries of an existing index for regions which have not changed since it was built"
+    )]
+    update: bool,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+#[derive(Parser)]
+struct QueryOptions {
+    #[clap(long, help = "Path of the block index file")]
+    index: String,
+
+    #[clap(
+        long,
+        help = "Block to count, either by name (minecraft:chest) or exact state (minecraft:chest[facing=north,...])"
+    )]
+    block: String,
+
+    #[clap(
+        long,
+        help = "Directory of region files the index was built from, used to rescan regions which changed since"
+    )]
+    input_dir: Option<String>,
+
+    #[clap(long, help = "Print the count of each chunk containing the block")]
+    chunks: bool,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+pub fn run(options: &Options) -> Result<(), io::Error> {
+    match &options.command {
+        IndexCommand::Build(options) => build_index(options),
+        IndexCommand::Query(options) => query_index(options),
+    }
+}
+
+fn build_index(options: &BuildOptions) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    let mut index = if options.update && Path::exists(output_path) {
+        BlockIndex::load(output_path)?
+    } else {
+        BlockIndex::default()
+    };
+
+    let regions = list_region_files(input_path)?;
+
+    // Regions which have been deleted since the existing index was built shouldn't be carried over
+    index.retain_regions(|name| regions.iter().any(|(region, _, _)| region == name));
+
+    let stale_regions: Vec<(String, PathBuf, FileStamp)> = regions
+        .into_iter()
+        .filter(|(name, _, stamp)| match index.region(name) {
+            Some(region) => !region.is_current(stamp),
+            None => true,
+        })
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let counted = pool.install(|| {
+        stale_regions
+            .into_par_iter()
+            .map(|(name, path, stamp)| Ok((name, stamp, count_region_blocks(&path)?)))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let scanned_regions = counted.len();
+
+    for (name, stamp, chunks) in counted {
+        index.add_region(name, stamp, chunks);
+    }
+
+    index.save(output_path)?;
+
+    report!(
+        info,
+        messages::INDEX_BUILT,
+        regions = index.regions().len(),
+        scanned_regions = scanned_regions,
+        unchanged_regions = index.regions().len() - scanned_regions
+    );
+
+    Ok(())
+}
+
+fn query_index(options: &QueryOptions) -> Result<(), io::Error> {
+    let index_path = Path::new(&options.index);
+
+    if !Path::exists(index_path) {
+        return Err(Message::new(messages::INDEX_FILE_MISSING)
+            .arg("path", index_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let index = BlockIndex::load(index_path)?;
+    let states = index.matching_states(&options.block);
+
+    let mut chunk_counts: Vec<(ChunkPos, u64)> = Vec::new();
+    let mut rescanned_regions = Vec::new();
+
+    let current_regions = match &options.input_dir {
+        Some(input_dir) => {
+            let mut current = Vec::new();
+
+            for (name, path, stamp) in list_region_files(Path::new(input_dir))? {
+                match index.region(&name) {
+                    Some(region) if region.is_current(&stamp) => current.push(region),
+                    _ => rescanned_regions.push(path),
+                }
+            }
+
+            current
+        }
+        None => index.regions().iter().collect(),
+    };
+
+    for region in current_regions {
+        chunk_counts.extend(
+            region
+                .chunks
+                .iter()
+                .map(|chunk| (chunk.position, chunk.count_of(&states))),
+        );
+    }
+
+    if !rescanned_regions.is_empty() {
+        report!(
+            info,
+            messages::INDEX_RESCANNING,
+            rescanned_regions = rescanned_regions.len()
+        );
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.threads.unwrap_or(1) as usize)
+            .build()
+            .unwrap();
+
+        let rescanned = pool.install(|| {
+            rescanned_regions
+                .into_par_iter()
+                .map(|path| count_region_blocks(&path))
+                .collect::<Result<Vec<_>, io::Error>>()
+        })?;
+
+        for (position, counts) in rescanned.into_iter().flatten() {
+            chunk_counts.push((position, count_matching(&counts, &options.block)));
+        }
+    }
+
+    chunk_counts.retain(|(_, count)| *count > 0);
+    chunk_counts.sort_by_key(|(position, _)| (position.x, position.z));
+
+    if options.chunks {
+        for (position, count) in &chunk_counts {
+            println!("{} {} {}", position.x, position.z, count);
+        }
+    }
+
+    println!(
+        "{}",
+        Message::new(messages::INDEX_QUERY_RESULT)
+            .arg("block", &options.block)
+            .arg(
+                "blocks",
+                chunk_counts.iter().map(|(_, count)| count).sum::<u64>()
+            )
+            .arg("chunks", chunk_counts.len())
+    );
+
+    Ok(())
+}
+
+fn count_matching(counts: &HashMap<String, u64>, query: &str) -> u64 {
+    counts
+        .iter()
+        .filter(|(state, _)| block_state_matches(state, query))
+        .map(|(_, count)| count)
+        .sum()
+}
+
+fn list_region_files(input_dir: &Path) -> Result<Vec<(String, PathBuf, FileStamp)>, io::Error> {
+    let mut regions = Vec::new();
+
+    for entry in fs::read_dir(input_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            let stamp = FileStamp::of(&path)?;
+            regions.push((name, path, stamp));
+        }
+    }
+
+    Ok(regions)
+}
@@ -0,0 +1,347 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::find_long_tag;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+/// The number of game ticks in a second.
+const TICKS_PER_SECOND: u64 = 20;
+
+/// The upper bounds of each bucket of the histogram in ticks, exclusive, with a description of the time. Chunks which
+/// have never been inhabited get a bucket of their own, since they are usually the bulk of a world.
+const BUCKETS: &[(u64, &str)] = &[
+    (1, "never"),
+    (10 * TICKS_PER_SECOND, "under 10 s"),
+    (60 * TICKS_PER_SECOND, "under 1 min"),
+    (5 * 60 * TICKS_PER_SECOND, "under 5 min"),
+    (30 * 60 * TICKS_PER_SECOND, "under 30 min"),
+    (60 * 60 * TICKS_PER_SECOND, "under 1 h"),
+    (5 * 60 * 60 * TICKS_PER_SECOND, "under 5 h"),
+    (24 * 60 * 60 * TICKS_PER_SECOND, "under 24 h"),
+    (u64::MAX, "24 h or more"),
+];
+
+/// The width of the bar of the most common bucket, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// The colors of the heatmap, from chunks which have never been inhabited to the most inhabited chunk.
+const HEATMAP_COLORS: &[[u8; 3]] = &[[16, 16, 96], [220, 20, 20], [255, 240, 64]];
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to read")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Format of the histogram written to stdout"
+    )]
+    format: InhabitedFormat,
+
+    #[clap(
+        long,
+        help = "Output path for a PNG heatmap of the whole world, with one pixel per chunk"
+    )]
+    heatmap: Option<String>,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum InhabitedFormat {
+    /// One human-readable line per bucket, with a bar chart
+    Text,
+    /// One JSON object per line for each bucket
+    Json,
+}
+
+/// The inhabited time of every chunk of a region which has one, and the number of chunks which don't.
+struct RegionTimes {
+    position: ChunkPos,
+    chunks: Vec<(ChunkPos, u64)>,
+    missing_chunks: usize,
+}
+
+pub fn inhabited(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if let Some(heatmap_path) = &options.heatmap {
+        read_only::ensure_writable(Path::new(heatmap_path))?;
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let regions = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| read_region_times(path, &options.filter))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let mut times: Vec<u64> = regions
+        .iter()
+        .flat_map(|region| region.chunks.iter().map(|&(_, time)| time))
+        .collect();
+    times.sort_unstable();
+
+    let missing_chunks: usize = regions.iter().map(|region| region.missing_chunks).sum();
+
+    if missing_chunks > 0 {
+        report!(
+            warn,
+            messages::INHABITED_MISSING,
+            missing_chunks = missing_chunks
+        );
+    }
+
+    if times.is_empty() {
+        return Err(Message::new(messages::INHABITED_NO_CHUNKS)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    print_histogram(&times, options.format)?;
+
+    if let Some(heatmap_path) = &options.heatmap {
+        write_heatmap(Path::new(heatmap_path), &regions, times[times.len() - 1])?;
+    }
+
+    let percentile = |fraction: f64| times[((times.len() - 1) as f64 * fraction).round() as usize];
+
+    report!(
+        info,
+        messages::INHABITED_SUMMARY,
+        chunks = times.len(),
+        regions = regions.len(),
+        median = percentile(0.5),
+        p90 = percentile(0.9),
+        max = times[times.len() - 1]
+    );
+
+    Ok(())
+}
+
+/// Reads the inhabited time of every chunk in a region, scanning for the tag without decoding the rest of the chunk.
+/// Chunks saved before 1.18 keep it within their `Level` compound.
+fn read_region_times(path: &Path, filter: &FilterOptions) -> Result<RegionTimes, io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let mut times = RegionTimes {
+        position: region_position,
+        chunks: Vec::new(),
+        missing_chunks: 0,
+    };
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !filter.includes_chunk(region_position, chunk.position) {
+            continue;
+        }
+
+        let time = match find_long_tag(&chunk.data, &["InhabitedTime"])? {
+            Some(time) => Some(time),
+            None => find_long_tag(&chunk.data, &["Level", "InhabitedTime"])?,
+        };
+
+        match time {
+            Some(time) => times.chunks.push((chunk.position, time.max(0) as u64)),
+            None => times.missing_chunks += 1,
+        }
+    }
+
+    Ok(times)
+}
+
+/// Prints how many chunks fall into each bucket of inhabited time, and the fraction of chunks at or below each bucket,
+/// which is what pruning by a threshold would remove. `times` must be sorted.
+fn print_histogram(times: &[u64], format: InhabitedFormat) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    let mut counts = Vec::with_capacity(BUCKETS.len());
+    let mut start = 0;
+
+    for &(end, _) in BUCKETS {
+        let count = times[start..].partition_point(|&time| time < end);
+        counts.push(count);
+        start += count;
+    }
+
+    let largest = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut cumulative = 0;
+    let mut min_ticks = 0;
+
+    for (&(max_ticks, description), &count) in BUCKETS.iter().zip(&counts) {
+        cumulative += count;
+
+        let fraction = count as f64 / times.len() as f64;
+        let cumulative_fraction = cumulative as f64 / times.len() as f64;
+
+        match format {
+            InhabitedFormat::Text => {
+                let ticks = match max_ticks {
+                    1 => String::from("0 ticks"),
+                    u64::MAX => format!(">= {} ticks", min_ticks),
+                    _ => format!("< {} ticks", max_ticks),
+                };
+
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::INHABITED_BUCKET)
+                        .arg("range", format!("{:<13} {:<18}", description, ticks))
+                        .arg("chunks", format!("{:>9}", count))
+                        .arg("percent", format!("{:>5.1}", fraction * 100.0))
+                        .arg(
+                            "cumulative",
+                            format!("{:>5.1}", cumulative_fraction * 100.0)
+                        )
+                        .arg("bar", "#".repeat((count * BAR_WIDTH).div_ceil(largest)))
+                )?;
+            }
+            InhabitedFormat::Json => {
+                let object = serde_json::json!({
+                    "min_ticks": min_ticks,
+                    "max_ticks": if max_ticks == u64::MAX { None } else { Some(max_ticks) },
+                    "chunks": count,
+                    "fraction": fraction,
+                    "cumulative_fraction": cumulative_fraction,
+                });
+
+                writeln!(out, "{}", object)?;
+            }
+        }
+
+        min_ticks = max_ticks;
+    }
+
+    out.flush()
+}
+
+/// Writes a PNG of the whole world with one pixel per chunk, colored by its inhabited time on a logarithmic scale up to
+/// the most inhabited chunk. Chunks without an inhabited time are left transparent.
+fn write_heatmap(path: &Path, regions: &[RegionTimes], max_time: u64) -> Result<(), io::Error> {
+    let min_x = regions
+        .iter()
+        .map(|region| region.position.x)
+        .min()
+        .unwrap();
+    let max_x = regions
+        .iter()
+        .map(|region| region.position.x)
+        .max()
+        .unwrap();
+    let min_z = regions
+        .iter()
+        .map(|region| region.position.z)
+        .min()
+        .unwrap();
+    let max_z = regions
+        .iter()
+        .map(|region| region.position.z)
+        .max()
+        .unwrap();
+
+    let width = ((max_x - min_x + 1) * 32) as usize;
+    let height = ((max_z - min_z + 1) * 32) as usize;
+
+    let mut pixels = vec![0u8; width * height * 4];
+    let scale = ((max_time + 1) as f64).ln().max(f64::MIN_POSITIVE);
+
+    for region in regions {
+        let left = ((region.position.x - min_x) * 32) as usize;
+        let top = ((region.position.z - min_z) * 32) as usize;
+
+        for &(chunk, time) in &region.chunks {
+            let index = (top + chunk.z as usize) * width + left + chunk.x as usize;
+            let color = heatmap_color(((time + 1) as f64).ln() / scale);
+
+            pixels[(index * 4)..(index * 4 + 3)].copy_from_slice(&color);
+            pixels[index * 4 + 3] = 255;
+        }
+    }
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    report!(
+        info,
+        messages::INHABITED_HEATMAP,
+        width = width,
+        height = height,
+        min_x = min_x * 32,
+        min_z = min_z * 32,
+        path = path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Interpolates between the heatmap colors, where `position` is between 0 and 1.
+fn heatmap_color(position: f64) -> [u8; 3] {
+    let scaled = position.clamp(0.0, 1.0) * (HEATMAP_COLORS.len() - 1) as f64;
+    let index = (scaled as usize).min(HEATMAP_COLORS.len() - 2);
+    let fraction = scaled - index as f64;
+
+    let (from, to) = (HEATMAP_COLORS[index], HEATMAP_COLORS[index + 1]);
+    let mut color = [0u8; 3];
+
+    for ((channel, &from), &to) in color.iter_mut().zip(&from).zip(&to) {
+        *channel = (from as f64 + (to as f64 - from as f64) * fraction).round() as u8;
+    }
+
+    color
+}
@@ -0,0 +1,248 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::region::RegionFile;
+
+/// The width of the bar of the most common bucket, in characters.
+const BAR_WIDTH: usize = 40;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to read")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "month",
+        help = "Length of time covered by each bucket"
+    )]
+    bucket: Bucket,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Format of the report written to stdout"
+    )]
+    format: LastModifiedFormat,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Bucket {
+    /// 7 days
+    Week,
+    /// 30 days
+    Month,
+    /// 365 days
+    Year,
+}
+
+impl Bucket {
+    fn seconds(self) -> u64 {
+        match self {
+            Bucket::Week => 7 * SECONDS_PER_DAY,
+            Bucket::Month => 30 * SECONDS_PER_DAY,
+            Bucket::Year => 365 * SECONDS_PER_DAY,
+        }
+    }
+
+    fn name(self, count: u64) -> String {
+        let unit = match self {
+            Bucket::Week => "week",
+            Bucket::Month => "month",
+            Bucket::Year => "year",
+        };
+
+        if count == 1 {
+            format!("{} {}", count, unit)
+        } else {
+            format!("{} {}s", count, unit)
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LastModifiedFormat {
+    /// One human-readable line per bucket, with a bar chart
+    Text,
+    /// One JSON object per line for each bucket
+    Json,
+}
+
+pub fn last_modified(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let timestamps = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| read_region_timestamps(path, &options.filter))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let bucket_seconds = options.bucket.seconds();
+
+    let mut counts: Vec<usize> = Vec::new();
+    let mut unknown_chunks = 0;
+
+    for timestamp in timestamps.into_iter().flatten() {
+        if timestamp == 0 {
+            unknown_chunks += 1;
+            continue;
+        }
+
+        // Chunks saved with a clock ahead of this one are counted as modified just now
+        let bucket = (now.saturating_sub(timestamp as u64) / bucket_seconds) as usize;
+
+        if bucket >= counts.len() {
+            counts.resize(bucket + 1, 0);
+        }
+
+        counts[bucket] += 1;
+    }
+
+    if unknown_chunks > 0 {
+        report!(
+            warn,
+            messages::LAST_MODIFIED_UNKNOWN,
+            unknown_chunks = unknown_chunks
+        );
+    }
+
+    let chunks: usize = counts.iter().sum();
+
+    if chunks == 0 {
+        return Err(Message::new(messages::LAST_MODIFIED_NO_CHUNKS)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    print_buckets(&counts, chunks, options.bucket, options.format)?;
+
+    report!(
+        info,
+        messages::LAST_MODIFIED_SUMMARY,
+        chunks = chunks,
+        regions = files.len(),
+        oldest = options.bucket.name(counts.len() as u64 - 1)
+    );
+
+    Ok(())
+}
+
+/// Reads the time each chunk of a region was last saved from the region's header, without reading any chunks.
+fn read_region_timestamps(path: &Path, filter: &FilterOptions) -> Result<Vec<u32>, io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    region
+        .entries()?
+        .into_iter()
+        .filter(|entry| filter.includes_chunk(region_position, entry.position))
+        .map(|entry| region.timestamp(entry.position))
+        .collect()
+}
+
+/// Prints how many chunks were last modified in each bucket of age, newest first, along with the fraction of chunks
+/// which are at least as old as each bucket, which is what pruning by that age would remove.
+fn print_buckets(
+    counts: &[usize],
+    chunks: usize,
+    bucket: Bucket,
+    format: LastModifiedFormat,
+) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    let largest = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut older = chunks;
+
+    for (index, &count) in counts.iter().enumerate() {
+        let fraction = count as f64 / chunks as f64;
+        let older_fraction = older as f64 / chunks as f64;
+
+        match format {
+            LastModifiedFormat::Text => {
+                let age = format!(
+                    "{} to {}",
+                    bucket.name(index as u64),
+                    bucket.name(index as u64 + 1)
+                );
+
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LAST_MODIFIED_BUCKET)
+                        .arg("age", format!("{:<20}", age))
+                        .arg("chunks", format!("{:>9}", count))
+                        .arg("percent", format!("{:>5.1}", fraction * 100.0))
+                        .arg("older", format!("{:>5.1}", older_fraction * 100.0))
+                        .arg("bar", "#".repeat((count * BAR_WIDTH).div_ceil(largest)))
+                )?;
+            }
+            LastModifiedFormat::Json => {
+                let seconds = bucket.seconds();
+                let object = serde_json::json!({
+                    "min_age_seconds": index as u64 * seconds,
+                    "max_age_seconds": (index as u64 + 1) * seconds,
+                    "chunks": count,
+                    "fraction": fraction,
+                    "older_fraction": older_fraction,
+                });
+
+                writeln!(out, "{}", object)?;
+            }
+        }
+
+        older -= count;
+    }
+
+    out.flush()
+}
@@ -0,0 +1,349 @@
+use clap::{Parser, ValueEnum};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use atty::Stream;
+
+use crate::archive_version::ArchiveLayout;
+use crate::commands::archive::{
+    for_each_archive_entry, parse_chunk_entry_path, raw_chunk_entry_compression,
+    METADATA_ENTRY_PATH,
+};
+use crate::messages::{self, report, Message};
+use crate::metadata::ArchiveMetadata;
+use crate::region::ChunkPos;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Path of the archive file to list (default is pipe from stdin)"
+    )]
+    input_file: Option<String>,
+
+    #[clap(long, help = "Allow binary data to be piped from a TTY")]
+    ignore_tty: bool,
+
+    #[clap(
+        long,
+        help = "List every entry of the archive as it is read, before the totals of each region"
+    )]
+    long: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the listing written to stdout",
+        default_value = "text"
+    )]
+    format: ListFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ListFormat {
+    /// One human-readable line per region or entry
+    Text,
+    /// One JSON object per line for each region or entry
+    Json,
+}
+
+/// The chunks of one region within the archive, in the order the region was first seen.
+struct RegionListing {
+    position: ChunkPos,
+    chunks: usize,
+    bytes: u64,
+}
+
+pub fn list_archive(options: &Options) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    match &options.input_file {
+        Some(input_path) => {
+            let input_path = Path::new(input_path);
+
+            if !Path::exists(input_path) {
+                return Err(Message::new(messages::INPUT_FILE_MISSING)
+                    .arg("path", input_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            list_entries(BufReader::new(File::open(input_path)?), &mut out, options)?;
+        }
+        None => {
+            if atty::is(Stream::Stdin) && !options.ignore_tty {
+                return Err(Message::new(messages::TTY_INPUT_REFUSED)
+                    .into_error(io::ErrorKind::InvalidInput));
+            }
+
+            list_entries(io::stdin(), &mut out, options)?;
+        }
+    }
+
+    out.flush()
+}
+
+/// Streams the entries of an archive, tallying the chunks and bytes of each region. With `--long`, each entry is
+/// written as soon as it is read, so a long listing of a large archive can be followed as it is produced.
+fn list_entries<R, W>(reader: R, out: &mut W, options: &Options) -> Result<(), io::Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut regions: Vec<RegionListing> = Vec::new();
+    let mut region_indices: HashMap<ChunkPos, usize> = HashMap::new();
+    let mut other_entries = 0;
+    let mut first_entry = true;
+
+    for_each_archive_entry(reader, |path, size, entry| {
+        // The metadata is the first entry, so it is printed before anything else. Archives packed before the layout
+        // was versioned don't have any, and are listed as the version they were read as.
+        if first_entry {
+            first_entry = false;
+
+            if path == Path::new(METADATA_ENTRY_PATH) {
+                let metadata = ArchiveMetadata::read_from(BufReader::new(entry))?;
+                print_metadata(out, &metadata, options.format)?;
+            } else {
+                let metadata = ArchiveMetadata {
+                    format_version: ArchiveLayout::Unversioned.version(),
+                    ..ArchiveMetadata::default()
+                };
+                print_metadata(out, &metadata, options.format)?;
+            }
+        }
+
+        let (region, chunk) = match parse_chunk_entry_path(path) {
+            Some(positions) => positions,
+            None => {
+                if options.long {
+                    print_entry(out, path, size, options.format)?;
+                }
+
+                other_entries += 1;
+                return Ok(true);
+            }
+        };
+
+        let index = *region_indices.entry(region).or_insert_with(|| {
+            regions.push(RegionListing {
+                position: region,
+                chunks: 0,
+                bytes: 0,
+            });
+
+            regions.len() - 1
+        });
+
+        let listing = &mut regions[index];
+        listing.chunks += 1;
+        listing.bytes += size;
+
+        if options.long {
+            print_chunk(out, path, region, chunk, size, options.format)?;
+        }
+
+        Ok(true)
+    })?;
+
+    for listing in &regions {
+        print_region(out, listing, options.format)?;
+    }
+
+    report!(
+        info,
+        messages::LIST_SUMMARY,
+        chunks = regions.iter().map(|listing| listing.chunks).sum::<usize>(),
+        regions = regions.len(),
+        bytes = regions.iter().map(|listing| listing.bytes).sum::<u64>(),
+        other_entries = other_entries
+    );
+
+    Ok(())
+}
+
+fn print_region<W>(
+    out: &mut W,
+    listing: &RegionListing,
+    format: ListFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let name = format!("r.{}.{}", listing.position.x, listing.position.z);
+
+    match format {
+        ListFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(messages::LIST_REGION)
+                .arg("region", &name)
+                .arg("chunks", listing.chunks)
+                .arg("bytes", listing.bytes)
+        ),
+        ListFormat::Json => {
+            let object = serde_json::json!({
+                "kind": "region",
+                "region": name,
+                "region_x": listing.position.x,
+                "region_z": listing.position.z,
+                "chunks": listing.chunks,
+                "bytes": listing.bytes,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+fn print_chunk<W>(
+    out: &mut W,
+    path: &Path,
+    region: ChunkPos,
+    chunk: ChunkPos,
+    size: u64,
+    format: ListFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    match format {
+        ListFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(messages::LIST_ENTRY)
+                .arg("path", path.display())
+                .arg("bytes", size)
+        ),
+        ListFormat::Json => {
+            // Chunks packed with `--raw` keep the compression they had in their region file
+            let compression = match raw_chunk_entry_compression(path) {
+                Some(1) => "gzip",
+                Some(2) => "zlib",
+                _ => "none",
+            };
+
+            let object = serde_json::json!({
+                "kind": "chunk",
+                "path": path.to_string_lossy(),
+                "region_x": region.x,
+                "region_z": region.z,
+                "chunk_x": chunk.x,
+                "chunk_z": chunk.z,
+                "bytes": size,
+                "compression": compression,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+fn print_metadata<W>(
+    out: &mut W,
+    metadata: &ArchiveMetadata,
+    format: ListFormat,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    match format {
+        ListFormat::Text => {
+            let message = match &metadata.tool_version {
+                Some(tool_version) => {
+                    Message::new(messages::LIST_METADATA_TOOL).arg("tool_version", tool_version)
+                }
+                None => Message::new(messages::LIST_METADATA_VERSION),
+            };
+
+            writeln!(out, "{}", message.arg("version", metadata.format_version))?;
+
+            if !metadata.is_compatible() {
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LIST_METADATA_INCOMPATIBLE)
+                        .arg("version", metadata.format_version)
+                        .arg("supported", ArchiveLayout::CURRENT)
+                )?;
+            }
+
+            if let Some(world) = &metadata.world_name {
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LIST_METADATA_WORLD).arg("world", world)
+                )?;
+            }
+
+            if let Some(data_version) = metadata.data_version {
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LIST_METADATA_DATA_VERSION)
+                        .arg("data_version", data_version)
+                )?;
+            }
+
+            if let Some(chunks) = metadata.chunks {
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LIST_METADATA_CHUNKS).arg("chunks", chunks)
+                )?;
+            }
+
+            if let Some(created) = metadata.created {
+                writeln!(
+                    out,
+                    "{}",
+                    Message::new(messages::LIST_METADATA_CREATED).arg("created", created)
+                )?;
+            }
+
+            Ok(())
+        }
+        ListFormat::Json => {
+            let object = serde_json::json!({
+                "kind": "metadata",
+                "tool_version": metadata.tool_version,
+                "format_version": metadata.format_version,
+                "compatible": metadata.is_compatible(),
+                "world_name": metadata.world_name,
+                "data_version": metadata.data_version,
+                "chunks": metadata.chunks,
+                "created": metadata.created,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+/// Prints an entry which isn't a chunk, such as the profile or checksums of the archive.
+fn print_entry<W>(out: &mut W, path: &Path, size: u64, format: ListFormat) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    match format {
+        ListFormat::Text => writeln!(
+            out,
+            "{}",
+            Message::new(messages::LIST_ENTRY)
+                .arg("path", path.display())
+                .arg("bytes", size)
+        ),
+        ListFormat::Json => {
+            let object = serde_json::json!({
+                "kind": "entry",
+                "path": path.to_string_lossy(),
+                "bytes": size,
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
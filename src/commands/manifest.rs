@@ -0,0 +1,204 @@
+use clap::{Parser, Subcommand};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{canonical_hash, Manifest};
+use crate::messages::{self, report, Message, MessageId};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(subcommand)]
+    command: ManifestCommand,
+}
+
+#[derive(Subcommand)]
+enum ManifestCommand {
+    /// Record a hash of the NBT of every chunk in a world into a manifest file
+    Create(CreateOptions),
+    /// Compare the chunks of a world against a manifest file
+    Verify(VerifyOptions),
+}
+
+#[derive(Parser)]
+struct CreateOptions {
+    #[clap(long, help = "Input directory of region (.mca) files to hash")]
+    input_dir: String,
+
+    #[clap(long, help = "Output path for the manifest file")]
+    output_file: String,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+#[derive(Parser)]
+struct VerifyOptions {
+    #[clap(long, help = "Input directory of region (.mca) files to verify")]
+    input_dir: String,
+
+    #[clap(long, help = "Path of the manifest file to compare against")]
+    manifest_file: String,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+}
+
+pub fn run(options: &Options) -> Result<(), io::Error> {
+    match &options.command {
+        ManifestCommand::Create(options) => create_manifest(options),
+        ManifestCommand::Verify(options) => verify_manifest(options),
+    }
+}
+
+fn create_manifest(options: &CreateOptions) -> Result<(), io::Error> {
+    let output_path = Path::new(&options.output_file);
+    read_only::ensure_writable(output_path)?;
+
+    let (manifest, regions) = hash_world(Path::new(&options.input_dir), options.threads)?;
+    manifest.save(output_path)?;
+
+    report!(
+        info,
+        messages::MANIFEST_CREATED,
+        chunks = manifest.iter().count(),
+        regions = regions,
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+fn verify_manifest(options: &VerifyOptions) -> Result<(), io::Error> {
+    let manifest_path = Path::new(&options.manifest_file);
+
+    if !Path::exists(manifest_path) {
+        return Err(Message::new(messages::INPUT_FILE_MISSING)
+            .arg("path", manifest_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let expected = Manifest::load(manifest_path)?;
+    let (actual, regions) = hash_world(Path::new(&options.input_dir), options.threads)?;
+
+    let mut mismatches: Vec<(ChunkPos, ChunkPos, MessageId)> = expected
+        .iter()
+        .filter_map(|(region, chunk, hash)| {
+            let reason = match actual.get(region, chunk) {
+                Some(actual_hash) if actual_hash == hash => return None,
+                Some(_) => messages::MANIFEST_CHANGED,
+                None => messages::MANIFEST_MISSING,
+            };
+
+            Some((region, chunk, reason))
+        })
+        .collect();
+
+    mismatches.extend(
+        actual
+            .iter()
+            .filter(|&(region, chunk, _)| expected.get(region, chunk).is_none())
+            .map(|(region, chunk, _)| (region, chunk, messages::MANIFEST_UNLISTED)),
+    );
+
+    mismatches.sort_by_key(|&(region, chunk, _)| (region.x, region.z, chunk.z, chunk.x));
+
+    let count = |id: MessageId| {
+        mismatches
+            .iter()
+            .filter(|&&(_, _, reason)| reason == id)
+            .count()
+    };
+
+    for &(region, chunk, reason) in &mismatches {
+        report!(
+            warn,
+            reason,
+            region = format!("r.{}.{}.mca", region.x, region.z),
+            x = chunk.x,
+            z = chunk.z
+        );
+    }
+
+    report!(
+        info,
+        messages::MANIFEST_VERIFIED,
+        chunks = actual.iter().count(),
+        regions = regions,
+        changed = count(messages::MANIFEST_CHANGED),
+        missing = count(messages::MANIFEST_MISSING),
+        unlisted = count(messages::MANIFEST_UNLISTED)
+    );
+
+    if !mismatches.is_empty() {
+        return Err(Message::new(messages::MANIFEST_MISMATCHED)
+            .arg("chunks", mismatches.len())
+            .into_error(io::ErrorKind::InvalidData));
+    }
+
+    Ok(())
+}
+
+/// Hashes every chunk of the region files in a directory, returning the hashes and the number of region files read.
+fn hash_world(input_path: &Path, threads: Option<u32>) -> Result<(Manifest, usize), io::Error> {
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let results = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| hash_region(path))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let mut manifest = Manifest::new();
+
+    for (region, hashes) in results {
+        for (chunk, hash) in hashes {
+            manifest.insert(region, chunk, hash);
+        }
+    }
+
+    Ok((manifest, files.len()))
+}
+
+fn hash_region(path: &Path) -> Result<(ChunkPos, Vec<(ChunkPos, u64)>), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let mut hashes = Vec::new();
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        hashes.push((chunk.position, canonical_hash(&read_nbt(&chunk.data)?)));
+    }
+
+    Ok((region_position, hashes))
+}
@@ -0,0 +1,151 @@
+use clap::Parser;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+/// The shade of chunks which are stored in a region file.
+const CHUNK_SHADE: u8 = 255;
+
+/// The shade of chunks which aren't stored, but whose region file exists, so that the outline of each region shows.
+const REGION_SHADE: u8 = 48;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to map")]
+    input_dir: String,
+
+    #[clap(long, help = "Output path for the PNG image")]
+    output: String,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+pub fn map_occupancy(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    if files.is_empty() {
+        return Err(Message::new(messages::MAP_OCCUPANCY_NO_REGIONS)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let regions = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| read_region_chunks(path, &options.filter))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    // The image covers the bounding box of every region, so negative regions are shifted into it
+    let min_x = regions.iter().map(|(region, _)| region.x).min().unwrap();
+    let max_x = regions.iter().map(|(region, _)| region.x).max().unwrap();
+    let min_z = regions.iter().map(|(region, _)| region.z).min().unwrap();
+    let max_z = regions.iter().map(|(region, _)| region.z).max().unwrap();
+
+    let width = ((max_x - min_x + 1) * 32) as usize;
+    let height = ((max_z - min_z + 1) * 32) as usize;
+
+    let mut pixels = vec![0u8; width * height];
+    let mut chunks = 0;
+
+    for (region, positions) in &regions {
+        let left = ((region.x - min_x) * 32) as usize;
+        let top = ((region.z - min_z) * 32) as usize;
+
+        for z in 0..32 {
+            let start = (top + z) * width + left;
+            pixels[start..start + 32].fill(REGION_SHADE);
+        }
+
+        for position in positions {
+            pixels[(top + position.z as usize) * width + left + position.x as usize] = CHUNK_SHADE;
+        }
+
+        chunks += positions.len();
+    }
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(output_path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    report!(
+        info,
+        messages::MAP_OCCUPANCY_SUMMARY,
+        chunks = chunks,
+        regions = regions.len(),
+        width = width,
+        height = height,
+        min_x = min_x * 32,
+        min_z = min_z * 32,
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Lists the chunks stored in a region which pass the filter, reading only the region's header.
+fn read_region_chunks(
+    path: &Path,
+    filter: &FilterOptions,
+) -> Result<(ChunkPos, Vec<ChunkPos>), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let positions = region
+        .entries()?
+        .into_iter()
+        .map(|entry| entry.position)
+        .filter(|&position| filter.includes_chunk(region_position, position))
+        .collect();
+
+    Ok((region_position, positions))
+}
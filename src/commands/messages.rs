@@ -0,0 +1,26 @@
+use clap::Parser;
+use std::io::{self, Write};
+use toml::Value;
+
+use crate::messages::CATALOG;
+
+#[derive(Parser)]
+pub struct Options {}
+
+/// Prints every message in the catalog as a TOML file of English templates, which can be translated and passed back
+/// with `--messages`.
+pub fn list_messages(_options: &Options) -> Result<(), io::Error> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for message in CATALOG {
+        writeln!(
+            stdout,
+            "{} = {}",
+            Value::String(String::from(message.id)),
+            Value::String(String::from(message.template))
+        )?;
+    }
+
+    Ok(())
+}
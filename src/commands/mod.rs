@@ -1,2 +1,38 @@
+pub mod apply;
 pub mod archive;
+pub mod archive_info;
+pub mod blend;
+pub mod browse;
+pub mod check;
+pub mod cleanup_stale;
+pub mod clear;
+pub mod compare_archives;
+pub mod convert;
+pub mod copy;
+pub mod count;
+pub mod delete_chunks;
+pub mod dump;
+pub mod export_biomes;
+pub mod export_heightmap;
+pub mod export_layers;
+pub mod export_schematic;
+pub mod find;
+pub mod import_schematic;
+pub mod index;
+pub mod inhabited;
+pub mod last_modified;
+pub mod list;
+pub mod manifest;
+pub mod map_occupancy;
+pub mod messages;
+pub mod nbt_edit;
+pub mod normalize;
+pub mod prune_entities;
+pub mod query;
+pub mod render;
+pub mod replace_blocks;
+pub mod repo;
+pub mod roundtrip;
+pub mod shift;
 pub mod strip;
+pub mod verify;
@@ -0,0 +1,281 @@
+use clap::{ArgGroup, Parser};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::data_version;
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::nbt_path::{parse_assignment, parse_nbt_path, Assignment, NbtPath};
+use crate::observer::NullObserver;
+use crate::read_only;
+use crate::region::{RegionFile, RegionFileWriter};
+use crate::retry::{RetryOptions, RetryPolicy};
+use crate::version_rules::VersionRules;
+
+#[derive(Parser)]
+#[clap(group(ArgGroup::new("edits").required(true).multiple(true).args(&["remove", "set", "rules"])))]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to edit")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where edited region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        help = "Remove the tags at this path from every chunk, such as sections[].SkyLight (may be repeated)",
+        value_parser = parse_nbt_path
+    )]
+    remove: Vec<NbtPath>,
+
+    #[clap(
+        long,
+        help = "Set the tags at this path in every chunk to a value written as in SNBT, such as isLightOn=0b (may be repeated)",
+        value_parser = parse_assignment
+    )]
+    set: Vec<Assignment>,
+
+    #[clap(
+        long,
+        help = "TOML file of rules which remove or set tags only in chunks within a range of data versions, applied after --remove and --set"
+    )]
+    rules: Option<String>,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+}
+
+pub fn edit_files(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let version_rules = match &options.rules {
+        Some(rules_path) => Some(VersionRules::load(Path::new(rules_path))?),
+        None => None,
+    };
+
+    let retry = RetryPolicy::new(&options.retry);
+    let mut report = EditReport {
+        removed: vec![0; options.remove.len()],
+        set: vec![0; options.set.len()],
+        rule_removed: version_rules
+            .iter()
+            .flat_map(|version_rules| &version_rules.rules)
+            .map(|rule| vec![0; rule.remove.len()])
+            .collect(),
+        rule_set: version_rules
+            .iter()
+            .flat_map(|version_rules| &version_rules.rules)
+            .map(|rule| vec![0; rule.set.len()])
+            .collect(),
+        ..EditReport::default()
+    };
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            edit_file(
+                output_path,
+                &path,
+                options,
+                version_rules.as_ref(),
+                &mut report,
+                &retry,
+            )?;
+        }
+    }
+
+    report.print(options, version_rules.as_ref());
+    retry.report(&NullObserver);
+
+    Ok(())
+}
+
+fn edit_file(
+    output_dir: &Path,
+    path: &Path,
+    options: &Options,
+    version_rules: Option<&VersionRules>,
+    report: &mut EditReport,
+    retry: &RetryPolicy,
+) -> Result<(), io::Error> {
+    let name = path.file_name().unwrap();
+
+    let in_region = retry.run(|| RegionFile::open(path))?;
+    let mut out_region = retry.run(|| RegionFileWriter::create(&Path::join(output_dir, name)))?;
+    let mut edited_chunks = 0;
+    let started = Instant::now();
+
+    for result in retry.stream_raw_chunks(&in_region) {
+        let raw_chunk = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        let chunk = raw_chunk.decompress()?;
+        let mut nbt = read_nbt(&chunk.data)?;
+        let mut changed = false;
+
+        // Removals are applied first, so that a path can be cleared and then given a new value
+        for (path, removed) in options.remove.iter().zip(report.removed.iter_mut()) {
+            let count = path.remove(&mut nbt);
+
+            *removed += count as u64;
+            changed |= count > 0;
+        }
+
+        for (assignment, set) in options.set.iter().zip(report.set.iter_mut()) {
+            let count = assignment.path.set(&mut nbt, &assignment.value);
+
+            *set += count as u64;
+            changed |= count > 0;
+        }
+
+        if let Some(version_rules) = version_rules {
+            let version = data_version(&nbt);
+
+            for (index, rule) in version_rules.rules.iter().enumerate() {
+                if !rule.versions.contains(&version) {
+                    continue;
+                }
+
+                for (path, removed) in rule
+                    .remove
+                    .iter()
+                    .zip(report.rule_removed[index].iter_mut())
+                {
+                    let count = path.remove(&mut nbt);
+
+                    *removed += count as u64;
+                    changed |= count > 0;
+                }
+
+                for (assignment, set) in rule.set.iter().zip(report.rule_set[index].iter_mut()) {
+                    let count = assignment.path.set(&mut nbt, &assignment.value);
+
+                    *set += count as u64;
+                    changed |= count > 0;
+                }
+            }
+        }
+
+        report.chunks += 1;
+
+        // Chunks which none of the paths matched can be copied without recompressing them
+        if !changed {
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let edited_chunk = chunk.with_data(write_nbt(&nbt)?.into_boxed_slice());
+
+        retry.run(|| out_region.add_chunk(&edited_chunk))?;
+        edited_chunks += 1;
+    }
+
+    out_region.finish()?;
+
+    report.edited_chunks += edited_chunks;
+
+    report!(
+        debug,
+        messages::NBT_EDIT_REGION,
+        region = name.to_string_lossy().into_owned(),
+        edited_chunks = edited_chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(())
+}
+
+/// Counts the chunks edited and the tags matched by each path, in the order the paths were given. The paths of the
+/// rules file are counted for each rule.
+#[derive(Default)]
+struct EditReport {
+    chunks: u64,
+    edited_chunks: u64,
+    removed: Vec<u64>,
+    set: Vec<u64>,
+    rule_removed: Vec<Vec<u64>>,
+    rule_set: Vec<Vec<u64>>,
+}
+
+impl EditReport {
+    fn print(&self, options: &Options, version_rules: Option<&VersionRules>) {
+        report!(
+            info,
+            messages::NBT_EDIT_SUMMARY,
+            edited_chunks = self.edited_chunks,
+            chunks = self.chunks
+        );
+
+        for (path, &tags) in options.remove.iter().zip(&self.removed) {
+            report!(
+                info,
+                messages::NBT_EDIT_REMOVED,
+                tags = tags,
+                path = path.to_string()
+            );
+        }
+
+        for (assignment, &tags) in options.set.iter().zip(&self.set) {
+            report!(
+                info,
+                messages::NBT_EDIT_SET,
+                tags = tags,
+                path = assignment.path.to_string()
+            );
+        }
+
+        for (index, rule) in version_rules
+            .iter()
+            .flat_map(|version_rules| &version_rules.rules)
+            .enumerate()
+        {
+            for (path, &tags) in rule.remove.iter().zip(&self.rule_removed[index]) {
+                report!(
+                    info,
+                    messages::NBT_EDIT_RULE_REMOVED,
+                    tags = tags,
+                    path = path.to_string(),
+                    versions = rule.describe_versions()
+                );
+            }
+
+            for (assignment, &tags) in rule.set.iter().zip(&self.rule_set[index]) {
+                report!(
+                    info,
+                    messages::NBT_EDIT_RULE_SET,
+                    tags = tags,
+                    path = assignment.path.to_string(),
+                    versions = rule.describe_versions()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use clap::Parser;
+use fastnbt::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::messages::{self, report, Message};
+use crate::nbt::{chunk_level, chunk_position, read_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "World directory (containing level.dat) to normalize")]
+    world: String,
+
+    #[clap(
+        long,
+        help = "Print the changes which would be made without moving any files"
+    )]
+    dry_run: bool,
+}
+
+/// The kinds of files which use the region file format, each of which lives in its own folder of a dimension.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum StorageKind {
+    Region,
+    Entities,
+    Poi,
+}
+
+impl StorageKind {
+    const ALL: [StorageKind; 3] = [StorageKind::Region, StorageKind::Entities, StorageKind::Poi];
+
+    fn folder_name(&self) -> &'static str {
+        match self {
+            StorageKind::Region => "region",
+            StorageKind::Entities => "entities",
+            StorageKind::Poi => "poi",
+        }
+    }
+}
+
+pub fn normalize_world(options: &Options) -> Result<(), io::Error> {
+    let world_path = Path::new(&options.world);
+
+    if !Path::is_dir(world_path) {
+        return Err(Message::new(messages::WORLD_DIR_MISSING)
+            .arg("path", world_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let mut moves = Vec::new();
+
+    for dimension in find_dimensions(world_path)? {
+        for kind in StorageKind::ALL.iter() {
+            let folder = dimension.join(kind.folder_name());
+
+            if Path::is_dir(&folder) {
+                plan_moves(&dimension, &folder, *kind, &mut moves)?;
+            }
+        }
+    }
+
+    for (from, to) in &moves {
+        let from_display = from.strip_prefix(world_path).unwrap_or(from).display();
+        let to_display = to.strip_prefix(world_path).unwrap_or(to).display();
+
+        if options.dry_run {
+            println!(
+                "{}",
+                Message::new(messages::NORMALIZE_WOULD_MOVE)
+                    .arg("from", &from_display)
+                    .arg("to", &to_display)
+            );
+            continue;
+        }
+
+        read_only::ensure_writable(to)?;
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::rename(from, to)?;
+
+        println!(
+            "{}",
+            Message::new(messages::NORMALIZE_MOVED)
+                .arg("from", &from_display)
+                .arg("to", &to_display)
+        );
+    }
+
+    if moves.is_empty() {
+        println!("{}", Message::new(messages::NORMALIZE_ALREADY_CORRECT));
+    }
+
+    Ok(())
+}
+
+/// Returns the directories of every dimension in a world, including the custom dimensions added in 1.16.
+fn find_dimensions(world_path: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut dimensions = vec![
+        world_path.to_owned(),
+        world_path.join("DIM-1"),
+        world_path.join("DIM1"),
+    ];
+
+    let custom_path = world_path.join("dimensions");
+
+    if Path::is_dir(&custom_path) {
+        for namespace in fs::read_dir(custom_path)? {
+            let namespace = namespace?.path();
+
+            if Path::is_dir(&namespace) {
+                for dimension in fs::read_dir(namespace)? {
+                    dimensions.push(dimension?.path());
+                }
+            }
+        }
+    }
+
+    dimensions.retain(|dimension| Path::is_dir(dimension));
+
+    Ok(dimensions)
+}
+
+/// Inspects each file in a folder of a dimension, recording a move for every file which belongs in another folder or
+/// whose name does not match the position of the chunks within it.
+fn plan_moves(
+    dimension: &Path,
+    folder: &Path,
+    folder_kind: StorageKind,
+    moves: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), io::Error> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if !path.is_file() || !name.ends_with(".mca") {
+            continue;
+        }
+
+        let (kind, region) = match inspect_file(&path) {
+            Ok(Some(inspected)) => inspected,
+            Ok(None) => continue,
+            Err(err) => {
+                report!(
+                    warn,
+                    messages::NORMALIZE_SKIPPED,
+                    path = path.display().to_string(),
+                    error = err.to_string()
+                );
+                continue;
+            }
+        };
+
+        // Point of interest chunks don't store their position, so only the folder of those files can be checked
+        let target_name = match region {
+            Some(region) => format!("r.{}.{}.mca", region.x, region.z),
+            None => name.clone(),
+        };
+
+        if kind == folder_kind && target_name == name {
+            continue;
+        }
+
+        let target = dimension.join(kind.folder_name()).join(target_name);
+
+        if Path::exists(&target) || moves.iter().any(|(_, to)| *to == target) {
+            report!(
+                warn,
+                messages::NORMALIZE_TARGET_EXISTS,
+                path = path.display().to_string(),
+                target = target.display().to_string()
+            );
+            continue;
+        }
+
+        moves.push((path, target));
+    }
+
+    Ok(())
+}
+
+/// Determines the kind of a region file and the region its chunks belong to from the first chunk which identifies
+/// them. Returns `None` if the file has no chunks.
+fn inspect_file(path: &Path) -> Result<Option<(StorageKind, Option<ChunkPos>)>, io::Error> {
+    let region_file = RegionFile::open(path)?;
+
+    for result in region_file.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        let nbt = read_nbt(&chunk.data)?;
+
+        let kind = match classify_chunk(&nbt) {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let position = match kind {
+            StorageKind::Region => chunk_position(&nbt),
+            StorageKind::Entities => entities_position(&nbt),
+            StorageKind::Poi => return Ok(Some((kind, None))),
+        };
+
+        let position = match position {
+            Some(position) => position,
+            None => continue,
+        };
+
+        // The chunk's slot in the header must agree with its position, otherwise the file is corrupt rather than
+        // misnamed
+        if position.x.rem_euclid(32) != chunk.position.x
+            || position.z.rem_euclid(32) != chunk.position.z
+        {
+            return Err(Message::new(messages::NORMALIZE_WRONG_SLOT)
+                .arg("x", position.x)
+                .arg("z", position.z)
+                .arg("slot_x", chunk.position.x)
+                .arg("slot_z", chunk.position.z)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        let region = ChunkPos {
+            x: position.x.div_euclid(32),
+            z: position.z.div_euclid(32),
+        };
+
+        return Ok(Some((kind, Some(region))));
+    }
+
+    Ok(None)
+}
+
+fn classify_chunk(nbt: &Value) -> Option<StorageKind> {
+    let level = chunk_level(nbt)?;
+
+    if level.contains_key("xPos") {
+        Some(StorageKind::Region)
+    } else if level.contains_key("Entities") && level.contains_key("Position") {
+        Some(StorageKind::Entities)
+    } else if level.contains_key("Sections") {
+        Some(StorageKind::Poi)
+    } else {
+        None
+    }
+}
+
+/// Returns the chunk position of an entities chunk, which is stored as a pair of integers in its `Position` tag.
+fn entities_position(nbt: &Value) -> Option<ChunkPos> {
+    match chunk_level(nbt)?.get("Position") {
+        Some(Value::IntArray(position)) if position.len() == 2 => Some(ChunkPos {
+            x: position[0],
+            z: position[1],
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,262 @@
+use clap::{ArgGroup, Parser};
+use fastnbt::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::qualify;
+use crate::dimension::{DimensionOptions, ENTITIES_DIR};
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::observer::NullObserver;
+use crate::read_only;
+use crate::region::{RegionFile, RegionFileWriter};
+use crate::retry::{RetryOptions, RetryPolicy};
+
+#[derive(Parser, Clone)]
+#[clap(group(ArgGroup::new("prune").required(true).multiple(true).args(&["types", "max_per_chunk"])))]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Input directory of entity region files, or of region files saved before 1.17"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where the pruned region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long = "type",
+        help = "Remove entities of this type, such as minecraft:item (may be repeated)"
+    )]
+    types: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Keep up to this many entities of each type in every chunk, removing only those over the limit"
+    )]
+    max_per_chunk: Option<usize>,
+
+    #[clap(flatten)]
+    dimensions: DimensionOptions,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+}
+
+pub fn prune_entities(options: &Options) -> Result<(), io::Error> {
+    if !options.dimensions.is_active() {
+        return prune_directory(options);
+    }
+
+    let output_world = Path::new(&options.output_dir);
+
+    options.dimensions.for_each(
+        Path::new(&options.input_dir),
+        ENTITIES_DIR,
+        |dimension, input_dir| {
+            prune_directory(&Options {
+                input_dir: input_dir.to_string_lossy().into_owned(),
+                output_dir: dimension
+                    .dir(output_world, ENTITIES_DIR)
+                    .to_string_lossy()
+                    .into_owned(),
+                ..options.clone()
+            })
+        },
+    )
+}
+
+fn prune_directory(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let types: Vec<String> = options.types.iter().map(|id| qualify(id)).collect();
+    let retry = RetryPolicy::new(&options.retry);
+    let mut report = PruneReport::default();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            prune_file(output_path, &path, &types, options, &mut report, &retry)?;
+        }
+    }
+
+    report!(
+        info,
+        messages::PRUNE_ENTITIES_SUMMARY,
+        entities = report.removed.values().sum::<u64>(),
+        changed_chunks = report.changed_chunks,
+        chunks = report.chunks
+    );
+
+    for (id, &entities) in &report.removed {
+        report!(
+            info,
+            messages::PRUNE_ENTITIES_REMOVED,
+            entities = entities,
+            id = id.clone()
+        );
+    }
+
+    retry.report(&NullObserver);
+
+    Ok(())
+}
+
+/// Counts the chunks which were read and changed, and the entities of each type which were removed.
+#[derive(Default)]
+struct PruneReport {
+    chunks: u64,
+    changed_chunks: u64,
+    removed: BTreeMap<String, u64>,
+}
+
+fn prune_file(
+    output_dir: &Path,
+    path: &Path,
+    types: &[String],
+    options: &Options,
+    report: &mut PruneReport,
+    retry: &RetryPolicy,
+) -> Result<(), io::Error> {
+    let name = path.file_name().unwrap();
+
+    let in_region = retry.run(|| RegionFile::open(path))?;
+    let mut out_region = retry.run(|| RegionFileWriter::create(&Path::join(output_dir, name)))?;
+    let mut changed_chunks = 0;
+    let started = Instant::now();
+
+    for result in retry.stream_raw_chunks(&in_region) {
+        let raw_chunk = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        report.chunks += 1;
+
+        let chunk = raw_chunk.decompress()?;
+        let mut nbt = read_nbt(&chunk.data)?;
+
+        let removed = prune_chunk(&mut nbt, types, options.max_per_chunk, &mut report.removed);
+
+        // Chunks which lost no entities can be copied without recompressing them
+        if removed == 0 {
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let pruned_chunk = chunk.with_data(write_nbt(&nbt)?.into_boxed_slice());
+
+        retry.run(|| out_region.add_chunk(&pruned_chunk))?;
+        changed_chunks += 1;
+    }
+
+    out_region.finish()?;
+
+    report.changed_chunks += changed_chunks;
+
+    report!(
+        debug,
+        messages::PRUNE_ENTITIES_REGION,
+        region = name.to_string_lossy().into_owned(),
+        changed_chunks = changed_chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(())
+}
+
+/// Removes the matching entities from a chunk, returning how many were removed. Entity files added in 1.17 keep their
+/// entities in a list at the root, while older chunks keep them in the `Level` compound. Entities riding another entity
+/// are removed along with it, and aren't matched by themselves.
+fn prune_chunk(
+    nbt: &mut Value,
+    types: &[String],
+    max_per_chunk: Option<usize>,
+    removed: &mut BTreeMap<String, u64>,
+) -> usize {
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return 0,
+    };
+
+    let container = if root.contains_key("Level") {
+        match root.get_mut("Level") {
+            Some(Value::Compound(level)) => level,
+            _ => return 0,
+        }
+    } else {
+        root
+    };
+
+    let entities = match container.get_mut("Entities") {
+        Some(Value::List(entities)) => entities,
+        _ => return 0,
+    };
+
+    let mut kept_per_type: HashMap<String, usize> = HashMap::new();
+    let before = entities.len();
+
+    entities.retain(|entity| {
+        let id = match entity_id(entity) {
+            Some(id) => id,
+            None => return true,
+        };
+
+        if !types.is_empty() && !types.iter().any(|matched| matched == id) {
+            return true;
+        }
+
+        // Without a limit, every matching entity is removed
+        if let Some(max) = max_per_chunk {
+            let kept = kept_per_type.entry(String::from(id)).or_default();
+
+            if *kept < max {
+                *kept += 1;
+                return true;
+            }
+        }
+
+        *removed.entry(String::from(id)).or_default() += 1;
+
+        false
+    });
+
+    before - entities.len()
+}
+
+fn entity_id(entity: &Value) -> Option<&str> {
+    match entity {
+        Value::Compound(entity) => match entity.get("id") {
+            Some(Value::String(id)) => Some(id),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,302 @@
+use clap::{Parser, ValueEnum};
+use fastnbt::Value;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::nbt_path::{parse_nbt_path, NbtPath};
+use crate::region::{ChunkPos, RegionFile};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to query")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Path of the tags to print from every chunk, such as sections[?Y==4].block_states.palette[].Name",
+        value_parser = parse_nbt_path
+    )]
+    path: NbtPath,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the matches written to stdout",
+        default_value = "text"
+    )]
+    format: QueryFormat,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum QueryFormat {
+    /// One line per match with the tag written as SNBT
+    Text,
+    /// One JSON object per match with the tag converted to JSON
+    Json,
+}
+
+/// A tag selected by the path, with the absolute position of the chunk it was found in.
+struct Match {
+    position: ChunkPos,
+    value: Value,
+}
+
+pub fn query(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    // Matches are printed in a stable order, regardless of which thread found them
+    files.sort();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let results = pool.install(|| {
+        files
+            .par_iter()
+            .map(|path| query_region(path, &options.path, &options.filter))
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut matches = 0;
+    let mut matched_chunks = 0;
+    let mut chunks = 0;
+
+    for (region_matches, region_chunks) in results {
+        chunks += region_chunks;
+
+        let mut last_position = None;
+
+        for found in region_matches {
+            print_match(&mut out, &found, options.format)?;
+            matches += 1;
+
+            if last_position != Some(found.position) {
+                matched_chunks += 1;
+                last_position = Some(found.position);
+            }
+        }
+    }
+
+    out.flush()?;
+
+    report!(
+        info,
+        messages::QUERY_SUMMARY,
+        matches = matches,
+        matched_chunks = matched_chunks,
+        chunks = chunks,
+        regions = files.len()
+    );
+
+    Ok(())
+}
+
+/// Evaluates the path against every chunk of a region file, returning the matches and the number of chunks queried.
+fn query_region(
+    path: &Path,
+    nbt_path: &NbtPath,
+    filter: &FilterOptions,
+) -> Result<(Vec<Match>, usize), io::Error> {
+    let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+    let region = RegionFile::open(path)?;
+
+    let mut matches = Vec::new();
+    let mut chunks = 0;
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !filter.includes_chunk(region_position, chunk.position) {
+            continue;
+        }
+
+        let position = ChunkPos {
+            x: (region_position.x * 32) + chunk.position.x,
+            z: (region_position.z * 32) + chunk.position.z,
+        };
+
+        let nbt = read_nbt(&chunk.data)?;
+
+        for value in nbt_path.select(&nbt) {
+            matches.push(Match {
+                position,
+                value: value.clone(),
+            });
+        }
+
+        chunks += 1;
+    }
+
+    Ok((matches, chunks))
+}
+
+fn print_match<W>(out: &mut W, found: &Match, format: QueryFormat) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    match format {
+        QueryFormat::Text => {
+            let mut text = String::new();
+            write_snbt(&mut text, &found.value);
+
+            writeln!(
+                out,
+                "{}",
+                Message::new(messages::QUERY_MATCH)
+                    .arg("x", found.position.x)
+                    .arg("z", found.position.z)
+                    .arg("value", text)
+            )
+        }
+        QueryFormat::Json => {
+            let object = serde_json::json!({
+                "x": found.position.x,
+                "z": found.position.z,
+                "value": to_json(&found.value),
+            });
+
+            writeln!(out, "{}", object)
+        }
+    }
+}
+
+/// Writes a tag as SNBT on a single line. Keys of compounds are sorted, since the order they were stored in is lost
+/// once the chunk is decoded.
+fn write_snbt(text: &mut String, value: &Value) {
+    match value {
+        Value::Byte(value) => write!(text, "{}b", value).unwrap(),
+        Value::Short(value) => write!(text, "{}s", value).unwrap(),
+        Value::Int(value) => write!(text, "{}", value).unwrap(),
+        Value::Long(value) => write!(text, "{}L", value).unwrap(),
+        Value::Float(value) => write!(text, "{}f", value).unwrap(),
+        Value::Double(value) => write!(text, "{}d", value).unwrap(),
+        Value::String(value) => write!(text, "{:?}", value).unwrap(),
+        Value::ByteArray(array) => write_array(text, "B", array.iter(), "b"),
+        Value::IntArray(array) => write_array(text, "I", array.iter(), ""),
+        Value::LongArray(array) => write_array(text, "L", array.iter(), "L"),
+        Value::List(list) => {
+            text.push('[');
+
+            for (index, element) in list.iter().enumerate() {
+                if index > 0 {
+                    text.push(',');
+                }
+
+                write_snbt(text, element);
+            }
+
+            text.push(']');
+        }
+        Value::Compound(compound) => {
+            let mut names: Vec<&String> = compound.keys().collect();
+            names.sort();
+
+            text.push('{');
+
+            for (index, name) in names.into_iter().enumerate() {
+                if index > 0 {
+                    text.push(',');
+                }
+
+                if !name.is_empty() && name.chars().all(is_unquoted_char) {
+                    text.push_str(name);
+                } else {
+                    write!(text, "{:?}", name).unwrap();
+                }
+
+                text.push(':');
+                write_snbt(text, &compound[name]);
+            }
+
+            text.push('}');
+        }
+    }
+}
+
+fn write_array<T: std::fmt::Display>(
+    text: &mut String,
+    prefix: &str,
+    values: impl Iterator<Item = T>,
+    suffix: &str,
+) {
+    write!(text, "[{};", prefix).unwrap();
+
+    for (index, value) in values.enumerate() {
+        if index > 0 {
+            text.push(',');
+        }
+
+        write!(text, "{}{}", value, suffix).unwrap();
+    }
+
+    text.push(']');
+}
+
+fn is_unquoted_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '+'
+}
+
+/// Converts a tag to JSON, where numbers of every type become JSON numbers and arrays become JSON arrays.
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Byte(value) => serde_json::json!(value),
+        Value::Short(value) => serde_json::json!(value),
+        Value::Int(value) => serde_json::json!(value),
+        Value::Long(value) => serde_json::json!(value),
+        Value::Float(value) => serde_json::json!(value),
+        Value::Double(value) => serde_json::json!(value),
+        Value::String(value) => serde_json::json!(value),
+        Value::ByteArray(array) => array.iter().copied().collect(),
+        Value::IntArray(array) => array.iter().copied().collect(),
+        Value::LongArray(array) => array.iter().copied().collect(),
+        Value::List(list) => list.iter().map(to_json).collect(),
+        Value::Compound(compound) => serde_json::Value::Object(
+            compound
+                .iter()
+                .map(|(name, value)| (name.clone(), to_json(value)))
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,403 @@
+use clap::{Parser, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fs::{self, File};
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::blocks::{block_name, decode_sections, BlockSection};
+use crate::filter::FilterOptions;
+use crate::messages::{self, report, Message};
+use crate::nbt::read_nbt;
+use crate::read_only;
+use crate::region::RegionFile;
+
+/// The colors of common blocks, by name without the `minecraft:` namespace. Blocks which aren't listed are matched by
+/// the suffix of their name, and are otherwise given a muted color derived from their name.
+const BLOCK_COLORS: &[(&str, [u8; 3])] = &[
+    ("grass_block", [95, 159, 53]),
+    ("dirt", [134, 96, 67]),
+    ("coarse_dirt", [119, 85, 59]),
+    ("podzol", [91, 63, 24]),
+    ("mycelium", [111, 99, 105]),
+    ("dirt_path", [148, 121, 65]),
+    ("farmland", [110, 75, 45]),
+    ("mud", [60, 57, 60]),
+    ("stone", [125, 125, 125]),
+    ("cobblestone", [122, 122, 122]),
+    ("mossy_cobblestone", [110, 118, 94]),
+    ("deepslate", [80, 80, 82]),
+    ("granite", [149, 103, 85]),
+    ("diorite", [188, 188, 188]),
+    ("andesite", [136, 136, 136]),
+    ("tuff", [108, 109, 102]),
+    ("calcite", [223, 224, 220]),
+    ("gravel", [131, 127, 126]),
+    ("sand", [219, 207, 163]),
+    ("sandstone", [216, 203, 155]),
+    ("red_sand", [190, 102, 33]),
+    ("red_sandstone", [186, 99, 29]),
+    ("clay", [160, 166, 179]),
+    ("terracotta", [152, 94, 67]),
+    ("water", [63, 118, 228]),
+    ("bubble_column", [63, 118, 228]),
+    ("lava", [207, 92, 20]),
+    ("ice", [145, 183, 253]),
+    ("packed_ice", [141, 180, 250]),
+    ("blue_ice", [116, 167, 253]),
+    ("snow", [249, 254, 254]),
+    ("snow_block", [249, 254, 254]),
+    ("powder_snow", [248, 253, 253]),
+    ("obsidian", [15, 10, 24]),
+    ("bedrock", [85, 85, 85]),
+    ("netherrack", [97, 38, 38]),
+    ("soul_sand", [81, 62, 50]),
+    ("soul_soil", [75, 57, 46]),
+    ("basalt", [73, 72, 77]),
+    ("blackstone", [42, 36, 41]),
+    ("crimson_nylium", [130, 31, 31]),
+    ("warped_nylium", [43, 114, 101]),
+    ("end_stone", [219, 222, 158]),
+    ("grass", [95, 159, 53]),
+    ("short_grass", [95, 159, 53]),
+    ("tall_grass", [95, 159, 53]),
+    ("fern", [85, 140, 50]),
+    ("large_fern", [85, 140, 50]),
+    ("vine", [70, 120, 40]),
+    ("lily_pad", [32, 128, 48]),
+    ("seagrass", [40, 100, 60]),
+    ("tall_seagrass", [40, 100, 60]),
+    ("kelp", [60, 110, 40]),
+    ("kelp_plant", [60, 110, 40]),
+    ("sugar_cane", [148, 192, 101]),
+    ("cactus", [85, 127, 43]),
+    ("pumpkin", [198, 118, 24]),
+    ("melon", [111, 145, 30]),
+    ("glass", [175, 213, 219]),
+    ("bricks", [150, 97, 83]),
+    ("stone_bricks", [122, 121, 122]),
+    ("torch", [255, 214, 90]),
+];
+
+/// The colors of blocks which aren't listed by name, by the suffix of their name.
+const SUFFIX_COLORS: &[(&str, [u8; 3])] = &[
+    ("_leaves", [60, 120, 40]),
+    ("_log", [102, 81, 51]),
+    ("_wood", [102, 81, 51]),
+    ("_stem", [92, 25, 29]),
+    ("_planks", [162, 130, 78]),
+    ("_ore", [115, 115, 115]),
+    ("_terracotta", [152, 94, 67]),
+    ("_concrete", [140, 140, 140]),
+    ("_wool", [220, 220, 220]),
+    ("_flower", [200, 60, 60]),
+    ("_tulip", [200, 60, 60]),
+    ("_mushroom", [150, 110, 90]),
+    ("_coral", [200, 80, 120]),
+];
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to render")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where a PNG will be written for each region, named after its region file"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "block",
+        help = "Whether each pixel shows a single block or the average color of a whole chunk"
+    )]
+    scale: RenderScale,
+
+    #[clap(long, help = "Threads used for reading region files")]
+    threads: Option<u32>,
+
+    #[clap(flatten)]
+    filter: FilterOptions,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RenderScale {
+    /// One pixel per block, for a 512x512 image of each region
+    Block,
+    /// One pixel per chunk, for a 32x32 image of each region
+    Chunk,
+}
+
+/// The highest block of a column, and the Y level it is at.
+#[derive(Clone, Copy)]
+struct Column {
+    color: [u8; 3],
+    y: i32,
+}
+
+pub fn render_world(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file()
+            && RegionFile::is_region_name(&name)
+            && options
+                .filter
+                .includes_region(RegionFile::parse_name(&name))
+        {
+            files.push(path);
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let chunks: usize = pool
+        .install(|| {
+            files
+                .par_iter()
+                .map(|path| render_region(path, output_path, options))
+                .collect::<Result<Vec<_>, io::Error>>()
+        })?
+        .into_iter()
+        .sum();
+
+    report!(
+        info,
+        messages::RENDER_SUMMARY,
+        regions = files.len(),
+        chunks = chunks,
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Renders a region file to a PNG of the same name, returning the number of chunks drawn. Pixels of chunks which
+/// haven't been generated are left transparent.
+fn render_region(path: &Path, output_dir: &Path, options: &Options) -> Result<usize, io::Error> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let region_position = RegionFile::parse_name(&name);
+    let region = RegionFile::open(path)?;
+    let started = Instant::now();
+
+    let size = match options.scale {
+        RenderScale::Block => 32 * 16,
+        RenderScale::Chunk => 32,
+    };
+
+    let mut pixels = vec![0u8; size * size * 4];
+    let mut chunks = 0;
+
+    for result in region.stream_chunks() {
+        let chunk = match result? {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+
+        if !options
+            .filter
+            .includes_chunk(region_position, chunk.position)
+        {
+            continue;
+        }
+
+        let nbt = read_nbt(&chunk.data)?;
+        let sections = decode_sections(&nbt).map_err(|err| {
+            Message::new(messages::RENDER_DECODE_FAILED)
+                .arg("x", chunk.position.x)
+                .arg("z", chunk.position.z)
+                .arg("path", path.display())
+                .arg("reason", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })?;
+
+        let columns = top_columns(sections);
+        let (chunk_x, chunk_z) = (chunk.position.x as usize, chunk.position.z as usize);
+
+        match options.scale {
+            RenderScale::Block => {
+                for z in 0..16 {
+                    for x in 0..16 {
+                        let column = match columns[(z * 16) + x] {
+                            Some(column) => column,
+                            None => continue,
+                        };
+
+                        // Blocks higher than the one to their north are lit, and lower ones are shaded, so that
+                        // terrain has some relief as it does on in-game maps
+                        let north = if z > 0 {
+                            columns[((z - 1) * 16) + x]
+                        } else {
+                            None
+                        };
+                        let shade = match north {
+                            Some(north) if column.y > north.y => 1.15,
+                            Some(north) if column.y < north.y => 0.85,
+                            _ => 1.0,
+                        };
+
+                        let pixel = ((chunk_z * 16) + z) * size + (chunk_x * 16) + x;
+                        set_pixel(&mut pixels, pixel, shade_color(column.color, shade));
+                    }
+                }
+            }
+            RenderScale::Chunk => {
+                let drawn: Vec<[u8; 3]> = columns
+                    .iter()
+                    .flatten()
+                    .map(|column| column.color)
+                    .collect();
+
+                if !drawn.is_empty() {
+                    let mut total = [0u32; 3];
+
+                    for color in &drawn {
+                        for (channel, value) in total.iter_mut().zip(color) {
+                            *channel += *value as u32;
+                        }
+                    }
+
+                    let average = total.map(|channel| (channel / drawn.len() as u32) as u8);
+                    set_pixel(&mut pixels, (chunk_z * size) + chunk_x, average);
+                }
+            }
+        }
+
+        chunks += 1;
+    }
+
+    let output_path = output_dir.join(Path::new(&name).with_extension("png"));
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(output_path)?),
+        size as u32,
+        size as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(&pixels)?;
+    png_writer.finish()?;
+
+    report!(
+        debug,
+        messages::RENDER_REGION,
+        region = name,
+        chunks = chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(chunks)
+}
+
+/// Finds the highest block of each column in a chunk, in ZX order. Heightmaps aren't used since stripped chunks don't
+/// have them, so each column is scanned from the top down instead.
+fn top_columns(mut sections: Vec<BlockSection>) -> Vec<Option<Column>> {
+    sections.sort_by_key(|section| std::cmp::Reverse(section.y));
+
+    let mut columns: Vec<Option<Column>> = vec![None; 16 * 16];
+    let mut remaining = columns.len();
+
+    for section in &sections {
+        let colors: Vec<Option<[u8; 3]>> = section
+            .palette
+            .iter()
+            .map(|entry| block_color(block_name(entry).unwrap_or("minecraft:air")))
+            .collect();
+
+        // Sections made up entirely of air can't contain the top of any column
+        if colors.iter().all(Option::is_none) {
+            continue;
+        }
+
+        for (index, column) in columns.iter_mut().enumerate() {
+            if column.is_some() {
+                continue;
+            }
+
+            for y in (0..16).rev() {
+                if let Some(color) = colors[section.indices[(y * 256) + index] as usize] {
+                    *column = Some(Column {
+                        color,
+                        y: (section.y * 16) + y as i32,
+                    });
+                    remaining -= 1;
+                    break;
+                }
+            }
+        }
+
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    columns
+}
+
+/// Returns the color a block is drawn with, or `None` if the block can be seen through from above.
+fn block_color(name: &str) -> Option<[u8; 3]> {
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+
+    if matches!(
+        name,
+        "air" | "cave_air" | "void_air" | "barrier" | "light" | "structure_void"
+    ) {
+        return None;
+    }
+
+    if let Some((_, color)) = BLOCK_COLORS.iter().find(|(block, _)| *block == name) {
+        return Some(*color);
+    }
+
+    if let Some((_, color)) = SUFFIX_COLORS
+        .iter()
+        .find(|(suffix, _)| name.ends_with(suffix))
+    {
+        return Some(*color);
+    }
+
+    // Any other block is given a color of its own, mixed with gray so that it doesn't stand out too much
+    let hash = xxh64(name.as_bytes(), 0).to_le_bytes();
+
+    Some([
+        ((hash[0] as u16 + 128) / 2) as u8,
+        ((hash[1] as u16 + 128) / 2) as u8,
+        ((hash[2] as u16 + 128) / 2) as u8,
+    ])
+}
+
+fn shade_color(color: [u8; 3], shade: f32) -> [u8; 3] {
+    color.map(|channel| (channel as f32 * shade).min(255.0) as u8)
+}
+
+fn set_pixel(pixels: &mut [u8], pixel: usize, color: [u8; 3]) {
+    pixels[pixel * 4..(pixel * 4) + 3].copy_from_slice(&color);
+    pixels[(pixel * 4) + 3] = 255;
+}
@@ -0,0 +1,182 @@
+use clap::Parser;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::{block_state_string, parse_block_state, replace_blocks, BlockReplacement};
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::observer::NullObserver;
+use crate::read_only;
+use crate::region::{RegionFile, RegionFileWriter};
+use crate::retry::{RetryOptions, RetryPolicy};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to rewrite")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where rewritten region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        required = true,
+        help = "Replace a block with another, written as FROM->TO, such as diamond_ore->stone or oak_log[axis=y]->stone; a block name without properties matches all of its states (may be repeated, the first match wins)",
+        value_parser = parse_replacement
+    )]
+    replace: Vec<BlockReplacement>,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+}
+
+fn parse_replacement(value: &str) -> Result<BlockReplacement, String> {
+    let (from, to) = value
+        .split_once("->")
+        .ok_or_else(|| format!("Expected a replacement in the form FROM->TO: {}", value))?;
+
+    // The source is parsed too, so that it is matched against the same canonical form as the palettes
+    Ok(BlockReplacement {
+        from: block_state_string(&parse_block_state(from)?),
+        to: parse_block_state(to)?,
+    })
+}
+
+pub fn replace_files(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let retry = RetryPolicy::new(&options.retry);
+    let mut report = ReplaceReport {
+        replaced: vec![0; options.replace.len()],
+        ..ReplaceReport::default()
+    };
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            replace_file(output_path, &path, &options.replace, &mut report, &retry)?;
+        }
+    }
+
+    report.print(options);
+    retry.report(&NullObserver);
+
+    Ok(())
+}
+
+fn replace_file(
+    output_dir: &Path,
+    path: &Path,
+    replacements: &[BlockReplacement],
+    report: &mut ReplaceReport,
+    retry: &RetryPolicy,
+) -> Result<(), io::Error> {
+    let name = path.file_name().unwrap();
+
+    let in_region = retry.run(|| RegionFile::open(path))?;
+    let mut out_region = retry.run(|| RegionFileWriter::create(&Path::join(output_dir, name)))?;
+    let mut changed_chunks = 0;
+    let started = Instant::now();
+
+    for result in retry.stream_raw_chunks(&in_region) {
+        let raw_chunk = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        let chunk = raw_chunk.decompress()?;
+        let mut nbt = read_nbt(&chunk.data)?;
+
+        let changed =
+            replace_blocks(&mut nbt, replacements, &mut report.replaced).map_err(|reason| {
+                Message::new(messages::REPLACE_BLOCKS_INVALID_CHUNK)
+                    .arg("region", name.to_string_lossy())
+                    .arg("x", chunk.position.x)
+                    .arg("z", chunk.position.z)
+                    .arg("reason", reason)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+        report.chunks += 1;
+
+        // Chunks which hold none of the blocks can be copied without recompressing them
+        if !changed {
+            retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+            continue;
+        }
+
+        let changed_chunk = chunk.with_data(write_nbt(&nbt)?.into_boxed_slice());
+
+        retry.run(|| out_region.add_chunk(&changed_chunk))?;
+        changed_chunks += 1;
+    }
+
+    out_region.finish()?;
+
+    report.changed_chunks += changed_chunks;
+
+    report!(
+        debug,
+        messages::REPLACE_BLOCKS_REGION,
+        region = name.to_string_lossy().into_owned(),
+        changed_chunks = changed_chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(())
+}
+
+/// Counts the chunks rewritten and the blocks changed by each replacement, in the order the replacements were given.
+#[derive(Default)]
+struct ReplaceReport {
+    chunks: u64,
+    changed_chunks: u64,
+    replaced: Vec<u64>,
+}
+
+impl ReplaceReport {
+    fn print(&self, options: &Options) {
+        report!(
+            info,
+            messages::REPLACE_BLOCKS_SUMMARY,
+            changed_chunks = self.changed_chunks,
+            chunks = self.chunks
+        );
+
+        for (replacement, &blocks) in options.replace.iter().zip(&self.replaced) {
+            report!(
+                info,
+                messages::REPLACE_BLOCKS_REPLACED,
+                blocks = blocks,
+                from = replacement.from.clone(),
+                to = block_state_string(&replacement.to)
+            );
+        }
+    }
+}
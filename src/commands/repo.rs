@@ -0,0 +1,327 @@
+use clap::{Parser, Subcommand};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::commands::archive::chunk_entry_path;
+use crate::manifest::hash_chunk_data;
+use crate::messages::{self, report, Message};
+use crate::read_only;
+use crate::region::{ChunkPos, RawChunk, RegionFile, RegionFileWriter};
+use crate::repository::{Repository, Snapshot, SnapshotEntry};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(subcommand)]
+    command: RepoCommand,
+}
+
+#[derive(Subcommand)]
+enum RepoCommand {
+    /// Create an empty repository, which stores the chunks of every backup by their contents
+    Init(InitOptions),
+    /// Store the chunks of a world which the repository doesn't hold yet, and record a snapshot of the world
+    Backup(BackupOptions),
+    /// Write the region files of a world as it was when a snapshot was taken
+    Restore(RestoreOptions),
+    /// Remove old snapshots, and the chunks which no remaining snapshot refers to
+    Prune(PruneOptions),
+}
+
+#[derive(Parser)]
+struct InitOptions {
+    #[clap(long, help = "Directory of the repository to create")]
+    repo: String,
+}
+
+#[derive(Parser)]
+struct BackupOptions {
+    #[clap(long, help = "Directory of the repository to back up into")]
+    repo: String,
+
+    #[clap(long, help = "Input directory of region (.mca) files to back up")]
+    input_dir: String,
+}
+
+#[derive(Parser)]
+struct RestoreOptions {
+    #[clap(long, help = "Directory of the repository to restore from")]
+    repo: String,
+
+    #[clap(long, help = "ID of the snapshot to restore (default is the newest)")]
+    snapshot: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Output directory where the restored region files will be stored"
+    )]
+    output_dir: String,
+}
+
+#[derive(Parser)]
+struct PruneOptions {
+    #[clap(long, help = "Directory of the repository to prune")]
+    repo: String,
+
+    #[clap(long, help = "Keep only this many of the newest snapshots")]
+    keep_last: Option<usize>,
+
+    #[clap(long, help = "ID of a snapshot to remove (may be repeated)")]
+    snapshot: Vec<u64>,
+}
+
+pub fn run(options: &Options) -> Result<(), io::Error> {
+    match &options.command {
+        RepoCommand::Init(options) => init(options),
+        RepoCommand::Backup(options) => backup(options),
+        RepoCommand::Restore(options) => restore(options),
+        RepoCommand::Prune(options) => prune(options),
+    }
+}
+
+fn init(options: &InitOptions) -> Result<(), io::Error> {
+    let repository = Repository::init(Path::new(&options.repo))?;
+
+    report!(
+        info,
+        messages::REPO_INITIALIZED,
+        path = repository.path().display().to_string()
+    );
+
+    Ok(())
+}
+
+/// Counts the chunks read by a backup, and the blobs which weren't already stored in the repository.
+#[derive(Default)]
+struct BackupReport {
+    chunks: u64,
+    regions: u64,
+    new_blobs: u64,
+    new_bytes: u64,
+}
+
+fn backup(options: &BackupOptions) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    let repository = Repository::open(Path::new(&options.repo))?;
+    read_only::ensure_writable(repository.path())?;
+
+    let mut snapshot = Snapshot::new();
+    let mut report = BackupReport::default();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if !path.is_file() || !RegionFile::is_region_name(&name) {
+            continue;
+        }
+
+        let region_position = RegionFile::parse_name(&name);
+        let region = RegionFile::open(&path)?;
+        let mut chunks = 0;
+        let mut new_blobs = 0;
+
+        for result in region.stream_raw_chunks() {
+            let raw_chunk = match result? {
+                Some(raw_chunk) => raw_chunk,
+                None => continue,
+            };
+
+            // Blobs are named by the NBT data rather than the payload, so that a chunk which was only recompressed
+            // still shares its blob
+            let hash = hash_chunk_data(&raw_chunk.decompress()?.data);
+
+            if repository.write_blob(hash, &raw_chunk.payload)? {
+                new_blobs += 1;
+                report.new_bytes += raw_chunk.payload.len() as u64;
+            }
+
+            let timestamp = region.timestamp(raw_chunk.position)?;
+            snapshot.insert(
+                region_position,
+                raw_chunk.position,
+                SnapshotEntry { hash, timestamp },
+            );
+
+            chunks += 1;
+        }
+
+        report!(
+            debug,
+            messages::REPO_BACKUP_REGION,
+            region = name,
+            chunks = chunks,
+            new_blobs = new_blobs
+        );
+
+        report.chunks += chunks;
+        report.new_blobs += new_blobs;
+        report.regions += 1;
+    }
+
+    let id = repository.save_snapshot(&snapshot)?;
+
+    report!(
+        info,
+        messages::REPO_BACKED_UP,
+        snapshot = id,
+        chunks = report.chunks,
+        regions = report.regions,
+        new_blobs = report.new_blobs,
+        bytes = report.new_bytes
+    );
+
+    Ok(())
+}
+
+fn restore(options: &RestoreOptions) -> Result<(), io::Error> {
+    let repository = Repository::open(Path::new(&options.repo))?;
+    let output_path = Path::new(&options.output_dir);
+
+    let id = match options.snapshot {
+        Some(id) => id,
+        None => *repository.snapshots()?.last().ok_or_else(|| {
+            Message::new(messages::REPO_NO_SNAPSHOTS)
+                .arg("path", repository.path().display())
+                .into_error(io::ErrorKind::NotFound)
+        })?,
+    };
+
+    let snapshot = repository.load_snapshot(id)?;
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut regions: BTreeMap<(i32, i32), Vec<(ChunkPos, SnapshotEntry)>> = BTreeMap::new();
+
+    for (region, chunk, entry) in snapshot.iter() {
+        regions
+            .entry((region.x, region.z))
+            .or_default()
+            .push((chunk, entry));
+    }
+
+    for (&(x, z), chunks) in &mut regions {
+        // Snapshots aren't ordered, so chunks are sorted to make restoring the same snapshot give identical files
+        chunks.sort_unstable_by_key(|&(chunk, _)| (chunk.z, chunk.x));
+
+        let region_position = ChunkPos { x, z };
+        let mut writer = RegionFileWriter::create(&output_path.join(format!("r.{}.{}.mca", x, z)))?;
+
+        for &(chunk, entry) in chunks.iter() {
+            let blob_error = |id| {
+                Message::new(id)
+                    .arg("hash", format!("{:016x}", entry.hash))
+                    .arg("entry", chunk_entry_path(region_position, chunk))
+                    .into_error(io::ErrorKind::InvalidData)
+            };
+
+            let raw_chunk = RawChunk {
+                payload: repository
+                    .read_blob(entry.hash)?
+                    .ok_or_else(|| blob_error(messages::REPO_BLOB_MISSING))?,
+                position: chunk,
+            };
+
+            match raw_chunk.decompress() {
+                Ok(data) if hash_chunk_data(&data.data) == entry.hash => {}
+                _ => return Err(blob_error(messages::REPO_BLOB_CORRUPT)),
+            }
+
+            writer.add_raw_chunk(&raw_chunk)?;
+            writer.set_timestamp(chunk, entry.timestamp);
+        }
+
+        writer.finish()?;
+    }
+
+    report!(
+        info,
+        messages::REPO_RESTORED,
+        snapshot = id,
+        chunks = snapshot.len(),
+        regions = regions.len(),
+        path = output_path.display().to_string()
+    );
+
+    Ok(())
+}
+
+fn prune(options: &PruneOptions) -> Result<(), io::Error> {
+    let repository = Repository::open(Path::new(&options.repo))?;
+    read_only::ensure_writable(repository.path())?;
+
+    let snapshots = repository.snapshots()?;
+
+    for id in &options.snapshot {
+        if !snapshots.contains(id) {
+            return Err(Message::new(messages::REPO_SNAPSHOT_MISSING)
+                .arg("snapshot", id)
+                .into_error(io::ErrorKind::NotFound));
+        }
+    }
+
+    let keep_from = match options.keep_last {
+        Some(keep_last) => snapshots.len().saturating_sub(keep_last),
+        None => 0,
+    };
+
+    let mut removed = Vec::new();
+    let mut kept = Vec::new();
+
+    for (index, &id) in snapshots.iter().enumerate() {
+        if index < keep_from || options.snapshot.contains(&id) {
+            removed.push(id);
+        } else {
+            kept.push(id);
+        }
+    }
+
+    // Every snapshot which is kept is read before anything is removed, so that a damaged snapshot doesn't lose the
+    // blobs it refers to
+    let mut referenced: HashSet<u64> = HashSet::new();
+
+    for &id in &kept {
+        for (_, _, entry) in repository.load_snapshot(id)?.iter() {
+            referenced.insert(entry.hash);
+        }
+    }
+
+    for &id in &removed {
+        repository.remove_snapshot(id)?;
+
+        report!(debug, messages::REPO_SNAPSHOT_REMOVED, snapshot = id);
+    }
+
+    let mut removed_blobs = 0;
+    let mut removed_bytes = 0;
+
+    for hash in repository.blobs()? {
+        if !referenced.contains(&hash) {
+            removed_bytes += repository.remove_blob(hash)?;
+            removed_blobs += 1;
+        }
+    }
+
+    report!(
+        info,
+        messages::REPO_PRUNED,
+        snapshots = kept.len(),
+        removed_snapshots = removed.len(),
+        blobs = removed_blobs,
+        bytes = removed_bytes
+    );
+
+    Ok(())
+}
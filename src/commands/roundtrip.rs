@@ -0,0 +1,217 @@
+use clap::{Parser, ValueEnum};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::messages::{self, Message, MessageId};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::read_only;
+use crate::region::{ChunkPos, RawChunk, RegionFile, RegionFileWriter};
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(long, help = "Input directory of region (.mca) files to copy")]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where the copied region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "How chunks are copied",
+        default_value = "reencode"
+    )]
+    mode: RoundtripMode,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum RoundtripMode {
+    /// Copy each chunk's compressed payload verbatim, which tests reading and writing region files
+    Raw,
+    /// Decompress and decode each chunk, then encode and compress it again, which also tests the NBT codec
+    Reencode,
+}
+
+/// A chunk which did not survive being copied, and why.
+struct Difference {
+    region: String,
+    position: ChunkPos,
+    reason: MessageId,
+}
+
+pub fn roundtrip_files(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::exists(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let mut regions = 0;
+    let mut chunks = 0;
+    let mut differences = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if !path.is_file() || !RegionFile::is_region_name(&name) {
+            continue;
+        }
+
+        let output_file = output_path.join(&name);
+
+        chunks += copy_region(&path, &output_file, options.mode)?;
+        regions += 1;
+
+        compare_regions(&name, &path, &output_file, options.mode, &mut differences)?;
+    }
+
+    for difference in &differences {
+        println!(
+            "{}",
+            Message::new(messages::ROUNDTRIP_DIFFERENCE)
+                .arg("region", &difference.region)
+                .arg("x", difference.position.x)
+                .arg("z", difference.position.z)
+                .arg("reason", Message::new(difference.reason))
+        );
+    }
+
+    println!(
+        "{}",
+        Message::new(messages::ROUNDTRIP_SUMMARY)
+            .arg("chunks", chunks)
+            .arg("regions", regions)
+            .arg("differences", differences.len())
+    );
+
+    if !differences.is_empty() {
+        return Err(Message::new(messages::ROUNDTRIP_CHANGED)
+            .arg("differences", differences.len())
+            .into_error(io::ErrorKind::InvalidData));
+    }
+
+    Ok(())
+}
+
+/// Copies every chunk of a region file without transforming it, returning the number of chunks copied.
+fn copy_region(input: &Path, output: &Path, mode: RoundtripMode) -> Result<usize, io::Error> {
+    let in_region = RegionFile::open(input)?;
+    let mut out_region = RegionFileWriter::create(output)?;
+    let mut chunks = 0;
+
+    for result in in_region.stream_raw_chunks() {
+        let raw_chunk = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        match mode {
+            RoundtripMode::Raw => out_region.add_raw_chunk(&raw_chunk)?,
+            RoundtripMode::Reencode => {
+                let chunk = raw_chunk.decompress()?;
+                let nbt = read_nbt(&chunk.data)?;
+
+                out_region.add_chunk(&chunk.with_data(write_nbt(&nbt)?.into_boxed_slice()))?;
+            }
+        }
+
+        chunks += 1;
+    }
+
+    out_region.finish()?;
+
+    Ok(chunks)
+}
+
+/// Compares the chunks of the original and copied region files. Raw copies must have identical payloads, while
+/// re-encoded copies must decode to the same NBT, since compression and the order of compound tags may change.
+fn compare_regions(
+    name: &str,
+    input: &Path,
+    output: &Path,
+    mode: RoundtripMode,
+    differences: &mut Vec<Difference>,
+) -> Result<(), io::Error> {
+    let mut difference = |position: ChunkPos, reason: MessageId| {
+        differences.push(Difference {
+            region: String::from(name),
+            position,
+            reason,
+        })
+    };
+
+    let mut copied: HashMap<ChunkPos, RawChunk> = HashMap::new();
+
+    for result in RegionFile::open(output)?.stream_raw_chunks() {
+        if let Some(raw_chunk) = result? {
+            copied.insert(raw_chunk.position, raw_chunk);
+        }
+    }
+
+    for result in RegionFile::open(input)?.stream_raw_chunks() {
+        let original = match result? {
+            Some(raw_chunk) => raw_chunk,
+            None => continue,
+        };
+
+        let copy = match copied.remove(&original.position) {
+            Some(copy) => copy,
+            None => {
+                difference(original.position, messages::ROUNDTRIP_MISSING);
+                continue;
+            }
+        };
+
+        let identical = match mode {
+            RoundtripMode::Raw => original.payload == copy.payload,
+            RoundtripMode::Reencode => {
+                let copy_data = match copy.decompress() {
+                    Ok(chunk) => chunk.data,
+                    Err(_) => {
+                        difference(original.position, messages::ROUNDTRIP_UNREADABLE);
+                        continue;
+                    }
+                };
+
+                match read_nbt(&copy_data) {
+                    Ok(copy_nbt) => read_nbt(&original.decompress()?.data)? == copy_nbt,
+                    Err(_) => {
+                        difference(original.position, messages::ROUNDTRIP_INVALID_NBT);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if !identical {
+            difference(original.position, messages::ROUNDTRIP_MISMATCH);
+        }
+    }
+
+    for position in copied.into_keys() {
+        difference(position, messages::ROUNDTRIP_EXTRA);
+    }
+
+    Ok(())
+}
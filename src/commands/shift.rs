@@ -0,0 +1,409 @@
+use clap::{ArgGroup, Parser};
+use fastnbt::Value;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::messages::{self, report, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::read_only;
+use crate::region::{Chunk, ChunkPos, RegionFile, RegionFileWriter};
+
+#[derive(Parser)]
+#[clap(group(ArgGroup::new("offset").required(true).args(&["chunks", "regions"])))]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Input directory of region (.mca) files to shift, or of entity or POI files"
+    )]
+    input_dir: String,
+
+    #[clap(
+        long,
+        help = "Output directory where the shifted region files will be stored"
+    )]
+    output_dir: String,
+
+    #[clap(
+        long,
+        help = "Offset to move every chunk by, in chunks, such as 64,-32",
+        value_parser = parse_offset,
+        allow_hyphen_values = true
+    )]
+    chunks: Option<ChunkPos>,
+
+    #[clap(
+        long,
+        help = "Offset to move every chunk by, in whole regions, such as 2,-1",
+        value_parser = parse_offset,
+        allow_hyphen_values = true
+    )]
+    regions: Option<ChunkPos>,
+
+    #[clap(long, help = "Threads used for shifting region files")]
+    threads: Option<u32>,
+}
+
+pub fn shift_world(options: &Options) -> Result<(), io::Error> {
+    let input_path = Path::new(&options.input_dir);
+    let output_path = Path::new(&options.output_dir);
+
+    if !Path::is_dir(input_path) {
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
+    }
+
+    if input_path == output_path {
+        return Err(
+            Message::new(messages::IN_PLACE_UNSUPPORTED).into_error(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    read_only::ensure_writable(output_path)?;
+
+    if !Path::exists(output_path) {
+        fs::create_dir_all(output_path)?;
+    }
+
+    let offset = match (options.chunks, options.regions) {
+        (Some(chunks), _) => chunks,
+        (None, Some(regions)) => ChunkPos {
+            x: regions.x * 32,
+            z: regions.z * 32,
+        },
+        (None, None) => unreachable!(),
+    };
+
+    let mut sources: HashMap<ChunkPos, PathBuf> = HashMap::new();
+
+    for entry in fs::read_dir(input_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        if path.is_file() && RegionFile::is_region_name(&name) {
+            sources.insert(RegionFile::parse_name(&name), path);
+        }
+    }
+
+    // Unless the offset is a whole number of regions, the chunks of each region are spread across up to four regions,
+    // so each target region is written in turn from every region which has chunks that move into it
+    let mut targets: BTreeMap<(i32, i32), BTreeSet<(i32, i32)>> = BTreeMap::new();
+
+    for region in sources.keys() {
+        for target in target_regions(*region, offset) {
+            targets
+                .entry((target.x, target.z))
+                .or_default()
+                .insert((region.x, region.z));
+        }
+    }
+
+    let targets: Vec<_> = targets.into_iter().collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(1) as usize)
+        .build()
+        .unwrap();
+
+    let shifted = pool.install(|| {
+        targets
+            .par_iter()
+            .map(|((x, z), target_sources)| {
+                let source_paths: Vec<&PathBuf> = target_sources
+                    .iter()
+                    .map(|&(x, z)| &sources[&ChunkPos { x, z }])
+                    .collect();
+
+                shift_into_region(
+                    output_path,
+                    ChunkPos { x: *x, z: *z },
+                    &source_paths,
+                    offset,
+                )
+            })
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let chunks: usize = shifted.iter().sum();
+    let regions = shifted.iter().filter(|&&chunks| chunks > 0).count();
+
+    report!(
+        info,
+        messages::SHIFT_SUMMARY,
+        chunks = chunks,
+        regions = regions,
+        x = offset.x,
+        z = offset.z
+    );
+
+    Ok(())
+}
+
+/// Writes every chunk which moves into the target region, from each of the regions it can come from, returning the
+/// number of chunks written.
+fn shift_into_region(
+    output_dir: &Path,
+    target: ChunkPos,
+    sources: &[&PathBuf],
+    offset: ChunkPos,
+) -> Result<usize, io::Error> {
+    let name = format!("r.{}.{}.mca", target.x, target.z);
+    let started = Instant::now();
+
+    let mut writer: Option<RegionFileWriter> = None;
+    let mut chunks = 0;
+
+    for path in sources {
+        let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+        let region = RegionFile::open(path)?;
+
+        for result in region.stream_chunks() {
+            let chunk = match result? {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+
+            if target_region(region_position, chunk.position, offset) != target {
+                continue;
+            }
+
+            let mut nbt = read_nbt(&chunk.data)?;
+            shift_chunk(&mut nbt, offset);
+
+            let shifted = Chunk {
+                data: write_nbt(&nbt)?.into_boxed_slice(),
+                position: ChunkPos {
+                    x: (region_position.x * 32 + chunk.position.x + offset.x).rem_euclid(32),
+                    z: (region_position.z * 32 + chunk.position.z + offset.z).rem_euclid(32),
+                },
+            };
+
+            // Regions which only overlap the shifted world at a corner may not receive any chunks at all
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => writer.insert(RegionFileWriter::create(&output_dir.join(&name))?),
+            };
+
+            // The chunk keeps the time it was last saved, so that `pack --since` still sees it as unmodified
+            writer.add_chunk(&shifted)?;
+            writer.set_timestamp(shifted.position, region.timestamp(chunk.position)?);
+            chunks += 1;
+        }
+    }
+
+    let writer = match writer {
+        Some(writer) => writer,
+        None => return Ok(0),
+    };
+
+    writer.finish()?;
+
+    report!(
+        debug,
+        messages::SHIFT_REGION,
+        region = name,
+        chunks = chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
+    Ok(chunks)
+}
+
+/// Returns the regions which the chunks of a region can move into, which are the regions its corners move into.
+pub fn target_regions(region: ChunkPos, offset: ChunkPos) -> [ChunkPos; 4] {
+    [(0, 0), (31, 0), (0, 31), (31, 31)]
+        .map(|(x, z)| target_region(region, ChunkPos { x, z }, offset))
+}
+
+/// Returns the region which a chunk moves into, given its region and its position within that region.
+pub fn target_region(region: ChunkPos, chunk: ChunkPos, offset: ChunkPos) -> ChunkPos {
+    ChunkPos {
+        x: (region.x * 32 + chunk.x + offset.x).div_euclid(32),
+        z: (region.z * 32 + chunk.z + offset.z).div_euclid(32),
+    }
+}
+
+/// Moves everything in a chunk which records its own position by the offset. This covers the chunk's position, its
+/// entities, block entities, scheduled ticks, and the structures which start in or reference it, in chunks saved by
+/// any version since 1.13, as well as the POI and entity files added in 1.14 and 1.17. Data which mods or plugins keep
+/// in their own tags isn't moved.
+pub fn shift_chunk(nbt: &mut Value, offset: ChunkPos) {
+    let blocks = (offset.x * 16, offset.z * 16);
+
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return,
+    };
+
+    // Entity files store their position as an array on the root, rather than in a level compound
+    if let Some(Value::IntArray(position)) = root.get_mut("Position") {
+        if position.len() == 2 {
+            position[0] += offset.x;
+            position[1] += offset.z;
+        }
+    }
+
+    // POI files store the position of each record in every section
+    if let Some(Value::Compound(sections)) = root.get_mut("Sections") {
+        for section in sections.values_mut() {
+            if let Some(Value::List(records)) = get_mut(section, "Records") {
+                for record in records {
+                    shift_position_array(get_mut(record, "pos"), blocks);
+                }
+            }
+        }
+    }
+
+    let level = match root.get_mut("Level") {
+        Some(Value::Compound(level)) => level,
+        _ => root,
+    };
+
+    shift_int(level.get_mut("xPos"), offset.x);
+    shift_int(level.get_mut("zPos"), offset.z);
+
+    for key in &["Entities", "entities"] {
+        if let Some(Value::List(entities)) = level.get_mut(*key) {
+            for entity in entities {
+                shift_entity(entity, blocks);
+            }
+        }
+    }
+
+    for key in &[
+        "block_entities",
+        "TileEntities",
+        "block_ticks",
+        "fluid_ticks",
+        "TileTicks",
+        "LiquidTicks",
+    ] {
+        if let Some(Value::List(entries)) = level.get_mut(*key) {
+            for entry in entries {
+                shift_int(get_mut(entry, "x"), blocks.0);
+                shift_int(get_mut(entry, "z"), blocks.1);
+            }
+        }
+    }
+
+    for key in &["structures", "Structures"] {
+        if let Some(Value::Compound(structures)) = level.get_mut(*key) {
+            shift_structures(structures, offset);
+        }
+    }
+}
+
+fn shift_entity(entity: &mut Value, blocks: (i32, i32)) {
+    if let Some(Value::List(position)) = get_mut(entity, "Pos") {
+        if let [Value::Double(x), _, Value::Double(z)] = &mut position[..] {
+            *x += blocks.0 as f64;
+            *z += blocks.1 as f64;
+        }
+    }
+
+    // Hanging entities such as item frames and paintings also record the block they are attached to
+    shift_int(get_mut(entity, "TileX"), blocks.0);
+    shift_int(get_mut(entity, "TileZ"), blocks.1);
+
+    if let Some(Value::List(passengers)) = get_mut(entity, "Passengers") {
+        for passenger in passengers {
+            shift_entity(passenger, blocks);
+        }
+    }
+}
+
+/// Moves the structures which start in a chunk, along with the bounding boxes of their pieces, and the positions of
+/// the chunks which structures referenced by the chunk start in.
+fn shift_structures(structures: &mut HashMap<String, Value>, offset: ChunkPos) {
+    let blocks = (offset.x * 16, offset.z * 16);
+
+    for key in &["starts", "Starts"] {
+        if let Some(Value::Compound(starts)) = structures.get_mut(*key) {
+            for start in starts.values_mut() {
+                shift_int(get_mut(start, "ChunkX"), offset.x);
+                shift_int(get_mut(start, "ChunkZ"), offset.z);
+                shift_bounding_box(get_mut(start, "BB"), blocks);
+
+                if let Some(Value::List(children)) = get_mut(start, "Children") {
+                    for child in children {
+                        shift_bounding_box(get_mut(child, "BB"), blocks);
+                        shift_int(get_mut(child, "TPX"), blocks.0);
+                        shift_int(get_mut(child, "TPZ"), blocks.1);
+                    }
+                }
+            }
+        }
+    }
+
+    // References are packed with the X coordinate of the chunk in the low 32 bits and the Z coordinate in the high bits
+    for key in &["References", "references"] {
+        if let Some(Value::Compound(references)) = structures.get_mut(*key) {
+            for positions in references.values_mut() {
+                if let Value::LongArray(positions) = positions {
+                    for position in positions.iter_mut() {
+                        let x = (*position as i32).wrapping_add(offset.x);
+                        let z = ((*position >> 32) as i32).wrapping_add(offset.z);
+
+                        *position = (x as u32 as i64) | ((z as i64) << 32);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn shift_bounding_box(value: Option<&mut Value>, blocks: (i32, i32)) {
+    if let Some(Value::IntArray(bounds)) = value {
+        if bounds.len() == 6 {
+            bounds[0] += blocks.0;
+            bounds[2] += blocks.1;
+            bounds[3] += blocks.0;
+            bounds[5] += blocks.1;
+        }
+    }
+}
+
+fn shift_position_array(value: Option<&mut Value>, blocks: (i32, i32)) {
+    if let Some(Value::IntArray(position)) = value {
+        if position.len() == 3 {
+            position[0] += blocks.0;
+            position[2] += blocks.1;
+        }
+    }
+}
+
+fn shift_int(value: Option<&mut Value>, amount: i32) {
+    if let Some(Value::Int(value)) = value {
+        *value += amount;
+    }
+}
+
+fn get_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Compound(compound) => compound.get_mut(key),
+        _ => None,
+    }
+}
+
+/// Parses an offset such as `64,-32`.
+pub fn parse_offset(value: &str) -> Result<ChunkPos, String> {
+    let (x, z) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Expected an offset such as 64,-32: {}", value))?;
+
+    let parse = |coordinate: &str| {
+        coordinate
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid offset: {}", value))
+    };
+
+    Ok(ChunkPos {
+        x: parse(x)?,
+        z: parse(z)?,
+    })
+}
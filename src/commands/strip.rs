@@ -1,101 +1,540 @@
 use clap::Parser;
-use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::region::{Chunk, RegionFile, RegionFileWriter};
+use fastnbt::Value;
 
-#[derive(Parser)]
+use crate::blocks::{data_version, qualify};
+use crate::dimension::{DimensionOptions, REGION_DIR};
+use crate::filter::{is_proto_chunk, StatusFilter, StatusOptions};
+use crate::keep_going::{KeepGoingOptions, SkippedChunks};
+use crate::messages::{self, report, Message};
+use crate::nbt::payload_size;
+use crate::observer::{NullObserver, OperationObserver, ProgressObserver};
+use crate::position_check::{PositionCheck, PositionCheckOptions};
+use crate::read_only;
+use crate::region::{
+    AnyRegionFile, Chunk, ChunkLayout, ChunkPos, RawChunk, RegionFile, RegionFileWriter,
+};
+use crate::retry::{RetryOptions, RetryPolicy};
+use crate::stripping::{needs_strip, strip_chunk_with, StripReport, StripRules};
+use crate::version_rules::{parse_version_range, VersionRules};
+
+/// Added to the name of a region file for its stripped copy while it is written next to the original with `--in-place`.
+const IN_PLACE_SUFFIX: &str = ".tmp";
+
+/// The heightmaps which chunks save, of which `--keep-heightmaps` can keep any.
+const HEIGHTMAP_NAMES: [&str; 6] = [
+    "MOTION_BLOCKING",
+    "MOTION_BLOCKING_NO_LEAVES",
+    "OCEAN_FLOOR",
+    "OCEAN_FLOOR_WG",
+    "WORLD_SURFACE",
+    "WORLD_SURFACE_WG",
+];
+
+#[derive(Parser, Clone)]
 pub struct Options {
-    #[clap(long, help = "Input directory of region (.mca) files to strip")]
+    #[clap(
+        long,
+        help = "Input directory of region files to strip, in the Anvil (.mca), Linear (.linear) or MCRegion (.mcr) format"
+    )]
     input_dir: String,
 
     #[clap(
         long,
+        required_unless_present = "in_place",
         help = "Output directory where stripped region files will be stored"
     )]
-    output_dir: String,
+    output_dir: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "output_dir",
+        help = "Replace each region file with its stripped copy once the copy is complete, instead of writing to an output directory"
+    )]
+    in_place: bool,
+
+    #[clap(
+        long,
+        requires = "in_place",
+        help = "Keep each original region file with this suffix added to its name, such as .bak"
+    )]
+    backup_suffix: Option<String>,
+
+    #[clap(flatten)]
+    status: StatusOptions,
+
+    #[clap(
+        long,
+        help = "Omit chunks which haven't reached the features stage of generation, since the server will regenerate them"
+    )]
+    drop_proto_chunks: bool,
+
+    #[clap(
+        long,
+        help = "Also remove structure starts and references, which stops structures from being found or spawning their mobs"
+    )]
+    strip_structures: bool,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        requires = "strip_structures",
+        help = "Structures to keep when stripping structures, such as minecraft:stronghold or minecraft:village_* (comma-separated)"
+    )]
+    keep_structures: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Also remove scheduled block and fluid ticks (block_ticks and fluid_ticks, or TileTicks and LiquidTicks before 1.18), which delays pending updates such as flowing water until they are triggered again"
+    )]
+    strip_ticks: bool,
+
+    #[clap(
+        long,
+        help = "Also remove the PostProcessing lists of blocks which world generation left to be updated"
+    )]
+    strip_postprocessing: bool,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = HEIGHTMAP_NAMES,
+        help = "Heightmaps to keep instead of removing every heightmap, such as MOTION_BLOCKING,WORLD_SURFACE for map renderers (comma-separated)"
+    )]
+    keep_heightmaps: Vec<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "keep_heightmaps",
+        help = "Recompute the heightmaps from each chunk's blocks instead of removing them, for map renderers which need them"
+    )]
+    recalculate_heightmaps: bool,
+
+    #[clap(
+        long,
+        help = "TOML file of rules which remove or set tags only in chunks within a range of data versions, applied on top of the usual stripping"
+    )]
+    rules: Option<String>,
+
+    #[clap(
+        long,
+        help = "Set isLightOn (and LightPopulated before 1.14) to false instead of only removing it, for servers which otherwise don't relight stripped chunks"
+    )]
+    force_relight: bool,
+
+    #[clap(
+        long,
+        requires = "force_relight",
+        value_parser = parse_version_range,
+        help = "Only force relighting chunks whose DataVersion is in this range, such as ..2860, 2860..3000 or 2860.. (defaults to every chunk)"
+    )]
+    relight_versions: Option<RangeInclusive<i32>>,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Order in which the chunks of each stripped region file are laid out",
+        default_value = "arrival"
+    )]
+    chunk_layout: ChunkLayout,
+
+    #[clap(flatten)]
+    position_check: PositionCheckOptions,
+
+    #[clap(flatten)]
+    dimensions: DimensionOptions,
+
+    #[clap(flatten)]
+    retry: RetryOptions,
+
+    #[clap(flatten)]
+    keep_going: KeepGoingOptions,
+}
+
+/// State which is carried across every region file of a strip run.
+struct StripState {
+    report: StripReport,
+    status_filter: StatusFilter,
+    position_check: PositionCheck,
+    retry: RetryPolicy,
+    skipped: SkippedChunks,
+    version_rules: Option<VersionRules>,
+}
+
+/// What becomes of a chunk read from a region which is being stripped.
+enum StripOutcome {
+    /// The chunk doesn't match the generation status filter, so it is left out.
+    Omitted,
+    /// The chunk is a proto-chunk, which is left out with `--drop-proto-chunks`.
+    Dropped,
+    /// The chunk had nothing to strip, so it is copied as it is unless its position was fixed.
+    Unchanged {
+        chunk: Chunk,
+        relocated: bool,
+    },
+    Stripped(Chunk),
+}
+
+impl Options {
+    fn strip_rules(&self) -> StripRules {
+        StripRules {
+            structures: if self.strip_structures {
+                Some(
+                    self.keep_structures
+                        .iter()
+                        .map(|name| qualify(name))
+                        .collect(),
+                )
+            } else {
+                None
+            },
+            ticks: self.strip_ticks,
+            postprocessing: self.strip_postprocessing,
+            keep_heightmaps: self.keep_heightmaps.clone(),
+            recalculate_heightmaps: self.recalculate_heightmaps,
+            force_relight: if self.force_relight {
+                Some(self.relight_versions.clone().unwrap_or(i32::MIN..=i32::MAX))
+            } else {
+                None
+            },
+        }
+    }
 }
 
 pub fn strip_files(options: &Options) -> Result<(), io::Error> {
+    if !options.dimensions.is_active() {
+        return strip_files_with_observer(
+            options,
+            &ProgressObserver::new("strip", "Stripping region files"),
+        );
+    }
+
+    // Chunks skipped in any dimension are reported once every dimension has been stripped
+    let skipped = SkippedChunks::new(&options.keep_going);
+
+    // Each dimension is stripped into the same directory of the output world
+    let output_world = options.output_dir.as_deref().map(Path::new);
+
+    options.dimensions.for_each(
+        Path::new(&options.input_dir),
+        REGION_DIR,
+        |dimension, input_dir| {
+            let dimension_options = Options {
+                input_dir: input_dir.to_string_lossy().into_owned(),
+                output_dir: output_world.map(|output_world| {
+                    dimension
+                        .dir(output_world, REGION_DIR)
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+                ..options.clone()
+            };
+
+            strip_files_skipping(
+                &dimension_options,
+                &ProgressObserver::new("strip", "Stripping region files"),
+                &skipped,
+            )
+        },
+    )?;
+
+    skipped.finish(&NullObserver)
+}
+
+/// Strips every region file, passing the progress of the operation to the observer.
+pub fn strip_files_with_observer(
+    options: &Options,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error> {
+    let skipped = SkippedChunks::new(&options.keep_going);
+
+    strip_files_skipping(options, observer, &skipped)?;
+    skipped.finish(observer)
+}
+
+/// Strips every region file, recording the chunks skipped by `--keep-going` without reporting them.
+fn strip_files_skipping(
+    options: &Options,
+    observer: &dyn OperationObserver,
+    skipped: &SkippedChunks,
+) -> Result<(), io::Error> {
     let input_path = Path::new(&options.input_dir);
-    let output_path = Path::new(&options.output_dir);
+    let output_path = match &options.output_dir {
+        Some(output_dir) => Path::new(output_dir),
+        None => input_path,
+    };
 
     if !Path::exists(input_path) {
-        panic!("Input directory does not exist");
+        return Err(Message::new(messages::INPUT_DIR_MISSING)
+            .arg("path", input_path.display())
+            .into_error(io::ErrorKind::NotFound));
     }
 
-    if input_path == output_path {
-        panic!("In-place operations are not supported")
+    if input_path == output_path && !options.in_place {
+        return Err(
+            Message::new(messages::STRIP_IN_PLACE_FLAG).into_error(io::ErrorKind::InvalidInput)
+        );
     }
 
+    read_only::ensure_writable(output_path)?;
+
     if !Path::exists(output_path) {
-        fs::create_dir_all(output_path).expect("Could not create output directory");
+        fs::create_dir_all(output_path)?;
     }
 
-    fs::read_dir(input_path)?.try_for_each(|entry| {
+    let mut state = StripState {
+        report: StripReport::default(),
+        status_filter: StatusFilter::new(&options.status),
+        position_check: PositionCheck::new(&options.position_check),
+        retry: RetryPolicy::new(&options.retry),
+        skipped: skipped.clone(),
+        version_rules: match &options.rules {
+            Some(rules_path) => Some(VersionRules::load(Path::new(rules_path))?),
+            None => None,
+        },
+    };
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(input_path)? {
         let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
 
-        if path.is_file() {
-            strip_file(input_path, output_path, &path)
-        } else {
-            Ok(())
+        if path.is_file()
+            && AnyRegionFile::is_region_name(&name)
+            && !AnyRegionFile::is_superseded(&path)
+        {
+            let size = fs::metadata(&path)?.len();
+            files.push((path, size));
         }
-    })
+    }
+
+    let input_chunks = files
+        .iter()
+        .map(|(path, _)| AnyRegionFile::count_chunks(path))
+        .sum::<Result<u64, io::Error>>()?;
+
+    observer.started(
+        Some(files.iter().map(|(_, size)| size).sum()),
+        Some(input_chunks),
+    );
+
+    for (path, size) in &files {
+        strip_file(options, path, &mut state, observer)?;
+
+        let region_position = RegionFile::parse_name(&path.file_name().unwrap().to_string_lossy());
+        observer.region_finished(region_position, *size);
+    }
+
+    observer.finished();
+
+    state.report.print();
+    state.status_filter.report();
+    state.position_check.report(observer);
+    state.retry.report(observer);
+
+    Ok(())
 }
 
-fn strip_file(input_dir: &Path, output_dir: &Path, path: &Path) -> Result<(), io::Error> {
+fn strip_file(
+    options: &Options,
+    path: &Path,
+    state: &mut StripState,
+    observer: &dyn OperationObserver,
+) -> Result<(), io::Error> {
+    let StripState {
+        report,
+        status_filter,
+        position_check,
+        retry,
+        skipped,
+        version_rules,
+    } = state;
+    let version_rules = version_rules.as_ref();
+
     let name = path.file_name().unwrap();
+    let region_position = RegionFile::parse_name(&name.to_string_lossy());
+    let rules = options.strip_rules();
+
+    let in_region = retry.run(|| AnyRegionFile::open(path))?;
+
+    // Linear and MCRegion regions are written back out as Anvil regions
+    let out_name = format!("r.{}.{}.mca", region_position.x, region_position.z);
+    let out_path = match &options.output_dir {
+        Some(output_dir) => Path::new(output_dir).join(&out_name),
+        None => path.with_file_name(&out_name),
+    };
 
-    let in_region = RegionFile::open(&Path::join(input_dir, name))?;
-    let mut out_region = RegionFileWriter::create(&Path::join(output_dir, name))?;
+    // In place, the stripped region is written next to the original, and only replaces it once it is complete
+    let write_path = if options.in_place {
+        let mut write_path = out_path.clone().into_os_string();
+        write_path.push(IN_PLACE_SUFFIX);
+        PathBuf::from(write_path)
+    } else {
+        out_path.clone()
+    };
+
+    let mut out_region = retry.run(|| RegionFileWriter::create(&write_path))?;
+    out_region.set_layout(options.chunk_layout);
+    let mut dropped_chunks = 0;
+    let mut stripped_chunks = 0;
+    let started = Instant::now();
+
+    // Everything which can fail because a chunk is corrupt happens here, apart from writing the chunk
+    let read_chunk =
+        |raw_chunk: &RawChunk, report: &mut StripReport| -> Result<StripOutcome, io::Error> {
+            let mut chunk = raw_chunk.decompress()?;
+            let relocated = position_check.check(region_position, &mut chunk)?;
+
+            if !status_filter.includes(&chunk.data)? {
+                return Ok(StripOutcome::Omitted);
+            }
 
-    for result in in_region.stream_chunks() {
-        let chunk = match result? {
-            Some(chunk) => chunk,
-            None => continue,
+            if options.drop_proto_chunks && is_proto_chunk(&chunk.data)? {
+                return Ok(StripOutcome::Dropped);
+            }
+
+            let applies_version_rules = match version_rules {
+                Some(version_rules) => version_rules.applies_to(&chunk.data)?,
+                None => false,
+            };
+
+            if !needs_strip(&chunk.data, &rules)? && !applies_version_rules {
+                report.record_unchanged(chunk.data.len() as u64);
+
+                return Ok(StripOutcome::Unchanged { chunk, relocated });
+            }
+
+            Ok(StripOutcome::Stripped(strip_chunk_with(
+                &chunk,
+                &rules,
+                report,
+                |nbt, report| {
+                    if let Some(version_rules) = version_rules {
+                        apply_version_rules(version_rules, nbt, report);
+                    }
+                },
+            )?))
+        };
+
+    for (index, result) in retry.stream_any_raw_chunks(&in_region).enumerate() {
+        // Every slot of the region is visited in order, so a chunk which can't be read is still known by its position
+        let position = ChunkPos {
+            x: (index % 32) as i32,
+            z: (index / 32) as i32,
         };
 
-        let stripped_chunk = strip_chunk(&chunk)?;
+        let raw_chunk = match skipped.check(region_position, position, result, observer)? {
+            Some(Some(raw_chunk)) => raw_chunk,
+            _ => continue,
+        };
 
-        out_region.add_chunk(&stripped_chunk)?;
+        observer.chunk_processed(region_position, raw_chunk.position);
+
+        let outcome = read_chunk(&raw_chunk, report);
+
+        match skipped.check(region_position, raw_chunk.position, outcome, observer)? {
+            Some(StripOutcome::Omitted) | None => {}
+            Some(StripOutcome::Dropped) => dropped_chunks += 1,
+            Some(StripOutcome::Unchanged { chunk, relocated }) => {
+                // Chunks which were already stripped by a previous run can be copied without recompressing them
+                if relocated || !in_region.has_compressed_chunks() {
+                    retry.run(|| out_region.add_chunk(&chunk))?;
+                } else {
+                    retry.run(|| out_region.add_raw_chunk(&raw_chunk))?;
+                }
+            }
+            Some(StripOutcome::Stripped(stripped_chunk)) => {
+                retry.run(|| out_region.add_chunk(&stripped_chunk))?;
+                stripped_chunks += 1;
+            }
+        }
     }
 
+    out_region.finish()?;
+
+    if options.in_place {
+        // Both regions must be closed before the original can be replaced
+        drop(in_region);
+
+        replace_original(
+            path,
+            &write_path,
+            &out_path,
+            options.backup_suffix.as_deref(),
+        )?;
+    }
+
+    if dropped_chunks > 0 {
+        report!(
+            info,
+            messages::STRIP_REGION_DROPPED,
+            region = name.to_string_lossy().into_owned(),
+            dropped_chunks = dropped_chunks
+        );
+        report.dropped_chunks += dropped_chunks;
+    }
+
+    report!(
+        debug,
+        messages::STRIP_REGION,
+        region = name.to_string_lossy().into_owned(),
+        stripped_chunks = stripped_chunks,
+        elapsed_ms = started.elapsed().as_millis() as u64
+    );
+
     Ok(())
 }
 
-pub fn strip_chunk(chunk: &Chunk) -> Result<Chunk, io::Error> {
-    let mut nbt: fastnbt::Value = fastnbt::from_bytes(&chunk.data).map_err(|err| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("Couldn't deserialize NBT: {}", err),
-        )
-    })?;
-
-    if let fastnbt::Value::Compound(level) = &mut nbt {
-        level.remove("Heightmaps");
-        level.remove("isLightOn");
-
-        if let Some(fastnbt::Value::List(sections)) = level.get_mut("sections") {
-            for section in sections {
-                if let fastnbt::Value::Compound(section) = section {
-                    section.remove("SkyLight");
-                    section.remove("BlockLight");
-                }
+/// Applies the rules which match a chunk's data version, attributing the tags each path removes to that path in the
+/// report.
+fn apply_version_rules(version_rules: &VersionRules, nbt: &mut Value, report: &mut StripReport) {
+    for rule in version_rules.matching(data_version(nbt)) {
+        for path in &rule.remove {
+            let bytes: usize = path.select(nbt).into_iter().map(payload_size).sum();
+            let tags = path.remove(nbt);
+
+            if tags > 0 {
+                report.record_savings(&path.to_string(), tags as u64, bytes as u64);
             }
         }
+
+        for assignment in &rule.set {
+            assignment.path.set(nbt, &assignment.value);
+        }
+    }
+}
+
+/// Moves a stripped region over the region file it was stripped from. With a backup suffix, the original is kept under
+/// its name with the suffix added. A Linear or MCRegion original is removed once its stripped copy is in place, since
+/// the copy is an Anvil region with another name.
+fn replace_original(
+    original_path: &Path,
+    write_path: &Path,
+    out_path: &Path,
+    backup_suffix: Option<&str>,
+) -> Result<(), io::Error> {
+    if let Some(backup_suffix) = backup_suffix {
+        let mut backup_path = original_path.as_os_str().to_owned();
+        backup_path.push(backup_suffix);
+
+        // A hard link keeps the original without a moment where the region is missing, but not every file system
+        // supports them
+        if fs::hard_link(original_path, &backup_path).is_err() {
+            fs::rename(original_path, &backup_path)?;
+        }
     }
 
-    let mut rewritten_data: Vec<u8> = Vec::new();
-    fastnbt::to_writer(&mut rewritten_data, &nbt).map_err(|err| {
-        io::Error::new(
-            io::ErrorKind::Other,
-            format!("Couldn't serialize NBT: {}", err),
-        )
-    })?;
+    fs::rename(write_path, out_path)?;
 
-    let rewritten_chunk = chunk.with_data(rewritten_data.into_boxed_slice());
+    if original_path != out_path && original_path.exists() {
+        fs::remove_file(original_path)?;
+    }
 
-    Ok(rewritten_chunk)
+    Ok(())
 }
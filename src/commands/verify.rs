@@ -0,0 +1,347 @@
+use clap::Parser;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use atty::Stream;
+
+use crate::commands::archive::{
+    chunk_entry_nbt, chunk_entry_path, for_each_archive_entry_with_type, parse_chunk_entry_path,
+    CHECKSUMS_ENTRY_PATH, DEDUP_ENTRY_PATH, LABELS_ENTRY_PATH, METADATA_ENTRY_PATH,
+    PROFILE_ENTRY_PATH,
+};
+use crate::dedup::ChunkReferences;
+use crate::labels::RegionLabels;
+use crate::manifest::{hash_chunk_data, Manifest};
+use crate::messages::{self, Message};
+use crate::metadata::ArchiveMetadata;
+use crate::nbt::{chunk_position, read_nbt};
+use crate::profile::{ArchiveProfile, ProfileTransform};
+use crate::region::ChunkPos;
+
+#[derive(Parser)]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Path of the archive file to verify (default is pipe from stdin)"
+    )]
+    input_file: Option<String>,
+
+    #[clap(long, help = "Allow binary data to be piped from a TTY")]
+    ignore_tty: bool,
+}
+
+pub fn verify_archive(options: &Options) -> Result<(), io::Error> {
+    let report = match &options.input_file {
+        Some(input_path) => {
+            let input_path = Path::new(input_path);
+
+            if !Path::exists(input_path) {
+                return Err(Message::new(messages::INPUT_FILE_MISSING)
+                    .arg("path", input_path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+
+            let file = File::open(input_path)?;
+
+            verify_archive_with_reader(&mut BufReader::new(file))
+        }
+        None => {
+            if atty::is(Stream::Stdin) && !options.ignore_tty {
+                return Err(Message::new(messages::TTY_INPUT_REFUSED)
+                    .into_error(io::ErrorKind::InvalidInput));
+            }
+
+            verify_archive_with_reader(&mut io::stdin())
+        }
+    };
+
+    for (path, reason) in &report.bad_entries {
+        println!(
+            "{}",
+            Message::new(messages::VERIFY_BAD_ENTRY)
+                .arg("entry", path)
+                .arg("reason", reason)
+        );
+    }
+
+    println!(
+        "{}",
+        Message::new(messages::VERIFY_SUMMARY)
+            .arg("entries", report.entries)
+            .arg("problems", report.bad_entries.len())
+    );
+
+    if !report.has_checksums && report.fatal_error.is_none() {
+        println!("{}", Message::new(messages::VERIFY_NO_CHECKSUMS));
+    }
+
+    if let Some(err) = report.fatal_error {
+        let message =
+            Message::new(messages::VERIFY_TRUNCATED).arg("error", messages::error_message(&err));
+        println!("{}", message);
+
+        // The archive readers report truncated archives in their own ways, which are all a failed verification
+        return Err(message.into_error(io::ErrorKind::InvalidData));
+    }
+
+    if !report.bad_entries.is_empty() {
+        return Err(Message::new(messages::VERIFY_PROBLEMS)
+            .arg("problems", report.bad_entries.len())
+            .into_error(io::ErrorKind::InvalidData));
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct VerifyReport {
+    entries: usize,
+    has_checksums: bool,
+    bad_entries: Vec<(String, Message)>,
+    fatal_error: Option<io::Error>,
+}
+
+fn verify_archive_with_reader<R>(reader: &mut R) -> VerifyReport
+where
+    R: io::Read,
+{
+    let mut report = VerifyReport::default();
+    let mut seen = HashSet::new();
+
+    let mut checksums = None;
+    let mut actual_checksums = Manifest::new();
+    let mut references = None;
+    let mut corrupt = HashSet::new();
+
+    // Errors from the archive reader mean the stream itself is damaged, so nothing after them can be trusted
+    let result = for_each_archive_entry_with_type(reader, |path, is_file, size, entry| {
+        report.entries += 1;
+
+        let path = path.to_string_lossy().into_owned();
+
+        if !is_file {
+            report
+                .bad_entries
+                .push((path, Message::new(messages::VERIFY_NOT_FILE)));
+            return Ok(true);
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+
+        // A chunk which fails the checksum in its own header is reported once, rather than again by the index
+        if let Err(err) = entry.read_to_end(&mut data) {
+            let message = messages::error_message(&err);
+
+            if message.id() != messages::ARCHIVE_ENTRY_CORRUPT.id {
+                return Err(err);
+            }
+
+            corrupt.insert(path.clone());
+            report
+                .bad_entries
+                .push((path, Message::new(messages::CHECKSUM_MISMATCH)));
+            return Ok(true);
+        }
+
+        if path == CHECKSUMS_ENTRY_PATH {
+            match Manifest::read_from(&data[..]) {
+                Ok(manifest) => checksums = Some(manifest),
+                Err(err) => report
+                    .bad_entries
+                    .push((path, messages::error_message(&err))),
+            }
+
+            return Ok(true);
+        }
+
+        if path == DEDUP_ENTRY_PATH {
+            match ChunkReferences::read_from(&data[..]) {
+                Ok(table) => references = Some(table),
+                Err(err) => report
+                    .bad_entries
+                    .push((path, messages::error_message(&err))),
+            }
+
+            return Ok(true);
+        }
+
+        // Readers settle the layout of an archive from its first entry, so metadata anywhere else would be misread
+        if path == METADATA_ENTRY_PATH {
+            let layout =
+                ArchiveMetadata::read_from(&data[..]).and_then(|metadata| metadata.layout(false));
+
+            if report.entries > 1 {
+                report
+                    .bad_entries
+                    .push((path, Message::new(messages::VERIFY_METADATA_NOT_FIRST)));
+            } else if let Err(err) = layout {
+                report
+                    .bad_entries
+                    .push((path, messages::error_message(&err)));
+            }
+
+            return Ok(true);
+        }
+
+        if path == LABELS_ENTRY_PATH {
+            if let Err(err) = RegionLabels::read_from(&data[..]) {
+                report
+                    .bad_entries
+                    .push((path, messages::error_message(&err)));
+            }
+
+            return Ok(true);
+        }
+
+        if path == PROFILE_ENTRY_PATH {
+            let profile = ArchiveProfile::read_from(&data[..])
+                .and_then(|profile| ProfileTransform::new(&profile));
+
+            if let Err(err) = profile {
+                report
+                    .bad_entries
+                    .push((path, messages::error_message(&err)));
+            }
+
+            return Ok(true);
+        }
+
+        if let Some((region, chunk)) = parse_chunk_entry_path(Path::new(&path)) {
+            actual_checksums.insert(region, chunk, hash_chunk_data(&data));
+        }
+
+        if let Err(reason) = verify_entry(&path, &data, &mut seen) {
+            report.bad_entries.push((path, reason));
+        }
+
+        Ok(true)
+    });
+
+    if let Err(err) = result {
+        report.fatal_error = Some(err);
+    }
+
+    // Every duplicate must refer to a chunk which is stored in the archive, and must not be stored itself
+    for ((region, chunk), original) in references.iter().flat_map(ChunkReferences::iter) {
+        let path = chunk_entry_path(region, chunk);
+
+        if !seen.contains(&original) {
+            report.bad_entries.push((
+                path,
+                Message::new(messages::VERIFY_MISSING_ORIGINAL)
+                    .arg("original", chunk_entry_path(original.0, original.1)),
+            ));
+        } else if !seen.insert((region, chunk)) {
+            report
+                .bad_entries
+                .push((path, Message::new(messages::VERIFY_DUPLICATE)));
+        }
+    }
+
+    if let Some(checksums) = checksums {
+        report.has_checksums = true;
+        report.bad_entries.extend(
+            checksums
+                .find_mismatches(&actual_checksums)
+                .into_iter()
+                .filter(|(path, _)| !corrupt.contains(path))
+                .map(|(path, reason)| (path, Message::new(reason))),
+        );
+    }
+
+    report
+}
+
+fn verify_entry(
+    path: &str,
+    data: &[u8],
+    seen: &mut HashSet<(ChunkPos, ChunkPos)>,
+) -> Result<(), Message> {
+    let (region, chunk) = parse_chunk_entry_path(Path::new(path))
+        .ok_or_else(|| Message::new(messages::VERIFY_NOT_CHUNK))?;
+
+    if !(0..32).contains(&chunk.x) || !(0..32).contains(&chunk.z) {
+        return Err(Message::new(messages::VERIFY_OUTSIDE_REGION));
+    }
+
+    if !seen.insert((region, chunk)) {
+        return Err(Message::new(messages::VERIFY_DUPLICATE));
+    }
+
+    let data =
+        chunk_entry_nbt(Path::new(path), data).map_err(|err| messages::error_message(&err))?;
+    let nbt = read_nbt(&data).map_err(|err| messages::error_message(&err))?;
+
+    let expected = ChunkPos {
+        x: (region.x * 32) + chunk.x,
+        z: (region.z * 32) + chunk.z,
+    };
+
+    match chunk_position(&nbt) {
+        Some(actual) if actual == expected => Ok(()),
+        Some(actual) => Err(Message::new(messages::VERIFY_WRONG_POSITION)
+            .arg("actual_x", actual.x)
+            .arg("actual_z", actual.z)
+            .arg("expected_x", expected.x)
+            .arg("expected_z", expected.z)),
+        None => Err(Message::new(messages::VERIFY_MISSING_POSITION)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::commands::archive::{pack_files_with_observer, PackOptions};
+    use crate::observer::NullObserver;
+    use crate::region::{Chunk, RegionFileWriter};
+    use crate::snbt::from_snbt;
+
+    #[test]
+    fn verifies_zip_archive() {
+        let dir =
+            std::env::temp_dir().join(format!("anvil-tools-verify-zip-{}", std::process::id()));
+        let region_dir = dir.join("region");
+        let archive_path = dir.join("world.zip");
+        fs::create_dir_all(&region_dir).unwrap();
+
+        let mut writer = RegionFileWriter::create(&region_dir.join("r.0.0.mca")).unwrap();
+
+        for (x, z) in [(0, 0), (3, 7)] {
+            let data = from_snbt(format!("{{xPos: {}, zPos: {}}}", x, z).as_bytes()).unwrap();
+
+            writer
+                .add_chunk(&Chunk {
+                    data: data.into_boxed_slice(),
+                    position: ChunkPos { x, z },
+                })
+                .unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        let options = PackOptions::parse_from([
+            "pack",
+            "--input-dir",
+            region_dir.to_str().unwrap(),
+            "--output-file",
+            archive_path.to_str().unwrap(),
+            "--format",
+            "zip",
+        ]);
+        pack_files_with_observer(&options, &NullObserver).unwrap();
+
+        let report = verify_archive_with_reader(&mut File::open(&archive_path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(report.fatal_error.is_none());
+        assert!(report.bad_entries.is_empty());
+        assert!(report.has_checksums);
+        assert!(report.entries >= 2);
+    }
+}
@@ -0,0 +1,279 @@
+use std::io::{self, Read, Write};
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use xz2::read::XzDecoder;
+use xz2::stream::{Check, MtStreamBuilder};
+use xz2::write::XzEncoder;
+
+use crate::encryption;
+
+/// The compression applied to an archive as a whole.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveCompression {
+    /// No compression, leaving it to an external compressor
+    None,
+    /// gzip, which every platform can decompress
+    Gzip,
+    /// xz, which compresses the best but is the slowest
+    Xz,
+    /// Zstandard, which compresses almost as well as xz while being much faster
+    Zstd,
+}
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\x00";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// The default preset of xz, which is what the `xz` tool uses without any options.
+const XZ_PRESET: u32 = 6;
+
+/// The default level of Zstandard, which is what the `zstd` tool uses without any options.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The amount of the archive compressed into each gzip member. Every member starts without a dictionary, but since
+/// deflate only looks back 32 KiB, blocks this large compress almost as well as a single stream.
+const GZIP_BLOCK_SIZE: usize = 1 << 20;
+
+/// A writer which compresses everything written to it. The compressed stream must be finished once everything has
+/// been written, as some compressors don't finish it when they are dropped.
+pub enum ArchiveEncoder<W: Write> {
+    None(W),
+    Gzip(BlockGzEncoder<W>),
+    Xz(XzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> ArchiveEncoder<W> {
+    /// Creates a compressor for a writer. Compressors which can spread their work over several threads use the given
+    /// number of threads.
+    pub fn new(
+        writer: W,
+        compression: ArchiveCompression,
+        threads: u32,
+    ) -> Result<Self, io::Error> {
+        Ok(match compression {
+            ArchiveCompression::None => ArchiveEncoder::None(writer),
+            ArchiveCompression::Gzip => ArchiveEncoder::Gzip(BlockGzEncoder::new(writer, threads)),
+            ArchiveCompression::Xz => {
+                let stream = MtStreamBuilder::new()
+                    .threads(threads.max(1))
+                    .preset(XZ_PRESET)
+                    .check(Check::Crc64)
+                    .encoder()?;
+
+                ArchiveEncoder::Xz(XzEncoder::new_stream(writer, stream))
+            }
+            ArchiveCompression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+
+                // Zstandard compresses on the calling thread unless it is given workers
+                if threads > 1 {
+                    encoder.multithread(threads)?;
+                }
+
+                ArchiveEncoder::Zstd(encoder)
+            }
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        match self {
+            ArchiveEncoder::None(writer) => writer,
+            ArchiveEncoder::Gzip(encoder) => encoder.get_mut(),
+            ArchiveEncoder::Xz(encoder) => encoder.get_mut(),
+            ArchiveEncoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self {
+            ArchiveEncoder::None(writer) => writer,
+            ArchiveEncoder::Gzip(encoder) => encoder.get_ref(),
+            ArchiveEncoder::Xz(encoder) => encoder.get_ref(),
+            ArchiveEncoder::Zstd(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Finishes the compressed stream and flushes it to the underlying writer.
+    pub fn finish(&mut self) -> Result<(), io::Error> {
+        match self {
+            ArchiveEncoder::None(writer) => writer.flush(),
+            ArchiveEncoder::Gzip(encoder) => encoder.finish(),
+            ArchiveEncoder::Xz(encoder) => encoder.try_finish(),
+            ArchiveEncoder::Zstd(encoder) => encoder.do_finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for ArchiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveEncoder::None(writer) => writer.write(buf),
+            ArchiveEncoder::Gzip(encoder) => encoder.write(buf),
+            ArchiveEncoder::Xz(encoder) => encoder.write(buf),
+            ArchiveEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveEncoder::None(writer) => writer.flush(),
+            ArchiveEncoder::Gzip(encoder) => encoder.flush(),
+            ArchiveEncoder::Xz(encoder) => encoder.flush(),
+            ArchiveEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A gzip compressor which splits its input into blocks and writes each of them as a gzip member of its own, so that
+/// a batch of blocks can be compressed at once on several threads, like `pigz` does. Decompressors read the
+/// concatenated members as a single stream. The blocks don't depend on the number of threads, so the output is the
+/// same however many threads compressed it.
+pub struct BlockGzEncoder<W: Write> {
+    writer: W,
+    pool: rayon::ThreadPool,
+    /// The blocks waiting to be compressed, of which only the last can be partly filled.
+    blocks: Vec<Vec<u8>>,
+    batch_size: usize,
+    members: usize,
+}
+
+impl<W: Write> BlockGzEncoder<W> {
+    pub fn new(writer: W, threads: u32) -> Self {
+        let threads = threads.max(1) as usize;
+
+        BlockGzEncoder {
+            writer,
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap(),
+            blocks: Vec::with_capacity(threads),
+            batch_size: threads,
+            members: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Compresses the blocks waiting to be compressed, and writes their members in order.
+    fn compress_blocks(&mut self) -> Result<(), io::Error> {
+        let blocks = std::mem::take(&mut self.blocks);
+
+        let members = self.pool.install(|| {
+            blocks
+                .par_iter()
+                .map(|block| {
+                    let mut encoder = GzEncoder::new(
+                        Vec::with_capacity(block.len()),
+                        flate2::Compression::default(),
+                    );
+                    encoder.write_all(block)?;
+                    encoder.finish()
+                })
+                .collect::<Result<Vec<_>, io::Error>>()
+        })?;
+
+        for member in &members {
+            self.writer.write_all(member)?;
+        }
+
+        self.members += members.len();
+
+        Ok(())
+    }
+
+    /// Compresses the rest of the input and flushes it to the underlying writer. An empty input is still written as a
+    /// single empty member, so that the output is a valid gzip file.
+    pub fn finish(&mut self) -> Result<(), io::Error> {
+        if self.members == 0 && self.blocks.is_empty() {
+            self.blocks.push(Vec::new());
+        }
+
+        self.compress_blocks()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Write for BlockGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let full = match self.blocks.last() {
+            Some(block) => block.len() == GZIP_BLOCK_SIZE,
+            None => true,
+        };
+
+        if full {
+            if self.blocks.len() == self.batch_size {
+                self.compress_blocks()?;
+            }
+
+            self.blocks.push(Vec::with_capacity(GZIP_BLOCK_SIZE));
+        }
+
+        let block = self.blocks.last_mut().unwrap();
+        let length = buf.len().min(GZIP_BLOCK_SIZE - block.len());
+        block.extend_from_slice(&buf[..length]);
+
+        Ok(length)
+    }
+
+    /// Compresses the input written so far, ending its block early, and flushes it to the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.blocks.is_empty() {
+            self.compress_blocks()?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+/// Returns a reader which decompresses an archive, detecting whether it was compressed with gzip, xz or Zstandard
+/// from its first bytes. Archives which aren't compressed with any of them are read as they are. Streams made of
+/// several concatenated parts, as written by parallel compressors such as `pigz`, are read in full. Archives encrypted
+/// with age are decrypted first, and then detected again.
+pub fn decompressed_reader<'a, R>(mut reader: R) -> Result<Box<dyn Read + 'a>, io::Error>
+where
+    R: Read + 'a,
+{
+    let magic_len = XZ_MAGIC.len().max(encryption::magic_len());
+
+    let mut magic = Vec::with_capacity(magic_len);
+    reader
+        .by_ref()
+        .take(magic_len as u64)
+        .read_to_end(&mut magic)?;
+
+    if encryption::is_encrypted(&magic) {
+        let reader = io::Cursor::new(magic).chain(reader);
+
+        return decompressed_reader(encryption::decrypted_reader(reader)?);
+    }
+
+    let compression = if magic.starts_with(GZIP_MAGIC) {
+        ArchiveCompression::Gzip
+    } else if magic.starts_with(XZ_MAGIC) {
+        ArchiveCompression::Xz
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        ArchiveCompression::Zstd
+    } else {
+        ArchiveCompression::None
+    };
+
+    let reader = io::Cursor::new(magic).chain(reader);
+
+    Ok(match compression {
+        ArchiveCompression::None => Box::new(reader),
+        ArchiveCompression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        ArchiveCompression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        ArchiveCompression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    })
+}
@@ -0,0 +1,187 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Arg, Command};
+use toml::value::Table;
+use toml::Value;
+
+use crate::messages::{self, Message};
+
+/// The config file which is read when `--config` isn't given, if it exists in the working directory.
+const DEFAULT_CONFIG_FILE: &str = "anvil-tools.toml";
+
+/// Adds the `--config` option to the command line, and applies the options in the config file as defaults, so that
+/// any option given on the command line still takes precedence.
+///
+/// Keys in the config file are the long names of options. Keys at the top of the file set global options, or options
+/// of every command which has them (such as `threads`), while keys in a table named after a command (such as `[pack]`
+/// or `[index.build]`) only set options of that command.
+pub fn apply(command: Command, args: &[OsString]) -> Result<Command, io::Error> {
+    let command = command.arg(
+        Arg::new("config")
+            .long("config")
+            .global(true)
+            .value_name("FILE")
+            .help("Config file of default options (default is ./anvil-tools.toml, if it exists)"),
+    );
+
+    let path = match find_config_path(args) {
+        Some(path) => path,
+        None => {
+            let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+
+            if !path.is_file() {
+                return Ok(command);
+            }
+
+            path
+        }
+    };
+
+    let invalid = |kind: io::ErrorKind, error: &dyn fmt::Display| {
+        Message::new(messages::CONFIG_INVALID)
+            .arg("path", path.display())
+            .arg("error", error)
+            .into_error(kind)
+    };
+
+    let contents = fs::read_to_string(&path).map_err(|err| invalid(err.kind(), &err))?;
+
+    let table = match contents.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => unreachable!(),
+        Err(err) => return Err(invalid(io::ErrorKind::InvalidData, &err)),
+    };
+
+    check_table(&command, &table, None)?;
+
+    Ok(apply_table(command, &table, true))
+}
+
+/// Finds the value of `--config` in the command line arguments, since it must be known before they can be parsed.
+fn find_config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--") => break,
+            Some("--config") => return args.next().map(PathBuf::from),
+            Some(arg) => {
+                if let Some(path) = arg.strip_prefix("--config=") {
+                    return Some(PathBuf::from(path));
+                }
+            }
+            None => continue,
+        }
+    }
+
+    None
+}
+
+/// Checks that every key of a table names an option or a subcommand of the command.
+fn check_table(command: &Command, table: &Table, section: Option<&str>) -> Result<(), io::Error> {
+    for (key, value) in table {
+        let name = match section {
+            Some(section) => format!("{}.{}", section, key),
+            None => key.clone(),
+        };
+
+        let known = match value {
+            Value::Table(table) => match command.find_subcommand(key) {
+                Some(subcommand) => {
+                    check_table(subcommand, table, Some(&name))?;
+                    true
+                }
+                None => false,
+            },
+            _ if section.is_none() => has_option_anywhere(command, key),
+            _ => find_option(command, key).is_some(),
+        };
+
+        if !known {
+            return Err(Message::new(messages::CONFIG_UNKNOWN_OPTION)
+                .arg("option", name)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the defaults of a command's options from a table. Options of the command itself are set before those of its
+/// subcommands, so that a command's own table overrides the keys at the top of the file.
+fn apply_table(mut command: Command, table: &Table, top_level: bool) -> Command {
+    for (key, value) in table {
+        if !matches!(value, Value::Table(_)) {
+            command = if top_level {
+                set_default_anywhere(command, key, value)
+            } else {
+                set_default(command, key, value)
+            };
+        }
+    }
+
+    for (key, value) in table {
+        if let Value::Table(table) = value {
+            command =
+                command.mut_subcommand(key, |subcommand| apply_table(subcommand, table, false));
+        }
+    }
+
+    command
+}
+
+fn set_default(command: Command, key: &str, value: &Value) -> Command {
+    match find_option(&command, key) {
+        Some(id) => command.mut_arg(&id, |arg| {
+            arg.required(false).default_values(default_values(value))
+        }),
+        None => command,
+    }
+}
+
+/// Sets the default of an option on the command if it has it, or otherwise on every subcommand which has it.
+fn set_default_anywhere(mut command: Command, key: &str, value: &Value) -> Command {
+    if find_option(&command, key).is_some() {
+        return set_default(command, key, value);
+    }
+
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| String::from(subcommand.get_name()))
+        .collect();
+
+    for name in names {
+        command = command.mut_subcommand(name, |subcommand| {
+            set_default_anywhere(subcommand, key, value)
+        });
+    }
+
+    command
+}
+
+fn has_option_anywhere(command: &Command, key: &str) -> bool {
+    find_option(command, key).is_some()
+        || command
+            .get_subcommands()
+            .any(|subcommand| has_option_anywhere(subcommand, key))
+}
+
+fn find_option(command: &Command, key: &str) -> Option<clap::Id> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(key) && arg.get_id() != "config")
+        .map(|arg| arg.get_id().clone())
+}
+
+/// Converts a value from the config file into the values of an option, as they would be written on the command line.
+fn default_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(value) => vec![value.clone()],
+        Value::Array(values) => values.iter().flat_map(default_values).collect(),
+        value => vec![value.to_string()],
+    }
+}
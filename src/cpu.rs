@@ -0,0 +1,110 @@
+use std::io;
+
+use clap::Args;
+
+#[cfg(not(target_os = "linux"))]
+use crate::messages::{self, Message};
+
+#[derive(Args)]
+pub struct CpuOptions {
+    #[clap(
+        long,
+        global = true,
+        help = "Only run on these CPUs, given as a list such as 0-3 or 0,2,4-6 (Linux only)",
+        value_parser = parse_cpu_set
+    )]
+    cpu_set: Option<CpuSet>,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Run at the lowest scheduling priority, so that other processes such as a server always come first"
+    )]
+    low_priority: bool,
+}
+
+/// A set of CPU indices to which the process is restricted.
+#[derive(Clone, Debug)]
+pub struct CpuSet(Vec<usize>);
+
+/// The number of CPUs which can be given in a CPU set, as defined by glibc.
+const MAX_CPUS: usize = 1024;
+
+/// Parses a list of CPUs such as `0-3,6`, where each element is either a single CPU or an inclusive range.
+fn parse_cpu_set(value: &str) -> Result<CpuSet, String> {
+    let mut cpus = Vec::new();
+
+    for element in value.split(',') {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|cpu| *cpu < MAX_CPUS)
+                .ok_or_else(|| format!("Invalid CPU index: {}", value))
+        };
+
+        match element.split_once('-') {
+            Some((start, end)) => cpus.extend(parse(start)?..=parse(end)?),
+            None => cpus.push(parse(element)?),
+        }
+    }
+
+    if cpus.is_empty() {
+        return Err(format!("CPU set is empty: {}", value));
+    }
+
+    Ok(CpuSet(cpus))
+}
+
+/// Applies the CPU options to the current thread. This must be called before any other threads are started, since
+/// they inherit the affinity and priority of the thread which spawns them.
+pub fn apply(options: &CpuOptions) -> Result<(), io::Error> {
+    if let Some(cpu_set) = &options.cpu_set {
+        set_affinity(&cpu_set.0)?;
+    }
+
+    if options.low_priority {
+        set_low_priority()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> Result<(), io::Error> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> Result<(), io::Error> {
+    Err(Message::new(messages::CPU_SET_UNSUPPORTED).into_error(io::ErrorKind::Unsupported))
+}
+
+#[cfg(unix)]
+fn set_low_priority() -> Result<(), io::Error> {
+    // On Linux this only changes the priority of the calling thread, which is why it must happen before any threads
+    // are spawned
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_low_priority() -> Result<(), io::Error> {
+    Err(Message::new(messages::CPU_LOW_PRIORITY_UNSUPPORTED).into_error(io::ErrorKind::Unsupported))
+}
@@ -0,0 +1,142 @@
+use std::convert::TryInto;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::commands::archive::{chunk_entry_path, parse_chunk_entry_path};
+use crate::manifest::hash_chunk_data;
+use crate::messages::{self, Message};
+use crate::nbt::find_int_tag_offset;
+use crate::region::ChunkPos;
+
+/// The chunks of a deduplicated archive which were not stored because their contents were identical to a chunk stored
+/// earlier in the archive, apart from their position. The table is stored as plain text with one `<entry path> <original entry path>` pair per
+/// line, and is written after all chunks so that unpack has already restored every original by the time it is read.
+#[derive(Default)]
+pub struct ChunkReferences {
+    references: Vec<((ChunkPos, ChunkPos), (ChunkPos, ChunkPos))>,
+}
+
+impl ChunkReferences {
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut references = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (entry_path, original_path) = line.split_once(' ').ok_or_else(|| {
+                invalid_data(Message::new(messages::DEDUP_MALFORMED_LINE).arg("line", line))
+            })?;
+
+            let parse = |path: &str| {
+                parse_chunk_entry_path(Path::new(path)).ok_or_else(|| {
+                    invalid_data(Message::new(messages::DEDUP_MALFORMED_ENTRY).arg("entry", path))
+                })
+            };
+
+            references.push((parse(entry_path)?, parse(original_path)?));
+        }
+
+        Ok(ChunkReferences { references })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut lines: Vec<String> = self
+            .references
+            .iter()
+            .map(|&((region, chunk), (original_region, original_chunk))| {
+                format!(
+                    "{} {}",
+                    chunk_entry_path(region, chunk),
+                    chunk_entry_path(original_region, original_chunk)
+                )
+            })
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records that a chunk has the same contents as the original chunk, which is stored in the archive.
+    pub fn insert(&mut self, region: ChunkPos, chunk: ChunkPos, original: (ChunkPos, ChunkPos)) {
+        self.references.push(((region, chunk), original));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((ChunkPos, ChunkPos), (ChunkPos, ChunkPos))> + '_ {
+        self.references.iter().copied()
+    }
+}
+
+/// Returns the offsets of the values of a chunk's `xPos` and `zPos` tags within its serialized NBT, which are at the root
+/// of the chunk since 1.18 and in the `Level` compound before that.
+fn position_offsets(data: &[u8]) -> Result<Option<(usize, usize)>, io::Error> {
+    for prefix in [&[][..], &["Level"][..]] {
+        let x = find_int_tag_offset(data, &[prefix, &["xPos"]].concat())?;
+        let z = find_int_tag_offset(data, &[prefix, &["zPos"]].concat())?;
+
+        if let (Some(x), Some(z)) = (x, z) {
+            return Ok(Some((x, z)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// A chunk's serialized NBT with its position cleared, so that chunks which only differ in where they are (such as
+/// empty ocean or void chunks) are found to be duplicates, along with its hash. Chunks with the same hash are only
+/// duplicates if their contents are equal too, since a collision would otherwise replace a chunk with another one.
+pub struct DedupKey {
+    pub hash: u64,
+    pub contents: Box<[u8]>,
+}
+
+pub fn dedup_key(data: &[u8]) -> Result<DedupKey, io::Error> {
+    let mut contents = data.to_vec().into_boxed_slice();
+
+    if let Some((x, z)) = position_offsets(data)? {
+        contents[x..(x + 4)].fill(0);
+        contents[z..(z + 4)].fill(0);
+    }
+
+    Ok(DedupKey {
+        hash: hash_chunk_data(&contents),
+        contents,
+    })
+}
+
+/// Rewrites the position of a chunk in its serialized NBT, which restores a duplicate from a copy of its original.
+pub fn relocate_chunk(data: &mut [u8], position: ChunkPos) -> Result<(), io::Error> {
+    if let Some((x, z)) = position_offsets(data)? {
+        data[x..(x + 4)].copy_from_slice(&position.x.to_be_bytes());
+        data[z..(z + 4)].copy_from_slice(&position.z.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+/// Reads the position of a chunk from its serialized NBT, without decoding the rest of the chunk.
+pub fn read_chunk_position(data: &[u8]) -> Result<Option<ChunkPos>, io::Error> {
+    Ok(position_offsets(data)?.map(|(x, z)| ChunkPos {
+        x: i32::from_be_bytes(data[x..(x + 4)].try_into().unwrap()),
+        z: i32::from_be_bytes(data[z..(z + 4)].try_into().unwrap()),
+    }))
+}
+
+fn invalid_data(message: Message) -> io::Error {
+    message.into_error(io::ErrorKind::InvalidData)
+}
@@ -0,0 +1,138 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+
+use crate::messages::{self, report, Message};
+
+/// The directory of each dimension which holds its region files.
+pub const REGION_DIR: &str = "region";
+
+/// The directory of each dimension which holds its entity files, since 1.17.
+pub const ENTITIES_DIR: &str = "entities";
+
+/// A dimension of a world, each of which keeps its files in its own directory under the root of the world.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Dimension {
+    /// The Overworld, which is stored directly in the world's root
+    Overworld,
+    /// The Nether, which is stored in DIM-1
+    Nether,
+    /// The End, which is stored in DIM1
+    End,
+}
+
+impl Dimension {
+    const ALL: [Dimension; 3] = [Dimension::Overworld, Dimension::Nether, Dimension::End];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dimension::Overworld => "overworld",
+            Dimension::Nether => "nether",
+            Dimension::End => "end",
+        }
+    }
+
+    /// Returns the directory of the dimension within a world which holds the given kind of files, such as `region`.
+    pub fn dir(self, world: &Path, files: &str) -> PathBuf {
+        match self {
+            Dimension::Overworld => world.join(files),
+            Dimension::Nether => world.join("DIM-1").join(files),
+            Dimension::End => world.join("DIM1").join(files),
+        }
+    }
+
+    /// Returns the path of the dimension's own copy of an output file, which has the name of the dimension inserted
+    /// before its extensions, such as `backup.nether.tar` for `backup.tar`.
+    pub fn file_path(self, path: &str) -> String {
+        let path = Path::new(path);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let name = match name.find('.') {
+            Some(index) if index > 0 => {
+                format!("{}.{}{}", &name[..index], self.name(), &name[index..])
+            }
+            _ => format!("{}.{}", name, self.name()),
+        };
+
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct DimensionOptions {
+    #[clap(
+        long = "dimension",
+        value_enum,
+        value_delimiter = ',',
+        help = "Treat the input directory as the root of a world, and process these of its dimensions (comma-separated)"
+    )]
+    dimensions: Vec<Dimension>,
+
+    #[clap(
+        long,
+        conflicts_with = "dimensions",
+        help = "Treat the input directory as the root of a world, and process every one of its dimensions"
+    )]
+    all_dimensions: bool,
+}
+
+impl DimensionOptions {
+    /// Whether the input directory is the root of a world, rather than a directory of region files.
+    pub fn is_active(&self) -> bool {
+        self.all_dimensions || !self.dimensions.is_empty()
+    }
+
+    /// Returns the selected dimensions in a fixed order, without duplicates.
+    pub fn selected(&self) -> Vec<Dimension> {
+        Dimension::ALL
+            .iter()
+            .copied()
+            .filter(|dimension| self.all_dimensions || self.dimensions.contains(dimension))
+            .collect()
+    }
+
+    /// Calls `run` with each selected dimension and its directory of the given kind of files within the world. Dimensions
+    /// which the world doesn't have are skipped, but at least one of them must exist.
+    pub fn for_each<F>(&self, world: &Path, files: &str, mut run: F) -> Result<(), io::Error>
+    where
+        F: FnMut(Dimension, &Path) -> Result<(), io::Error>,
+    {
+        let mut found = false;
+
+        for dimension in self.selected() {
+            let dir = dimension.dir(world, files);
+
+            if !dir.is_dir() {
+                report!(
+                    info,
+                    messages::DIMENSION_MISSING,
+                    dimension = dimension.name(),
+                    path = dir.display().to_string()
+                );
+                continue;
+            }
+
+            report!(
+                info,
+                messages::DIMENSION_STARTED,
+                dimension = dimension.name(),
+                path = dir.display().to_string()
+            );
+
+            run(dimension, &dir)?;
+            found = true;
+        }
+
+        if !found {
+            return Err(Message::new(messages::DIMENSION_NONE_FOUND)
+                .arg("path", world.display())
+                .into_error(io::ErrorKind::NotFound));
+        }
+
+        Ok(())
+    }
+}
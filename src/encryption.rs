@@ -0,0 +1,258 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use age::secrecy::SecretString;
+use age::stream::StreamWriter;
+use clap::Args;
+
+use crate::messages::{self, Message};
+
+#[derive(Args)]
+pub struct EncryptionOptions {
+    #[clap(
+        long,
+        global = true,
+        env = "ANVIL_TOOLS_IDENTITY",
+        help = "age identity file used to decrypt encrypted archives (may be repeated)"
+    )]
+    identity: Vec<PathBuf>,
+
+    #[clap(
+        long,
+        global = true,
+        env = "ANVIL_TOOLS_PASSPHRASE_FILE",
+        help = "File whose first line is the passphrase used to encrypt archives with --encrypt passphrase, and to decrypt them"
+    )]
+    passphrase_file: Option<PathBuf>,
+}
+
+/// The start of every binary age file, which is how encrypted archives are recognized.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+struct EncryptionState {
+    identities: Vec<PathBuf>,
+    passphrase_file: Option<PathBuf>,
+}
+
+static STATE: Mutex<EncryptionState> = Mutex::new(EncryptionState {
+    identities: Vec::new(),
+    passphrase_file: None,
+});
+
+pub fn init(options: &EncryptionOptions) {
+    let mut state = STATE.lock().unwrap();
+
+    state.identities = options.identity.clone();
+    state.passphrase_file = options.passphrase_file.clone();
+}
+
+/// Who an archive is encrypted to, as given to `pack --encrypt`.
+#[derive(Clone)]
+pub enum EncryptionTarget {
+    /// The holder of the identity of an age recipient, given as `age:<recipient>`
+    Recipient(age::x25519::Recipient),
+    /// Anyone who knows the passphrase read from `--passphrase-file`, given as `passphrase`
+    Passphrase,
+}
+
+pub fn parse_encryption_target(value: &str) -> Result<EncryptionTarget, String> {
+    if value == "passphrase" {
+        return Ok(EncryptionTarget::Passphrase);
+    }
+
+    match value.strip_prefix("age:") {
+        Some(recipient) => age::x25519::Recipient::from_str(recipient)
+            .map(EncryptionTarget::Recipient)
+            .map_err(|err| format!("Invalid age recipient {}: {}", recipient, err)),
+        None => Err(format!(
+            "Expected age:<recipient> or passphrase, but found {}",
+            value
+        )),
+    }
+}
+
+/// Checks that an archive can be encrypted to the targets, before anything is written. age can't mix passphrases and
+/// recipients, since anyone with a recipient's identity could learn that the file was encrypted to a passphrase and try
+/// to guess it.
+pub fn check_targets(targets: &[EncryptionTarget]) -> Result<(), io::Error> {
+    let passphrases = targets
+        .iter()
+        .filter(|target| matches!(target, EncryptionTarget::Passphrase))
+        .count();
+
+    if passphrases == 0 {
+        return Ok(());
+    }
+
+    if passphrases < targets.len() {
+        return Err(Message::new(messages::ENCRYPTION_MIXED_TARGETS)
+            .into_error(io::ErrorKind::InvalidInput));
+    }
+
+    read_passphrase().map(|_| ())
+}
+
+/// A writer which encrypts everything written to it with age, unless no targets were given. Like the compressors, the
+/// encrypted stream must be finished once everything has been written, or its end can't be decrypted.
+pub enum ArchiveEncryptor<W: Write> {
+    None(W),
+    /// The encrypted stream, which is taken when it is finished.
+    Age(Option<StreamWriter<W>>),
+}
+
+impl<W: Write> ArchiveEncryptor<W> {
+    pub fn new(writer: W, targets: &[EncryptionTarget]) -> Result<Self, io::Error> {
+        if targets.is_empty() {
+            return Ok(ArchiveEncryptor::None(writer));
+        }
+
+        check_targets(targets)?;
+
+        let recipients: Vec<&age::x25519::Recipient> = targets
+            .iter()
+            .filter_map(|target| match target {
+                EncryptionTarget::Recipient(recipient) => Some(recipient),
+                EncryptionTarget::Passphrase => None,
+            })
+            .collect();
+
+        let encryptor = if recipients.is_empty() {
+            age::Encryptor::with_user_passphrase(read_passphrase()?)
+        } else {
+            age::Encryptor::with_recipients(
+                recipients
+                    .into_iter()
+                    .map(|recipient| recipient as &dyn age::Recipient),
+            )
+            .map_err(|err| {
+                Message::new(messages::ENCRYPTION_ENCRYPT_FAILED)
+                    .arg("error", err)
+                    .into_error(io::ErrorKind::InvalidInput)
+            })?
+        };
+
+        Ok(ArchiveEncryptor::Age(Some(encryptor.wrap_output(writer)?)))
+    }
+
+    /// Finishes the encrypted stream and flushes it to the underlying writer.
+    pub fn finish(&mut self) -> Result<(), io::Error> {
+        match self {
+            ArchiveEncryptor::None(writer) => writer.flush(),
+            ArchiveEncryptor::Age(stream) => match stream.take() {
+                Some(stream) => stream.finish()?.flush(),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+impl<W: Write> Write for ArchiveEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveEncryptor::None(writer) => writer.write(buf),
+            ArchiveEncryptor::Age(Some(stream)) => stream.write(buf),
+            ArchiveEncryptor::Age(None) => Err(finished_error()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveEncryptor::None(writer) => writer.flush(),
+            ArchiveEncryptor::Age(Some(stream)) => stream.flush(),
+            ArchiveEncryptor::Age(None) => Ok(()),
+        }
+    }
+}
+
+fn finished_error() -> io::Error {
+    Message::new(messages::ENCRYPTION_FINISHED).into_error(io::ErrorKind::BrokenPipe)
+}
+
+/// Returns true if the first bytes of an archive show that it was encrypted with age.
+pub fn is_encrypted(magic: &[u8]) -> bool {
+    magic.starts_with(AGE_MAGIC)
+}
+
+/// The number of bytes needed to recognize an encrypted archive.
+pub fn magic_len() -> usize {
+    AGE_MAGIC.len()
+}
+
+/// Returns a reader which decrypts an archive encrypted with age, using the identity files or the passphrase given to
+/// the run.
+pub fn decrypted_reader<'a, R>(reader: R) -> Result<Box<dyn Read + 'a>, io::Error>
+where
+    R: Read + 'a,
+{
+    let decryptor = age::Decryptor::new(reader).map_err(decrypt_error)?;
+
+    let stream = if decryptor.is_scrypt() {
+        let identity = age::scrypt::Identity::new(read_passphrase()?);
+
+        decryptor
+            .decrypt(iter::once(&identity as &dyn age::Identity))
+            .map_err(decrypt_error)?
+    } else {
+        let identity_files = STATE.lock().unwrap().identities.clone();
+
+        if identity_files.is_empty() {
+            return Err(Message::new(messages::ENCRYPTION_IDENTITY_REQUIRED)
+                .into_error(io::ErrorKind::InvalidInput));
+        }
+
+        let mut identities = Vec::new();
+
+        for path in identity_files {
+            let identity_file = age::IdentityFile::from_file(path.to_string_lossy().into_owned())?;
+
+            identities.extend(identity_file.into_identities().map_err(|err| {
+                Message::new(messages::ENCRYPTION_INVALID_IDENTITY)
+                    .arg("path", path.display())
+                    .arg("error", err)
+                    .into_error(io::ErrorKind::InvalidInput)
+            })?);
+        }
+
+        decryptor
+            .decrypt(identities.iter().map(|identity| identity.as_ref()))
+            .map_err(decrypt_error)?
+    };
+
+    Ok(Box::new(stream))
+}
+
+/// Reads the passphrase from the first line of the file given by `--passphrase-file`.
+fn read_passphrase() -> Result<SecretString, io::Error> {
+    let path = STATE
+        .lock()
+        .unwrap()
+        .passphrase_file
+        .clone()
+        .ok_or_else(|| {
+            Message::new(messages::ENCRYPTION_PASSPHRASE_REQUIRED)
+                .into_error(io::ErrorKind::InvalidInput)
+        })?;
+
+    let contents = fs::read_to_string(path)?;
+    let passphrase = contents.lines().next().unwrap_or("");
+
+    if passphrase.is_empty() {
+        return Err(Message::new(messages::ENCRYPTION_PASSPHRASE_EMPTY)
+            .into_error(io::ErrorKind::InvalidInput));
+    }
+
+    Ok(SecretString::from(passphrase.to_owned()))
+}
+
+fn decrypt_error(err: age::DecryptError) -> io::Error {
+    match err {
+        age::DecryptError::Io(err) => err,
+        err => Message::new(messages::ENCRYPTION_DECRYPT_FAILED)
+            .arg("error", err)
+            .into_error(io::ErrorKind::InvalidData),
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+
+use crate::messages::{self, Message, MessageId};
+
+/// The code the process exits with when a command fails, so that scripts can branch on the kind of failure instead of
+/// parsing the error. These codes are stable across releases. Bad arguments share their code with the errors which clap
+/// reports for arguments it can't parse.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// The command failed for a reason which none of the other codes cover.
+    Failure = 1,
+    /// The arguments, config file or message catalog given to the command are invalid.
+    Usage = 2,
+    /// The command finished, but skipped chunks which couldn't be read because of `--keep-going`.
+    Skipped = 3,
+    /// An input file or directory doesn't exist.
+    NotFound = 4,
+    /// The input is corrupt, or failed a check such as `verify`, `check` or `roundtrip`.
+    InvalidData = 5,
+    /// Reading or writing a file failed, such as because the disk is full or access to the file was denied.
+    Io = 6,
+}
+
+impl ExitCode {
+    /// Chooses the code for a command which failed with the given error.
+    pub fn of(failure: MessageId, err: &io::Error) -> ExitCode {
+        // The config file, message catalog and CPU options are all applied before any command starts
+        if [
+            messages::CONFIG_FAILED,
+            messages::MESSAGES_FAILED,
+            messages::CPU_FAILED,
+        ]
+        .contains(&failure)
+        {
+            return ExitCode::Usage;
+        }
+
+        let message = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Message>());
+
+        if message.map(Message::id) == Some(messages::KEEP_GOING_FAILED.id) {
+            return ExitCode::Skipped;
+        }
+
+        match err.kind() {
+            io::ErrorKind::NotFound => ExitCode::NotFound,
+            // The tool's own errors about its options are in the catalog, while decoders such as flate2 report corrupt
+            // streams as invalid input
+            io::ErrorKind::InvalidInput if message.is_some() => ExitCode::Usage,
+            // Writing in read-only mode is refused before the file is touched
+            io::ErrorKind::PermissionDenied if message.is_some() => ExitCode::Usage,
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof => ExitCode::InvalidData,
+            _ if err.raw_os_error().is_some() => ExitCode::Io,
+            io::ErrorKind::PermissionDenied
+            | io::ErrorKind::AlreadyExists
+            | io::ErrorKind::WriteZero
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted => ExitCode::Io,
+            _ => ExitCode::Failure,
+        }
+    }
+}
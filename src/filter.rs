@@ -0,0 +1,418 @@
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use clap::Args;
+
+use crate::messages::{self, report};
+use crate::nbt::find_string_tag;
+use crate::profile::ArchiveProfile;
+use crate::region::ChunkPos;
+
+#[derive(Args, Clone)]
+pub struct FilterOptions {
+    #[clap(
+        long,
+        help = "Only include regions within these coordinates, given as X,Z or ranges such as -10..10,-10..10 (may be repeated)",
+        value_parser = parse_coordinate_box
+    )]
+    include_regions: Vec<CoordinateBox>,
+
+    #[clap(
+        long,
+        help = "Exclude regions within these coordinates, in the same form as --include-regions (may be repeated)",
+        value_parser = parse_coordinate_box
+    )]
+    exclude_regions: Vec<CoordinateBox>,
+
+    #[clap(
+        long,
+        help = "Only include chunks within this box of absolute chunk coordinates, such as -320..320,-320..320",
+        value_parser = parse_coordinate_box
+    )]
+    chunk_bounds: Option<CoordinateBox>,
+}
+
+impl FilterOptions {
+    pub fn includes_region(&self, region: ChunkPos) -> bool {
+        if !self.include_regions.is_empty()
+            && !self
+                .include_regions
+                .iter()
+                .any(|area| area.contains(region))
+        {
+            return false;
+        }
+
+        if self
+            .exclude_regions
+            .iter()
+            .any(|area| area.contains(region))
+        {
+            return false;
+        }
+
+        // Regions which lie entirely outside the chunk bounds don't need to be opened at all
+        match &self.chunk_bounds {
+            Some(bounds) => bounds.intersects_region(region),
+            None => true,
+        }
+    }
+
+    /// Returns true if a chunk should be included, given its region and its position relative to that region.
+    pub fn includes_chunk(&self, region: ChunkPos, chunk: ChunkPos) -> bool {
+        match &self.chunk_bounds {
+            Some(bounds) => bounds.contains(ChunkPos {
+                x: (region.x * 32) + chunk.x,
+                z: (region.z * 32) + chunk.z,
+            }),
+            None => true,
+        }
+    }
+
+    /// Records the areas selected by the filter in the profile of an archive.
+    pub fn record_profile(&self, profile: &mut ArchiveProfile) {
+        profile.include_regions = self
+            .include_regions
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        profile.exclude_regions = self
+            .exclude_regions
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        profile.chunk_bounds = self.chunk_bounds.as_ref().map(ToString::to_string);
+    }
+}
+
+/// An area of region or chunk coordinates, with inclusive bounds on each axis.
+#[derive(Clone, Debug)]
+pub struct CoordinateBox {
+    x: RangeInclusive<i32>,
+    z: RangeInclusive<i32>,
+}
+
+impl CoordinateBox {
+    pub fn new(x: RangeInclusive<i32>, z: RangeInclusive<i32>) -> CoordinateBox {
+        CoordinateBox { x, z }
+    }
+
+    /// Returns the area of chunks which contain any block of this area, taking its coordinates as block coordinates.
+    pub fn block_area_chunks(&self) -> CoordinateBox {
+        let chunks =
+            |range: &RangeInclusive<i32>| range.start().div_euclid(16)..=range.end().div_euclid(16);
+
+        CoordinateBox {
+            x: chunks(&self.x),
+            z: chunks(&self.z),
+        }
+    }
+
+    /// Returns the area of blocks covered by this area, taking its coordinates as chunk coordinates.
+    pub fn chunk_area_blocks(&self) -> CoordinateBox {
+        let blocks = |range: &RangeInclusive<i32>| (range.start() * 16)..=((range.end() * 16) + 15);
+
+        CoordinateBox {
+            x: blocks(&self.x),
+            z: blocks(&self.z),
+        }
+    }
+
+    /// Returns the area of absolute chunk coordinates covered by a region.
+    fn of_region(region: ChunkPos) -> CoordinateBox {
+        CoordinateBox {
+            x: (region.x * 32)..=((region.x * 32) + 31),
+            z: (region.z * 32)..=((region.z * 32) + 31),
+        }
+    }
+
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        self.contains_coordinates(pos.x, pos.z)
+    }
+
+    /// Returns true if the area contains a position, for areas of coordinates other than chunks, such as blocks.
+    pub fn contains_coordinates(&self, x: i32, z: i32) -> bool {
+        self.x.contains(&x) && self.z.contains(&z)
+    }
+
+    /// Returns true if any chunk of a region lies within the area, given the position of the region.
+    pub fn intersects_region(&self, region: ChunkPos) -> bool {
+        self.intersects(&CoordinateBox::of_region(region))
+    }
+
+    fn intersects(&self, other: &CoordinateBox) -> bool {
+        fn overlaps(a: &RangeInclusive<i32>, b: &RangeInclusive<i32>) -> bool {
+            a.start() <= b.end() && b.start() <= a.end()
+        }
+
+        overlaps(&self.x, &other.x) && overlaps(&self.z, &other.z)
+    }
+}
+
+impl fmt::Display for CoordinateBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_range(f: &mut fmt::Formatter<'_>, range: &RangeInclusive<i32>) -> fmt::Result {
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())
+            } else {
+                write!(f, "{}..={}", range.start(), range.end())
+            }
+        }
+
+        write_range(f, &self.x)?;
+        f.write_str(",")?;
+        write_range(f, &self.z)
+    }
+}
+
+/// Parses a coordinate box such as `-10..10,-10..10`, where each axis is either a single coordinate, an exclusive
+/// range (`a..b`), or an inclusive range (`a..=b`).
+pub fn parse_coordinate_box(value: &str) -> Result<CoordinateBox, String> {
+    let (x, z) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Expected coordinates in the form X,Z: {}", value))?;
+
+    Ok(CoordinateBox {
+        x: parse_coordinate_range(x)?,
+        z: parse_coordinate_range(z)?,
+    })
+}
+
+/// Matches a name against a pattern in which `*` stands for any text, including none, and `?` for any single character.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+
+    // The position of the last `*` and of the name where it started matching, to backtrack to on a mismatch
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    n = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parses a single axis of a coordinate box, which is either a single coordinate, an exclusive range (`a..b`), or an
+/// inclusive range (`a..=b`).
+pub fn parse_coordinate_range(value: &str) -> Result<RangeInclusive<i32>, String> {
+    let value = value.trim();
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid coordinate: {}", value))
+    };
+
+    let range = if let Some((start, end)) = value.split_once("..=") {
+        parse(start)?..=parse(end)?
+    } else if let Some((start, end)) = value.split_once("..") {
+        parse(start)?..=parse(end)?.saturating_sub(1)
+    } else {
+        let coordinate = parse(value)?;
+        coordinate..=coordinate
+    };
+
+    if range.is_empty() {
+        return Err(format!("Coordinate range is empty: {}", value));
+    }
+
+    Ok(range)
+}
+
+#[derive(Args, Clone)]
+pub struct StatusOptions {
+    #[clap(
+        long,
+        help = "Skip chunks which have not reached this generation status (e.g. full, features)",
+        value_parser = parse_chunk_status
+    )]
+    min_status: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Only include chunks with one of these generation statuses (comma-separated)",
+        value_delimiter = ',',
+        conflicts_with = "min_status"
+    )]
+    status: Vec<String>,
+}
+
+/// The generation statuses of chunks in the order they are reached, including the names used before 1.14. Later
+/// versions prefix the status with `minecraft:`, which is removed before comparing.
+const CHUNK_STATUSES: &[(&str, usize)] = &[
+    ("empty", 0),
+    ("structure_starts", 1),
+    ("structure_references", 2),
+    ("biomes", 3),
+    ("noise", 4),
+    ("base", 4),
+    ("surface", 5),
+    ("carvers", 6),
+    ("carved", 6),
+    ("liquid_carvers", 7),
+    ("liquid_carved", 7),
+    ("features", 8),
+    ("decorated", 8),
+    ("initialize_light", 9),
+    ("light", 10),
+    ("lighted", 10),
+    ("spawn", 11),
+    ("mobs_spawned", 11),
+    ("heightmaps", 12),
+    ("finalized", 12),
+    ("full", 13),
+    ("fullchunk", 13),
+    ("postprocessed", 13),
+];
+
+fn normalize_status(status: &str) -> &str {
+    status.strip_prefix("minecraft:").unwrap_or(status)
+}
+
+fn chunk_status_rank(status: &str) -> Option<usize> {
+    let status = normalize_status(status);
+
+    CHUNK_STATUSES
+        .iter()
+        .find(|(name, _)| *name == status)
+        .map(|(_, rank)| *rank)
+}
+
+/// The status a chunk must have reached to no longer be considered a proto-chunk. Chunks before this stage only
+/// contain terrain shape and structure data, which the server regenerates cheaply when the chunk is next loaded.
+const PROTO_CHUNK_RANK: usize = 8;
+
+/// Reads the generation status of a chunk from its uncompressed NBT data, which is stored at the root since 1.18 and in
+/// the `Level` compound before that.
+fn chunk_status(data: &[u8]) -> Result<Option<String>, io::Error> {
+    match find_string_tag(data, &["Status"])? {
+        Some(status) => Ok(Some(status)),
+        None => find_string_tag(data, &["Level", "Status"]),
+    }
+}
+
+/// Returns true if a chunk has not yet reached the `features` stage of generation. Chunks without a status, or with a
+/// status which isn't recognized, are never considered proto-chunks.
+pub fn is_proto_chunk(data: &[u8]) -> Result<bool, io::Error> {
+    let rank = chunk_status(data)?.and_then(|status| chunk_status_rank(&status));
+
+    Ok(match rank {
+        Some(rank) => rank < PROTO_CHUNK_RANK,
+        None => false,
+    })
+}
+
+fn parse_chunk_status(value: &str) -> Result<usize, String> {
+    chunk_status_rank(value).ok_or_else(|| format!("Unknown chunk status: {}", value))
+}
+
+/// Skips chunks by their generation status, counting how many were skipped so they can be reported once a command
+/// finishes. Chunks without a status (saved before 1.13) or with a status which isn't recognized are always included.
+pub struct StatusFilter {
+    min_rank: Option<usize>,
+    allowed: Vec<String>,
+    skipped: AtomicU64,
+}
+
+impl StatusFilter {
+    pub fn new(options: &StatusOptions) -> Self {
+        StatusFilter {
+            min_rank: options.min_status,
+            allowed: options
+                .status
+                .iter()
+                .map(|status| String::from(normalize_status(status)))
+                .collect(),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a filter from the names of statuses, as recorded in the profile of an archive.
+    pub fn from_names(min_status: Option<&str>, statuses: &[String]) -> Result<Self, String> {
+        Ok(StatusFilter {
+            min_rank: min_status.map(parse_chunk_status).transpose()?,
+            allowed: statuses
+                .iter()
+                .map(|status| String::from(normalize_status(status)))
+                .collect(),
+            skipped: AtomicU64::new(0),
+        })
+    }
+
+    /// Records the statuses chunks are filtered by in the profile of an archive.
+    pub fn record_profile(&self, profile: &mut ArchiveProfile) {
+        profile.min_status = self.min_rank.map(|min_rank| {
+            let (name, _) = CHUNK_STATUSES
+                .iter()
+                .find(|(_, rank)| *rank == min_rank)
+                .unwrap();
+
+            String::from(*name)
+        });
+        profile.status = self.allowed.clone();
+    }
+
+    /// Returns true if any chunks can be skipped by the filter.
+    pub fn is_active(&self) -> bool {
+        self.min_rank.is_some() || !self.allowed.is_empty()
+    }
+
+    /// Returns true if a chunk should be included, given its uncompressed NBT data.
+    pub fn includes(&self, data: &[u8]) -> Result<bool, io::Error> {
+        if !self.is_active() {
+            return Ok(true);
+        }
+
+        let status = match chunk_status(data)? {
+            Some(status) => status,
+            None => return Ok(true),
+        };
+
+        let included = match self.min_rank {
+            Some(min_rank) => match chunk_status_rank(&status) {
+                Some(rank) => rank >= min_rank,
+                None => true,
+            },
+            None => self
+                .allowed
+                .iter()
+                .any(|allowed| allowed == normalize_status(&status)),
+        };
+
+        if !included {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(included)
+    }
+
+    pub fn report(&self) {
+        let skipped = self.skipped.load(Ordering::Relaxed);
+
+        if skipped > 0 {
+            report!(info, messages::STATUS_SKIPPED, skipped = skipped);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::messages::{self, Message};
+use crate::read_only;
+
+/// An append-only log of the work completed by a command, which allows an interrupted run to be resumed. Each journal
+/// starts with a header naming the command which wrote it, followed by one record per line. The meaning of records is
+/// left to the command, which should write them only once the work they describe has been written out.
+///
+/// Since a run may be killed part-way through writing a record, the last line of a journal may be incomplete, and
+/// commands should ignore records which they cannot parse. Records are flushed but not synced, so a journal protects
+/// against the process being interrupted rather than against the machine losing power.
+pub struct Journal {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it does not exist. The records written by a previous run are
+    /// returned, or an error if the journal was written by a different kind of command.
+    pub fn open(path: &Path, kind: &str) -> Result<(Journal, Vec<String>), io::Error> {
+        read_only::ensure_writable(path)?;
+
+        let header = format!("# anvil-tools {} journal", kind);
+        let mut records = Vec::new();
+
+        if Path::exists(path) {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+
+            match lines.next().transpose()? {
+                Some(line) if line == header => {}
+                _ => {
+                    return Err(Message::new(messages::JOURNAL_INVALID)
+                        .arg("path", path.display())
+                        .arg("kind", kind)
+                        .into_error(io::ErrorKind::InvalidData))
+                }
+            }
+
+            for line in lines {
+                records.push(line?);
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut journal = Journal {
+            writer: BufWriter::new(file),
+            path: path.to_owned(),
+        };
+
+        if records.is_empty() {
+            journal.writer.get_mut().set_len(0)?;
+            journal.record(&[header])?;
+        }
+
+        Ok((journal, records))
+    }
+
+    /// Appends records to the journal and flushes them to the file.
+    pub fn record(&mut self, records: &[String]) -> Result<(), io::Error> {
+        for record in records {
+            writeln!(self.writer, "{}", record)?;
+        }
+
+        self.writer.flush()
+    }
+
+    /// Removes the journal once the command has completed, so that a later run starts from scratch.
+    pub fn finish(self) -> Result<(), io::Error> {
+        let path = self.path.clone();
+        drop(self);
+
+        fs::remove_file(path)
+    }
+}
@@ -0,0 +1,102 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use clap::Args;
+
+use crate::messages::{self, report, Message};
+use crate::observer::OperationObserver;
+use crate::region::ChunkPos;
+
+#[derive(Args, Clone, Default)]
+pub struct KeepGoingOptions {
+    #[clap(
+        long,
+        help = "Log and skip chunks which can't be read instead of failing, and exit with code 3 once the rest are done"
+    )]
+    keep_going: bool,
+}
+
+/// Skips chunks which can't be read or decoded when `--keep-going` is given, so that a single corrupt chunk doesn't
+/// stop a run over a whole world. Otherwise, the error of the first such chunk fails the command as usual.
+///
+/// Cloned trackers share the same skipped chunks, so they can be reported once a command finishes.
+#[derive(Clone)]
+pub struct SkippedChunks {
+    keep_going: bool,
+    /// The position of each skipped chunk's region, and its position within that region.
+    skipped: Arc<Mutex<Vec<(ChunkPos, ChunkPos)>>>,
+}
+
+impl SkippedChunks {
+    pub fn new(options: &KeepGoingOptions) -> Self {
+        SkippedChunks {
+            keep_going: options.keep_going,
+            skipped: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Passes on the result of reading a chunk of the given region, or returns `None` if reading it failed and the chunk
+    /// is skipped. Chunks refused by `--on-position-mismatch refuse` could be read, so they still fail the command.
+    pub fn check<T>(
+        &self,
+        region: ChunkPos,
+        chunk: ChunkPos,
+        result: Result<T, io::Error>,
+        observer: &dyn OperationObserver,
+    ) -> Result<Option<T>, io::Error> {
+        let err = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) => err,
+        };
+
+        let error = messages::error_message(&err);
+
+        if !self.keep_going || error.id() == messages::POSITION_REFUSED.id {
+            return Err(err);
+        }
+
+        observer.warning(&report!(
+            warn,
+            messages::KEEP_GOING_SKIPPED,
+            x = chunk.x,
+            z = chunk.z,
+            region = format!("r.{}.{}.mca", region.x, region.z),
+            error = error.text()
+        ));
+
+        self.skipped.lock().unwrap().push((region, chunk));
+
+        Ok(None)
+    }
+
+    /// Logs the chunks which were skipped from each region, passing each warning on to the observer, and fails if any
+    /// chunks were skipped so that the command exits with its own code.
+    pub fn finish(&self, observer: &dyn OperationObserver) -> Result<(), io::Error> {
+        let mut skipped = self.skipped.lock().unwrap();
+        skipped.sort_by_key(|(region, chunk)| (region.x, region.z, chunk.z, chunk.x));
+
+        for chunks in skipped.chunk_by(|(a, _), (b, _)| a == b) {
+            let region = chunks[0].0;
+            let positions: Vec<String> = chunks
+                .iter()
+                .map(|(_, chunk)| format!("({}, {})", chunk.x, chunk.z))
+                .collect();
+
+            observer.warning(&report!(
+                warn,
+                messages::KEEP_GOING_REGION,
+                chunks = chunks.len(),
+                region = format!("r.{}.{}.mca", region.x, region.z),
+                positions = positions.join(", ")
+            ));
+        }
+
+        if !skipped.is_empty() {
+            return Err(Message::new(messages::KEEP_GOING_FAILED)
+                .arg("chunks", skipped.len())
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::filter::{parse_coordinate_box, CoordinateBox};
+use crate::messages::{self, Message};
+use crate::region::{ChunkPos, RegionFile};
+
+/// Rules which assign labels (such as the tenant or owner of a world) to regions, loaded from a sidecar file. Each line
+/// gives either `*` for the whole world or a box of region coordinates such as `-2..2,-2..2`, followed by the labels
+/// which apply to it, separated by commas. Blank lines and lines starting with `#` are ignored.
+pub struct LabelRules {
+    rules: Vec<(Option<CoordinateBox>, Vec<String>)>,
+}
+
+impl LabelRules {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let mut rules = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (selector, labels) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                invalid_data(Message::new(messages::LABELS_MALFORMED_RULE).arg("line", line))
+            })?;
+
+            let area = match selector {
+                "*" => None,
+                selector => Some(parse_coordinate_box(selector).map_err(|err| {
+                    invalid_data(Message::new(messages::LABELS_MALFORMED_REGIONS).arg("error", err))
+                })?),
+            };
+
+            rules.push((area, parse_label_list(labels)?));
+        }
+
+        Ok(LabelRules { rules })
+    }
+
+    /// Returns the labels of every rule which applies to a region.
+    pub fn labels_for(&self, region: ChunkPos) -> BTreeSet<String> {
+        self.rules
+            .iter()
+            .filter(|(area, _)| match area {
+                Some(area) => area.contains(region),
+                None => true,
+            })
+            .flat_map(|(_, labels)| labels.iter().cloned())
+            .collect()
+    }
+}
+
+/// The labels of each region in an archive, stored as plain text with one `<region> <label>,<label>...` line per
+/// labelled region.
+#[derive(Default)]
+pub struct RegionLabels {
+    regions: HashMap<ChunkPos, BTreeSet<String>>,
+}
+
+impl RegionLabels {
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut regions = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (region, labels) = line
+                .split_once(' ')
+                .and_then(|(region, labels)| Some((RegionFile::try_parse_name(region)?, labels)))
+                .ok_or_else(|| {
+                    invalid_data(Message::new(messages::LABELS_MALFORMED_LINE).arg("line", line))
+                })?;
+
+            regions.insert(region, parse_label_list(labels)?.into_iter().collect());
+        }
+
+        Ok(RegionLabels { regions })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut lines: Vec<String> = self
+            .regions
+            .iter()
+            .filter(|(_, labels)| !labels.is_empty())
+            .map(|(region, labels)| {
+                let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+
+                format!("r.{}.{} {}", region.x, region.z, labels.join(","))
+            })
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, region: ChunkPos, labels: BTreeSet<String>) {
+        self.regions.insert(region, labels);
+    }
+
+    /// Returns true if a region has any of the given labels.
+    pub fn has_any(&self, region: ChunkPos, labels: &[String]) -> bool {
+        match self.regions.get(&region) {
+            Some(region_labels) => labels.iter().any(|label| region_labels.contains(label)),
+            None => false,
+        }
+    }
+}
+
+fn parse_label_list(labels: &str) -> Result<Vec<String>, io::Error> {
+    let labels: Vec<String> = labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(String::from)
+        .collect();
+
+    if let Some(label) = labels
+        .iter()
+        .find(|label| label.contains(char::is_whitespace))
+    {
+        return Err(invalid_data(
+            Message::new(messages::LABELS_WHITESPACE).arg("label", label),
+        ));
+    }
+
+    Ok(labels)
+}
+
+fn invalid_data(message: Message) -> io::Error {
+    message.into_error(io::ErrorKind::InvalidData)
+}
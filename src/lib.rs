@@ -0,0 +1,71 @@
+//! Tools for working with Minecraft's Anvil chunk storage while out of the game. The `anvil-tools-rs` binary is built
+//! on this library, and the modules below are exposed for programs which need to read or write region files
+//! themselves.
+//!
+//! Without the default `native` feature, only the modules which work on data held in memory are built: region files
+//! can be parsed from bytes and their chunks decoded and stripped, but nothing touches the file system. This is the
+//! configuration which compiles to wasm32, for tools which inspect region files in a browser.
+
+#[cfg(feature = "native")]
+mod archive_version;
+#[cfg(feature = "async")]
+pub mod async_region;
+#[cfg(feature = "native")]
+mod block_index;
+pub mod blocks;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "native")]
+pub mod commands;
+#[cfg(feature = "native")]
+mod compression;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod cpu;
+#[cfg(feature = "native")]
+mod dedup;
+#[cfg(feature = "native")]
+mod dimension;
+#[cfg(feature = "native")]
+pub mod encryption;
+pub mod exit_code;
+#[cfg(feature = "native")]
+mod filter;
+#[cfg(feature = "native")]
+mod journal;
+#[cfg(feature = "native")]
+mod keep_going;
+#[cfg(feature = "native")]
+mod labels;
+#[cfg(feature = "native")]
+pub mod linear;
+#[cfg(feature = "native")]
+pub mod logging;
+#[cfg(feature = "native")]
+mod manifest;
+pub mod messages;
+#[cfg(feature = "native")]
+mod metadata;
+pub mod nbt;
+#[cfg(feature = "native")]
+mod nbt_path;
+#[cfg(feature = "native")]
+pub mod observer;
+#[cfg(feature = "native")]
+mod position_check;
+#[cfg(feature = "native")]
+mod profile;
+#[cfg(feature = "native")]
+pub mod read_only;
+pub mod region;
+#[cfg(feature = "native")]
+mod repository;
+#[cfg(feature = "native")]
+pub mod retry;
+pub mod snbt;
+pub mod stripping;
+#[cfg(feature = "native")]
+pub mod temp;
+#[cfg(feature = "native")]
+mod version_rules;
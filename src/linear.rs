@@ -0,0 +1,230 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::messages::{self, Message};
+use crate::read_only;
+use crate::region::{Chunk, ChunkPos, RawChunk, RegionFile};
+
+/// Marks the start and end of a Linear region file.
+const SUPERBLOCK: u64 = 0xC3FF_1318_3CCA_9D9A;
+
+/// Linear regions written by every version of the format so far share the same layout.
+const SUPPORTED_VERSIONS: [u8; 2] = [1, 2];
+
+/// Regions are written with the first version of the format, which every reader accepts.
+const WRITTEN_VERSION: u8 = 1;
+
+/// The superblock, version, newest timestamp, compression level, chunk count, compressed length and a reserved field.
+const HEADER_SIZE: usize = 8 + 1 + 8 + 1 + 2 + 4 + 8;
+const FOOTER_SIZE: usize = 8;
+
+const ENTRY_COUNT: usize = 32 * 32;
+
+/// Each chunk's entry holds the length of its data followed by when it was last saved.
+const ENTRY_LENGTH: usize = 8;
+
+/// A region file in the Linear format used by some server forks, which compresses all of a region's chunks together with
+/// Zstandard rather than each chunk by itself. The whole region is decompressed when it is opened, and its chunks are
+/// read as uncompressed NBT.
+pub struct LinearRegionFile {
+    data: Box<[u8]>,
+    entries: Vec<Option<LinearEntry>>,
+}
+
+#[derive(Copy, Clone)]
+struct LinearEntry {
+    offset: usize,
+    length: usize,
+    timestamp: u32,
+}
+
+impl LinearRegionFile {
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let file = fs::read(path)?;
+
+        if file.len() < HEADER_SIZE + FOOTER_SIZE {
+            return Err(invalid_error(
+                path,
+                "the file is too short to contain a header",
+            ));
+        }
+
+        let superblock = u64::from_be_bytes(file[0..8].try_into().unwrap());
+        let footer = u64::from_be_bytes(file[file.len() - FOOTER_SIZE..].try_into().unwrap());
+
+        if superblock != SUPERBLOCK || footer != SUPERBLOCK {
+            return Err(Message::new(messages::REGION_NOT_REGION)
+                .arg("path", path.display())
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        let version = file[8];
+
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(Message::new(messages::LINEAR_UNSUPPORTED_VERSION)
+                .arg("path", path.display())
+                .arg("version", version)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        let compressed_length = u32::from_be_bytes(file[20..24].try_into().unwrap()) as usize;
+        let compressed = file
+            .get(HEADER_SIZE..HEADER_SIZE + compressed_length)
+            .ok_or_else(|| {
+                invalid_error(path, "the compressed data extends past the end of the file")
+            })?;
+
+        let data = zstd::decode_all(compressed)?.into_boxed_slice();
+
+        if data.len() < ENTRY_COUNT * ENTRY_LENGTH {
+            return Err(invalid_error(path, "the chunk table is truncated"));
+        }
+
+        // Chunks are stored one after another following the table, in the same order as their entries
+        let mut offset = ENTRY_COUNT * ENTRY_LENGTH;
+        let mut entries = Vec::with_capacity(ENTRY_COUNT);
+
+        for index in 0..ENTRY_COUNT {
+            let entry = &data[index * ENTRY_LENGTH..(index + 1) * ENTRY_LENGTH];
+            let length = u32::from_be_bytes(entry[0..4].try_into().unwrap()) as usize;
+            let timestamp = u32::from_be_bytes(entry[4..8].try_into().unwrap());
+
+            if length == 0 {
+                entries.push(None);
+                continue;
+            }
+
+            if offset + length > data.len() {
+                return Err(invalid_error(
+                    path,
+                    "a chunk extends past the end of the data",
+                ));
+            }
+
+            entries.push(Some(LinearEntry {
+                offset,
+                length,
+                timestamp,
+            }));
+
+            offset += length;
+        }
+
+        Ok(LinearRegionFile { data, entries })
+    }
+
+    /// Counts the chunks stored in a region file from the count in its header, without decompressing the region.
+    pub fn count_chunks(path: &Path) -> Result<u64, io::Error> {
+        let mut header = [0u8; HEADER_SIZE];
+        File::open(path)?.read_exact(&mut header)?;
+
+        Ok(u16::from_be_bytes(header[18..20].try_into().unwrap()) as u64)
+    }
+
+    /// Streams the chunks of the region. Their payloads are marked as uncompressed, since the region's compression
+    /// has already been undone.
+    pub fn stream_raw_chunks(
+        &self,
+    ) -> impl Iterator<Item = Result<Option<RawChunk>, io::Error>> + '_ {
+        self.entries.iter().enumerate().map(move |(index, entry)| {
+            Ok(entry.map(|entry| {
+                RawChunk::uncompressed(
+                    ChunkPos {
+                        x: (index % 32) as i32,
+                        z: (index / 32) as i32,
+                    },
+                    &self.data[entry.offset..entry.offset + entry.length],
+                )
+            }))
+        })
+    }
+
+    /// Returns when a chunk was last saved as seconds since the Unix epoch, given its position within the region, or
+    /// zero if the time wasn't recorded.
+    pub fn timestamp(&self, position: ChunkPos) -> u32 {
+        let index = (position.x as usize % 32) + ((position.z as usize % 32) * 32);
+
+        self.entries[index].map_or(0, |entry| entry.timestamp)
+    }
+
+    /// Checks whether a file name is that of a Linear region file, such as `r.-1.2.linear`.
+    pub fn is_region_name(name: &str) -> bool {
+        name.ends_with(".linear") && RegionFile::try_parse_name(name).is_some()
+    }
+}
+
+/// Writes a Linear region file. Chunks are collected in memory as they are added, and the region is compressed and
+/// written out all at once when it is finished.
+pub struct LinearRegionFileWriter {
+    path: PathBuf,
+    chunks: Vec<Option<(Box<[u8]>, u32)>>,
+    compression_level: i32,
+}
+
+impl LinearRegionFileWriter {
+    pub fn create(path: &Path, compression_level: i32) -> Result<Self, io::Error> {
+        read_only::ensure_writable(path)?;
+
+        Ok(LinearRegionFileWriter {
+            path: path.to_path_buf(),
+            chunks: vec![None; ENTRY_COUNT],
+            compression_level,
+        })
+    }
+
+    /// Adds a chunk along with when it was last saved, as seconds since the Unix epoch.
+    pub fn add_chunk(&mut self, chunk: &Chunk, timestamp: u32) {
+        let index = (chunk.position.x as usize % 32) + ((chunk.position.z as usize % 32) * 32);
+
+        self.chunks[index] = Some((chunk.data.clone(), timestamp));
+    }
+
+    pub fn finish(self) -> Result<(), io::Error> {
+        let chunks = self.chunks.iter().flatten();
+        let data_size: usize = chunks.clone().map(|(data, _)| data.len()).sum();
+
+        let mut data = Vec::with_capacity(ENTRY_COUNT * ENTRY_LENGTH + data_size);
+
+        for chunk in &self.chunks {
+            let (length, timestamp) = chunk
+                .as_ref()
+                .map_or((0, 0), |(data, timestamp)| (data.len() as u32, *timestamp));
+
+            data.extend_from_slice(&length.to_be_bytes());
+            data.extend_from_slice(&timestamp.to_be_bytes());
+        }
+
+        for (chunk_data, _) in chunks.clone() {
+            data.extend_from_slice(chunk_data);
+        }
+
+        let compressed = zstd::encode_all(&data[..], self.compression_level)?;
+        let newest_timestamp = chunks
+            .clone()
+            .map(|&(_, timestamp)| timestamp)
+            .max()
+            .unwrap_or(0);
+
+        let mut file = Vec::with_capacity(HEADER_SIZE + compressed.len() + FOOTER_SIZE);
+        file.extend_from_slice(&SUPERBLOCK.to_be_bytes());
+        file.push(WRITTEN_VERSION);
+        file.extend_from_slice(&(newest_timestamp as i64).to_be_bytes());
+        file.push(self.compression_level as u8);
+        file.extend_from_slice(&(chunks.count() as i16).to_be_bytes());
+        file.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        file.extend_from_slice(&0u64.to_be_bytes());
+        file.extend_from_slice(&compressed);
+        file.extend_from_slice(&SUPERBLOCK.to_be_bytes());
+
+        fs::write(&self.path, file)
+    }
+}
+
+fn invalid_error(path: &Path, reason: &str) -> io::Error {
+    Message::new(messages::LINEAR_INVALID)
+        .arg("path", path.display())
+        .arg("reason", reason)
+        .into_error(io::ErrorKind::InvalidData)
+}
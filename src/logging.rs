@@ -0,0 +1,118 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use atty::Stream;
+use clap::{Args, ValueEnum};
+use indicatif::ProgressBar;
+use tracing::Level;
+
+use crate::messages;
+
+#[derive(Args)]
+pub struct LogOptions {
+    #[clap(
+        long,
+        short,
+        global = true,
+        conflicts_with = "verbose",
+        help = "Only log warnings and errors, and hide progress bars"
+    )]
+    quiet: bool,
+
+    #[clap(
+        long,
+        short,
+        global = true,
+        help = "Also log details such as the time taken by each region"
+    )]
+    verbose: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        help = "Format of the log messages written to stderr",
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        help = "How the progress of pack, unpack and strip is shown on stderr",
+        default_value = "bar"
+    )]
+    progress: ProgressFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with each value of a message as its own field
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    /// A progress bar, which is hidden when logging quietly or as JSON
+    Bar,
+    /// One JSON object per line for each progress event, which is written even when logging quietly
+    Json,
+}
+
+static SHOW_PROGRESS: AtomicBool = AtomicBool::new(true);
+
+static PROGRESS_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// Installs the logger for the rest of the process. Progress bars are hidden when logging quietly or as JSON, since
+/// they would otherwise be interleaved with the log messages.
+pub fn init(options: &LogOptions) {
+    let level = if options.quiet {
+        Level::WARN
+    } else if options.verbose {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(atty::is(Stream::Stderr))
+        .with_target(false);
+
+    match options.log_format {
+        LogFormat::Text => builder.without_time().init(),
+        LogFormat::Json => {
+            builder.json().flatten_event(true).init();
+            messages::install_panic_hook();
+        }
+    }
+
+    if options.quiet
+        || options.log_format == LogFormat::Json
+        || options.progress == ProgressFormat::Json
+    {
+        SHOW_PROGRESS.store(false, Ordering::SeqCst);
+    }
+
+    if options.progress == ProgressFormat::Json {
+        PROGRESS_EVENTS.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether progress is written as JSON events instead of being shown on a progress bar.
+pub fn progress_events() -> bool {
+    PROGRESS_EVENTS.load(Ordering::SeqCst)
+}
+
+/// Creates a progress bar of the given length, which is hidden if progress shouldn't be shown.
+pub fn progress_bar(length: u64) -> ProgressBar {
+    if SHOW_PROGRESS.load(Ordering::SeqCst) {
+        ProgressBar::new(length)
+    } else {
+        ProgressBar::hidden()
+    }
+}
@@ -1,15 +1,141 @@
-use clap::Parser;
+use clap::builder::BoolishValueParser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::ffi::OsString;
+use std::io;
 
-mod commands;
-mod region;
+use anvil_tools::{commands, config, cpu, encryption, logging, messages, read_only, region, temp};
 
 fn main() {
-    let opts: Opts = Opts::parse();
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let command = config::apply(Opts::command(), &args)
+        .unwrap_or_else(|err| messages::fail(messages::CONFIG_FAILED, &err));
+    let opts =
+        Opts::from_arg_matches(&command.get_matches_from(args)).unwrap_or_else(|err| err.exit());
 
-    match opts.command {
-        Command::Pack(v) => commands::archive::pack_files(&v).expect("Failed to pack files"),
-        Command::Unpack(v) => commands::archive::unpack_files(&v).expect("Failed to strip files"),
-        Command::Strip(v) => commands::strip::strip_files(&v).expect("Failed to strip files"),
+    logging::init(&opts.log);
+    messages::init(&opts.messages)
+        .unwrap_or_else(|err| messages::fail(messages::MESSAGES_FAILED, &err));
+
+    if opts.read_only {
+        read_only::enable();
+    }
+
+    if opts.no_mmap {
+        region::disable_mmap();
+    }
+
+    cpu::apply(&opts.cpu).unwrap_or_else(|err| messages::fail(messages::CPU_FAILED, &err));
+
+    temp::init(&opts.temp);
+    encryption::init(&opts.encryption);
+    let cleanup = temp::Cleanup;
+
+    let result = run(opts.command);
+
+    // Exiting skips destructors, so the temporary directory must be removed first
+    drop(cleanup);
+
+    if let Err((failure, err)) = result {
+        messages::fail(failure, &err);
+    }
+}
+
+fn run(command: Command) -> Result<(), (messages::MessageId, io::Error)> {
+    match command {
+        Command::Pack(v) => {
+            commands::archive::pack_files(&v).map_err(|err| (messages::PACK_FAILED, err))
+        }
+        Command::Unpack(v) => {
+            commands::archive::unpack_files(&v).map_err(|err| (messages::UNPACK_FAILED, err))
+        }
+        Command::Strip(v) => {
+            commands::strip::strip_files(&v).map_err(|err| (messages::STRIP_FAILED, err))
+        }
+        Command::Verify(v) => {
+            commands::verify::verify_archive(&v).map_err(|err| (messages::VERIFY_FAILED, err))
+        }
+        Command::Index(v) => commands::index::run(&v).map_err(|err| (messages::INDEX_FAILED, err)),
+        Command::ExportLayers(v) => commands::export_layers::export_layers(&v)
+            .map_err(|err| (messages::EXPORT_LAYERS_FAILED, err)),
+        Command::ExportBiomes(v) => commands::export_biomes::export_biomes(&v)
+            .map_err(|err| (messages::EXPORT_BIOMES_FAILED, err)),
+        Command::ExportHeightmap(v) => commands::export_heightmap::export_heightmap(&v)
+            .map_err(|err| (messages::EXPORT_HEIGHTMAP_FAILED, err)),
+        Command::ExportSchematic(v) => commands::export_schematic::export_schematic(&v)
+            .map_err(|err| (messages::EXPORT_SCHEMATIC_FAILED, err)),
+        Command::ImportSchematic(v) => commands::import_schematic::import_schematic(&v)
+            .map_err(|err| (messages::IMPORT_SCHEMATIC_FAILED, err)),
+        Command::Roundtrip(v) => commands::roundtrip::roundtrip_files(&v)
+            .map_err(|err| (messages::ROUNDTRIP_FAILED, err)),
+        Command::Normalize(v) => commands::normalize::normalize_world(&v)
+            .map_err(|err| (messages::NORMALIZE_FAILED, err)),
+        Command::CleanupStale(v) => commands::cleanup_stale::cleanup_stale(&v)
+            .map_err(|err| (messages::CLEANUP_STALE_FAILED, err)),
+        Command::NbtEdit(v) => {
+            commands::nbt_edit::edit_files(&v).map_err(|err| (messages::NBT_EDIT_FAILED, err))
+        }
+        Command::Archive(v) => {
+            commands::archive_info::run(&v).map_err(|err| (messages::ARCHIVE_FAILED, err))
+        }
+        Command::Count(v) => {
+            commands::count::count_blocks(&v).map_err(|err| (messages::COUNT_FAILED, err))
+        }
+        Command::Render(v) => {
+            commands::render::render_world(&v).map_err(|err| (messages::RENDER_FAILED, err))
+        }
+        Command::MapOccupancy(v) => commands::map_occupancy::map_occupancy(&v)
+            .map_err(|err| (messages::MAP_OCCUPANCY_FAILED, err)),
+        Command::Inhabited(v) => {
+            commands::inhabited::inhabited(&v).map_err(|err| (messages::INHABITED_FAILED, err))
+        }
+        Command::LastModified(v) => commands::last_modified::last_modified(&v)
+            .map_err(|err| (messages::LAST_MODIFIED_FAILED, err)),
+        Command::ReplaceBlocks(v) => commands::replace_blocks::replace_files(&v)
+            .map_err(|err| (messages::REPLACE_BLOCKS_FAILED, err)),
+        Command::Shift(v) => {
+            commands::shift::shift_world(&v).map_err(|err| (messages::SHIFT_FAILED, err))
+        }
+        Command::Copy(v) => {
+            commands::copy::copy_chunks(&v).map_err(|err| (messages::COPY_FAILED, err))
+        }
+        Command::DeleteChunks(v) => commands::delete_chunks::delete_chunks(&v)
+            .map_err(|err| (messages::DELETE_CHUNKS_FAILED, err)),
+        Command::Clear(v) => {
+            commands::clear::clear(&v).map_err(|err| (messages::CLEAR_FAILED, err))
+        }
+        Command::Blend(v) => {
+            commands::blend::blend_files(&v).map_err(|err| (messages::BLEND_FAILED, err))
+        }
+        Command::PruneEntities(v) => commands::prune_entities::prune_entities(&v)
+            .map_err(|err| (messages::PRUNE_ENTITIES_FAILED, err)),
+        Command::Convert(v) => {
+            commands::convert::convert_world(&v).map_err(|err| (messages::CONVERT_FAILED, err))
+        }
+        Command::Find(v) => commands::find::find(&v).map_err(|err| (messages::FIND_FAILED, err)),
+        Command::Check(v) => {
+            commands::check::check_regions(&v).map_err(|err| (messages::CHECK_FAILED, err))
+        }
+        Command::List(v) => {
+            commands::list::list_archive(&v).map_err(|err| (messages::LIST_FAILED, err))
+        }
+        Command::Browse(v) => {
+            commands::browse::browse(&v).map_err(|err| (messages::BROWSE_FAILED, err))
+        }
+        Command::Dump(v) => commands::dump::dump(&v).map_err(|err| (messages::DUMP_FAILED, err)),
+        Command::Query(v) => {
+            commands::query::query(&v).map_err(|err| (messages::QUERY_FAILED, err))
+        }
+        Command::Manifest(v) => {
+            commands::manifest::run(&v).map_err(|err| (messages::MANIFEST_FAILED, err))
+        }
+        Command::Apply(v) => {
+            commands::apply::apply(&v).map_err(|err| (messages::APPLY_FAILED, err))
+        }
+        Command::CompareArchives(v) => commands::compare_archives::compare_archives(&v)
+            .map_err(|err| (messages::COMPARE_FAILED, err)),
+        Command::Repo(v) => commands::repo::run(&v).map_err(|err| (messages::REPO_FAILED, err)),
+        Command::Messages(v) => commands::messages::list_messages(&v)
+            .map_err(|err| (messages::MESSAGES_LIST_FAILED, err)),
     }
 }
 
@@ -18,6 +144,39 @@ fn main() {
 struct Opts {
     #[clap(subcommand)]
     command: Command,
+
+    #[clap(
+        long,
+        global = true,
+        env = "ANVIL_TOOLS_READ_ONLY",
+        value_parser = BoolishValueParser::new(),
+        help = "Fail any command which would write to a file"
+    )]
+    read_only: bool,
+
+    #[clap(
+        long,
+        global = true,
+        env = "ANVIL_TOOLS_NO_MMAP",
+        value_parser = BoolishValueParser::new(),
+        help = "Read region files with buffered I/O instead of mapping them into memory, which some network filesystems don't support"
+    )]
+    no_mmap: bool,
+
+    #[clap(flatten)]
+    cpu: cpu::CpuOptions,
+
+    #[clap(flatten)]
+    log: logging::LogOptions,
+
+    #[clap(flatten)]
+    temp: temp::TempOptions,
+
+    #[clap(flatten)]
+    messages: messages::MessageOptions,
+
+    #[clap(flatten)]
+    encryption: encryption::EncryptionOptions,
 }
 
 #[derive(Parser)]
@@ -25,4 +184,40 @@ enum Command {
     Strip(commands::strip::Options),
     Pack(commands::archive::PackOptions),
     Unpack(commands::archive::UnpackOptions),
+    Verify(commands::verify::Options),
+    Index(commands::index::Options),
+    Normalize(commands::normalize::Options),
+    ExportLayers(commands::export_layers::Options),
+    ExportHeightmap(commands::export_heightmap::Options),
+    ExportBiomes(commands::export_biomes::Options),
+    ExportSchematic(commands::export_schematic::Options),
+    ImportSchematic(commands::import_schematic::Options),
+    Roundtrip(commands::roundtrip::Options),
+    CleanupStale(commands::cleanup_stale::Options),
+    Messages(commands::messages::Options),
+    NbtEdit(commands::nbt_edit::Options),
+    Archive(commands::archive_info::Options),
+    Find(commands::find::Options),
+    Count(commands::count::Options),
+    Render(commands::render::Options),
+    MapOccupancy(commands::map_occupancy::Options),
+    Inhabited(commands::inhabited::Options),
+    LastModified(commands::last_modified::Options),
+    ReplaceBlocks(commands::replace_blocks::Options),
+    Shift(commands::shift::Options),
+    Copy(commands::copy::Options),
+    DeleteChunks(commands::delete_chunks::Options),
+    Clear(commands::clear::Options),
+    Blend(commands::blend::Options),
+    PruneEntities(commands::prune_entities::Options),
+    Convert(commands::convert::Options),
+    List(commands::list::Options),
+    Check(commands::check::Options),
+    Browse(commands::browse::Options),
+    Dump(commands::dump::Options),
+    Query(commands::query::Options),
+    Manifest(commands::manifest::Options),
+    Apply(commands::apply::Options),
+    CompareArchives(commands::compare_archives::Options),
+    Repo(commands::repo::Options),
 }
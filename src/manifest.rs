@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use fastnbt::Value;
+use xxhash_rust::xxh64::{xxh64, Xxh64};
+
+use crate::commands::archive::{chunk_entry_path, parse_chunk_entry_path};
+use crate::messages::{self, Message, MessageId};
+use crate::read_only;
+use crate::region::ChunkPos;
+
+/// A record of the hash of every chunk seen by a pack run, keyed by region and chunk position. The manifest is stored
+/// as plain text with one `<entry path> <hash>` pair per line, so it can be inspected or diffed with ordinary tools.
+#[derive(Default)]
+pub struct Manifest {
+    entries: HashMap<(ChunkPos, ChunkPos), u64>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Manifest::default()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        Manifest::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut entries = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (entry_path, hash) = line.split_once(' ').ok_or_else(|| {
+                invalid_data(Message::new(messages::MANIFEST_MALFORMED_LINE).arg("line", line))
+            })?;
+
+            let position = parse_chunk_entry_path(Path::new(entry_path)).ok_or_else(|| {
+                invalid_data(
+                    Message::new(messages::MANIFEST_MALFORMED_ENTRY).arg("entry", entry_path),
+                )
+            })?;
+
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| {
+                invalid_data(Message::new(messages::MANIFEST_MALFORMED_HASH).arg("hash", hash))
+            })?;
+
+            entries.insert(position, hash);
+        }
+
+        Ok(Manifest { entries })
+    }
+
+    /// Loads the manifest at `path`, or returns an empty manifest if no such file exists yet.
+    pub fn load_or_default(path: &Path) -> Result<Self, io::Error> {
+        if Path::exists(path) {
+            Manifest::load(path)
+        } else {
+            Ok(Manifest::new())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        read_only::ensure_writable(path)?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+
+        writer.flush()
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(&(region, chunk), hash)| {
+                format!("{} {:016x}", chunk_entry_path(region, chunk), hash)
+            })
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, region: ChunkPos, chunk: ChunkPos) -> Option<u64> {
+        self.entries.get(&(region, chunk)).copied()
+    }
+
+    pub fn insert(&mut self, region: ChunkPos, chunk: ChunkPos, hash: u64) {
+        self.entries.insert((region, chunk), hash);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkPos, ChunkPos, u64)> + '_ {
+        self.entries
+            .iter()
+            .map(|(&(region, chunk), &hash)| (region, chunk, hash))
+    }
+
+    /// Compares the hashes recorded in this manifest against those of the chunks which were actually read, returning
+    /// the entry path of every chunk which is missing or whose hash does not match, along with the reason.
+    pub fn find_mismatches(&self, actual: &Manifest) -> Vec<(String, MessageId)> {
+        let mut mismatches: Vec<(String, MessageId)> = self
+            .iter()
+            .filter_map(|(region, chunk, hash)| {
+                let reason = match actual.get(region, chunk) {
+                    Some(actual_hash) if actual_hash == hash => return None,
+                    Some(_) => messages::CHECKSUM_MISMATCH,
+                    None => messages::CHECKSUM_MISSING_CHUNK,
+                };
+
+                Some((chunk_entry_path(region, chunk), reason))
+            })
+            .collect();
+
+        mismatches.extend(
+            actual
+                .iter()
+                .filter(|&(region, chunk, _)| self.get(region, chunk).is_none())
+                .map(|(region, chunk, _)| {
+                    (
+                        chunk_entry_path(region, chunk),
+                        messages::CHECKSUM_UNLISTED_CHUNK,
+                    )
+                }),
+        );
+
+        mismatches.sort();
+        mismatches
+    }
+}
+
+pub fn hash_chunk_data(data: &[u8]) -> u64 {
+    xxh64(data, 0)
+}
+
+/// Hashes the NBT of a chunk in a canonical form, in which the tags of every compound are sorted by name. Unlike
+/// `hash_chunk_data`, the hash only changes when the tags themselves do, and not when the game or another tool writes
+/// them in a different order or compresses them differently.
+pub fn canonical_hash(nbt: &Value) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    hash_value(&mut hasher, nbt);
+
+    hasher.digest()
+}
+
+fn hash_value(hasher: &mut Xxh64, value: &Value) {
+    fn hash_length(hasher: &mut Xxh64, length: usize) {
+        hasher.update(&(length as u32).to_be_bytes());
+    }
+
+    match value {
+        Value::Byte(value) => hasher.update(&[1, *value as u8]),
+        Value::Short(value) => {
+            hasher.update(&[2]);
+            hasher.update(&value.to_be_bytes());
+        }
+        Value::Int(value) => {
+            hasher.update(&[3]);
+            hasher.update(&value.to_be_bytes());
+        }
+        Value::Long(value) => {
+            hasher.update(&[4]);
+            hasher.update(&value.to_be_bytes());
+        }
+        Value::Float(value) => {
+            hasher.update(&[5]);
+            hasher.update(&value.to_bits().to_be_bytes());
+        }
+        Value::Double(value) => {
+            hasher.update(&[6]);
+            hasher.update(&value.to_bits().to_be_bytes());
+        }
+        Value::ByteArray(array) => {
+            hasher.update(&[7]);
+            hash_length(hasher, array.len());
+
+            for value in array.iter() {
+                hasher.update(&[*value as u8]);
+            }
+        }
+        Value::String(value) => {
+            hasher.update(&[8]);
+            hash_length(hasher, value.len());
+            hasher.update(value.as_bytes());
+        }
+        Value::List(list) => {
+            hasher.update(&[9]);
+            hash_length(hasher, list.len());
+
+            for element in list {
+                hash_value(hasher, element);
+            }
+        }
+        Value::Compound(compound) => {
+            hasher.update(&[10]);
+            hash_length(hasher, compound.len());
+
+            let mut names: Vec<&String> = compound.keys().collect();
+            names.sort();
+
+            for name in names {
+                hash_length(hasher, name.len());
+                hasher.update(name.as_bytes());
+                hash_value(hasher, &compound[name]);
+            }
+        }
+        Value::IntArray(array) => {
+            hasher.update(&[11]);
+            hash_length(hasher, array.len());
+
+            for value in array.iter() {
+                hasher.update(&value.to_be_bytes());
+            }
+        }
+        Value::LongArray(array) => {
+            hasher.update(&[12]);
+            hash_length(hasher, array.len());
+
+            for value in array.iter() {
+                hasher.update(&value.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Hashes chunk data as it is written, giving the same hash as `hash_chunk_data` without holding all of the data in
+/// memory.
+pub struct ChunkHasher {
+    hasher: Xxh64,
+    length: u64,
+}
+
+impl Default for ChunkHasher {
+    fn default() -> Self {
+        ChunkHasher {
+            hasher: Xxh64::new(0),
+            length: 0,
+        }
+    }
+}
+
+impl ChunkHasher {
+    /// Returns the number of bytes written and their hash.
+    pub fn finish(&self) -> (u64, u64) {
+        (self.length, self.hasher.digest())
+    }
+}
+
+impl Write for ChunkHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.length += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid_data(message: Message) -> io::Error {
+    message.into_error(io::ErrorKind::InvalidData)
+}
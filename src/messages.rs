@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "native")]
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Args;
+#[cfg(feature = "native")]
+use toml::Value;
+use tracing::error;
+
+use crate::exit_code::ExitCode;
+
+#[derive(Args)]
+pub struct MessageOptions {
+    #[clap(
+        long,
+        global = true,
+        env = "ANVIL_TOOLS_MESSAGES",
+        help = "TOML file of translated message templates, keyed by message ID (see the messages command)"
+    )]
+    messages: Option<PathBuf>,
+}
+
+/// A user-facing message in the catalog. The ID is stable across releases, so that programs wrapping the tool can
+/// recognize messages (and translate them) without parsing their text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageId {
+    pub id: &'static str,
+    /// The English template of the message, where `{name}` is replaced by the argument of that name.
+    pub template: &'static str,
+}
+
+macro_rules! catalog {
+    ($($name:ident = $id:literal => $template:literal,)*) => {
+        $(pub const $name: MessageId = MessageId { id: $id, template: $template };)*
+
+        /// Every message which the tool can show, in the order they are listed by the `messages` command.
+        pub const CATALOG: &[MessageId] = &[$($name),*];
+    };
+}
+
+catalog! {
+    // Failures of whole commands, which are followed by the error which caused them
+    CONFIG_FAILED = "config.failed" => "Failed to load config file",
+    CONFIG_UNKNOWN_OPTION = "config.unknown_option" => "Unknown option in config file: {option}",
+    CONFIG_INVALID = "config.invalid" => "{path}: {error}",
+    RULES_INVALID = "rules.invalid" => "Invalid rules file {path}: {error}",
+    MESSAGES_FAILED = "messages.failed" => "Failed to load message catalog",
+    MESSAGES_UNKNOWN_ID = "messages.unknown_id" => "Unknown message ID in message catalog: {id}",
+    MESSAGES_INVALID = "messages.invalid" => "{path}: {error}",
+    CPU_FAILED = "cpu.failed" => "Failed to apply CPU options",
+    CPU_SET_UNSUPPORTED = "cpu.set_unsupported" => "CPU sets are only supported on Linux",
+    CPU_LOW_PRIORITY_UNSUPPORTED = "cpu.low_priority_unsupported" => "Low priority mode is only supported on Unix systems",
+    PACK_FAILED = "pack.failed" => "Failed to pack files",
+    UNPACK_FAILED = "unpack.failed" => "Failed to unpack files",
+    STRIP_FAILED = "strip.failed" => "Failed to strip files",
+    VERIFY_FAILED = "verify.failed" => "Failed to verify archive",
+    INDEX_FAILED = "index.failed" => "Failed to run index command",
+    EXPORT_LAYERS_FAILED = "export_layers.failed" => "Failed to export layers",
+    EXPORT_HEIGHTMAP_FAILED = "export_heightmap.failed" => "Failed to export heightmaps",
+    EXPORT_BIOMES_FAILED = "export_biomes.failed" => "Failed to export biomes",
+    EXPORT_SCHEMATIC_FAILED = "export_schematic.failed" => "Failed to export schematic",
+    IMPORT_SCHEMATIC_FAILED = "import_schematic.failed" => "Failed to import schematic",
+    ARCHIVE_FAILED = "archive.failed" => "Failed to read archive",
+    COUNT_FAILED = "count.failed" => "Failed to count blocks",
+    FIND_FAILED = "find.failed" => "Failed to search world",
+    RENDER_FAILED = "render.failed" => "Failed to render world",
+    MAP_OCCUPANCY_FAILED = "map_occupancy.failed" => "Failed to map chunk occupancy",
+    INHABITED_FAILED = "inhabited.failed" => "Failed to report inhabited time",
+    LAST_MODIFIED_FAILED = "last_modified.failed" => "Failed to report modification times",
+    REPLACE_BLOCKS_FAILED = "replace_blocks.failed" => "Failed to replace blocks",
+    SHIFT_FAILED = "shift.failed" => "Failed to shift world",
+    COPY_FAILED = "copy.failed" => "Failed to copy chunks",
+    DELETE_CHUNKS_FAILED = "delete_chunks.failed" => "Failed to delete chunks",
+    CLEAR_FAILED = "clear.failed" => "Failed to clear area",
+    BLEND_FAILED = "blend.failed" => "Failed to change blending data",
+    PRUNE_ENTITIES_FAILED = "prune_entities.failed" => "Failed to prune entities",
+    CONVERT_FAILED = "convert.failed" => "Failed to convert world",
+    CHECK_FAILED = "check.failed" => "Failed to check region files",
+    LIST_FAILED = "list.failed" => "Failed to list archive",
+    BROWSE_FAILED = "browse.failed" => "Failed to browse region files",
+    DUMP_FAILED = "dump.failed" => "Failed to dump region file",
+    QUERY_FAILED = "query.failed" => "Failed to query world",
+    MANIFEST_FAILED = "manifest.failed" => "Failed to run manifest command",
+    APPLY_FAILED = "apply.failed" => "Failed to apply archives",
+    REPO_FAILED = "repo.failed" => "Failed to run repo command",
+    COMPARE_FAILED = "compare.failed" => "Failed to compare archives",
+    NBT_EDIT_FAILED = "nbt_edit.failed" => "Failed to edit files",
+    ROUNDTRIP_FAILED = "roundtrip.failed" => "Failed to roundtrip files",
+    NORMALIZE_FAILED = "normalize.failed" => "Failed to normalize world",
+    CLEANUP_STALE_FAILED = "cleanup_stale.failed" => "Failed to clean up stale runs",
+    MESSAGES_LIST_FAILED = "messages.list_failed" => "Failed to list messages",
+    COMMAND_ERROR = "command.error" => "{message}: {error}",
+    IO_ERROR = "io.error" => "{error}",
+    INTERNAL_ERROR = "internal.error" => "Internal error: {error}",
+
+    // Problems with the input or options of a command
+    INPUT_DIR_MISSING = "input.dir_missing" => "Input directory does not exist: {path}",
+    INPUT_FILE_MISSING = "input.file_missing" => "Input file does not exist: {path}",
+    WORLD_DIR_MISSING = "input.world_missing" => "World directory does not exist: {path}",
+    INDEX_FILE_MISSING = "input.index_missing" => "Index file does not exist: {path}",
+    IN_PLACE_UNSUPPORTED = "input.in_place" => "In-place operations are not supported",
+    TTY_OUTPUT_REFUSED = "tty.output_refused" => "Refusing to pipe binary data to a terminal",
+    TTY_INPUT_REFUSED = "tty.input_refused" => "Refusing to pipe binary data from a terminal",
+    READ_ONLY_REFUSED = "read_only.refused" => "Refusing to write {path} in read-only mode",
+
+    // Temporary files
+    TEMP_REMOVE_FAILED = "temp.remove_failed" => "Failed to remove temporary directory {path}: {error}",
+    CLEANUP_NONE = "cleanup_stale.none" => "No stale temporary directories found in {path}",
+    CLEANUP_WOULD_REMOVE = "cleanup_stale.would_remove" => "Would remove {path}",
+    CLEANUP_REMOVED = "cleanup_stale.removed" => "Removed {path}",
+
+    // Shared reports
+    STATUS_SKIPPED = "filter.status_skipped" => "Skipped {skipped} chunks which did not match the generation status filter",
+    RETRY_RETRIED = "retry.retried" => "Retried {retried} I/O operations after transient errors",
+    POSITION_MISMATCH = "position.mismatch" => "Chunk ({actual_x}, {actual_z}) is stored as ({expected_x}, {expected_z})",
+    POSITION_REFUSED = "position.refused" => "Chunk ({actual_x}, {actual_z}) is stored in region {region} as ({expected_x}, {expected_z}), so the region file may have been renamed",
+    POSITION_FIXED = "position.fixed" => "Fixed the position of {chunks} chunks in {region}, which may have been renamed",
+    POSITION_MISMATCHED = "position.mismatched" => "{chunks} chunks in {region} have positions outside the region, so it may have been renamed (see --on-position-mismatch)",
+    KEEP_GOING_SKIPPED = "keep_going.skipped" => "Skipped chunk ({x}, {z}) of {region}, which couldn't be read: {error}",
+    KEEP_GOING_REGION = "keep_going.region" => "Skipped {chunks} unreadable chunks of {region}: {positions}",
+    KEEP_GOING_FAILED = "keep_going.failed" => "{chunks} chunks couldn't be read and were skipped (see the warnings above)",
+
+    // Files read and written by commands
+    REGION_EXTERNAL_UNKNOWN = "region.external_unknown" => "Chunk ({x}, {z}) is stored in an external file, but the position of {path} is unknown",
+    REGION_EXTERNAL_UNAVAILABLE = "region.external_unavailable" => "The chunk is stored in {path}, which can't be read without the file system",
+    REGION_FINISH_FAILED = "region.finish_failed" => "Couldn't finish writing {path}: {error}",
+    REGION_TOO_LARGE = "region.too_large" => "Chunk ({x}, {z}) needs {sectors} sectors, which is more than {path} can store, and it cannot be moved to an external file because the region's position is unknown",
+    REGION_UNKNOWN_COMPRESSION = "region.unknown_compression" => "Chunk ({x}, {z}) has an unknown compression type {compression}",
+    REGION_TRUNCATED_HEADER = "region.truncated_header" => "{path} is too short to contain a region header",
+    REGION_TRUNCATED_CHUNK = "region.truncated_chunk" => "Chunk ({x}, {z}) extends past the end of {path}, which may be truncated",
+    REGION_MMAP_FAILED = "region.mmap_failed" => "Couldn't map {path} into memory, so it will be read with buffered I/O: {error}",
+    LINEAR_INVALID = "linear.invalid" => "{path} is not a valid Linear region file, as {reason}",
+    LINEAR_UNSUPPORTED_VERSION = "linear.unsupported_version" => "{path} is a Linear region file of version {version}, which isn't supported",
+    REGION_NOT_REGION = "region.not_region" => "{path} is not a region file",
+    NBT_DESERIALIZE = "nbt.deserialize" => "Couldn't deserialize NBT: {error}",
+    NBT_SERIALIZE = "nbt.serialize" => "Couldn't serialize NBT: {error}",
+    NBT_SCAN = "nbt.scan" => "Couldn't scan NBT: {reason}",
+    SNBT_INVALID = "snbt.invalid" => "Couldn't parse SNBT at line {line}, column {column}: {reason}",
+    ENCRYPTION_MIXED_TARGETS = "encryption.mixed_targets" => "An archive can be encrypted to recipients or to a passphrase, but not both",
+    ENCRYPTION_ENCRYPT_FAILED = "encryption.encrypt_failed" => "Couldn't encrypt archive: {error}",
+    ENCRYPTION_FINISHED = "encryption.finished" => "The encrypted archive was already finished",
+    ENCRYPTION_DECRYPT_FAILED = "encryption.decrypt_failed" => "Couldn't decrypt archive: {error}",
+    ENCRYPTION_IDENTITY_REQUIRED = "encryption.identity_required" => "The archive is encrypted to age recipients, so it needs --identity to decrypt",
+    ENCRYPTION_INVALID_IDENTITY = "encryption.invalid_identity" => "Invalid identity file {path}: {error}",
+    ENCRYPTION_PASSPHRASE_REQUIRED = "encryption.passphrase_required" => "A passphrase is needed, which is read from the file given by --passphrase-file",
+    ENCRYPTION_PASSPHRASE_EMPTY = "encryption.passphrase_empty" => "The passphrase file is empty",
+    JOURNAL_INVALID = "journal.invalid" => "{path} is not a {kind} journal",
+    MANIFEST_MALFORMED_LINE = "manifest.malformed_line" => "Malformed manifest line: {line}",
+    MANIFEST_MALFORMED_ENTRY = "manifest.malformed_entry" => "Malformed manifest entry: {entry}",
+    MANIFEST_MALFORMED_HASH = "manifest.malformed_hash" => "Malformed manifest hash: {hash}",
+    REPO_NOT_FOUND = "repo.not_found" => "{path} is not a repository (see repo init)",
+    REPO_UNSUPPORTED_VERSION = "repo.unsupported_version" => "{path} is a repository of version {version}, which isn't supported",
+    REPO_MALFORMED_LINE = "repo.malformed_line" => "Malformed snapshot line: {line}",
+    REPO_SNAPSHOT_INVALID = "repo.snapshot_invalid" => "Snapshot {snapshot} can't be read: {error}",
+    DEDUP_MALFORMED_LINE = "dedup.malformed_line" => "Malformed reference line: {line}",
+    DEDUP_MALFORMED_ENTRY = "dedup.malformed_entry" => "Malformed reference entry: {entry}",
+    LABELS_MALFORMED_RULE = "labels.malformed_rule" => "Malformed label rule: {line}",
+    LABELS_MALFORMED_LINE = "labels.malformed_line" => "Malformed labels line: {line}",
+    LABELS_MALFORMED_REGIONS = "labels.malformed_regions" => "Malformed regions in label rule: {error}",
+    LABELS_WHITESPACE = "labels.whitespace" => "Label contains whitespace: {label}",
+    PROFILE_MALFORMED_LINE = "profile.malformed_line" => "Malformed profile line: {line}",
+    PROFILE_UNKNOWN_OPTION = "profile.unknown_option" => "Unknown option in profile: {option}",
+    PROFILE_INVALID_VALUE = "profile.invalid_value" => "Invalid value in profile: {error}",
+    PROFILE_MISSING = "profile.missing" => "Archive has no profile, or it does not precede its chunks",
+    PROFILE_CONFLICT = "profile.conflict" => "Archives were packed with different profiles, so there is no single profile to reapply",
+    METADATA_MALFORMED_LINE = "metadata.malformed_line" => "Malformed metadata line: {line}",
+    METADATA_INVALID_VALUE = "metadata.invalid_value" => "Invalid {key} in metadata: {value}",
+    METADATA_MISSING_FORMAT_VERSION = "metadata.missing_format_version" => "Archive metadata has no format version",
+    METADATA_INCOMPATIBLE = "metadata.incompatible" => "Archive was packed by anvil-tools {tool_version} in format version {version}, but only versions up to {supported} can be restored (use --force to restore it anyway)",
+    METADATA_LEVEL_UNREADABLE = "metadata.level_unreadable" => "Couldn't read the world's details from {path}: {error}",
+    LABELS_MISSING = "labels.missing" => "Archive has no labels to filter by, or they do not precede its chunks",
+    BLOCK_INDEX_INVALID = "block_index.invalid" => "{path} is not a block index",
+    BLOCK_INDEX_VERSION = "block_index.version" => "Block index version {version} is not supported",
+    BLOCK_INDEX_REGION_NAME = "block_index.region_name" => "Block index has an invalid region name: {name}",
+    BLOCK_INDEX_UNKNOWN_STATE = "block_index.unknown_state" => "Block index refers to an unknown block state",
+    BLOCK_INDEX_INVALID_STRING = "block_index.invalid_string" => "Block index contains an invalid string",
+    BLOCK_COUNT_FAILED = "block_index.count_failed" => "Couldn't count blocks of chunk ({x}, {z}) in {path}: {reason}",
+
+    // pack
+    PACK_ZIP_REQUIRES_FILE = "pack.zip_requires_file" => "Zip archives must be written to a file, since they cannot be streamed",
+    PACK_ZIP_COMPRESSED = "pack.zip_compressed" => "Zip archives compress each entry already, and cannot also be compressed as a whole",
+    PACK_JOURNAL_UNCOMPRESSED_ONLY = "pack.journal_uncompressed_only" => "Only uncompressed archives can be resumed with a journal",
+    PACK_JOURNAL_TAR_ONLY = "pack.journal_tar_only" => "Journals are only supported when packing tar archives",
+    PACK_ZIP_ENCRYPTED = "pack.zip_encrypted" => "Zip archives cannot be encrypted, since they must be written to a file which can be seeked",
+    PACK_JOURNAL_UNENCRYPTED_ONLY = "pack.journal_unencrypted_only" => "Only unencrypted archives can be resumed with a journal",
+    PACK_ZIP_NOT_RESUMABLE = "pack.zip_not_resumable" => "Zip archives cannot be resumed",
+    PACK_UNCHANGED = "pack.unchanged" => "Skipped {unchanged_chunks} chunks which were unchanged since the previous manifest",
+    PACK_UNMODIFIED = "pack.unmodified" => "Skipped {unmodified_chunks} chunks which were not modified since {since}",
+    PACK_SINCE_TIMESTAMP_MANIFEST = "pack.since_timestamp_manifest" => "A manifest cannot be recorded when packing chunks modified since a timestamp, since unmodified chunks are not read",
+    PACK_SINCE_MANIFEST_RAW = "pack.since_manifest_raw" => "Raw chunks cannot be compared against a manifest, since they are not decompressed",
+    PACK_DEDUPLICATED = "pack.deduplicated" => "Deduplicated {deduplicated_chunks} chunks which were identical to a chunk already in the archive",
+    PACK_SUMMARY = "pack.summary" => "Packed {chunks} chunks from {regions} regions in {elapsed}: {input_size} of region files archived into {output_size}",
+    PACK_REGION = "pack.region" => "Packed {region}",
+
+    // unpack
+    UNPACK_METADATA = "unpack.metadata" => "Unpacking archive packed by anvil-tools {tool_version} in format version {version}",
+    UNPACK_METADATA_WORLD = "unpack.metadata_world" => "Unpacking archive of {world} packed by anvil-tools {tool_version} in format version {version}",
+    UNPACK_UNVERSIONED = "unpack.unversioned" => "Archive has no metadata, so it is read as format version {version}",
+    UNPACK_FORCED = "unpack.forced" => "Restoring an archive in format version {version}, which is newer than this build supports",
+    UNPACK_COLLISION = "unpack.collision" => "Chunk {entry} appears more than once in the input archives",
+    UNPACK_MISSING_ORIGINAL = "unpack.missing_original" => "Chunk {entry} is a duplicate of {original}, which was not unpacked",
+    UNPACK_ORIGINAL_NOT_FOUND = "unpack.original_not_found" => "Chunk {entry} is missing from its region file",
+    UNPACK_MALFORMED_ENTRY = "unpack.malformed_entry" => "Malformed chunk location in archive entry path: {entry}",
+    UNPACK_SHARD_STOPPED = "unpack.shard_stopped" => "Unpack shard stopped unexpectedly",
+    UNPACK_CORRUPT_CHUNK = "unpack.corrupt_chunk" => "Corrupt chunk {entry}: {reason}",
+    UNPACK_CHECKSUMS_FAILED = "unpack.checksums_failed" => "{chunks} chunks failed checksum validation, including {entry}: {reason}",
+    CHECKSUM_MISMATCH = "checksum.mismatch" => "Checksum mismatch",
+    ARCHIVE_ENTRY_CORRUPT = "archive.entry_corrupt" => "Entry {entry} doesn't match the checksum recorded in its header",
+    CHECKSUM_MISSING_CHUNK = "checksum.missing_chunk" => "Chunk is listed in the checksums but is missing from the archive",
+    CHECKSUM_UNLISTED_CHUNK = "checksum.unlisted_chunk" => "Chunk is not listed in the checksums",
+
+    // strip
+    STRIP_REGION_DROPPED = "strip.region_dropped" => "Dropped {dropped_chunks} proto-chunks from {region}",
+    STRIP_REGION = "strip.region" => "Stripped {region}",
+    STRIP_SUMMARY = "strip.summary" => "Stripped {chunks} chunks: {input_size} of NBT data reduced to {output_size} ({saved}% saved)",
+    STRIP_UNCHANGED = "strip.unchanged" => "{unchanged_chunks} chunks had nothing to strip and were kept unchanged",
+    STRIP_DROPPED = "strip.dropped" => "{dropped_chunks} proto-chunks were dropped entirely",
+    STRIP_RELIT = "strip.relit" => "Marked {relit_chunks} chunks as unlit so that they are relit when loaded",
+    STRIP_HEIGHTMAPS_RECALCULATED = "strip.heightmaps_recalculated" => "Recalculated the heightmaps of {recalculated_chunks} chunks from their blocks",
+    STRIP_HEIGHTMAPS_FAILED = "strip.heightmaps_failed" => "Couldn't recalculate heightmaps: {reason}",
+    STRIP_RULE = "strip.rule" => "Rule {rule} removed {tags} tags totalling {size} ({percentage}%)",
+    STRIP_IN_PLACE_FLAG = "strip.in_place_flag" => "The output directory is the input directory, which needs --in-place to replace each region file as it is stripped",
+
+    // archive show-profile
+    PROFILE_SHOW_EMPTY = "profile.show_empty" => "Every chunk of the packed regions was archived unchanged",
+    PROFILE_SHOW_REMOVED = "profile.show_removed" => "Removed from every chunk: {path}",
+    PROFILE_SHOW_MIN_STATUS = "profile.show_min_status" => "Left out chunks which had not reached the {status} status",
+    PROFILE_SHOW_STATUS = "profile.show_status" => "Only kept chunks with one of these statuses: {statuses}",
+    PROFILE_SHOW_INCLUDE_REGIONS = "profile.show_include_regions" => "Only included regions within {area}",
+    PROFILE_SHOW_EXCLUDE_REGIONS = "profile.show_exclude_regions" => "Excluded regions within {area}",
+    PROFILE_SHOW_CHUNK_BOUNDS = "profile.show_chunk_bounds" => "Only included chunks within {area}",
+
+    // count
+    COUNT_SUMMARY = "count.summary" => "Counted {blocks} blocks in {chunks} chunks from {regions} regions",
+
+    // find
+    FIND_BLOCK = "find.block" => "Block {id} at {x}, {y}, {z}",
+    FIND_ENTITY = "find.entity" => "Entity {id} at {x}, {y}, {z}",
+    FIND_BLOCK_ENTITY = "find.block_entity" => "Block entity {id} at {x}, {y}, {z}",
+    FIND_DECODE_FAILED = "find.decode_failed" => "Couldn't decode blocks of chunk ({x}, {z}) in {path}: {reason}",
+    FIND_SUMMARY = "find.summary" => "Found {matches} matches in {chunks} chunks from {regions} regions",
+
+    // nbt-edit
+    NBT_EDIT_REGION = "nbt_edit.region" => "Edited {region}",
+    NBT_EDIT_SUMMARY = "nbt_edit.summary" => "Edited {edited_chunks} of {chunks} chunks",
+    NBT_EDIT_REMOVED = "nbt_edit.removed" => "Removed {tags} tags matching {path}",
+    NBT_EDIT_RULE_REMOVED = "nbt_edit.rule_removed" => "Removed {tags} tags matching {path} from chunks of data version {versions}",
+    NBT_EDIT_RULE_SET = "nbt_edit.rule_set" => "Set {tags} tags matching {path} in chunks of data version {versions}",
+    NBT_EDIT_SET = "nbt_edit.set" => "Set {tags} tags matching {path}",
+
+    // replace-blocks
+    REPLACE_BLOCKS_INVALID_CHUNK = "replace_blocks.invalid_chunk" => "Chunk ({x}, {z}) of {region} could not be rewritten: {reason}",
+    REPLACE_BLOCKS_REGION = "replace_blocks.region" => "Rewrote {region}, changing {changed_chunks} chunks",
+    REPLACE_BLOCKS_SUMMARY = "replace_blocks.summary" => "Changed {changed_chunks} of {chunks} chunks",
+    REPLACE_BLOCKS_REPLACED = "replace_blocks.replaced" => "Replaced {blocks} blocks of {from} with {to}",
+
+    // render
+    RENDER_REGION = "render.region" => "Rendered {chunks} chunks of {region} in {elapsed_ms} ms",
+    RENDER_DECODE_FAILED = "render.decode_failed" => "Couldn't decode blocks of chunk ({x}, {z}) in {path}: {reason}",
+    RENDER_SUMMARY = "render.summary" => "Rendered {chunks} chunks from {regions} regions into {path}",
+
+    // inhabited
+    INHABITED_BUCKET = "inhabited.bucket" => "{range} {chunks} chunks {percent}%, {cumulative}% up to here {bar}",
+    INHABITED_MISSING = "inhabited.missing" => "{missing_chunks} chunks have no InhabitedTime, and were left out",
+    INHABITED_NO_CHUNKS = "inhabited.no_chunks" => "No chunks with an InhabitedTime were found in {path}",
+    INHABITED_HEATMAP = "inhabited.heatmap" => "Wrote a {width}x{height} heatmap to {path}, with its top left corner at chunk ({min_x}, {min_z})",
+    INHABITED_SUMMARY = "inhabited.summary" => "Read InhabitedTime of {chunks} chunks from {regions} regions: median {median} ticks, 90th percentile {p90} ticks, most {max} ticks",
+
+    // last-modified
+    LAST_MODIFIED_BUCKET = "last_modified.bucket" => "{age} {chunks} chunks {percent}%, {older}% at least this old {bar}",
+    LAST_MODIFIED_UNKNOWN = "last_modified.unknown" => "{unknown_chunks} chunks have no timestamp in their region header, and were left out",
+    LAST_MODIFIED_NO_CHUNKS = "last_modified.no_chunks" => "No chunks with a timestamp were found in {path}",
+    LAST_MODIFIED_SUMMARY = "last_modified.summary" => "Read timestamps of {chunks} chunks from {regions} regions, the oldest of which was last modified at least {oldest} ago",
+
+    // map-occupancy
+    MAP_OCCUPANCY_NO_REGIONS = "map_occupancy.no_regions" => "No region files found in {path}",
+    MAP_OCCUPANCY_SUMMARY = "map_occupancy.summary" => "Mapped {chunks} chunks from {regions} regions into a {width}x{height} image at {path}, with its top left corner at chunk ({min_x}, {min_z})",
+
+    // verify
+    VERIFY_BAD_ENTRY = "verify.bad_entry" => "{entry}: {reason}",
+    VERIFY_SUMMARY = "verify.summary" => "Verified {entries} entries: {problems} problems found",
+    VERIFY_NO_CHECKSUMS = "verify.no_checksums" => "Archive has no checksum index, so chunks could only be checked for valid NBT",
+    VERIFY_TRUNCATED = "verify.truncated" => "Archive is truncated or corrupt: {error}",
+    VERIFY_PROBLEMS = "verify.problems" => "Archive failed verification with {problems} problems",
+    VERIFY_NOT_FILE = "verify.not_file" => "Entry is not a regular file",
+    VERIFY_NOT_CHUNK = "verify.not_chunk" => "Path is not a chunk entry",
+    VERIFY_METADATA_NOT_FIRST = "verify.metadata_not_first" => "Metadata must be the first entry of the archive",
+    VERIFY_OUTSIDE_REGION = "verify.outside_region" => "Chunk coordinates are outside of the region",
+    VERIFY_DUPLICATE = "verify.duplicate" => "Duplicate entry for chunk",
+    VERIFY_MISSING_ORIGINAL = "verify.missing_original" => "Duplicate refers to {original}, which is missing from the archive",
+    VERIFY_WRONG_POSITION = "verify.wrong_position" => "Chunk data is for position ({actual_x}, {actual_z}), but the entry is for ({expected_x}, {expected_z})",
+    VERIFY_MISSING_POSITION = "verify.missing_position" => "Chunk data is missing xPos/zPos",
+
+    // index
+    INDEX_BUILT = "index.built" => "Indexed {regions} regions ({scanned_regions} scanned, {unchanged_regions} unchanged)",
+    INDEX_RESCANNING = "index.rescanning" => "{rescanned_regions} regions have changed since the index was built and will be scanned directly",
+    INDEX_QUERY_RESULT = "index.query_result" => "{block}: {blocks} blocks in {chunks} chunks",
+
+    // export-layers
+    EXPORT_LAYERS_PNG_REQUIRES_OUTPUT = "export_layers.png_requires_output" => "PNG layers must be written to a file with --output",
+    EXPORT_LAYERS_DECODE_FAILED = "export_layers.decode_failed" => "Couldn't decode blocks of chunk ({x}, {z}) in {path}: {reason}",
+    EXPORT_LAYERS_EMPTY = "export_layers.empty" => "No blocks were found in the Y range to export",
+    EXPORT_LAYERS_WRITTEN = "export_layers.written" => "Wrote {layers} layers from Y={min_y} to Y={max_y}, with columns from left to right: {columns}",
+
+    // export-heightmap
+    EXPORT_HEIGHTMAP_REGION = "export_heightmap.region" => "Exported {chunks} chunks of {region} in {elapsed_ms} ms",
+    EXPORT_HEIGHTMAP_DECODE_FAILED = "export_heightmap.decode_failed" => "Couldn't decode heightmap of chunk ({x}, {z}) in {path}: {reason}",
+    EXPORT_HEIGHTMAP_MISSING = "export_heightmap.missing" => "{missing_chunks} chunks have no {heightmap} heightmap, and were left at zero",
+    EXPORT_HEIGHTMAP_SUMMARY = "export_heightmap.summary" => "Exported heightmaps of {chunks} chunks from {regions} regions into {path}",
+
+    // export-biomes
+    EXPORT_BIOMES_REGION = "export_biomes.region" => "Exported {chunks} chunks of {region} in {elapsed_ms} ms",
+    EXPORT_BIOMES_DECODE_FAILED = "export_biomes.decode_failed" => "Couldn't decode biomes of chunk ({x}, {z}) in {path}: {reason}",
+    EXPORT_BIOMES_MISSING = "export_biomes.missing" => "{missing_chunks} chunks have no biomes at Y={y}, and were left empty",
+    EXPORT_BIOMES_SUMMARY = "export_biomes.summary" => "Exported biomes of {chunks} chunks from {regions} regions into {path}",
+
+    // export-schematic
+    EXPORT_SCHEMATIC_TOO_LARGE = "export_schematic.too_large" => "Selection of {width}x{height}x{length} blocks is too large for a Sponge schematic, which holds at most {max} blocks on each axis",
+    EXPORT_SCHEMATIC_DECODE_FAILED = "export_schematic.decode_failed" => "Couldn't decode blocks of chunk ({x}, {z}) in {path}: {reason}",
+    EXPORT_SCHEMATIC_NO_CHUNKS = "export_schematic.no_chunks" => "No chunks within the selection have been saved, so the schematic only holds air",
+    EXPORT_SCHEMATIC_SUMMARY = "export_schematic.summary" => "Exported {width}x{height}x{length} blocks from {chunks} chunks into {path}, with {palette} block states, {block_entities} block entities and {entities} entities",
+
+    // import-schematic
+    IMPORT_SCHEMATIC_INVALID = "import_schematic.invalid" => "Invalid schematic {path}: {reason}",
+    IMPORT_SCHEMATIC_INVALID_CHUNK = "import_schematic.invalid_chunk" => "Couldn't paste into chunk ({x}, {z}) of {region}: {reason}",
+    IMPORT_SCHEMATIC_REGION = "import_schematic.region" => "Pasted into {chunks} chunks of {region}",
+    IMPORT_SCHEMATIC_MISSING = "import_schematic.missing" => "{missing_chunks} chunks within the schematic's area haven't been generated, so its blocks there were left out",
+    IMPORT_SCHEMATIC_SUMMARY = "import_schematic.summary" => "Pasted {blocks} blocks and {block_entities} block entities into {chunks} chunks at {x}, {y}, {z}",
+
+    // shift
+    SHIFT_REGION = "shift.region" => "Wrote {chunks} shifted chunks into {region} in {elapsed_ms} ms",
+    SHIFT_SUMMARY = "shift.summary" => "Shifted {chunks} chunks by ({x}, {z}) chunks into {regions} regions",
+
+    // copy
+    COPY_REGION = "copy.region" => "Copied {chunks} chunks into {region} in {elapsed_ms} ms, skipping {skipped} existing chunks",
+    COPY_SUMMARY = "copy.summary" => "Copied {chunks} chunks into {regions} regions in {path}, skipping {skipped} existing chunks",
+
+    // delete-chunks
+    DELETE_CHUNKS_LIST_INVALID = "delete_chunks.list_invalid" => "Invalid chunk on line {line} of {path}: {reason}",
+    DELETE_CHUNKS_REGION = "delete_chunks.region" => "Deleted {chunks} chunks from {region}",
+    DELETE_CHUNKS_REGION_REMOVED = "delete_chunks.region_removed" => "Removed {region}, which has no chunks left",
+    DELETE_CHUNKS_SUMMARY = "delete_chunks.summary" => "Deleted {chunks} of {listed} listed chunks from {regions} regions, removing {removed} empty regions",
+    DELETE_CHUNKS_PROTECT_INVALID = "delete_chunks.protect_invalid" => "Invalid protect file {path}: {reason}",
+    DELETE_CHUNKS_PROTECTED = "delete_chunks.protected" => "Kept {chunks} listed chunks which are within {areas} protected areas",
+    DELETE_CHUNKS_PLAYER_PROTECTED = "delete_chunks.player_protected" => "Kept {chunks} listed chunks near {built} chunks which players appear to have built in",
+    DELETE_CHUNKS_DRY_RUN = "delete_chunks.dry_run" => "Would delete {chunks} of {listed} listed chunks from {regions} regions, removing {removed} empty regions",
+    DELETE_CHUNKS_MAP = "delete_chunks.map" => "{region}: {kept} chunks kept (#), {deleted} deleted (x)",
+
+    // clear
+    CLEAR_INVALID_CHUNK = "clear.invalid_chunk" => "Chunk ({x}, {z}) of {region} could not be cleared: {reason}",
+    CLEAR_REGION = "clear.region" => "Cleared {chunks} chunks of {region}",
+    CLEAR_SUMMARY = "clear.summary" => "Cleared {blocks} blocks from {chunks} chunks in {regions} regions, removing {block_entities} block entities, {entities} entities and {ticks} scheduled ticks",
+    CLEAR_DELETED = "clear.deleted" => "Deleted {chunks} chunks from {regions} regions, removing {removed} empty regions",
+
+    // blend
+    BLEND_REGION = "blend.region" => "Changed blending data of {changed_chunks} chunks in {region} in {elapsed_ms} ms",
+    BLEND_OLD_CHUNKS = "blend.old_chunks" => "Left {chunks} chunks saved before 1.18 alone, as Minecraft blends them when it upgrades them",
+    BLEND_ADDED_SUMMARY = "blend.added_summary" => "Added blending data to {changed_chunks} of {chunks} chunks",
+    BLEND_REMOVED_SUMMARY = "blend.removed_summary" => "Removed blending data from {changed_chunks} of {chunks} chunks",
+
+    // prune-entities
+    PRUNE_ENTITIES_REGION = "prune_entities.region" => "Pruned entities from {changed_chunks} chunks in {region} in {elapsed_ms} ms",
+    PRUNE_ENTITIES_SUMMARY = "prune_entities.summary" => "Removed {entities} entities from {changed_chunks} of {chunks} chunks",
+    PRUNE_ENTITIES_REMOVED = "prune_entities.removed" => "Removed {entities} entities of type {id}",
+
+    // convert
+    CONVERT_REGION = "convert.region" => "Converted {chunks} chunks into {region} in {elapsed_ms} ms",
+    CONVERT_SUMMARY = "convert.summary" => "Converted {chunks} chunks from {regions} regions into .{format} region files",
+
+    // list
+    LIST_REGION = "list.region" => "{region}: {chunks} chunks, {bytes} bytes",
+    LIST_ENTRY = "list.entry" => "{path} ({bytes} bytes)",
+    LIST_METADATA_TOOL = "list.metadata_tool" => "Packed by anvil-tools {tool_version} in format version {version}",
+    LIST_METADATA_VERSION = "list.metadata_version" => "Format version {version}",
+    LIST_METADATA_INCOMPATIBLE = "list.metadata_incompatible" => "Format version {version} is newer than this build can restore (up to {supported})",
+    LIST_METADATA_WORLD = "list.metadata_world" => "World: {world}",
+    LIST_METADATA_DATA_VERSION = "list.metadata_data_version" => "Data version: {data_version}",
+    LIST_METADATA_CHUNKS = "list.metadata_chunks" => "Chunks in the packed region files: {chunks}",
+    LIST_METADATA_CREATED = "list.metadata_created" => "Created: {created} (seconds since the Unix epoch)",
+    LIST_SUMMARY = "list.summary" => "Listed {chunks} chunks in {regions} regions, totalling {bytes} bytes, and {other_entries} other entries",
+
+    // browse
+    BROWSE_NO_TERMINAL = "browse.no_terminal" => "Browsing needs stdout to be an interactive terminal",
+    BROWSE_NO_REGIONS = "browse.no_regions" => "No region files found in {path}",
+    BROWSE_HELP = "browse.help" => "Arrow keys move, Enter opens, Esc goes back, Tab switches pane and q quits",
+    BROWSE_REGIONS_TITLE = "browse.regions_title" => "Regions",
+    BROWSE_GRID_TITLE = "browse.grid_title" => "{region}: {chunks} chunks",
+    BROWSE_TREE_TITLE = "browse.tree_title" => "Chunk ({x}, {z})",
+    BROWSE_CHUNK_INFO = "browse.chunk_info" => "Chunk ({x}, {z}): {bytes} bytes compressed with {compression}, saved at {timestamp}",
+    BROWSE_CHUNK_EMPTY = "browse.chunk_empty" => "Chunk ({x}, {z}) isn't stored in this region",
+    BROWSE_CHUNK_UNREADABLE = "browse.chunk_unreadable" => "Chunk ({x}, {z}) can't be read: {error}",
+    BROWSE_REGION_UNREADABLE = "browse.region_unreadable" => "{region} can't be read: {error}",
+    BROWSE_COMPOUND = "browse.compound" => "{entries} entries",
+    BROWSE_LIST = "browse.list" => "{items} items",
+
+    // manifest
+    MANIFEST_CREATED = "manifest.created" => "Recorded the hashes of {chunks} chunks in {regions} regions to {path}",
+    MANIFEST_CHANGED = "manifest.changed" => "{region}: chunk ({x}, {z}) has changed since the manifest was created",
+    MANIFEST_MISSING = "manifest.missing" => "{region}: chunk ({x}, {z}) is listed in the manifest but is missing from the world",
+    MANIFEST_UNLISTED = "manifest.unlisted" => "{region}: chunk ({x}, {z}) is not listed in the manifest",
+    MANIFEST_VERIFIED = "manifest.verified" => "Verified {chunks} chunks in {regions} regions: {changed} changed, {missing} missing and {unlisted} unlisted",
+    MANIFEST_MISMATCHED = "manifest.mismatched" => "{chunks} chunks don't match the manifest",
+    REPO_INITIALIZED = "repo.initialized" => "Created an empty repository in {path}",
+    REPO_EXISTS = "repo.exists" => "{path} already holds a repository",
+    REPO_BACKUP_REGION = "repo.backup_region" => "Backed up {chunks} chunks of {region}, {new_blobs} of which weren't stored yet",
+    REPO_BACKED_UP = "repo.backed_up" => "Saved snapshot {snapshot} of {chunks} chunks in {regions} regions, storing {new_blobs} new chunks in {bytes} bytes",
+    REPO_NO_SNAPSHOTS = "repo.no_snapshots" => "The repository in {path} has no snapshots",
+    REPO_SNAPSHOT_MISSING = "repo.snapshot_missing" => "Snapshot {snapshot} does not exist",
+    REPO_BLOB_MISSING = "repo.blob_missing" => "Blob {hash} of chunk {entry} is missing from the repository",
+    REPO_BLOB_CORRUPT = "repo.blob_corrupt" => "Blob {hash} of chunk {entry} doesn't match its hash, so the repository is corrupt",
+    REPO_RESTORED = "repo.restored" => "Restored {chunks} chunks in {regions} regions from snapshot {snapshot} into {path}",
+    REPO_SNAPSHOT_REMOVED = "repo.snapshot_removed" => "Removed snapshot {snapshot}",
+    REPO_PRUNED = "repo.pruned" => "Kept {snapshots} snapshots and removed {removed_snapshots}, along with {blobs} chunks no longer referred to, freeing {bytes} bytes",
+    APPLY_ARCHIVE = "apply.archive" => "Applied {archive} ({index} of {archives})",
+    APPLY_REMOVED = "apply.removed" => "Removed {chunks} chunks which are not listed in the manifest, since they were deleted after the base world was packed",
+    COMPARE_REGION_ADDED = "compare.region_added" => "{region}: added with {chunks} chunks",
+    COMPARE_REGION_REMOVED = "compare.region_removed" => "{region}: removed with {chunks} chunks",
+    COMPARE_CHUNK_ADDED = "compare.chunk_added" => "{entry}: added",
+    COMPARE_CHUNK_REMOVED = "compare.chunk_removed" => "{entry}: removed",
+    COMPARE_CHUNK_CHANGED = "compare.chunk_changed" => "{entry}: changed",
+    COMPARE_MISSING_ORIGINAL = "compare.missing_original" => "Chunk {entry} is a duplicate of {original}, which is missing from {archive}",
+    COMPARE_SUMMARY = "compare.summary" => "{regions_added} regions added and {regions_removed} removed; {chunks_added} chunks added, {chunks_removed} removed, {chunks_changed} changed and {chunks_unchanged} unchanged",
+    APPLY_VERIFIED = "apply.verified" => "Verified {chunks} chunks in {regions} regions against the manifest",
+
+    // query
+    QUERY_MATCH = "query.match" => "({x}, {z}): {value}",
+    QUERY_SUMMARY = "query.summary" => "Found {matches} matching tags in {matched_chunks} of {chunks} chunks in {regions} regions",
+
+    // dump
+    DUMP_REGION = "dump.region" => "{region}: {chunks} chunks in {sectors} sectors",
+    DUMP_ENTRY = "dump.entry" => "Chunk ({x}, {z}), entry {index}: {count} sectors from sector {first}, saved at {timestamp}",
+    DUMP_CHUNK_MISSING = "dump.chunk_missing" => "Chunk ({x}, {z}), entry {index}: no location recorded, saved at {timestamp}",
+    DUMP_CHUNK_HEADER = "dump.chunk_header" => "Declared length of {length} bytes, compression type {compression} ({compression_name})",
+    DUMP_CHUNK_PAYLOAD = "dump.chunk_payload" => "Payload of {bytes} bytes, including the compression type",
+    DUMP_CHUNK_UNREADABLE = "dump.chunk_unreadable" => "The chunk's sectors can't be read: {error}",
+
+    // check
+    CHECK_TRUNCATED_HEADER = "check.truncated_header" => "{region}: the file is too short to hold the header",
+    CHECK_PAST_END = "check.past_end" => "{region}: chunk ({x}, {z}) uses sectors {first}-{last}, past the end of the file at {sectors} sectors",
+    CHECK_IN_HEADER = "check.in_header" => "{region}: chunk ({x}, {z}) uses sectors {first}-{last}, which overlap the header",
+    CHECK_EMPTY = "check.empty" => "{region}: chunk ({x}, {z}) has a location but no sectors",
+    CHECK_OVERLAP = "check.overlap" => "{region}: chunks ({x}, {z}) and ({other_x}, {other_z}) both use sectors {shared_first}-{shared_last}",
+    CHECK_UNUSED = "check.unused" => "{region}: sectors {first}-{last} aren't used by any chunk",
+    CHECK_REGION = "check.region" => "{region}: {chunks} chunks in {sectors} sectors, {problems} problems, {unused_sectors} unused sectors",
+    CHECK_SUMMARY = "check.summary" => "Checked {regions} region files: {problems} problems found, {unused_sectors} sectors unused",
+    CHECK_PROBLEMS = "check.problems" => "Region files failed the check with {problems} problems",
+    CHECK_DISCARDED = "check.discarded" => "{region}: discarded chunk ({x}, {z}), as {reason}",
+    CHECK_REASON_IN_HEADER = "check.reason_in_header" => "its sectors overlap the header",
+    CHECK_REASON_EMPTY = "check.reason_empty" => "it has no sectors",
+    CHECK_REASON_UNREADABLE = "check.reason_unreadable" => "its data can't be read: {error}",
+    CHECK_REASON_OVERLAP_POSITION = "check.reason_overlap_position" => "it shares sectors with chunk ({other_x}, {other_z}), whose data holds its own position",
+    CHECK_REASON_OVERLAP_OLDER = "check.reason_overlap_older" => "it shares sectors with chunk ({other_x}, {other_z}), which was saved more recently",
+    CHECK_FIX_SUMMARY = "check.fix_summary" => "Rewrote {regions} region files, discarding {chunks} chunks",
+
+    // C API
+    CAPI_INVALID_PATH = "capi.invalid_path" => "Paths must be valid UTF-8",
+    CAPI_INVALID_POSITION = "capi.invalid_position" => "Chunk ({x}, {z}) is outside of the region, whose chunks are numbered 0 to 31 on each axis",
+    CAPI_PANIC = "capi.panic" => "The library hit an internal error, which was printed to stderr",
+
+    // dimensions
+    DIMENSION_STARTED = "dimension.started" => "Processing the {dimension} in {path}",
+    DIMENSION_MISSING = "dimension.missing" => "Skipped the {dimension}, as {path} doesn't exist",
+    DIMENSION_NONE_FOUND = "dimension.none_found" => "None of the selected dimensions were found in the world at {path}",
+    DIMENSION_STDOUT = "dimension.stdout" => "Several dimensions can't be packed to stdout, so an output file must be given",
+
+    // roundtrip
+    ROUNDTRIP_DIFFERENCE = "roundtrip.difference" => "{region} chunk ({x}, {z}): {reason}",
+    ROUNDTRIP_SUMMARY = "roundtrip.summary" => "Copied {chunks} chunks in {regions} regions: {differences} differences found",
+    ROUNDTRIP_MISSING = "roundtrip.missing" => "Chunk is missing from the copy",
+    ROUNDTRIP_UNREADABLE = "roundtrip.unreadable" => "Copy could not be decompressed",
+    ROUNDTRIP_INVALID_NBT = "roundtrip.invalid_nbt" => "Copy does not contain valid NBT",
+    ROUNDTRIP_MISMATCH = "roundtrip.mismatch" => "Copy does not match the original",
+    ROUNDTRIP_EXTRA = "roundtrip.extra" => "Copy contains a chunk which is not in the original",
+    ROUNDTRIP_CHANGED = "roundtrip.changed" => "Roundtrip changed {differences} chunks, so this tool may not be lossless on this world",
+
+    // normalize
+    NORMALIZE_SKIPPED = "normalize.skipped" => "Skipping {path}: {error}",
+    NORMALIZE_TARGET_EXISTS = "normalize.target_exists" => "Skipping {path}: it belongs at {target}, but that file already exists",
+    NORMALIZE_WOULD_MOVE = "normalize.would_move" => "Would move {from} to {to}",
+    NORMALIZE_MOVED = "normalize.moved" => "Moved {from} to {to}",
+    NORMALIZE_ALREADY_CORRECT = "normalize.already_correct" => "World layout is already correct",
+    NORMALIZE_WRONG_SLOT = "normalize.wrong_slot" => "Chunk ({x}, {z}) is stored in the slot for ({slot_x}, {slot_z})",
+}
+
+/// Translated templates loaded with `--messages`, which replace the English templates of the catalog.
+static TRANSLATIONS: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+/// Loads the translated templates given with `--messages`, if any.
+#[cfg(feature = "native")]
+pub fn init(options: &MessageOptions) -> Result<(), io::Error> {
+    let path = match &options.messages {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let invalid = |error: &dyn fmt::Display| {
+        Message::new(MESSAGES_INVALID)
+            .arg("path", path.display())
+            .arg("error", error)
+            .into_error(io::ErrorKind::InvalidData)
+    };
+
+    let contents = fs::read_to_string(path).map_err(|err| invalid(&err))?;
+    let table = match contents.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => unreachable!(),
+        Err(err) => return Err(invalid(&err)),
+    };
+
+    let mut translations = HashMap::new();
+
+    for (id, template) in table {
+        if !CATALOG.iter().any(|message| message.id == id) {
+            return Err(Message::new(MESSAGES_UNKNOWN_ID)
+                .arg("id", &id)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        match template {
+            Value::String(template) => translations.insert(id, template),
+            value => return Err(invalid(&format!("{} is not a string: {}", id, value))),
+        };
+    }
+
+    *TRANSLATIONS.lock().unwrap() = Some(translations);
+
+    Ok(())
+}
+
+/// A message from the catalog along with the values of its arguments.
+#[derive(Clone)]
+pub struct Message {
+    id: MessageId,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    pub fn new(id: MessageId) -> Self {
+        Message {
+            id,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.id.id
+    }
+
+    /// Renders the message in the language of the loaded catalog, or in English if it has no translation.
+    pub fn text(&self) -> String {
+        let translations = TRANSLATIONS.lock().unwrap();
+        let mut text = translations
+            .as_ref()
+            .and_then(|translations| translations.get(self.id.id))
+            .map_or(self.id.template, String::as_str)
+            .to_owned();
+
+        for (name, value) in &self.args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+
+        text
+    }
+
+    /// Wraps the message in an I/O error, so that it can be returned through code which only deals in I/O errors and
+    /// still be recognized when it is reported.
+    pub fn into_error(self, kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, self)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("id", &self.id.id)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl Error for Message {}
+
+/// Logs a message from the catalog at the given level. Each argument is recorded as a field of the log event, along
+/// with the ID of the message, and is also substituted into the message's text. Evaluates to the logged message.
+macro_rules! report {
+    ($level:ident, $id:expr $(, $name:ident = $value:expr)* $(,)?) => {{
+        match ($($value,)*) {
+            ($($name,)*) => {
+                let message = $crate::messages::Message::new($id)$(.arg(stringify!($name), &$name))*;
+                tracing::$level!(message_id = message.id(), $($name,)* "{}", message);
+                message
+            }
+        }
+    }};
+}
+
+// Only the commands report progress, which the core built without `native` leaves out
+#[cfg_attr(not(feature = "native"), allow(unused_imports))]
+pub(crate) use report;
+
+/// Returns the message carried by an error, or wraps the error's text in a generic message.
+pub fn error_message(err: &io::Error) -> Message {
+    match err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<Message>())
+    {
+        Some(message) => message.clone(),
+        None => Message::new(IO_ERROR).arg("error", err),
+    }
+}
+
+/// Reports the failure of a command along with the error which caused it, and exits the process with the code for the
+/// kind of failure.
+pub fn fail(failure: MessageId, err: &io::Error) -> ! {
+    let cause = error_message(err);
+    let message = Message::new(COMMAND_ERROR)
+        .arg("message", Message::new(failure))
+        .arg("error", &cause);
+
+    // Errors from loading the config file happen before logging is set up
+    if tracing::dispatcher::has_been_set() {
+        error!(
+            message_id = failure.id,
+            error_id = cause.id(),
+            error = %cause,
+            "{}",
+            message
+        );
+    } else {
+        eprintln!("{}", message);
+    }
+
+    std::process::exit(ExitCode::of(failure, err) as i32)
+}
+
+/// Reports panics through the logger, so that they are still structured when logging as JSON.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|payload| String::from(*payload))
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        let error = match info.location() {
+            Some(location) => format!("{} at {}", payload, location),
+            None => payload,
+        };
+
+        report!(error, INTERNAL_ERROR, error = error);
+    }));
+}
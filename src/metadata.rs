@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+
+use crate::archive_version::ArchiveLayout;
+use crate::messages::{self, report, Message};
+use crate::nbt::{find_int_tag, find_string_tag};
+
+/// Describes an archive and the world it was packed from. It is the first entry of every archive, and is stored as
+/// plain text with one `<key> <value>` line per field: `tool-version` and `format-version` give the version of
+/// anvil-tools which packed the archive and of the archive layout, `world-name` and `data-version` are read from the
+/// world's `level.dat`, `chunks` counts the chunks of the packed region files before any were filtered out, and
+/// `created` gives the time the archive was packed in seconds since the Unix epoch, unless it was packed to be
+/// reproducible. Keys which aren't known are ignored, so newer versions can add fields without breaking older readers.
+#[derive(Clone, Default)]
+pub struct ArchiveMetadata {
+    pub tool_version: Option<String>,
+    pub format_version: u32,
+    pub world_name: Option<String>,
+    pub data_version: Option<i32>,
+    pub chunks: Option<u64>,
+    pub created: Option<u64>,
+}
+
+impl ArchiveMetadata {
+    /// Describes an archive being packed from a directory of region files, which was created at the given time unless
+    /// it has to be reproducible. The world's `level.dat` is looked for in the parent of the directory, or in its
+    /// grandparent for the region files of the Nether and the End.
+    pub fn new(input_dir: &Path, chunks: u64, created: Option<u64>) -> Self {
+        let mut metadata = ArchiveMetadata {
+            tool_version: Some(String::from(env!("CARGO_PKG_VERSION"))),
+            format_version: ArchiveLayout::CURRENT.version(),
+            world_name: None,
+            data_version: None,
+            chunks: Some(chunks),
+            created,
+        };
+
+        if let Some(level_path) = find_level_dat(input_dir) {
+            // The archive is still useful without the world's details, so a damaged level.dat only warns
+            match read_level_dat(&level_path) {
+                Ok((world_name, data_version)) => {
+                    metadata.world_name = world_name;
+                    metadata.data_version = data_version;
+                }
+                Err(err) => {
+                    report!(
+                        warn,
+                        messages::METADATA_LEVEL_UNREADABLE,
+                        path = level_path.display().to_string(),
+                        error = err.to_string()
+                    );
+                }
+            }
+        }
+
+        metadata
+    }
+
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut metadata = ArchiveMetadata::default();
+        let mut format_version = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').ok_or_else(|| {
+                invalid_data(Message::new(messages::METADATA_MALFORMED_LINE).arg("line", line))
+            })?;
+            let value = value.trim();
+
+            let invalid_value = || {
+                invalid_data(
+                    Message::new(messages::METADATA_INVALID_VALUE)
+                        .arg("key", key)
+                        .arg("value", value),
+                )
+            };
+
+            match key {
+                "tool-version" => metadata.tool_version = Some(String::from(value)),
+                "format-version" => {
+                    format_version = Some(value.parse().map_err(|_| invalid_value())?)
+                }
+                "world-name" => metadata.world_name = Some(String::from(value)),
+                "data-version" => {
+                    metadata.data_version = Some(value.parse().map_err(|_| invalid_value())?)
+                }
+                "chunks" => metadata.chunks = Some(value.parse().map_err(|_| invalid_value())?),
+                "created" => metadata.created = Some(value.parse().map_err(|_| invalid_value())?),
+                _ => {}
+            }
+        }
+
+        metadata.format_version = format_version
+            .ok_or_else(|| invalid_data(Message::new(messages::METADATA_MISSING_FORMAT_VERSION)))?;
+
+        Ok(metadata)
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        if let Some(tool_version) = &self.tool_version {
+            writeln!(writer, "tool-version {}", tool_version)?;
+        }
+
+        writeln!(writer, "format-version {}", self.format_version)?;
+
+        // World names can hold any text, but each value has to stay on its own line
+        if let Some(world_name) = &self.world_name {
+            writeln!(
+                writer,
+                "world-name {}",
+                world_name.replace(['\r', '\n'], " ")
+            )?;
+        }
+
+        if let Some(data_version) = self.data_version {
+            writeln!(writer, "data-version {}", data_version)?;
+        }
+
+        if let Some(chunks) = self.chunks {
+            writeln!(writer, "chunks {}", chunks)?;
+        }
+
+        if let Some(created) = self.created {
+            writeln!(writer, "created {}", created)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the archive can be restored by this build, which reads every older layout.
+    pub fn is_compatible(&self) -> bool {
+        ArchiveLayout::from_version(self.format_version).is_some()
+    }
+
+    /// Returns the layout the rest of the archive is read with. An archive in a layout newer than this build knows
+    /// about is refused, unless `force` was given to read it as though it were in the current layout.
+    pub fn layout(&self, force: bool) -> Result<ArchiveLayout, io::Error> {
+        match ArchiveLayout::from_version(self.format_version) {
+            Some(layout) => Ok(layout),
+            None if force => Ok(ArchiveLayout::CURRENT),
+            None => Err(invalid_data(
+                Message::new(messages::METADATA_INCOMPATIBLE)
+                    .arg("version", self.format_version)
+                    .arg("supported", ArchiveLayout::CURRENT)
+                    .arg(
+                        "tool_version",
+                        self.tool_version.as_deref().unwrap_or("unknown"),
+                    ),
+            )),
+        }
+    }
+}
+
+fn find_level_dat(input_dir: &Path) -> Option<PathBuf> {
+    let input_dir = input_dir.canonicalize().ok()?;
+
+    input_dir
+        .ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join("level.dat"))
+        .find(|path| path.is_file())
+}
+
+/// Reads the name of a world and the data version it was last saved with from its gzipped `level.dat`.
+fn read_level_dat(path: &Path) -> Result<(Option<String>, Option<i32>), io::Error> {
+    let mut data = Vec::new();
+    GzDecoder::new(File::open(path)?).read_to_end(&mut data)?;
+
+    let world_name = find_string_tag(&data, &["Data", "LevelName"])?;
+    let data_version = find_int_tag(&data, &["Data", "DataVersion"])?;
+
+    Ok((world_name, data_version))
+}
+
+fn invalid_data(message: Message) -> io::Error {
+    message.into_error(io::ErrorKind::InvalidData)
+}
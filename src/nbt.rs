@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+
+use fastnbt::Value;
+
+use crate::messages::{self, Message};
+use crate::region::ChunkPos;
+
+pub fn read_nbt(data: &[u8]) -> Result<Value, io::Error> {
+    fastnbt::from_bytes(data).map_err(|err| {
+        Message::new(messages::NBT_DESERIALIZE)
+            .arg("error", err)
+            .into_error(io::ErrorKind::InvalidData)
+    })
+}
+
+pub fn write_nbt(nbt: &Value) -> Result<Vec<u8>, io::Error> {
+    let mut data: Vec<u8> = Vec::new();
+    fastnbt::to_writer(&mut data, nbt).map_err(|err| {
+        Message::new(messages::NBT_SERIALIZE)
+            .arg("error", err)
+            .into_error(io::ErrorKind::Other)
+    })?;
+
+    Ok(data)
+}
+
+/// Serializes NBT with a name for its root compound, which chunks leave empty but some other formats require, such as
+/// `Schematic` for Sponge schematics.
+pub fn write_named_nbt(nbt: &Value, name: &str) -> Result<Vec<u8>, io::Error> {
+    let data = write_nbt(nbt)?;
+
+    // The root is written as a compound tag followed by the length of its empty name
+    let mut named = Vec::with_capacity(data.len() + name.len());
+    named.push(data[0]);
+    named.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    named.extend_from_slice(name.as_bytes());
+    named.extend_from_slice(&data[3..]);
+
+    Ok(named)
+}
+
+/// Returns the compound holding a chunk's data. Chunks saved before 1.18 nest everything inside a `Level` compound,
+/// while newer chunks store it at the root.
+pub fn chunk_level(nbt: &Value) -> Option<&HashMap<String, Value>> {
+    match nbt {
+        Value::Compound(root) => match root.get("Level") {
+            Some(Value::Compound(level)) => Some(level),
+            _ => Some(root),
+        },
+        _ => None,
+    }
+}
+
+/// Returns the absolute chunk position stored in a chunk's `xPos` and `zPos` tags.
+pub fn chunk_position(nbt: &Value) -> Option<ChunkPos> {
+    let level = chunk_level(nbt)?;
+
+    match (level.get("xPos"), level.get("zPos")) {
+        (Some(Value::Int(x)), Some(Value::Int(z))) => Some(ChunkPos { x: *x, z: *z }),
+        _ => None,
+    }
+}
+
+/// Returns the number of bytes a tag's payload occupies when serialized.
+pub fn payload_size(value: &Value) -> usize {
+    match value {
+        Value::Byte(_) => 1,
+        Value::Short(_) => 2,
+        Value::Int(_) | Value::Float(_) => 4,
+        Value::Long(_) | Value::Double(_) => 8,
+        Value::ByteArray(array) => 4 + array.len(),
+        Value::IntArray(array) => 4 + (array.len() * 4),
+        Value::LongArray(array) => 4 + (array.len() * 8),
+        Value::String(string) => 2 + string.len(),
+        Value::List(list) => 5 + list.iter().map(payload_size).sum::<usize>(),
+        Value::Compound(compound) => {
+            1 + compound
+                .iter()
+                .map(|(name, value)| named_tag_size(name, value))
+                .sum::<usize>()
+        }
+    }
+}
+
+/// Returns the number of bytes a named tag (such as an entry in a compound) occupies when serialized, including its
+/// type and name.
+pub fn named_tag_size(name: &str, value: &Value) -> usize {
+    3 + name.len() + payload_size(value)
+}
+
+/// Scans serialized NBT for a tag without decoding it, which is much cheaper than deserializing the whole document.
+/// `predicate` is called with the path of names leading to each named tag below the root compound, where elements of
+/// lists are not given a name of their own (so a tag in a compound within the `sections` list has the path
+/// `["sections", "SkyLight"]`). Scanning stops as soon as the predicate returns true.
+pub fn contains_tag<F>(data: &[u8], mut predicate: F) -> Result<bool, io::Error>
+where
+    F: FnMut(&[&[u8]]) -> bool,
+{
+    NbtScanner::open(data)?.scan_compound(&mut Vec::new(), &mut predicate)
+}
+
+/// Reads the value of a string tag from serialized NBT without decoding the rest of the document, where `path` gives
+/// the names of the compounds leading to the tag from the root compound. Returns `None` if there is no string tag at
+/// that path.
+pub fn find_string_tag(data: &[u8], path: &[&str]) -> Result<Option<String>, io::Error> {
+    let mut scanner = NbtScanner::open(data)?;
+
+    match scanner.find_tag(path)? {
+        Some(TAG_STRING) => {
+            let length = scanner.read_u16()? as usize;
+            let value = scanner.take(length)?;
+
+            Ok(Some(String::from_utf8_lossy(value).into_owned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns the offset of the value of an int tag within serialized NBT, so that it can be read or patched in place,
+/// where `path` is given in the same way as for `find_string_tag`. Returns `None` if there is no int tag at that path.
+pub fn find_int_tag_offset(data: &[u8], path: &[&str]) -> Result<Option<usize>, io::Error> {
+    let mut scanner = NbtScanner::open(data)?;
+
+    match scanner.find_tag(path)? {
+        Some(TAG_INT) if scanner.offset + 4 <= data.len() => Ok(Some(scanner.offset)),
+        Some(TAG_INT) => Err(invalid_nbt("Unexpected end of data")),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the value of an int tag from serialized NBT without decoding the rest of the document, where `path` is given in
+/// the same way as for `find_string_tag`. Returns `None` if there is no int tag at that path.
+pub fn find_int_tag(data: &[u8], path: &[&str]) -> Result<Option<i32>, io::Error> {
+    Ok(find_int_tag_offset(data, path)?
+        .map(|offset| i32::from_be_bytes(data[offset..(offset + 4)].try_into().unwrap())))
+}
+
+/// Reads the value of a long tag from serialized NBT without decoding the rest of the document, where `path` is given in
+/// the same way as for `find_string_tag`. Returns `None` if there is no long tag at that path.
+pub fn find_long_tag(data: &[u8], path: &[&str]) -> Result<Option<i64>, io::Error> {
+    let mut scanner = NbtScanner::open(data)?;
+
+    match scanner.find_tag(path)? {
+        Some(TAG_LONG) => Ok(Some(i64::from_be_bytes(
+            scanner.take(8)?.try_into().unwrap(),
+        ))),
+        _ => Ok(None),
+    }
+}
+
+const TAG_END: u8 = 0;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+
+/// Deeply nested documents are rejected rather than risking a stack overflow.
+const MAX_SCAN_DEPTH: usize = 512;
+
+struct NbtScanner<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NbtScanner<'a> {
+    /// Creates a scanner positioned at the start of the root compound's payload.
+    fn open(data: &'a [u8]) -> Result<Self, io::Error> {
+        let mut scanner = NbtScanner { data, offset: 0 };
+
+        if scanner.read_u8()? != TAG_COMPOUND {
+            return Err(invalid_nbt("Root tag is not a compound"));
+        }
+
+        let name_length = scanner.read_u16()? as usize;
+        scanner.skip(name_length)?;
+
+        Ok(scanner)
+    }
+
+    /// Moves the scanner to the payload of the tag at `path` within the current compound, returning its type.
+    fn find_tag(&mut self, path: &[&str]) -> Result<Option<u8>, io::Error> {
+        let (name, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        loop {
+            let tag = self.read_u8()?;
+
+            if tag == TAG_END {
+                return Ok(None);
+            }
+
+            let name_length = self.read_u16()? as usize;
+
+            if self.take(name_length)? == name.as_bytes() {
+                return match (tag, rest.is_empty()) {
+                    (tag, true) => Ok(Some(tag)),
+                    (TAG_COMPOUND, false) => self.find_tag(rest),
+                    _ => Ok(None),
+                };
+            }
+
+            self.scan_payload(tag, &mut Vec::new(), &mut |_: &[&[u8]]| false)?;
+        }
+    }
+
+    fn scan_compound<F>(
+        &mut self,
+        path: &mut Vec<&'a [u8]>,
+        predicate: &mut F,
+    ) -> Result<bool, io::Error>
+    where
+        F: FnMut(&[&[u8]]) -> bool,
+    {
+        if path.len() > MAX_SCAN_DEPTH {
+            return Err(invalid_nbt("Tags are nested too deeply"));
+        }
+
+        loop {
+            let tag = self.read_u8()?;
+
+            if tag == TAG_END {
+                return Ok(false);
+            }
+
+            let name_length = self.read_u16()? as usize;
+            let name = self.take(name_length)?;
+
+            path.push(name);
+
+            let found = predicate(path) || self.scan_payload(tag, path, predicate)?;
+
+            path.pop();
+
+            if found {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn scan_payload<F>(
+        &mut self,
+        tag: u8,
+        path: &mut Vec<&'a [u8]>,
+        predicate: &mut F,
+    ) -> Result<bool, io::Error>
+    where
+        F: FnMut(&[&[u8]]) -> bool,
+    {
+        match tag {
+            1 => self.skip(1)?,
+            2 => self.skip(2)?,
+            TAG_INT | 5 => self.skip(4)?,
+            TAG_LONG | 6 => self.skip(8)?,
+            7 => self.skip_array(1)?,
+            TAG_STRING => {
+                let length = self.read_u16()? as usize;
+                self.skip(length)?;
+            }
+            TAG_LIST => {
+                let element_tag = self.read_u8()?;
+                let length = self.read_i32()?.max(0);
+
+                for _ in 0..length {
+                    if self.scan_payload(element_tag, path, predicate)? {
+                        return Ok(true);
+                    }
+                }
+            }
+            TAG_COMPOUND => return self.scan_compound(path, predicate),
+            11 => self.skip_array(4)?,
+            12 => self.skip_array(8)?,
+            _ => return Err(invalid_nbt("Unknown tag type")),
+        }
+
+        Ok(false)
+    }
+
+    fn skip_array(&mut self, element_size: usize) -> Result<(), io::Error> {
+        let length = self.read_i32()?.max(0) as usize;
+        self.skip(length * element_size)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], io::Error> {
+        let end = self.offset + length;
+
+        if end > self.data.len() {
+            return Err(invalid_nbt("Unexpected end of data"));
+        }
+
+        let slice = &self.data[self.offset..end];
+        self.offset = end;
+
+        Ok(slice)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), io::Error> {
+        self.take(length).map(|_| ())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, io::Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, io::Error> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn invalid_nbt(reason: &str) -> io::Error {
+    Message::new(messages::NBT_SCAN)
+        .arg("reason", reason)
+        .into_error(io::ErrorKind::InvalidData)
+}
@@ -0,0 +1,467 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use fastnbt::Value;
+
+/// A path to one or more tags within an NBT tree, such as `sections[].SkyLight`. Each segment is separated by a dot
+/// and names a key of a compound, optionally followed by `[]` to select every element of a list or `[N]` to select a
+/// single element. Keys containing dots or brackets can be written in double quotes.
+///
+/// Elements can also be selected by a filter such as `[?Y==4]`, which compares the tags at a path within each element
+/// against a value written as in SNBT using `==`, `!=`, `<`, `<=`, `>` or `>=`. A filter with only a path, such as
+/// `[?block_states]`, selects the elements which have a tag at that path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NbtPath {
+    segments: Vec<Segment>,
+    source: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    AllElements,
+    Element(usize),
+    Filter(Filter),
+}
+
+/// Selects the elements of a list whose tags at a path meet a condition, or which have any tags at that path.
+#[derive(Clone, Debug, PartialEq)]
+struct Filter {
+    path: NbtPath,
+    condition: Option<(Comparison, Value)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// The operators in the order they are looked for, so that `<=` isn't mistaken for `<`.
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Comparison::Equal => ordering == Some(Ordering::Equal),
+            Comparison::NotEqual => ordering != Some(Ordering::Equal),
+            Comparison::Less => ordering == Some(Ordering::Less),
+            Comparison::LessOrEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Greater => ordering == Some(Ordering::Greater),
+            Comparison::GreaterOrEqual => {
+                matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+            }
+        }
+    }
+}
+
+impl Filter {
+    fn matches(&self, element: &Value) -> bool {
+        let found = self.path.select(element);
+
+        match &self.condition {
+            Some((comparison, value)) => found
+                .iter()
+                .any(|tag| comparison.holds(compare_values(tag, value))),
+            None => !found.is_empty(),
+        }
+    }
+}
+
+/// Orders two tags, comparing numbers by value whatever their type, so that a byte tag such as a section's `Y` can be
+/// compared with a plain integer. Other tags can only be equal to tags of the same type, apart from strings.
+fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    fn number(value: &Value) -> Option<f64> {
+        match value {
+            Value::Byte(value) => Some(*value as f64),
+            Value::Short(value) => Some(*value as f64),
+            Value::Int(value) => Some(*value as f64),
+            Value::Long(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value as f64),
+            Value::Double(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    match (number(a), number(b), a, b) {
+        (Some(a), Some(b), _, _) => a.partial_cmp(&b),
+        (_, _, Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// A tag to set, given on the command line as `<path>=<value>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub path: NbtPath,
+    pub value: Value,
+}
+
+impl NbtPath {
+    /// Returns every tag selected by the path, in the order they are stored. Keys of compounds are visited in the order
+    /// of the compound, which isn't sorted.
+    pub fn select<'a>(&self, root: &'a Value) -> Vec<&'a Value> {
+        let mut found = Vec::new();
+        select_into(root, &self.segments, &mut found);
+
+        found
+    }
+
+    /// Removes every tag selected by the path, returning the number of tags removed.
+    pub fn remove(&self, root: &mut Value) -> usize {
+        let (last, parents) = self.segments.split_last().unwrap();
+        let mut removed = 0;
+
+        visit(root, parents, &mut |parent| {
+            removed += match (last, parent) {
+                (Segment::Key(key), Value::Compound(compound)) => {
+                    compound.remove(key).map_or(0, |_| 1)
+                }
+                (Segment::AllElements, Value::List(list)) => list.drain(..).count(),
+                (Segment::Element(index), Value::List(list)) if *index < list.len() => {
+                    list.remove(*index);
+                    1
+                }
+                (Segment::Filter(filter), Value::List(list)) => {
+                    let len = list.len();
+                    list.retain(|element| !filter.matches(element));
+
+                    len - list.len()
+                }
+                _ => 0,
+            };
+        });
+
+        removed
+    }
+
+    /// Sets every tag selected by the path to the given value, returning the number of tags set. Keys which are
+    /// missing from their compound are added, but the compounds and lists leading up to them must already exist.
+    pub fn set(&self, root: &mut Value, value: &Value) -> usize {
+        let (last, parents) = self.segments.split_last().unwrap();
+        let mut set = 0;
+
+        visit(root, parents, &mut |parent| match (last, parent) {
+            (Segment::Key(key), Value::Compound(compound)) => {
+                compound.insert(key.clone(), value.clone());
+                set += 1;
+            }
+            (Segment::AllElements, Value::List(list)) => {
+                for element in list.iter_mut() {
+                    *element = value.clone();
+                    set += 1;
+                }
+            }
+            (Segment::Element(index), Value::List(list)) => {
+                if let Some(element) = list.get_mut(*index) {
+                    *element = value.clone();
+                    set += 1;
+                }
+            }
+            (Segment::Filter(filter), Value::List(list)) => {
+                for element in list.iter_mut() {
+                    if filter.matches(element) {
+                        *element = value.clone();
+                        set += 1;
+                    }
+                }
+            }
+            _ => {}
+        });
+
+        set
+    }
+}
+
+/// Calls the visitor with every tag which the segments lead to, skipping any branch which doesn't match them.
+fn visit<F>(value: &mut Value, segments: &[Segment], visitor: &mut F)
+where
+    F: FnMut(&mut Value),
+{
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return visitor(value),
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Compound(compound)) => {
+            if let Some(child) = compound.get_mut(key) {
+                visit(child, rest, visitor);
+            }
+        }
+        (Segment::AllElements, Value::List(list)) => {
+            for element in list.iter_mut() {
+                visit(element, rest, visitor);
+            }
+        }
+        (Segment::Element(index), Value::List(list)) => {
+            if let Some(element) = list.get_mut(*index) {
+                visit(element, rest, visitor);
+            }
+        }
+        (Segment::Filter(filter), Value::List(list)) => {
+            for element in list.iter_mut() {
+                if filter.matches(element) {
+                    visit(element, rest, visitor);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects every tag which the segments lead to, as `visit` does without changing them.
+fn select_into<'a>(value: &'a Value, segments: &[Segment], found: &mut Vec<&'a Value>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return found.push(value),
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Compound(compound)) => {
+            if let Some(child) = compound.get(key) {
+                select_into(child, rest, found);
+            }
+        }
+        (Segment::AllElements, Value::List(list)) => {
+            for element in list {
+                select_into(element, rest, found);
+            }
+        }
+        (Segment::Element(index), Value::List(list)) => {
+            if let Some(element) = list.get(*index) {
+                select_into(element, rest, found);
+            }
+        }
+        (Segment::Filter(filter), Value::List(list)) => {
+            for element in list.iter().filter(|element| filter.matches(element)) {
+                select_into(element, rest, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl fmt::Display for NbtPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Parses a path such as `sections[].SkyLight`, `Level."some.key"[0]` or `sections[?Y==4].block_states`.
+pub fn parse_nbt_path(value: &str) -> Result<NbtPath, String> {
+    let mut segments = Vec::new();
+    let mut chars = value.trim().chars().peekable();
+
+    loop {
+        let key = match chars.peek() {
+            Some('"') => {
+                chars.next();
+
+                let mut key = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => key.extend(chars.next()),
+                        Some(c) => key.push(c),
+                        None => {
+                            return Err(format!("Unterminated quoted key in NBT path: {}", value))
+                        }
+                    }
+                }
+
+                key
+            }
+            _ => {
+                let mut key = String::new();
+
+                while let Some(&c) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        break;
+                    }
+
+                    key.push(c);
+                    chars.next();
+                }
+
+                key
+            }
+        };
+
+        if key.is_empty() {
+            return Err(format!("Empty key in NBT path: {}", value));
+        }
+
+        segments.push(Segment::Key(key));
+
+        while chars.peek() == Some(&'[') {
+            chars.next();
+
+            let index = take_bracket(&mut chars)
+                .ok_or_else(|| format!("Unterminated '[' in NBT path: {}", value))?;
+
+            if index.is_empty() {
+                segments.push(Segment::AllElements);
+            } else if let Some(filter) = index.strip_prefix('?') {
+                segments.push(Segment::Filter(parse_filter(filter)?));
+            } else {
+                let index = index
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid list index in NBT path: {}", value))?;
+
+                segments.push(Segment::Element(index));
+            }
+        }
+
+        match chars.next() {
+            Some('.') => continue,
+            None => break,
+            Some(c) => return Err(format!("Unexpected '{}' in NBT path: {}", c, value)),
+        }
+    }
+
+    Ok(NbtPath {
+        segments,
+        source: String::from(value.trim()),
+    })
+}
+
+/// Reads the text up to the `]` which closes a bracket that has already been read, skipping over nested brackets and
+/// quoted strings so that a filter can contain both. Returns `None` if the bracket is never closed.
+fn take_bracket(chars: &mut Peekable<Chars<'_>>) -> Option<String> {
+    let mut text = String::new();
+    let mut quote = None;
+    let mut depth = 0;
+
+    for c in chars {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' && depth == 0 => return Some(text),
+            None if c == ']' => depth -= 1,
+            None => {}
+        }
+
+        text.push(c);
+    }
+
+    None
+}
+
+/// Parses the inside of a filter after its `?`, such as `Y==4` or `block_states`.
+fn parse_filter(filter: &str) -> Result<Filter, String> {
+    let mut quote = None;
+
+    for (offset, c) in filter.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => continue,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => {}
+        }
+
+        let operator = Comparison::OPERATORS
+            .iter()
+            .find(|(operator, _)| filter[offset..].starts_with(operator));
+
+        if let Some((operator, comparison)) = operator {
+            return Ok(Filter {
+                path: parse_nbt_path(&filter[..offset])?,
+                condition: Some((
+                    *comparison,
+                    parse_value(&filter[offset + operator.len()..])?,
+                )),
+            });
+        }
+    }
+
+    Ok(Filter {
+        path: parse_nbt_path(filter)?,
+        condition: None,
+    })
+}
+
+/// Parses an assignment such as `isLightOn=0b`, where the value is written as in SNBT: integers take a `b`, `s` or `L`
+/// suffix for bytes, shorts and longs, decimals take an `f` or `d` suffix, and strings are quoted.
+pub fn parse_assignment(value: &str) -> Result<Assignment, String> {
+    // The path can hold filters such as `[?Y==4]`, so the `=` is the first one outside of brackets and quotes
+    let mut quote = None;
+    let mut depth = 0;
+    let split = value.char_indices().find(|&(_, c)| {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' => depth -= 1,
+            None => return c == '=' && depth == 0,
+        }
+
+        false
+    });
+
+    let (path, tag) = match split {
+        Some((offset, _)) => (&value[..offset], &value[offset + 1..]),
+        None => return Err(format!("Expected <path>=<value>: {}", value)),
+    };
+
+    Ok(Assignment {
+        path: parse_nbt_path(path)?,
+        value: parse_value(tag)?,
+    })
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    let value = value.trim();
+    let invalid = || format!("Invalid NBT value: {}", value);
+
+    if value.len() >= 2 {
+        for quote in &['"', '\''] {
+            if value.starts_with(*quote) && value.ends_with(*quote) {
+                return Ok(Value::String(String::from(&value[1..value.len() - 1])));
+            }
+        }
+    }
+
+    match value {
+        "true" => return Ok(Value::Byte(1)),
+        "false" => return Ok(Value::Byte(0)),
+        _ => {}
+    }
+
+    let (number, suffix) = match value.char_indices().last() {
+        Some((index, c)) if c.is_ascii_alphabetic() => (&value[..index], c.to_ascii_lowercase()),
+        _ => (value, ' '),
+    };
+
+    let tag = match suffix {
+        'b' => Value::Byte(number.parse().map_err(|_| invalid())?),
+        's' => Value::Short(number.parse().map_err(|_| invalid())?),
+        'l' => Value::Long(number.parse().map_err(|_| invalid())?),
+        'f' => Value::Float(number.parse().map_err(|_| invalid())?),
+        'd' => Value::Double(number.parse().map_err(|_| invalid())?),
+        ' ' if number.contains('.') => Value::Double(number.parse().map_err(|_| invalid())?),
+        ' ' => Value::Int(number.parse().map_err(|_| invalid())?),
+        _ => return Err(invalid()),
+    };
+
+    Ok(tag)
+}
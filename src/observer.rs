@@ -0,0 +1,199 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::logging;
+use crate::messages::Message;
+use crate::region::ChunkPos;
+
+/// The shortest time between two JSON progress events about chunks, so that a fast operation doesn't flood stderr.
+/// Events for finished regions are always written.
+const EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Receives the progress of a long operation such as pack, unpack or strip, so that a frontend embedding the tool can
+/// drive its own progress display rather than reading the log. Events may arrive from several threads at once, and each
+/// of them is ignored unless the observer overrides it.
+pub trait OperationObserver: Sync {
+    /// The operation has found its input, which is this many bytes and chunks in total if they are known up front.
+    fn started(&self, _input_bytes: Option<u64>, _input_chunks: Option<u64>) {}
+
+    /// A chunk has been processed, given by the position of its region and its position within that region.
+    fn chunk_processed(&self, _region: ChunkPos, _chunk: ChunkPos) {}
+
+    /// Every chunk of a region has been processed, which accounts for this many bytes of the input.
+    fn region_finished(&self, _region: ChunkPos, _input_bytes: u64) {}
+
+    /// A warning has been logged.
+    fn warning(&self, _message: &Message) {}
+
+    /// The operation has finished without an error.
+    fn finished(&self) {}
+}
+
+/// An observer which ignores every event.
+pub struct NullObserver;
+
+impl OperationObserver for NullObserver {}
+
+/// Shows the progress of an operation through its input on a progress bar, which is hidden if progress shouldn't be
+/// shown or the size of the input isn't known. Warnings are already logged, so they aren't shown again. With
+/// `--progress json`, the progress is written to stderr as JSON events instead.
+pub struct ProgressObserver {
+    message: &'static str,
+    bar: Mutex<Option<ProgressBar>>,
+    events: Option<Mutex<ProgressEvents>>,
+}
+
+impl ProgressObserver {
+    /// Creates an observer for the given phase, such as `pack`, which names the operation in JSON events. The message
+    /// is shown next to the progress bar.
+    pub fn new(phase: &'static str, message: &'static str) -> Self {
+        ProgressObserver {
+            message,
+            bar: Mutex::new(None),
+            events: if logging::progress_events() {
+                Some(Mutex::new(ProgressEvents::new(phase)))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl OperationObserver for ProgressObserver {
+    fn started(&self, input_bytes: Option<u64>, input_chunks: Option<u64>) {
+        if let Some(events) = &self.events {
+            let mut events = events.lock().unwrap();
+            events.bytes_total = input_bytes;
+            events.chunks_total = input_chunks;
+            events.started = Instant::now();
+            events.emit("started", None);
+
+            return;
+        }
+
+        let input_bytes = match input_bytes {
+            Some(input_bytes) => input_bytes,
+            None => return,
+        };
+
+        let bar = logging::progress_bar(input_bytes);
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg} {wide_bar} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, ETA {eta})",
+            )
+            .unwrap(),
+        );
+        bar.set_message(self.message);
+
+        *self.bar.lock().unwrap() = Some(bar);
+    }
+
+    fn chunk_processed(&self, _region: ChunkPos, _chunk: ChunkPos) {
+        if let Some(events) = &self.events {
+            let mut events = events.lock().unwrap();
+            events.chunks += 1;
+
+            if events.last_event.elapsed() >= EVENT_INTERVAL {
+                events.emit("progress", None);
+            }
+        }
+    }
+
+    fn region_finished(&self, region: ChunkPos, input_bytes: u64) {
+        if let Some(events) = &self.events {
+            let mut events = events.lock().unwrap();
+            events.bytes += input_bytes;
+            events.emit("progress", Some(region));
+        }
+
+        if let Some(bar) = self.bar.lock().unwrap().as_ref() {
+            bar.inc(input_bytes);
+        }
+    }
+
+    fn warning(&self, message: &Message) {
+        if let Some(events) = &self.events {
+            let events = events.lock().unwrap();
+
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "event": "warning",
+                    "phase": events.phase,
+                    "message_id": message.id(),
+                    "message": message.text(),
+                })
+            );
+        }
+    }
+
+    fn finished(&self) {
+        if let Some(events) = &self.events {
+            events.lock().unwrap().emit("finished", None);
+        }
+
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// The progress of an operation so far, which is written to stderr as one JSON object per line for frontends which run
+/// the tool as a process.
+struct ProgressEvents {
+    phase: &'static str,
+    started: Instant,
+    last_event: Instant,
+    chunks: u64,
+    chunks_total: Option<u64>,
+    bytes: u64,
+    bytes_total: Option<u64>,
+}
+
+impl ProgressEvents {
+    fn new(phase: &'static str) -> Self {
+        ProgressEvents {
+            phase,
+            started: Instant::now(),
+            last_event: Instant::now(),
+            chunks: 0,
+            chunks_total: None,
+            bytes: 0,
+            bytes_total: None,
+        }
+    }
+
+    fn emit(&mut self, event: &str, region: Option<ChunkPos>) {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+
+        // The rest of the input is expected to take as long per byte as the input read so far
+        let eta_ms = match self.bytes_total {
+            Some(bytes_total) if self.bytes > 0 => {
+                let remaining = bytes_total.saturating_sub(self.bytes);
+
+                Some((elapsed_ms as f64 * remaining as f64 / self.bytes as f64) as u64)
+            }
+            _ => None,
+        };
+
+        let mut object = serde_json::json!({
+            "event": event,
+            "phase": self.phase,
+            "chunks": self.chunks,
+            "chunks_total": self.chunks_total,
+            "bytes": self.bytes,
+            "bytes_total": self.bytes_total,
+            "elapsed_ms": elapsed_ms,
+            "eta_ms": eta_ms,
+        });
+
+        if let Some(region) = region {
+            object["region"] = serde_json::json!(format!("r.{}.{}.mca", region.x, region.z));
+        }
+
+        eprintln!("{}", object);
+        self.last_event = Instant::now();
+    }
+}
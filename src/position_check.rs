@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use clap::{Args, ValueEnum};
+
+use crate::dedup::{read_chunk_position, relocate_chunk};
+use crate::messages::{self, report, Message};
+use crate::observer::OperationObserver;
+use crate::region::{Chunk, ChunkPos};
+
+#[derive(Args, Clone)]
+pub struct PositionCheckOptions {
+    #[clap(
+        long,
+        value_enum,
+        help = "What to do with chunks whose xPos/zPos don't belong to the region file they are in, which usually means the file was renamed",
+        default_value = "warn"
+    )]
+    on_position_mismatch: MismatchPolicy,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum MismatchPolicy {
+    /// Keep the chunk as it is, and warn about the region once the command finishes
+    Warn,
+    /// Rewrite the chunk's position to match the region file and slot it is stored in
+    Fix,
+    /// Fail the command
+    Refuse,
+}
+
+/// Cross-checks the position stored in each chunk against the name of its region file and its slot in that region.
+/// Renaming region files is a common way of trying to move terrain, but the game rejects chunks whose position doesn't
+/// match where they are stored, so those chunks would be regenerated.
+pub struct PositionCheck {
+    policy: MismatchPolicy,
+    mismatches: Mutex<HashMap<ChunkPos, u64>>,
+}
+
+impl PositionCheck {
+    pub fn new(options: &PositionCheckOptions) -> Self {
+        PositionCheck {
+            policy: options.on_position_mismatch,
+            mismatches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks the position of a chunk from the given region, returning true if the chunk's data was rewritten to fix
+    /// its position.
+    pub fn check(&self, region: ChunkPos, chunk: &mut Chunk) -> Result<bool, io::Error> {
+        let expected = ChunkPos {
+            x: (region.x * 32) + chunk.position.x,
+            z: (region.z * 32) + chunk.position.z,
+        };
+
+        let actual = match read_chunk_position(&chunk.data)? {
+            Some(actual) if actual != expected => actual,
+            _ => return Ok(false),
+        };
+
+        if self.policy == MismatchPolicy::Refuse {
+            return Err(Message::new(messages::POSITION_REFUSED)
+                .arg("actual_x", actual.x)
+                .arg("actual_z", actual.z)
+                .arg("region", format!("r.{}.{}.mca", region.x, region.z))
+                .arg("expected_x", expected.x)
+                .arg("expected_z", expected.z)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        report!(
+            debug,
+            messages::POSITION_MISMATCH,
+            region = format!("r.{}.{}.mca", region.x, region.z),
+            actual_x = actual.x,
+            actual_z = actual.z,
+            expected_x = expected.x,
+            expected_z = expected.z
+        );
+
+        *self.mismatches.lock().unwrap().entry(region).or_default() += 1;
+
+        if self.policy == MismatchPolicy::Fix {
+            relocate_chunk(&mut chunk.data, expected)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Logs each region which contained chunks with mismatched positions, passing each warning on to the observer.
+    pub fn report(&self, observer: &dyn OperationObserver) {
+        let mismatches = self.mismatches.lock().unwrap();
+
+        let mut regions: Vec<(&ChunkPos, &u64)> = mismatches.iter().collect();
+        regions.sort_by_key(|(region, _)| (region.x, region.z));
+
+        for (region, &chunks) in regions {
+            let name = format!("r.{}.{}.mca", region.x, region.z);
+
+            let warning = if self.policy == MismatchPolicy::Fix {
+                report!(
+                    warn,
+                    messages::POSITION_FIXED,
+                    chunks = chunks,
+                    region = name
+                )
+            } else {
+                report!(
+                    warn,
+                    messages::POSITION_MISMATCHED,
+                    chunks = chunks,
+                    region = name
+                )
+            };
+
+            observer.warning(&warning);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::io;
+use std::io::prelude::*;
+
+use crate::filter::StatusFilter;
+use crate::messages::{self, Message};
+use crate::nbt::{read_nbt, write_nbt};
+use crate::nbt_path::{parse_nbt_path, NbtPath};
+use crate::region::{Chunk, RawChunk};
+
+/// The transformations applied to the chunks of an archive when it was packed, which record exactly which data the
+/// archive leaves out. It is stored as plain text with one `<option> <value>` line per setting: `remove` lines give the
+/// path of each tag stripped from every chunk, `min-status` and `status` the generation statuses chunks were filtered
+/// by, and `include-regions`, `exclude-regions` and `chunk-bounds` the areas of the world which were selected.
+#[derive(Clone, Default, PartialEq)]
+pub struct ArchiveProfile {
+    pub removed_tags: Vec<String>,
+    pub min_status: Option<String>,
+    pub status: Vec<String>,
+    pub include_regions: Vec<String>,
+    pub exclude_regions: Vec<String>,
+    pub chunk_bounds: Option<String>,
+}
+
+impl ArchiveProfile {
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut profile = ArchiveProfile::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (option, value) = line.split_once(' ').ok_or_else(|| {
+                invalid_data(Message::new(messages::PROFILE_MALFORMED_LINE).arg("line", line))
+            })?;
+            let value = String::from(value.trim());
+
+            match option {
+                "remove" => profile.removed_tags.push(value),
+                "min-status" => profile.min_status = Some(value),
+                "status" => profile.status.push(value),
+                "include-regions" => profile.include_regions.push(value),
+                "exclude-regions" => profile.exclude_regions.push(value),
+                "chunk-bounds" => profile.chunk_bounds = Some(value),
+                _ => {
+                    return Err(invalid_data(
+                        Message::new(messages::PROFILE_UNKNOWN_OPTION).arg("option", option),
+                    ))
+                }
+            }
+        }
+
+        Ok(profile)
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        for path in &self.removed_tags {
+            writeln!(writer, "remove {}", path)?;
+        }
+
+        if let Some(min_status) = &self.min_status {
+            writeln!(writer, "min-status {}", min_status)?;
+        }
+
+        for status in &self.status {
+            writeln!(writer, "status {}", status)?;
+        }
+
+        for area in &self.include_regions {
+            writeln!(writer, "include-regions {}", area)?;
+        }
+
+        for area in &self.exclude_regions {
+            writeln!(writer, "exclude-regions {}", area)?;
+        }
+
+        if let Some(bounds) = &self.chunk_bounds {
+            writeln!(writer, "chunk-bounds {}", bounds)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the profile doesn't leave anything out of the archive.
+    pub fn is_empty(&self) -> bool {
+        *self == ArchiveProfile::default()
+    }
+}
+
+/// Applies the chunk transformations of a profile to chunks outside of the archive it came from, such as the chunks
+/// kept in a replica's region files when an archive is merged into them. The areas selected by the profile are not
+/// applied, since they only decide which regions an archive covers.
+pub struct ProfileTransform {
+    removed_tags: Vec<NbtPath>,
+    status_filter: StatusFilter,
+}
+
+impl ProfileTransform {
+    pub fn new(profile: &ArchiveProfile) -> Result<Self, io::Error> {
+        let invalid = |err: String| {
+            invalid_data(Message::new(messages::PROFILE_INVALID_VALUE).arg("error", err))
+        };
+
+        let removed_tags = profile
+            .removed_tags
+            .iter()
+            .map(|path| parse_nbt_path(path))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+
+        let status_filter =
+            StatusFilter::from_names(profile.min_status.as_deref(), &profile.status)
+                .map_err(invalid)?;
+
+        Ok(ProfileTransform {
+            removed_tags,
+            status_filter,
+        })
+    }
+
+    /// Transforms a chunk as stored in a region file. Returns `None` if the profile leaves the chunk out entirely, or
+    /// the chunk to write otherwise, which is only recompressed if the profile changed it.
+    pub fn apply(&self, raw_chunk: RawChunk) -> Result<Option<ProfiledChunk>, io::Error> {
+        let chunk = raw_chunk.decompress()?;
+
+        if !self.status_filter.includes(&chunk.data)? {
+            return Ok(None);
+        }
+
+        if self.removed_tags.is_empty() {
+            return Ok(Some(ProfiledChunk::Unchanged(raw_chunk)));
+        }
+
+        let mut nbt = read_nbt(&chunk.data)?;
+        let removed: usize = self
+            .removed_tags
+            .iter()
+            .map(|path| path.remove(&mut nbt))
+            .sum();
+
+        if removed == 0 {
+            return Ok(Some(ProfiledChunk::Unchanged(raw_chunk)));
+        }
+
+        Ok(Some(ProfiledChunk::Changed(
+            chunk.with_data(write_nbt(&nbt)?.into_boxed_slice()),
+        )))
+    }
+}
+
+pub enum ProfiledChunk {
+    Unchanged(RawChunk),
+    Changed(Chunk),
+}
+
+fn invalid_data(message: Message) -> io::Error {
+    message.into_error(io::ErrorKind::InvalidData)
+}
@@ -0,0 +1,24 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::messages::{self, Message};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Prevents any file from being opened for writing for the rest of the process.
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::SeqCst);
+}
+
+/// Returns an error if read-only mode is enabled. This must be called before any file or directory is created or
+/// opened for writing, so that commands which would modify the filesystem fail before doing any work.
+pub fn ensure_writable(path: &Path) -> Result<(), io::Error> {
+    if READ_ONLY.load(Ordering::SeqCst) {
+        return Err(Message::new(messages::READ_ONLY_REFUSED)
+            .arg("path", path.display())
+            .into_error(io::ErrorKind::PermissionDenied));
+    }
+
+    Ok(())
+}
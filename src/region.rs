@@ -1,15 +1,36 @@
+use std::borrow::Cow;
+#[cfg(feature = "native")]
+use std::collections::BTreeMap;
 use std::convert::TryInto;
-use std::fs::{File, OpenOptions};
+#[cfg(feature = "native")]
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
-use std::io::{BufReader, SeekFrom};
-use std::path::Path;
-
-use byteorder::{BigEndian, ReadBytesExt};
+#[cfg(feature = "native")]
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "native")]
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use clap::ValueEnum;
 use flate2::read::{GzDecoder, ZlibDecoder};
-use flate2::write::ZlibEncoder;
+#[cfg(feature = "native")]
+use flate2::write::{GzEncoder, ZlibEncoder};
+#[cfg(feature = "native")]
 use flate2::Compression;
-use mapr::{Mmap, MmapMut};
+#[cfg(feature = "native")]
+use mapr::{Mmap, MmapMut, MmapOptions};
+
+#[cfg(feature = "native")]
+use crate::linear::LinearRegionFile;
+#[cfg(feature = "native")]
+use crate::messages::report;
+use crate::messages::{self, Message};
+#[cfg(feature = "native")]
+use crate::read_only;
 
 const ENTRY_COUNT: usize = 32 * 32;
 const ENTRY_LENGTH: usize = 4;
@@ -18,71 +39,249 @@ const HEADER_SIZE: usize = ENTRY_COUNT * ENTRY_LENGTH;
 
 const REGION_LOCATION_OFFSET: usize = 0;
 
+/// The table of when each chunk was last saved follows the table of their locations.
+const REGION_TIMESTAMP_OFFSET: usize = HEADER_SIZE;
+
 const SECTOR_SIZE: usize = 4096;
 const INITIAL_CAPACITY: usize = HEADER_SIZE * 2;
 
+/// The number of sectors at the start of every region file which hold its header rather than chunks.
+pub const HEADER_SECTORS: u32 = (INITIAL_CAPACITY / SECTOR_SIZE) as u32;
+
+/// The largest number of sectors which a chunk can occupy, since the count is stored in a single byte of its entry.
+#[cfg(feature = "native")]
+const MAX_SECTOR_COUNT: usize = 255;
+
+/// Set on the compression type of a chunk which is stored in an external `.mcc` file next to the region file, because
+/// it would not fit in the region file itself.
+const EXTERNAL_FLAG: u8 = 0x80;
+
+#[cfg(feature = "native")]
+static NO_MMAP: AtomicBool = AtomicBool::new(false);
+
+/// Makes every region file opened for the rest of the process be read with buffered I/O instead of being mapped into
+/// memory.
+#[cfg(feature = "native")]
+pub fn disable_mmap() {
+    NO_MMAP.store(true, Ordering::SeqCst);
+}
+
 pub struct RegionFile {
-    map: Mmap,
+    data: RegionData,
+    path: PathBuf,
+}
+
+/// The contents of a region file, which are either mapped into memory, read from the file as they are needed, or given
+/// as bytes which were read some other way.
+enum RegionData {
+    #[cfg(feature = "native")]
+    Mapped(Mmap),
+    #[cfg(feature = "native")]
+    Buffered {
+        file: File,
+        header: Box<[u8]>,
+    },
+    Bytes(Box<[u8]>),
 }
 
 impl RegionFile {
+    #[cfg(feature = "native")]
     pub fn open(path: &Path) -> Result<Self, io::Error> {
         let file = File::open(path)?;
 
-        let map = unsafe { Mmap::map(&file) }?;
+        let data = if NO_MMAP.load(Ordering::SeqCst) {
+            RegionFile::read_buffered(path, file)?
+        } else {
+            // Some network filesystems don't support mapping files, and those can still be read with buffered I/O
+            match unsafe { Mmap::map(&file) } {
+                Ok(map) => RegionData::Mapped(map),
+                Err(err) => {
+                    report!(
+                        debug,
+                        messages::REGION_MMAP_FAILED,
+                        path = path.display().to_string(),
+                        error = err.to_string()
+                    );
+
+                    RegionFile::read_buffered(path, file)?
+                }
+            }
+        };
+
+        if let RegionData::Mapped(map) = &data {
+            if map.len() < HEADER_SIZE {
+                return Err(truncated_header_error(path));
+            }
+        }
 
-        Ok(RegionFile { map })
+        Ok(RegionFile {
+            data,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Parses a region file whose contents were read some other way, such as a file uploaded to a browser. The path is
+    /// only used to name the region in errors and to find its position, so it needn't exist.
+    pub fn from_bytes(path: &Path, data: Vec<u8>) -> Result<Self, io::Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(truncated_header_error(path));
+        }
+
+        Ok(RegionFile {
+            data: RegionData::Bytes(data.into_boxed_slice()),
+            path: path.to_path_buf(),
+        })
+    }
+
+    #[cfg(feature = "native")]
+    fn read_buffered(path: &Path, file: File) -> Result<RegionData, io::Error> {
+        let mut header = vec![0u8; HEADER_SIZE].into_boxed_slice();
+
+        read_exact_at(&file, &mut header, 0).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => truncated_header_error(path),
+            _ => err,
+        })?;
+
+        Ok(RegionData::Buffered { file, header })
     }
 
     pub fn stream_chunks(&self) -> ChunkIterator {
         ChunkIterator::create(self)
     }
 
-    fn get_chunk_from_index(&self, index: usize) -> Result<Option<Chunk>, io::Error> {
+    /// Streams the chunks of the region without decompressing them.
+    pub fn stream_raw_chunks(&self) -> RawChunkIterator<'_> {
+        RawChunkIterator::create(self)
+    }
+
+    /// Returns when a chunk was last saved as seconds since the Unix epoch, given its position within the region, or
+    /// zero if the time wasn't recorded.
+    pub fn timestamp(&self, position: ChunkPos) -> Result<u32, io::Error> {
+        let entry_index = (position.x as usize % 32) + ((position.z as usize % 32) * 32);
+        let entry_offset = REGION_TIMESTAMP_OFFSET + (entry_index * 4);
+        let entry: [u8; 4] = self.read_at(position, entry_offset, 4)?[..]
+            .try_into()
+            .unwrap();
+
+        Ok(u32::from_be_bytes(entry))
+    }
+
+    /// Returns the location of every chunk which the region's header records, in the order of their entries.
+    pub fn entries(&self) -> Result<Vec<RegionEntry>, io::Error> {
+        (0..ENTRY_COUNT)
+            .filter_map(|index| self.read_entry(index).transpose())
+            .collect()
+    }
+
+    /// Returns the number of sectors in the file, counting a partial sector at the end of the file as a whole one.
+    pub fn sector_len(&self) -> Result<u32, io::Error> {
+        let length = match &self.data {
+            #[cfg(feature = "native")]
+            RegionData::Mapped(map) => map.len() as u64,
+            #[cfg(feature = "native")]
+            RegionData::Buffered { file, .. } => file.metadata()?.len(),
+            RegionData::Bytes(data) => data.len() as u64,
+        };
+
+        Ok(((length + SECTOR_SIZE as u64 - 1) / SECTOR_SIZE as u64) as u32)
+    }
+
+    /// Reads a chunk given its position within the region, without decompressing it, or `None` if the region doesn't
+    /// have the chunk.
+    pub fn get_raw_chunk(&self, position: ChunkPos) -> Result<Option<RawChunk>, io::Error> {
+        let index = (position.x as usize % 32) + ((position.z as usize % 32) * 32);
+
+        self.get_raw_chunk_from_index(index)
+    }
+
+    fn get_raw_chunk_from_index(&self, index: usize) -> Result<Option<RawChunk>, io::Error> {
         let entry = self.read_entry(index)?;
 
         match entry {
-            Some(entry) => self.get_chunk_from_entry(entry).map(|chunk| Some(chunk)),
+            Some(entry) => self.get_raw_chunk_from_entry(entry).map(Some),
             None => Ok(None),
         }
     }
 
-    fn get_chunk_from_entry(&self, entry: RegionEntry) -> Result<Chunk, io::Error> {
+    /// Reads the chunk stored at a location from the region's header, without decompressing it.
+    pub fn get_raw_chunk_from_entry(&self, entry: RegionEntry) -> Result<RawChunk, io::Error> {
         let offset = entry.sector_index as usize * SECTOR_SIZE;
         let length = entry.sector_count as usize * SECTOR_SIZE;
 
-        let mut reader =
-            BufReader::with_capacity(SECTOR_SIZE, &self.map[offset..(offset + length)]);
+        let length_field: [u8; 4] = self.read_at(entry.position, offset, 4)?[..]
+            .try_into()
+            .unwrap();
 
-        let exact_length = reader.read_u32::<BigEndian>()?;
+        // The payload can't extend past the sectors allocated to the chunk
+        let exact_length =
+            (u32::from_be_bytes(length_field) as usize).min(length.saturating_sub(4));
+        let payload = self
+            .read_at(entry.position, offset + 4, exact_length)?
+            .into_owned();
 
-        let mut data_stream = reader.take(exact_length as u64);
+        Ok(RawChunk {
+            payload: read_external_payload(&self.path, entry.position, payload)?.into_boxed_slice(),
+            position: entry.position.clone(),
+        })
+    }
 
-        let compression_mode_int = data_stream.read_u8()?;
-        let compression_mode =
-            CompressionMode::from_int(compression_mode_int).expect("Invalid compression type");
+    /// Reads the length and compression type recorded at the start of a chunk's sectors as they are, without checking
+    /// them against the sectors allocated to the chunk. This shows why a chunk can't be read.
+    pub fn read_chunk_header(&self, entry: RegionEntry) -> Result<(u32, u8), io::Error> {
+        let offset = entry.sector_index as usize * SECTOR_SIZE;
+        let header = self.read_at(entry.position, offset, 5)?;
 
-        let mut data_decompressed: Vec<u8> = Vec::new();
+        Ok((
+            u32::from_be_bytes(header[..4].try_into().unwrap()),
+            header[4],
+        ))
+    }
 
-        match compression_mode {
-            CompressionMode::Gzip => {
-                GzDecoder::new(data_stream).read_to_end(&mut data_decompressed)
-            }
-            CompressionMode::Zlib => {
-                ZlibDecoder::new(data_stream).read_to_end(&mut data_decompressed)
+    /// Reads the given range of the file, returning an error instead of panicking if the range extends past the end of
+    /// a truncated file.
+    fn read_at(
+        &self,
+        position: ChunkPos,
+        offset: usize,
+        length: usize,
+    ) -> Result<Cow<'_, [u8]>, io::Error> {
+        match &self.data {
+            #[cfg(feature = "native")]
+            RegionData::Mapped(map) => map
+                .get(offset..(offset + length))
+                .map(Cow::Borrowed)
+                .ok_or_else(|| truncated_chunk_error(&self.path, position)),
+            RegionData::Bytes(data) => data
+                .get(offset..(offset + length))
+                .map(Cow::Borrowed)
+                .ok_or_else(|| truncated_chunk_error(&self.path, position)),
+            #[cfg(feature = "native")]
+            RegionData::Buffered { file, .. } => {
+                let mut data = vec![0u8; length];
+
+                read_exact_at(file, &mut data, offset as u64).map_err(|err| match err.kind() {
+                    io::ErrorKind::UnexpectedEof => truncated_chunk_error(&self.path, position),
+                    _ => err,
+                })?;
+
+                Ok(Cow::Owned(data))
             }
-            CompressionMode::Uncompressed => data_stream.read_to_end(&mut data_decompressed),
-        }?;
+        }
+    }
 
-        Ok(Chunk {
-            data: data_decompressed.into_boxed_slice(),
-            position: entry.position.clone(),
-        })
+    fn header(&self) -> &[u8] {
+        match &self.data {
+            #[cfg(feature = "native")]
+            RegionData::Mapped(map) => &map[..HEADER_SIZE],
+            #[cfg(feature = "native")]
+            RegionData::Buffered { header, .. } => header,
+            RegionData::Bytes(data) => &data[..HEADER_SIZE],
+        }
     }
 
     fn read_entry(&self, entry_index: usize) -> Result<Option<RegionEntry>, io::Error> {
         let entry_offset = REGION_LOCATION_OFFSET + (entry_index * 4);
-        let entry: [u8; 4] = self.map[entry_offset..(entry_offset + 4)]
+        let entry: [u8; 4] = self.header()[entry_offset..(entry_offset + 4)]
             .try_into()
             .unwrap();
 
@@ -122,17 +321,202 @@ impl RegionFile {
             .expect("Failed to parse z-coordinate");
         ChunkPos { x, z }
     }
+
+    /// Checks whether a file name is that of a region file, which excludes the external chunk (`.mcc`) files stored next
+    /// to them.
+    pub fn is_region_name(name: &str) -> bool {
+        name.ends_with(".mca") && RegionFile::try_parse_name(name).is_some()
+    }
+
+    /// Parses the coordinates out of a region or chunk name such as `r.-1.2.mca`, returning `None` if the name is
+    /// malformed.
+    pub fn try_parse_name(name: &str) -> Option<ChunkPos> {
+        let mut values = name.split('.').skip(1);
+
+        let x = values.next()?.parse::<i32>().ok()?;
+        let z = values.next()?.parse::<i32>().ok()?;
+
+        Some(ChunkPos { x, z })
+    }
+}
+
+/// A region file to read chunks from, which is either an Anvil region file written by Minecraft or a Linear region file
+/// written by some server forks, told apart by its extension. MCRegion files from before 1.2 share the layout of Anvil
+/// region files and are read in the same way, though their chunks still hold blocks in the pre-Anvil format.
+#[cfg(feature = "native")]
+pub enum AnyRegionFile {
+    Anvil(RegionFile),
+    Linear(LinearRegionFile),
 }
 
+#[cfg(feature = "native")]
+impl AnyRegionFile {
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        if LinearRegionFile::is_region_name(&name) {
+            LinearRegionFile::open(path).map(AnyRegionFile::Linear)
+        } else {
+            RegionFile::open(path).map(AnyRegionFile::Anvil)
+        }
+    }
+
+    pub fn stream_raw_chunks(
+        &self,
+    ) -> Box<dyn Iterator<Item = Result<Option<RawChunk>, io::Error>> + '_> {
+        match self {
+            AnyRegionFile::Anvil(region) => Box::new(region.stream_raw_chunks()),
+            AnyRegionFile::Linear(region) => Box::new(region.stream_raw_chunks()),
+        }
+    }
+
+    pub fn timestamp(&self, position: ChunkPos) -> Result<u32, io::Error> {
+        match self {
+            AnyRegionFile::Anvil(region) => region.timestamp(position),
+            AnyRegionFile::Linear(region) => Ok(region.timestamp(position)),
+        }
+    }
+
+    /// Whether the raw chunks of the region are compressed in the way Minecraft stores them, so that they can be
+    /// copied into an Anvil region file as they are.
+    pub fn has_compressed_chunks(&self) -> bool {
+        matches!(self, AnyRegionFile::Anvil(_))
+    }
+
+    /// Checks whether a file name is that of a region file in any of the formats.
+    pub fn is_region_name(name: &str) -> bool {
+        RegionFile::is_region_name(name)
+            || LinearRegionFile::is_region_name(name)
+            || is_mcregion_name(name)
+    }
+
+    /// Counts the chunks stored in a region file from its header, without reading or decompressing any chunks.
+    pub fn count_chunks(path: &Path) -> Result<u64, io::Error> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        if LinearRegionFile::is_region_name(&name) {
+            return LinearRegionFile::count_chunks(path);
+        }
+
+        // Empty region files are left behind by the game, so a short header counts as having no chunks past its end
+        let mut locations = Vec::with_capacity(ENTRY_COUNT * 4);
+        File::open(path)?
+            .take((ENTRY_COUNT * 4) as u64)
+            .read_to_end(&mut locations)?;
+
+        Ok(locations
+            .chunks_exact(4)
+            .filter(|entry| entry.iter().any(|&byte| byte != 0))
+            .count() as u64)
+    }
+
+    /// Checks whether a region file has been replaced by a newer one for the same region. The game converts MCRegion
+    /// files to Anvil without deleting them, so an MCRegion file next to an Anvil file of the same region is stale.
+    pub fn is_superseded(path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        is_mcregion_name(&name) && path.with_extension("mca").is_file()
+    }
+}
+
+/// Checks whether a file name is that of an MCRegion file, such as `r.-1.2.mcr`.
+#[cfg(feature = "native")]
+fn is_mcregion_name(name: &str) -> bool {
+    name.ends_with(".mcr") && RegionFile::try_parse_name(name).is_some()
+}
+
+/// The compression of chunks written to a region file, which may be any of the types that Minecraft can read.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ChunkCompression {
+    /// zlib, which is what Minecraft writes
+    Zlib,
+    /// gzip, which Minecraft reads but never writes
+    Gzip,
+    /// No compression, which Minecraft reads but never writes
+    None,
+}
+
+/// The order in which a writer lays out the chunks of a region in its sectors.
+#[cfg(feature = "native")]
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ChunkLayout {
+    /// In the order the chunks are added
+    Arrival,
+    /// Along a Hilbert curve through the region, so chunks which are near each other are near each other in the file
+    Hilbert,
+    /// Row by row, in the same order as the region's header
+    RowMajor,
+}
+
+#[cfg(feature = "native")]
+impl ChunkLayout {
+    /// Returns where a chunk falls in the layout, given its position within the region.
+    fn order(self, position: ChunkPos) -> u32 {
+        let x = (position.x as u32) % 32;
+        let z = (position.z as u32) % 32;
+
+        match self {
+            ChunkLayout::Arrival => 0,
+            ChunkLayout::Hilbert => hilbert_index(x, z),
+            ChunkLayout::RowMajor => x + (z * 32),
+        }
+    }
+}
+
+/// Returns the distance along a Hilbert curve which fills the 32x32 chunks of a region to a chunk within it.
+#[cfg(feature = "native")]
+fn hilbert_index(mut x: u32, mut z: u32) -> u32 {
+    let mut index = 0;
+    let mut size = 16;
+
+    while size > 0 {
+        let rx = (x & size > 0) as u32;
+        let rz = (z & size > 0) as u32;
+
+        index += size * size * ((3 * rx) ^ rz);
+
+        // Rotates the quadrant so the curve through it joins up with the next one
+        if rz == 0 {
+            if rx == 1 {
+                x = 31 - x;
+                z = 31 - z;
+            }
+
+            std::mem::swap(&mut x, &mut z);
+        }
+
+        size /= 2;
+    }
+
+    index
+}
+
+#[cfg(feature = "native")]
 pub struct RegionFileWriter {
     file: File,
+    path: PathBuf,
     header_map: MmapMut,
     used_sectors: usize,
     capacity: usize,
+    compression: ChunkCompression,
+    compression_level: u32,
+    layout: ChunkLayout,
+    /// Chunks which are held back until the writer is flushed, keyed by their place in the layout.
+    pending: BTreeMap<u32, (ChunkPos, Box<[u8]>)>,
+    /// Ranges of sectors before the last one in use which no chunk uses, as their first sector and number of sectors,
+    /// sorted and with adjacent ranges merged.
+    free_sectors: Vec<(usize, usize)>,
+    /// Whether the sectors of replaced chunks can be freed, which is only safe if no two chunks share sectors.
+    reuse_sectors: bool,
+    /// Whether `finish` has been called, so that dropping the writer has nothing left to do.
+    finished: bool,
 }
 
+#[cfg(feature = "native")]
 impl RegionFileWriter {
     pub fn create(path: &Path) -> Result<Self, io::Error> {
+        read_only::ensure_writable(path)?;
+
         let capacity = INITIAL_CAPACITY;
 
         let file = OpenOptions::new()
@@ -148,30 +532,350 @@ impl RegionFileWriter {
 
         Ok(RegionFileWriter {
             file,
+            path: path.to_path_buf(),
             header_map: map,
             used_sectors: 2,
             capacity,
+            compression: ChunkCompression::Zlib,
+            compression_level: Compression::best().level(),
+            layout: ChunkLayout::Arrival,
+            pending: BTreeMap::new(),
+            free_sectors: Vec::new(),
+            reuse_sectors: true,
+            finished: false,
+        })
+    }
+
+    /// Opens an existing region file for writing without truncating it. Chunks which are already present are kept, and
+    /// any chunks added are written into the gaps between them where they fit, or after the last sector in use. A chunk
+    /// which replaces an existing one frees the sectors of the old copy, unless the region has chunks which share
+    /// sectors, in which case nothing is freed.
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        read_only::ensure_writable(path)?;
+
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut capacity = file.metadata()?.len() as usize;
+
+        if capacity < INITIAL_CAPACITY {
+            file.set_len(INITIAL_CAPACITY as u64)?;
+            capacity = INITIAL_CAPACITY;
+        }
+
+        let map = unsafe { MmapOptions::new().len(INITIAL_CAPACITY).map_mut(&file) }?;
+
+        // The sectors of the existing chunks, from which the gaps between them are found
+        let mut used_ranges: Vec<(usize, usize)> = (0..ENTRY_COUNT)
+            .filter_map(|entry_index| {
+                let entry_offset = REGION_LOCATION_OFFSET + (entry_index * 4);
+                let entry: [u8; 4] = map[entry_offset..(entry_offset + 4)].try_into().unwrap();
+                let entry_field = u32::from_be_bytes(entry);
+
+                match entry_field {
+                    0 => None,
+                    _ => Some((
+                        ((entry_field >> 8) & 0xFFFFFF) as usize,
+                        (entry_field & 0xFF) as usize,
+                    )),
+                }
+            })
+            .collect();
+        used_ranges.sort_unstable();
+
+        let mut used_sectors = HEADER_SECTORS as usize;
+        let mut free_sectors = Vec::new();
+        let mut reuse_sectors = true;
+
+        for (sector_index, sector_count) in used_ranges {
+            if sector_index < used_sectors || sector_count == 0 {
+                // Freeing the sectors of a chunk which shares them with another would let the other be overwritten
+                reuse_sectors = false;
+            } else if sector_index > used_sectors {
+                free_sectors.push((used_sectors, sector_index - used_sectors));
+            }
+
+            used_sectors = used_sectors.max(sector_index + sector_count);
+        }
+
+        if !reuse_sectors {
+            free_sectors.clear();
+        }
+
+        Ok(RegionFileWriter {
+            file,
+            path: path.to_path_buf(),
+            header_map: map,
+            used_sectors,
+            capacity,
+            compression: ChunkCompression::Zlib,
+            compression_level: Compression::best().level(),
+            layout: ChunkLayout::Arrival,
+            pending: BTreeMap::new(),
+            free_sectors,
+            reuse_sectors,
+            finished: false,
         })
     }
 
+    /// Sets how the chunks added with `add_chunk` are compressed, given a level from 0 to 9 for the types which have
+    /// levels. Chunks are compressed with zlib at the best level by default.
+    pub fn set_compression(&mut self, compression: ChunkCompression, level: u32) {
+        self.compression = compression;
+        self.compression_level = level;
+    }
+
+    /// Sets the order in which chunks are laid out in the region's sectors. Unless chunks are laid out in the order
+    /// they arrive, they are held in memory until the writer is flushed or dropped, and then written in a fixed order
+    /// no matter the order they were added in.
+    pub fn set_layout(&mut self, layout: ChunkLayout) {
+        self.layout = layout;
+    }
+
+    /// Writes the chunks which are held back by the layout. Chunks added afterwards are written after them.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        for (_, (position, payload)) in std::mem::take(&mut self.pending) {
+            self.write_payload(position, &payload)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the chunks which are held back, cuts off the sectors freed at the end of the file and flushes the header
+    /// to disk. Writers should always be finished, since dropping one can only log the errors it runs into.
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        self.finished = true;
+        self.finish_writing()
+    }
+
+    fn finish_writing(&mut self) -> Result<(), io::Error> {
+        self.flush()?;
+
+        // Sectors freed at the end of the file are cut off, along with any partial sector left by another tool
+        let used_len = self.used_sectors * SECTOR_SIZE;
+
+        if used_len != self.capacity {
+            self.file.set_len(used_len as u64)?;
+            self.capacity = used_len;
+        }
+
+        self.header_map.flush()?;
+        self.file.flush()
+    }
+
     pub fn add_chunk(&mut self, chunk: &Chunk) -> Result<(), io::Error> {
-        let data = RegionFileWriter::create_chunk_data_stream(&chunk.data[..])?;
+        let payload = self.create_compressed_chunk_payload(&chunk.data[..])?;
 
-        let sector_count = (data.len() + SECTOR_SIZE - 1) / SECTOR_SIZE;
-        let sector_index = self.used_sectors;
+        self.add_payload(chunk.position, &payload)
+    }
 
-        self.write_data(sector_index, sector_count, &data[..])?;
-        self.write_entry(RegionEntry {
-            position: chunk.position,
-            sector_index: sector_index as u32,
-            sector_count: sector_count as u32,
-        })?;
+    /// Adds a chunk which is already compressed, copying its payload verbatim.
+    pub fn add_raw_chunk(&mut self, chunk: &RawChunk) -> Result<(), io::Error> {
+        self.add_payload(chunk.position, &chunk.payload)
+    }
+
+    /// Records when a chunk was last saved, as seconds since the Unix epoch.
+    pub fn set_timestamp(&mut self, position: ChunkPos, timestamp: u32) {
+        let entry_index = (position.x as usize % 32) + ((position.z as usize % 32) * 32);
+        let entry_offset = REGION_TIMESTAMP_OFFSET + (entry_index * 4);
+
+        self.header_map[entry_offset..(entry_offset + 4)].copy_from_slice(&timestamp.to_be_bytes());
+    }
+
+    /// Reads back the payload of a chunk which has already been written to the region, without decompressing it.
+    pub fn read_raw_chunk(&mut self, position: ChunkPos) -> Result<Option<RawChunk>, io::Error> {
+        if let Some((_, payload)) = self.pending.get(&self.layout.order(position)) {
+            return Ok(Some(RawChunk {
+                payload: payload.clone(),
+                position,
+            }));
+        }
+
+        let sector_index = match self.entry(position) {
+            Some(entry) => entry.sector_index as usize,
+            None => return Ok(None),
+        };
+
+        self.file
+            .seek(SeekFrom::Start((sector_index * SECTOR_SIZE) as u64))?;
+
+        let exact_length = self.file.read_u32::<BigEndian>()?;
+
+        let mut payload: Vec<u8> = Vec::with_capacity(exact_length as usize);
+        (&mut self.file)
+            .take(exact_length as u64)
+            .read_to_end(&mut payload)?;
+
+        Ok(Some(RawChunk {
+            payload: read_external_payload(&self.path, position, payload)?.into_boxed_slice(),
+            position,
+        }))
+    }
+
+    fn add_payload(&mut self, position: ChunkPos, payload: &[u8]) -> Result<(), io::Error> {
+        if self.layout == ChunkLayout::Arrival {
+            return self.write_payload(position, payload);
+        }
 
-        self.used_sectors += sector_count as usize;
+        // A chunk which is added again replaces the copy which is still held back
+        self.pending
+            .insert(self.layout.order(position), (position, payload.into()));
 
         Ok(())
     }
 
+    /// Writes a chunk into the region, replacing any copy which is already there. The old copy stays where it is until
+    /// the new one has been written and the header points at it, so that a failed write can be retried without the
+    /// old sectors having been freed twice, and sectors taken by a failed write are given back.
+    fn write_payload(&mut self, position: ChunkPos, payload: &[u8]) -> Result<(), io::Error> {
+        let replaced = self.entry(position);
+
+        let mut data = RegionFileWriter::create_chunk_data_stream(payload);
+        let mut sector_count = (data.len() + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        let mut external = false;
+
+        // Like the game, chunks which are too large for the region file are moved into their own file, leaving only
+        // their compression type behind
+        if sector_count > MAX_SECTOR_COUNT {
+            let external_path = external_chunk_path(&self.path, position).ok_or_else(|| {
+                Message::new(messages::REGION_TOO_LARGE)
+                    .arg("x", position.x)
+                    .arg("z", position.z)
+                    .arg("sectors", sector_count)
+                    .arg("path", self.path.display())
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+            read_only::ensure_writable(&external_path)?;
+            fs::write(&external_path, &payload[1..])?;
+
+            data = RegionFileWriter::create_chunk_data_stream(&[payload[0] | EXTERNAL_FLAG]);
+            sector_count = 1;
+            external = true;
+        }
+
+        let sector_index = self.allocate_sectors(sector_count);
+
+        let written = self
+            .write_data(sector_index, sector_count, &data[..])
+            .and_then(|_| {
+                self.write_entry(RegionEntry {
+                    position,
+                    sector_index: sector_index as u32,
+                    sector_count: sector_count as u32,
+                })
+            });
+
+        if let Err(err) = written {
+            self.release_sectors(sector_index, sector_count);
+            return Err(err);
+        }
+
+        if let Some(replaced) = replaced {
+            if self.reuse_sectors {
+                self.release_sectors(
+                    replaced.sector_index as usize,
+                    replaced.sector_count as usize,
+                );
+            }
+
+            // Like the game, the external file of a replaced chunk is deleted once the chunk fits in the region again
+            if !external {
+                if let Some(external_path) = external_chunk_path(&self.path, position) {
+                    if external_path.exists() {
+                        fs::remove_file(&external_path)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the location of a chunk which has already been written to the region, if it has been.
+    fn entry(&self, position: ChunkPos) -> Option<RegionEntry> {
+        let entry_index = (position.x % 32) + ((position.z % 32) * 32);
+        let entry_offset = REGION_LOCATION_OFFSET + (entry_index as usize * 4);
+        let entry: [u8; 4] = self.header_map[entry_offset..(entry_offset + 4)]
+            .try_into()
+            .unwrap();
+
+        let entry_field = u32::from_be_bytes(entry);
+
+        if entry_field == 0 {
+            return None;
+        }
+
+        Some(RegionEntry {
+            position,
+            sector_index: (entry_field >> 8) & 0xFFFFFF,
+            sector_count: entry_field & 0xFF,
+        })
+    }
+
+    /// Finds room for a chunk of the given number of sectors, taking the first free range which it fits in, or the
+    /// sectors after the last one in use if there is none.
+    fn allocate_sectors(&mut self, sector_count: usize) -> usize {
+        let fitting = self
+            .free_sectors
+            .iter()
+            .position(|&(_, free_count)| free_count >= sector_count);
+
+        match fitting {
+            Some(range_index) => {
+                let (sector_index, free_count) = self.free_sectors[range_index];
+
+                if free_count == sector_count {
+                    self.free_sectors.remove(range_index);
+                } else {
+                    self.free_sectors[range_index] =
+                        (sector_index + sector_count, free_count - sector_count);
+                }
+
+                sector_index
+            }
+            None => {
+                let sector_index = self.used_sectors;
+                self.used_sectors += sector_count;
+
+                sector_index
+            }
+        }
+    }
+
+    /// Frees the sectors of a chunk which was replaced, merging them with the free ranges on either side. Sectors at
+    /// the end of the file are given back entirely, so the file shrinks when the writer is dropped.
+    fn release_sectors(&mut self, sector_index: usize, sector_count: usize) {
+        let range_index = self
+            .free_sectors
+            .partition_point(|&(free_index, _)| free_index < sector_index);
+
+        self.free_sectors
+            .insert(range_index, (sector_index, sector_count));
+
+        if let Some(&(next_index, next_count)) = self.free_sectors.get(range_index + 1) {
+            if sector_index + sector_count == next_index {
+                self.free_sectors[range_index].1 += next_count;
+                self.free_sectors.remove(range_index + 1);
+            }
+        }
+
+        if range_index > 0 {
+            let (previous_index, previous_count) = self.free_sectors[range_index - 1];
+
+            if previous_index + previous_count == sector_index {
+                self.free_sectors[range_index - 1].1 += self.free_sectors[range_index].1;
+                self.free_sectors.remove(range_index);
+            }
+        }
+
+        if let Some(&(last_index, last_count)) = self.free_sectors.last() {
+            if last_index + last_count == self.used_sectors {
+                self.used_sectors = last_index;
+                self.free_sectors.pop();
+            }
+        }
+    }
+
     fn write_data(
         &mut self,
         sector_index: usize,
@@ -205,33 +909,155 @@ impl RegionFileWriter {
         Ok(())
     }
 
-    fn create_chunk_data_stream(chunk_data: &[u8]) -> Result<Vec<u8>, io::Error> {
-        let payload = RegionFileWriter::create_compressed_chunk_payload(chunk_data)?;
-
+    fn create_chunk_data_stream(payload: &[u8]) -> Vec<u8> {
         let mut header = [0u8; 4];
         header[0..4].copy_from_slice(&u32::to_be_bytes(payload.len() as u32));
 
         let mut data: Vec<u8> = Vec::with_capacity(header.len() + payload.len());
         data.extend_from_slice(&header);
-        data.extend_from_slice(&payload);
+        data.extend_from_slice(payload);
+
+        data
+    }
+
+    fn create_compressed_chunk_payload(&self, payload: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let level = Compression::new(self.compression_level);
+
+        match self.compression {
+            ChunkCompression::Zlib => {
+                let vec = vec![CompressionMode::Zlib.to_int()];
+
+                let mut payload_encoder = ZlibEncoder::new(vec, level);
+                payload_encoder.write_all(payload)?;
+                payload_encoder.finish()
+            }
+            ChunkCompression::Gzip => {
+                let vec = vec![CompressionMode::Gzip.to_int()];
+
+                let mut payload_encoder = GzEncoder::new(vec, level);
+                payload_encoder.write_all(payload)?;
+                payload_encoder.finish()
+            }
+            ChunkCompression::None => {
+                let mut vec = Vec::with_capacity(payload.len() + 1);
+                vec.push(CompressionMode::Uncompressed.to_int());
+                vec.extend_from_slice(payload);
+
+                Ok(vec)
+            }
+        }
+    }
+}
+
+fn truncated_header_error(path: &Path) -> io::Error {
+    Message::new(messages::REGION_TRUNCATED_HEADER)
+        .arg("path", path.display())
+        .into_error(io::ErrorKind::UnexpectedEof)
+}
+
+fn truncated_chunk_error(path: &Path, position: ChunkPos) -> io::Error {
+    Message::new(messages::REGION_TRUNCATED_CHUNK)
+        .arg("x", position.x)
+        .arg("z", position.z)
+        .arg("path", path.display())
+        .into_error(io::ErrorKind::UnexpectedEof)
+}
 
-        Ok(data)
+/// Fills the buffer from the given offset of a file without moving its cursor, so that the file can be shared between
+/// threads.
+#[cfg(all(feature = "native", unix))]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> Result<(), io::Error> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "native", windows))]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> Result<(), io::Error> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
     }
 
-    fn create_compressed_chunk_payload(payload: &[u8]) -> Result<Vec<u8>, io::Error> {
-        let mut vec = Vec::new();
-        vec.push(CompressionMode::Zlib.to_int());
+    Ok(())
+}
+
+/// Returns the path of the external file which stores a chunk that is too large for its region file, which is named
+/// after the absolute coordinates of the chunk, or `None` if the region file's name doesn't give its position.
+fn external_chunk_path(region_path: &Path, position: ChunkPos) -> Option<PathBuf> {
+    let region = RegionFile::try_parse_name(&region_path.file_name()?.to_string_lossy())?;
 
-        let mut payload_encoder = ZlibEncoder::new(vec, Compression::best());
-        payload_encoder.write_all(payload)?;
-        payload_encoder.finish()
+    Some(region_path.with_file_name(format!(
+        "c.{}.{}.mcc",
+        (region.x * 32) + position.x.rem_euclid(32),
+        (region.z * 32) + position.z.rem_euclid(32)
+    )))
+}
+
+/// Replaces the payload of a chunk which is stored in an external file with the contents of that file, so that the
+/// chunk can be handled like any other. Other payloads are returned unchanged.
+fn read_external_payload(
+    region_path: &Path,
+    position: ChunkPos,
+    payload: Vec<u8>,
+) -> Result<Vec<u8>, io::Error> {
+    match payload.first() {
+        Some(&compression) if compression & EXTERNAL_FLAG != 0 => {
+            let external_path = external_chunk_path(region_path, position).ok_or_else(|| {
+                Message::new(messages::REGION_EXTERNAL_UNKNOWN)
+                    .arg("x", position.x)
+                    .arg("z", position.z)
+                    .arg("path", region_path.display())
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+            read_external_file(&external_path, compression & !EXTERNAL_FLAG)
+        }
+        _ => Ok(payload),
     }
 }
 
+/// Reads an external chunk file, returning the payload of the chunk it holds.
+#[cfg(feature = "native")]
+fn read_external_file(external_path: &Path, compression: u8) -> Result<Vec<u8>, io::Error> {
+    let mut external_payload = vec![compression];
+    File::open(external_path)?.read_to_end(&mut external_payload)?;
+
+    Ok(external_payload)
+}
+
+/// Without the file system, the chunks stored in external files can't be read along with their region.
+#[cfg(not(feature = "native"))]
+fn read_external_file(external_path: &Path, _compression: u8) -> Result<Vec<u8>, io::Error> {
+    Err(Message::new(messages::REGION_EXTERNAL_UNAVAILABLE)
+        .arg("path", external_path.display())
+        .into_error(io::ErrorKind::Unsupported))
+}
+
+#[cfg(feature = "native")]
 impl Drop for RegionFileWriter {
+    /// Finishes a writer which wasn't finished explicitly, such as when a command fails part of the way through. Errors
+    /// can't be returned from here, and panicking while another panic unwinds would abort, so they are only logged.
     fn drop(&mut self) {
-        self.header_map.flush().unwrap();
-        self.file.flush().unwrap();
+        if self.finished {
+            return;
+        }
+
+        if let Err(err) = self.finish_writing() {
+            report!(
+                error,
+                messages::REGION_FINISH_FAILED,
+                path = self.path.display().to_string(),
+                error = err.to_string()
+            );
+        }
     }
 }
 
@@ -260,12 +1086,115 @@ impl CompressionMode {
     }
 }
 
+/// Names a compression type as stored in region files, noting whether the chunk is stored in an external file.
+pub fn compression_name(compression: u8) -> String {
+    let name = match compression & !EXTERNAL_FLAG {
+        1 => "gzip",
+        2 => "zlib",
+        3 => "none",
+        4 => "lz4",
+        127 => "custom",
+        _ => return format!("unknown ({})", compression),
+    };
+
+    if compression & EXTERNAL_FLAG != 0 {
+        format!("{}, external", name)
+    } else {
+        name.to_owned()
+    }
+}
+
 #[derive(Clone)]
 pub struct Chunk {
     pub data: Box<[u8]>,
     pub position: ChunkPos,
 }
 
+/// A chunk's payload as it is stored in a region file, which is a byte giving the compression type followed by the
+/// compressed NBT data.
+pub struct RawChunk {
+    pub payload: Box<[u8]>,
+    pub position: ChunkPos,
+}
+
+impl RawChunk {
+    /// Creates a chunk from its compression type and its data compressed with it.
+    pub fn from_parts(position: ChunkPos, compression: u8, data: &[u8]) -> Self {
+        let mut payload = Vec::with_capacity(data.len() + 1);
+        payload.push(compression);
+        payload.extend_from_slice(data);
+
+        RawChunk {
+            payload: payload.into_boxed_slice(),
+            position,
+        }
+    }
+
+    /// Creates a chunk whose NBT data is stored without compression.
+    pub fn uncompressed(position: ChunkPos, data: &[u8]) -> Self {
+        RawChunk::from_parts(position, CompressionMode::Uncompressed.to_int(), data)
+    }
+
+    /// Returns the compression type of the chunk, as stored in region files.
+    pub fn compression(&self) -> u8 {
+        self.payload.first().copied().unwrap_or(0)
+    }
+
+    /// Returns the chunk's NBT data as it is compressed, without the compression type.
+    pub fn compressed_data(&self) -> &[u8] {
+        self.payload.get(1..).unwrap_or(&[])
+    }
+
+    pub fn decompress(&self) -> Result<Chunk, io::Error> {
+        let mut data_decompressed: Vec<u8> = Vec::new();
+        self.decompressed_reader()?
+            .read_to_end(&mut data_decompressed)?;
+
+        Ok(Chunk {
+            data: data_decompressed.into_boxed_slice(),
+            position: self.position,
+        })
+    }
+
+    /// Decompresses the chunk's NBT data, unless it is larger than the limit, in which case nothing is returned.
+    pub fn decompress_limited(&self, limit: u64) -> Result<Option<Chunk>, io::Error> {
+        let mut data_decompressed: Vec<u8> = Vec::new();
+        self.decompressed_reader()?
+            .take(limit + 1)
+            .read_to_end(&mut data_decompressed)?;
+
+        if data_decompressed.len() as u64 > limit {
+            return Ok(None);
+        }
+
+        Ok(Some(Chunk {
+            data: data_decompressed.into_boxed_slice(),
+            position: self.position,
+        }))
+    }
+
+    /// Returns a reader which decompresses the chunk's NBT data as it is read, rather than all at once.
+    pub fn decompressed_reader(&self) -> Result<Box<dyn Read + '_>, io::Error> {
+        let mut data_stream = &self.payload[..];
+
+        let compression_mode_int = data_stream.read_u8()?;
+        let compression_mode =
+            CompressionMode::from_int(compression_mode_int).ok_or_else(|| {
+                Message::new(messages::REGION_UNKNOWN_COMPRESSION)
+                    .arg("compression", compression_mode_int)
+                    .arg("x", self.position.x)
+                    .arg("z", self.position.z)
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+        Ok(match compression_mode {
+            CompressionMode::Gzip => Box::new(GzDecoder::new(data_stream)),
+            CompressionMode::Zlib => Box::new(ZlibDecoder::new(data_stream)),
+            CompressionMode::Uncompressed => Box::new(data_stream),
+        })
+    }
+}
+
 impl Chunk {
     pub fn with_data(&self, data: Box<[u8]>) -> Self {
         Chunk {
@@ -281,32 +1210,57 @@ pub struct ChunkPos {
     pub z: i32,
 }
 
+/// The location of a chunk within a region file, as recorded in the region's header.
 #[derive(Copy, Clone, Debug)]
 pub struct RegionEntry {
-    position: ChunkPos,
-    sector_index: u32,
-    sector_count: u32,
+    pub position: ChunkPos,
+    /// The first sector used by the chunk, counted from the start of the file.
+    pub sector_index: u32,
+    pub sector_count: u32,
 }
 
 pub struct ChunkIterator<'a> {
-    region: &'a RegionFile,
-    index: usize,
+    raw: RawChunkIterator<'a>,
 }
 
 impl<'a> ChunkIterator<'a> {
     fn create(region: &'a RegionFile) -> ChunkIterator<'a> {
-        ChunkIterator { region, index: 0 }
+        ChunkIterator {
+            raw: RawChunkIterator::create(region),
+        }
     }
 }
 
 impl<'a> Iterator for ChunkIterator<'a> {
     type Item = Result<Option<Chunk>, io::Error>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        self.raw.next().map(|result| match result? {
+            Some(raw_chunk) => raw_chunk.decompress().map(Some),
+            None => Ok(None),
+        })
+    }
+}
+
+pub struct RawChunkIterator<'a> {
+    region: &'a RegionFile,
+    index: usize,
+}
+
+impl<'a> RawChunkIterator<'a> {
+    fn create(region: &'a RegionFile) -> RawChunkIterator<'a> {
+        RawChunkIterator { region, index: 0 }
+    }
+}
+
+impl<'a> Iterator for RawChunkIterator<'a> {
+    type Item = Result<Option<RawChunk>, io::Error>;
+
     fn next(&mut self) -> Option<Self::Item> {
         if self.index >= ENTRY_COUNT {
             None
         } else {
-            let result = self.region.get_chunk_from_index(self.index);
+            let result = self.region.get_raw_chunk_from_index(self.index);
 
             self.index += 1;
 
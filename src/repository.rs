@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::archive::{chunk_entry_path, parse_chunk_entry_path};
+use crate::messages::{self, Message};
+use crate::read_only;
+use crate::region::ChunkPos;
+
+/// The contents of a repository's `config` file, which marks a directory as a repository and gives the version of its
+/// layout.
+const CONFIG_HEADER: &str = "anvil-tools repository";
+const VERSION: u32 = 1;
+
+const CONFIG_FILE: &str = "config";
+const BLOBS_DIR: &str = "blobs";
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// The extension of files which are still being written, and are renamed into place once complete so that an
+/// interrupted backup never leaves behind a blob or snapshot which looks whole.
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// A directory of chunks stored by their contents, and of snapshots listing which chunks made up a world when it was
+/// backed up. Each chunk is stored once as a blob named by the hash of its NBT data, so chunks which haven't changed
+/// between backups, or which are identical in several worlds, share their storage.
+///
+/// Blobs hold the chunk's payload as it was stored in its region file, so they are restored without recompressing them.
+/// They are kept in `blobs/<first two digits of the hash>/<hash>`, and snapshots in `snapshots/<id>`, where the ID is
+/// the time the snapshot was taken in seconds since the Unix epoch.
+pub struct Repository {
+    path: PathBuf,
+}
+
+impl Repository {
+    /// Creates an empty repository in a directory, which may already exist but must not hold a repository.
+    pub fn init(path: &Path) -> Result<Self, io::Error> {
+        read_only::ensure_writable(path)?;
+
+        let config_path = path.join(CONFIG_FILE);
+
+        if Path::exists(&config_path) {
+            return Err(Message::new(messages::REPO_EXISTS)
+                .arg("path", path.display())
+                .into_error(io::ErrorKind::AlreadyExists));
+        }
+
+        fs::create_dir_all(path.join(BLOBS_DIR))?;
+        fs::create_dir_all(path.join(SNAPSHOTS_DIR))?;
+        fs::write(config_path, format!("{} {}\n", CONFIG_HEADER, VERSION))?;
+
+        Ok(Repository {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn open(path: &Path) -> Result<Self, io::Error> {
+        let config = match fs::read_to_string(path.join(CONFIG_FILE)) {
+            Ok(config) => config,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Message::new(messages::REPO_NOT_FOUND)
+                    .arg("path", path.display())
+                    .into_error(io::ErrorKind::NotFound));
+            }
+            Err(err) => return Err(err),
+        };
+
+        let version = config
+            .trim()
+            .strip_prefix(CONFIG_HEADER)
+            .map(str::trim)
+            .ok_or_else(|| {
+                Message::new(messages::REPO_NOT_FOUND)
+                    .arg("path", path.display())
+                    .into_error(io::ErrorKind::InvalidData)
+            })?;
+
+        if version != VERSION.to_string() {
+            return Err(Message::new(messages::REPO_UNSUPPORTED_VERSION)
+                .arg("path", path.display())
+                .arg("version", version)
+                .into_error(io::ErrorKind::InvalidData));
+        }
+
+        Ok(Repository {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn blob_path(&self, hash: u64) -> PathBuf {
+        let name = format!("{:016x}", hash);
+
+        self.path.join(BLOBS_DIR).join(&name[..2]).join(name)
+    }
+
+    /// Stores the payload of a chunk under the hash of its NBT data, unless a blob with that hash is already stored.
+    /// Returns true if the blob was written.
+    pub fn write_blob(&self, hash: u64, payload: &[u8]) -> Result<bool, io::Error> {
+        let path = self.blob_path(hash);
+
+        if Path::exists(&path) {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        write_atomically(&path, |writer| writer.write_all(payload))?;
+
+        Ok(true)
+    }
+
+    pub fn read_blob(&self, hash: u64) -> Result<Option<Box<[u8]>>, io::Error> {
+        match fs::read(self.blob_path(hash)) {
+            Ok(payload) => Ok(Some(payload.into_boxed_slice())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Removes a blob, returning the number of bytes it took up.
+    pub fn remove_blob(&self, hash: u64) -> Result<u64, io::Error> {
+        let path = self.blob_path(hash);
+        let length = fs::metadata(&path)?.len();
+
+        fs::remove_file(path)?;
+
+        Ok(length)
+    }
+
+    /// Lists the hash of every blob in the repository. Files left behind by an interrupted backup are removed.
+    pub fn blobs(&self) -> Result<Vec<u64>, io::Error> {
+        let mut hashes = Vec::new();
+
+        for entry in fs::read_dir(self.path.join(BLOBS_DIR))? {
+            let dir = entry?.path();
+
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+
+                if is_partial(&path) {
+                    fs::remove_file(&path)?;
+                    continue;
+                }
+
+                let name = path.file_name().unwrap().to_string_lossy();
+
+                if let Ok(hash) = u64::from_str_radix(&name, 16) {
+                    hashes.push(hash);
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// Lists the IDs of the snapshots in the repository, from oldest to newest.
+    pub fn snapshots(&self) -> Result<Vec<u64>, io::Error> {
+        let mut ids = Vec::new();
+
+        for entry in fs::read_dir(self.path.join(SNAPSHOTS_DIR))? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy();
+
+            if let Ok(id) = name.parse::<u64>() {
+                ids.push(id);
+            }
+        }
+
+        ids.sort_unstable();
+
+        Ok(ids)
+    }
+
+    fn snapshot_path(&self, id: u64) -> PathBuf {
+        self.path.join(SNAPSHOTS_DIR).join(id.to_string())
+    }
+
+    pub fn load_snapshot(&self, id: u64) -> Result<Snapshot, io::Error> {
+        let file = match File::open(self.snapshot_path(id)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Message::new(messages::REPO_SNAPSHOT_MISSING)
+                    .arg("snapshot", id)
+                    .into_error(io::ErrorKind::NotFound));
+            }
+            Err(err) => return Err(err),
+        };
+
+        Snapshot::read_from(BufReader::new(file)).map_err(|err| {
+            Message::new(messages::REPO_SNAPSHOT_INVALID)
+                .arg("snapshot", id)
+                .arg("error", err)
+                .into_error(io::ErrorKind::InvalidData)
+        })
+    }
+
+    /// Saves a snapshot, returning its ID. The snapshot is only written once every blob it lists has been stored, so
+    /// the IDs are taken from the current time, and moved later if a snapshot was already taken in the same second.
+    pub fn save_snapshot(&self, snapshot: &Snapshot) -> Result<u64, io::Error> {
+        let mut id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        if let Some(&newest) = self.snapshots()?.last() {
+            id = id.max(newest + 1);
+        }
+
+        write_atomically(&self.snapshot_path(id), |writer| snapshot.write_to(writer))?;
+
+        Ok(id)
+    }
+
+    pub fn remove_snapshot(&self, id: u64) -> Result<(), io::Error> {
+        fs::remove_file(self.snapshot_path(id))
+    }
+}
+
+/// A chunk listed in a snapshot, given by the hash of its blob and the time it was last saved according to its region
+/// file.
+#[derive(Copy, Clone)]
+pub struct SnapshotEntry {
+    pub hash: u64,
+    pub timestamp: u32,
+}
+
+/// The chunks which made up a world when it was backed up. Snapshots are stored as plain text with one
+/// `<entry path> <hash> <timestamp>` line per chunk, using the entry paths of archives.
+#[derive(Default)]
+pub struct Snapshot {
+    entries: HashMap<(ChunkPos, ChunkPos), SnapshotEntry>,
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Snapshot::default()
+    }
+
+    pub fn read_from<R>(reader: R) -> Result<Self, io::Error>
+    where
+        R: BufRead,
+    {
+        let mut entries = HashMap::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let malformed = || {
+                Message::new(messages::REPO_MALFORMED_LINE)
+                    .arg("line", line)
+                    .into_error(io::ErrorKind::InvalidData)
+            };
+
+            let mut fields = line.split(' ');
+
+            let (entry_path, hash, timestamp) = match (fields.next(), fields.next(), fields.next())
+            {
+                (Some(entry_path), Some(hash), Some(timestamp)) => (entry_path, hash, timestamp),
+                _ => return Err(malformed()),
+            };
+
+            let position = parse_chunk_entry_path(Path::new(entry_path)).ok_or_else(malformed)?;
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| malformed())?;
+            let timestamp = timestamp.parse::<u32>().map_err(|_| malformed())?;
+
+            entries.insert(position, SnapshotEntry { hash, timestamp });
+        }
+
+        Ok(Snapshot { entries })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(&(region, chunk), entry)| {
+                format!(
+                    "{} {:016x} {}",
+                    chunk_entry_path(region, chunk),
+                    entry.hash,
+                    entry.timestamp
+                )
+            })
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(&mut self, region: ChunkPos, chunk: ChunkPos, entry: SnapshotEntry) {
+        self.entries.insert((region, chunk), entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ChunkPos, ChunkPos, SnapshotEntry)> + '_ {
+        self.entries
+            .iter()
+            .map(|(&(region, chunk), &entry)| (region, chunk, entry))
+    }
+}
+
+/// Writes a file next to its final path, and renames it into place once it has been written and flushed to disk.
+fn write_atomically<F>(path: &Path, write: F) -> Result<(), io::Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), io::Error>,
+{
+    read_only::ensure_writable(path)?;
+
+    let partial_path = path.with_extension(PARTIAL_EXTENSION);
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+
+    write(&mut writer)?;
+
+    writer.into_inner()?.sync_all()?;
+    fs::rename(partial_path, path)
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new(PARTIAL_EXTENSION))
+}
@@ -0,0 +1,207 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::messages::{self, report};
+use crate::observer::OperationObserver;
+use crate::region::{AnyRegionFile, ChunkPos, RawChunk, RegionFile};
+
+/// EIO, which network filesystems commonly return for requests that time out on the server side.
+#[cfg(unix)]
+const EIO: i32 = 5;
+
+#[derive(Args, Clone)]
+pub struct RetryOptions {
+    #[clap(
+        long,
+        help = "Number of times to retry I/O operations which fail with a transient error",
+        default_value = "0"
+    )]
+    retries: u32,
+
+    #[clap(
+        long,
+        help = "Delay before the first retry of a failed I/O operation, doubled after each attempt (e.g. 500ms, 2s)",
+        default_value = "1s",
+        value_parser = parse_duration
+    )]
+    retry_backoff: Duration,
+}
+
+impl RetryOptions {
+    /// Retries each failed operation up to `retries` times, waiting `backoff` before the first retry.
+    pub fn new(retries: u32, backoff: Duration) -> Self {
+        RetryOptions {
+            retries,
+            retry_backoff: backoff,
+        }
+    }
+}
+
+/// Retries I/O operations which fail with transient errors, backing off exponentially between attempts. Only
+/// operations which are safe to repeat (opening files, reading or writing at a fixed offset, or a single read or write
+/// call which failed without transferring anything) should be run through the policy.
+///
+/// Cloned policies share the same retry counter, so the total can be reported once a command finishes.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    retried: Arc<AtomicUsize>,
+}
+
+impl RetryPolicy {
+    pub fn new(options: &RetryOptions) -> Self {
+        RetryPolicy {
+            retries: options.retries,
+            backoff: options.retry_backoff,
+            retried: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn run<T, F>(&self, mut operation: F) -> Result<T, io::Error>
+    where
+        F: FnMut() -> Result<T, io::Error>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    self.retried.fetch_add(1, Ordering::Relaxed);
+
+                    std::thread::sleep(self.backoff.saturating_mul(1 << attempt.min(16)));
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Wraps a reader or writer, such as the file of an archive, so that each of its read and write calls is retried.
+    pub fn wrap<T>(&self, inner: T) -> Retrying<T> {
+        Retrying {
+            inner,
+            policy: self.clone(),
+        }
+    }
+
+    /// Reads every slot of a region in order like `RegionFile::stream_raw_chunks`, retrying each chunk which can't be
+    /// read. Chunks are read at their offset in the file, so a failed read can be repeated as it is.
+    pub fn stream_raw_chunks<'a>(
+        &'a self,
+        region: &'a RegionFile,
+    ) -> impl Iterator<Item = Result<Option<RawChunk>, io::Error>> + 'a {
+        (0..32 * 32).map(move |index| {
+            let position = ChunkPos {
+                x: index % 32,
+                z: index / 32,
+            };
+
+            self.run(|| region.get_raw_chunk(position))
+        })
+    }
+
+    /// Like `stream_raw_chunks`, for a region in either format. Linear regions are read whole when they are opened, so
+    /// only Anvil regions go back to the file.
+    pub fn stream_any_raw_chunks<'a>(
+        &'a self,
+        region: &'a AnyRegionFile,
+    ) -> Box<dyn Iterator<Item = Result<Option<RawChunk>, io::Error>> + 'a> {
+        match region {
+            AnyRegionFile::Anvil(region) => Box::new(self.stream_raw_chunks(region)),
+            AnyRegionFile::Linear(_) => region.stream_raw_chunks(),
+        }
+    }
+
+    pub fn retried(&self) -> usize {
+        self.retried.load(Ordering::Relaxed)
+    }
+
+    /// Logs the number of retried operations if there were any, passing the warning on to the observer.
+    pub fn report(&self, observer: &dyn OperationObserver) {
+        let retried = self.retried();
+
+        if retried > 0 {
+            observer.warning(&report!(warn, messages::RETRY_RETRIED, retried = retried));
+        }
+    }
+}
+
+/// A reader or writer whose calls are retried by a policy. A read or write which returns an error hasn't transferred
+/// any bytes, so repeating the same call continues the stream where it left off.
+pub struct Retrying<T> {
+    inner: T,
+    policy: RetryPolicy,
+}
+
+impl<T> Read for Retrying<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.read(buf))
+    }
+}
+
+impl<T> Write for Retrying<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.flush())
+    }
+}
+
+impl<T> Seek for Retrying<T>
+where
+    T: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let inner = &mut self.inner;
+        self.policy.run(|| inner.seek(pos))
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => true,
+        #[cfg(unix)]
+        _ => err.raw_os_error() == Some(EIO),
+        #[cfg(not(unix))]
+        _ => false,
+    }
+}
+
+/// Parses a duration such as `2s`, `500ms`, or `1m`. A bare number is interpreted as seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        _ => return Err(format!("Unknown duration unit: {}", unit)),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Duration is too large: {}", value))
+}
@@ -0,0 +1,739 @@
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::io;
+
+use crate::messages::{self, Message};
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// Deeply nested documents are rejected rather than risking a stack overflow.
+const MAX_DEPTH: usize = 512;
+
+const INDENT: &str = "    ";
+
+/// Converts serialized NBT into SNBT text, with each tag of a compound on its own line so that the text can be compared
+/// line by line. Tags are written in the order they are stored and always with the suffix of their type, so the text
+/// converts back into the same tags. The name of the root compound is left out, as SNBT has no way to record it and
+/// the game never reads it.
+pub fn to_snbt(data: &[u8]) -> Result<String, io::Error> {
+    let mut reader = NbtReader { data, offset: 0 };
+
+    if reader.read_u8()? != TAG_COMPOUND {
+        return Err(invalid_nbt("Root tag is not a compound"));
+    }
+
+    let name_length = reader.read_u16()? as usize;
+    reader.take(name_length)?;
+
+    let mut text = String::with_capacity(data.len() * 2);
+    reader.write_payload(TAG_COMPOUND, &mut text, 0)?;
+    text.push('\n');
+
+    Ok(text)
+}
+
+/// Converts UTF-8 SNBT text back into serialized NBT with an unnamed root compound, as written by `to_snbt`. Other SNBT
+/// is accepted as long as every value can be typed, where integers without a suffix are ints, decimals without a suffix
+/// are doubles, and words which aren't numbers are strings.
+pub fn from_snbt(text: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let text = match std::str::from_utf8(text) {
+        Ok(text) => text,
+        Err(err) => {
+            let parser = SnbtParser {
+                text: std::str::from_utf8(&text[..err.valid_up_to()]).unwrap(),
+                offset: err.valid_up_to(),
+            };
+
+            return Err(parser.error("The text is not valid UTF-8"));
+        }
+    };
+
+    let mut parser = SnbtParser { text, offset: 0 };
+    let mut data = Vec::with_capacity(text.len() / 2);
+
+    parser.skip_whitespace();
+    parser.expect('{')?;
+
+    data.push(TAG_COMPOUND);
+    data.extend_from_slice(&0u16.to_be_bytes());
+    parser.parse_compound(&mut data, 0)?;
+
+    parser.skip_whitespace();
+
+    if parser.offset < text.len() {
+        return Err(parser.error("Unexpected text after the root compound"));
+    }
+
+    Ok(data)
+}
+
+struct NbtReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NbtReader<'a> {
+    fn write_payload(&mut self, tag: u8, text: &mut String, depth: usize) -> Result<(), io::Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_nbt("Tags are nested too deeply"));
+        }
+
+        match tag {
+            TAG_BYTE => write!(text, "{}b", self.take(1)?[0] as i8).unwrap(),
+            TAG_SHORT => write!(text, "{}s", self.read_u16()? as i16).unwrap(),
+            TAG_INT => write!(text, "{}", self.read_i32()?).unwrap(),
+            TAG_LONG => write!(text, "{}L", self.read_i64()?).unwrap(),
+            TAG_FLOAT => write!(text, "{:?}f", f32::from_bits(self.read_i32()? as u32)).unwrap(),
+            TAG_DOUBLE => write!(text, "{:?}d", f64::from_bits(self.read_i64()? as u64)).unwrap(),
+            TAG_BYTE_ARRAY => {
+                let length = self.read_length()?;
+                text.push_str("[B;");
+
+                for (index, &value) in self.take(length)?.iter().enumerate() {
+                    if index > 0 {
+                        text.push(',');
+                    }
+
+                    write!(text, "{}b", value as i8).unwrap();
+                }
+
+                text.push(']');
+            }
+            TAG_INT_ARRAY => {
+                let length = self.read_length()?;
+                text.push_str("[I;");
+
+                for index in 0..length {
+                    if index > 0 {
+                        text.push(',');
+                    }
+
+                    write!(text, "{}", self.read_i32()?).unwrap();
+                }
+
+                text.push(']');
+            }
+            TAG_LONG_ARRAY => {
+                let length = self.read_length()?;
+                text.push_str("[L;");
+
+                for index in 0..length {
+                    if index > 0 {
+                        text.push(',');
+                    }
+
+                    write!(text, "{}L", self.read_i64()?).unwrap();
+                }
+
+                text.push(']');
+            }
+            TAG_STRING => {
+                let length = self.read_u16()? as usize;
+                write_string(text, &decode_mutf8(self.take(length)?)?);
+            }
+            TAG_LIST => {
+                let element_tag = self.read_u8()?;
+                let length = self.read_length()?;
+
+                // Lists of compounds and lists are spread over several lines, while lists of values are kept to one
+                let multiline = element_tag == TAG_COMPOUND || element_tag == TAG_LIST;
+
+                text.push('[');
+
+                for index in 0..length {
+                    if index > 0 {
+                        text.push(',');
+                    }
+
+                    if multiline {
+                        text.push('\n');
+                        indent(text, depth + 1);
+                    }
+
+                    self.write_payload(element_tag, text, depth + 1)?;
+                }
+
+                if multiline && length > 0 {
+                    text.push('\n');
+                    indent(text, depth);
+                }
+
+                text.push(']');
+            }
+            TAG_COMPOUND => {
+                text.push('{');
+
+                let mut first = true;
+
+                loop {
+                    let tag = self.read_u8()?;
+
+                    if tag == TAG_END {
+                        break;
+                    }
+
+                    if !first {
+                        text.push(',');
+                    }
+
+                    first = false;
+
+                    text.push('\n');
+                    indent(text, depth + 1);
+
+                    let name_length = self.read_u16()? as usize;
+                    write_key(text, &decode_mutf8(self.take(name_length)?)?);
+                    text.push_str(": ");
+
+                    self.write_payload(tag, text, depth + 1)?;
+                }
+
+                if !first {
+                    text.push('\n');
+                    indent(text, depth);
+                }
+
+                text.push('}');
+            }
+            _ => return Err(invalid_nbt("Unknown tag type")),
+        }
+
+        Ok(())
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], io::Error> {
+        let end = self.offset + length;
+
+        if end > self.data.len() {
+            return Err(invalid_nbt("Unexpected end of data"));
+        }
+
+        let slice = &self.data[self.offset..end];
+        self.offset = end;
+
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, io::Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, io::Error> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, io::Error> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads the length of a list or array, where negative lengths are read as empty as they are by the game.
+    fn read_length(&mut self) -> Result<usize, io::Error> {
+        Ok(self.read_i32()?.max(0) as usize)
+    }
+}
+
+fn indent(text: &mut String, depth: usize) {
+    for _ in 0..depth {
+        text.push_str(INDENT);
+    }
+}
+
+/// Writes the name of a tag, which only needs to be quoted if it contains characters outside of those allowed in
+/// unquoted SNBT strings.
+fn write_key(text: &mut String, name: &[u16]) {
+    let is_plain = !name.is_empty()
+        && name
+            .iter()
+            .all(|&unit| unit < 0x80 && is_unquoted_char(unit as u8 as char));
+
+    if is_plain {
+        text.extend(name.iter().map(|&unit| unit as u8 as char));
+    } else {
+        write_string(text, name);
+    }
+}
+
+/// Writes a quoted string given as UTF-16, which is how the game holds strings. Surrogates which aren't part of a pair
+/// can't be represented in UTF-8 text, so they are escaped along with control characters.
+fn write_string(text: &mut String, value: &[u16]) {
+    text.push('"');
+
+    for result in char::decode_utf16(value.iter().copied()) {
+        match result {
+            Ok('"') => text.push_str("\\\""),
+            Ok('\\') => text.push_str("\\\\"),
+            Ok('\n') => text.push_str("\\n"),
+            Ok('\r') => text.push_str("\\r"),
+            Ok('\t') => text.push_str("\\t"),
+            Ok(c) if c.is_control() => write!(text, "\\u{:04x}", c as u32).unwrap(),
+            Ok(c) => text.push(c),
+            Err(err) => write!(text, "\\u{:04x}", err.unpaired_surrogate()).unwrap(),
+        }
+    }
+
+    text.push('"');
+}
+
+fn is_unquoted_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+')
+}
+
+/// Decodes a string in the modified UTF-8 encoding used by Java, where characters outside of the Basic Multilingual
+/// Plane are encoded as a pair of surrogates, into its UTF-16 code units.
+fn decode_mutf8(data: &[u8]) -> Result<Vec<u16>, io::Error> {
+    let mut units = Vec::with_capacity(data.len());
+    let mut index = 0;
+
+    let continuation = |index: usize| match data.get(index) {
+        Some(&byte) if byte & 0xC0 == 0x80 => Ok((byte & 0x3F) as u16),
+        _ => Err(invalid_nbt("Malformed string")),
+    };
+
+    while index < data.len() {
+        let byte = data[index];
+
+        if byte & 0x80 == 0 {
+            units.push(byte as u16);
+            index += 1;
+        } else if byte & 0xE0 == 0xC0 {
+            units.push(((byte & 0x1F) as u16) << 6 | continuation(index + 1)?);
+            index += 2;
+        } else if byte & 0xF0 == 0xE0 {
+            units.push(
+                ((byte & 0x0F) as u16) << 12
+                    | continuation(index + 1)? << 6
+                    | continuation(index + 2)?,
+            );
+            index += 3;
+        } else {
+            return Err(invalid_nbt("Malformed string"));
+        }
+    }
+
+    Ok(units)
+}
+
+/// Encodes UTF-16 code units in the modified UTF-8 encoding used by Java, where the null character takes two bytes.
+fn encode_mutf8(units: &[u16], data: &mut Vec<u8>) {
+    for &unit in units {
+        match unit {
+            0x0001..=0x007F => data.push(unit as u8),
+            0x0000..=0x07FF => {
+                data.push(0xC0 | (unit >> 6) as u8);
+                data.push(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                data.push(0xE0 | (unit >> 12) as u8);
+                data.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                data.push(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+}
+
+struct SnbtParser<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> SnbtParser<'a> {
+    /// Parses the tags of a compound up to and including its closing brace, after its opening brace has been read.
+    fn parse_compound(&mut self, data: &mut Vec<u8>, depth: usize) -> Result<(), io::Error> {
+        self.skip_whitespace();
+
+        if self.peek() == Some('}') {
+            self.offset += 1;
+            data.push(TAG_END);
+            return Ok(());
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            let name = match self.peek() {
+                Some('"') | Some('\'') => self.parse_quoted()?,
+                _ => {
+                    let word = self.parse_word();
+
+                    if word.is_empty() {
+                        return Err(self.error("Expected the name of a tag"));
+                    }
+
+                    word.encode_utf16().collect()
+                }
+            };
+
+            self.skip_whitespace();
+            self.expect(':')?;
+
+            // The type of the tag is only known once its value has been parsed
+            let tag_index = data.len();
+            data.push(TAG_END);
+            write_string_payload(data, &name, self)?;
+
+            data[tag_index] = self.parse_value(data, depth + 1)?;
+
+            self.skip_whitespace();
+
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(self.error("Expected ',' or '}' after a tag")),
+            }
+        }
+
+        data.push(TAG_END);
+
+        Ok(())
+    }
+
+    /// Parses a value and appends its payload, returning its tag type.
+    fn parse_value(&mut self, data: &mut Vec<u8>, depth: usize) -> Result<u8, io::Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Tags are nested too deeply"));
+        }
+
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => {
+                self.offset += 1;
+                self.parse_compound(data, depth)?;
+                Ok(TAG_COMPOUND)
+            }
+            Some('[') => {
+                self.offset += 1;
+                self.parse_list(data, depth)
+            }
+            Some('"') | Some('\'') => {
+                let value = self.parse_quoted()?;
+                write_string_payload(data, &value, self)?;
+                Ok(TAG_STRING)
+            }
+            _ => {
+                let word = self.parse_word();
+
+                if word.is_empty() {
+                    return Err(self.error("Expected a value"));
+                }
+
+                self.parse_scalar(word, data)
+            }
+        }
+    }
+
+    /// Parses a list or array up to and including its closing bracket, after its opening bracket has been read.
+    fn parse_list(&mut self, data: &mut Vec<u8>, depth: usize) -> Result<u8, io::Error> {
+        let rest = &self.text[self.offset..];
+
+        for &(prefix, tag) in &[
+            ("B;", TAG_BYTE_ARRAY),
+            ("I;", TAG_INT_ARRAY),
+            ("L;", TAG_LONG_ARRAY),
+        ] {
+            if rest.starts_with(prefix) {
+                self.offset += prefix.len();
+                self.parse_array(data, tag)?;
+                return Ok(tag);
+            }
+        }
+
+        // The type of the elements and the length of the list are filled in once every element has been parsed
+        let header_index = data.len();
+        data.push(TAG_END);
+        data.extend_from_slice(&0i32.to_be_bytes());
+
+        let mut element_tag = None;
+        let mut length: i32 = 0;
+
+        self.skip_whitespace();
+
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(TAG_LIST);
+        }
+
+        loop {
+            let tag = self.parse_value(data, depth + 1)?;
+
+            match element_tag {
+                None => element_tag = Some(tag),
+                Some(element_tag) if element_tag != tag => {
+                    return Err(self.error("Elements of a list must all have the same type"));
+                }
+                _ => {}
+            }
+
+            length += 1;
+            self.skip_whitespace();
+
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => break,
+                _ => return Err(self.error("Expected ',' or ']' after an element of a list")),
+            }
+        }
+
+        data[header_index] = element_tag.unwrap_or(TAG_END);
+        data[header_index + 1..header_index + 5].copy_from_slice(&length.to_be_bytes());
+
+        Ok(TAG_LIST)
+    }
+
+    fn parse_array(&mut self, data: &mut Vec<u8>, tag: u8) -> Result<(), io::Error> {
+        let length_index = data.len();
+        data.extend_from_slice(&0i32.to_be_bytes());
+
+        let mut length: i32 = 0;
+
+        self.skip_whitespace();
+
+        if self.peek() == Some(']') {
+            self.offset += 1;
+            return Ok(());
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            let word = self.parse_word();
+            let number = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+
+            let parsed = match tag {
+                TAG_BYTE_ARRAY => number.parse::<i8>().map(|value| data.push(value as u8)),
+                TAG_INT_ARRAY => number
+                    .parse::<i32>()
+                    .map(|value| data.extend_from_slice(&value.to_be_bytes())),
+                _ => number
+                    .parse::<i64>()
+                    .map(|value| data.extend_from_slice(&value.to_be_bytes())),
+            };
+
+            if parsed.is_err() {
+                return Err(self.error("Invalid element of an array"));
+            }
+
+            length += 1;
+            self.skip_whitespace();
+
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => break,
+                _ => return Err(self.error("Expected ',' or ']' after an element of an array")),
+            }
+        }
+
+        data[length_index..length_index + 4].copy_from_slice(&length.to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Parses a quoted string into UTF-16 code units, so that escaped surrogates survive being converted back.
+    fn parse_quoted(&mut self) -> Result<Vec<u16>, io::Error> {
+        let quote = self.next().unwrap();
+        let mut units = Vec::new();
+        let mut buffer = [0u16; 2];
+
+        loop {
+            let c = match self.next() {
+                Some(c) => c,
+                None => return Err(self.error("Unterminated string")),
+            };
+
+            if c == quote {
+                return Ok(units);
+            }
+
+            if c != '\\' {
+                units.extend_from_slice(c.encode_utf16(&mut buffer));
+                continue;
+            }
+
+            let escaped = match self.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('u') => {
+                    let digits = self.text.get(self.offset..self.offset + 4);
+                    let unit = digits.and_then(|digits| u16::from_str_radix(digits, 16).ok());
+
+                    match unit {
+                        Some(unit) => {
+                            self.offset += 4;
+                            units.push(unit);
+                            continue;
+                        }
+                        None => return Err(self.error("Invalid unicode escape")),
+                    }
+                }
+                Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => c,
+                _ => return Err(self.error("Invalid escape")),
+            };
+
+            units.extend_from_slice(escaped.encode_utf16(&mut buffer));
+        }
+    }
+
+    /// Appends the payload of an unquoted value, returning its tag type. Words which aren't valid numbers are strings.
+    fn parse_scalar(&self, word: &str, data: &mut Vec<u8>) -> Result<u8, io::Error> {
+        match word {
+            "true" => {
+                data.push(1);
+                return Ok(TAG_BYTE);
+            }
+            "false" => {
+                data.push(0);
+                return Ok(TAG_BYTE);
+            }
+            _ => {}
+        }
+
+        let (number, suffix) = match word.char_indices().last() {
+            Some((index, c)) if c.is_ascii_alphabetic() => (&word[..index], c.to_ascii_lowercase()),
+            _ => (word, ' '),
+        };
+
+        let is_decimal = number.contains(['.', 'e', 'E']);
+
+        let parsed = match suffix {
+            'b' => number
+                .parse::<i8>()
+                .ok()
+                .map(|value| (TAG_BYTE, vec![value as u8])),
+            's' => number
+                .parse::<i16>()
+                .ok()
+                .map(|value| (TAG_SHORT, value.to_be_bytes().to_vec())),
+            'l' => number
+                .parse::<i64>()
+                .ok()
+                .map(|value| (TAG_LONG, value.to_be_bytes().to_vec())),
+            'f' => number
+                .parse::<f32>()
+                .ok()
+                .map(|value| (TAG_FLOAT, value.to_be_bytes().to_vec())),
+            'd' => number
+                .parse::<f64>()
+                .ok()
+                .map(|value| (TAG_DOUBLE, value.to_be_bytes().to_vec())),
+            ' ' if is_decimal => number
+                .parse::<f64>()
+                .ok()
+                .map(|value| (TAG_DOUBLE, value.to_be_bytes().to_vec())),
+            ' ' => number
+                .parse::<i32>()
+                .ok()
+                .map(|value| (TAG_INT, value.to_be_bytes().to_vec())),
+            _ => None,
+        };
+
+        match parsed {
+            Some((tag, payload)) => {
+                data.extend_from_slice(&payload);
+                Ok(tag)
+            }
+            None => {
+                let units: Vec<u16> = word.encode_utf16().collect();
+                write_string_payload(data, &units, self)?;
+                Ok(TAG_STRING)
+            }
+        }
+    }
+
+    fn parse_word(&mut self) -> &'a str {
+        let start = self.offset;
+
+        while let Some(c) = self.peek() {
+            if !is_unquoted_char(c) {
+                break;
+            }
+
+            self.offset += 1;
+        }
+
+        &self.text[start..self.offset]
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), io::Error> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("Expected '{}'", expected))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+
+            self.offset += c.len_utf8();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+
+        Some(c)
+    }
+
+    fn error(&self, reason: &str) -> io::Error {
+        let before = &self.text[..self.offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+
+        Message::new(messages::SNBT_INVALID)
+            .arg("line", line)
+            .arg("column", column)
+            .arg("reason", reason)
+            .into_error(io::ErrorKind::InvalidData)
+    }
+}
+
+/// Appends the payload of a string tag, which is prefixed by its length in bytes.
+fn write_string_payload(
+    data: &mut Vec<u8>,
+    value: &[u16],
+    parser: &SnbtParser,
+) -> Result<(), io::Error> {
+    let mut encoded = Vec::with_capacity(value.len());
+    encode_mutf8(value, &mut encoded);
+
+    if encoded.len() > u16::MAX as usize {
+        return Err(parser.error("String is too long"));
+    }
+
+    data.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+    data.extend_from_slice(&encoded);
+
+    Ok(())
+}
+
+fn invalid_nbt(reason: &str) -> io::Error {
+    Message::new(messages::NBT_SCAN)
+        .arg("reason", reason)
+        .into_error(io::ErrorKind::InvalidData)
+}
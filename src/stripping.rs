@@ -0,0 +1,445 @@
+//! Stripping of the data which the game caches in chunks and recalculates when it is missing, working on chunks held in
+//! memory so that it is shared by every command which strips chunks and by builds without the file system.
+
+use fastnbt::{LongArray, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "native")]
+use indicatif::HumanBytes;
+#[cfg(feature = "native")]
+use std::cmp::Reverse;
+
+use crate::blocks::{compute_heightmaps, data_version, qualify};
+#[cfg(feature = "native")]
+use crate::messages::report;
+use crate::messages::{self, Message};
+use crate::nbt::{contains_tag, find_int_tag, named_tag_size, read_nbt, write_nbt};
+use crate::region::Chunk;
+
+/// Bytes saved by a single strip rule, measured as the serialized size of the NBT tags it removed.
+#[derive(Clone, Copy, Default)]
+pub struct RuleSavings {
+    tags: u64,
+    bytes: u64,
+}
+
+/// Accounts for the data removed by stripping, attributed to the rule which removed it.
+#[derive(Default)]
+pub struct StripReport {
+    chunks: u64,
+    unchanged_chunks: u64,
+    pub(crate) dropped_chunks: u64,
+    relit_chunks: u64,
+    recalculated_chunks: u64,
+    input_bytes: u64,
+    output_bytes: u64,
+    rules: BTreeMap<String, RuleSavings>,
+}
+
+impl StripReport {
+    pub fn merge(&mut self, other: &StripReport) {
+        self.chunks += other.chunks;
+        self.unchanged_chunks += other.unchanged_chunks;
+        self.dropped_chunks += other.dropped_chunks;
+        self.relit_chunks += other.relit_chunks;
+        self.recalculated_chunks += other.recalculated_chunks;
+        self.input_bytes += other.input_bytes;
+        self.output_bytes += other.output_bytes;
+
+        for (rule, savings) in &other.rules {
+            self.record_savings(rule, savings.tags, savings.bytes);
+        }
+    }
+
+    /// Records a chunk which had nothing to strip and was kept as it was.
+    pub fn record_unchanged(&mut self, bytes: u64) {
+        self.chunks += 1;
+        self.unchanged_chunks += 1;
+        self.input_bytes += bytes;
+        self.output_bytes += bytes;
+    }
+
+    pub(crate) fn record_savings(&mut self, rule: &str, tags: u64, bytes: u64) {
+        let savings = match self.rules.get_mut(rule) {
+            Some(savings) => savings,
+            None => self.rules.entry(rule.to_owned()).or_default(),
+        };
+
+        savings.tags += tags;
+        savings.bytes += bytes;
+    }
+
+    /// Removes a tag from a compound, attributing its size to the given rule.
+    fn remove_tag(&mut self, rule: &str, compound: &mut HashMap<String, Value>, name: &str) {
+        if let Some(value) = compound.remove(name) {
+            self.record_savings(rule, 1, named_tag_size(name, &value) as u64);
+        }
+    }
+
+    /// Logs the savings of each rule, sorted from most to least effective. The sizes are of uncompressed NBT data,
+    /// so they show which rules pay off relative to each other rather than the exact change on disk.
+    #[cfg(feature = "native")]
+    pub fn print(&self) {
+        report!(
+            info,
+            messages::STRIP_SUMMARY,
+            chunks = self.chunks,
+            input_bytes = self.input_bytes,
+            input_size = HumanBytes(self.input_bytes).to_string(),
+            output_bytes = self.output_bytes,
+            output_size = HumanBytes(self.output_bytes).to_string(),
+            saved = percentage(
+                self.input_bytes.saturating_sub(self.output_bytes),
+                self.input_bytes
+            )
+        );
+
+        if self.unchanged_chunks > 0 {
+            report!(
+                info,
+                messages::STRIP_UNCHANGED,
+                unchanged_chunks = self.unchanged_chunks
+            );
+        }
+
+        if self.dropped_chunks > 0 {
+            report!(
+                info,
+                messages::STRIP_DROPPED,
+                dropped_chunks = self.dropped_chunks
+            );
+        }
+
+        if self.relit_chunks > 0 {
+            report!(
+                info,
+                messages::STRIP_RELIT,
+                relit_chunks = self.relit_chunks
+            );
+        }
+
+        if self.recalculated_chunks > 0 {
+            report!(
+                info,
+                messages::STRIP_HEIGHTMAPS_RECALCULATED,
+                recalculated_chunks = self.recalculated_chunks
+            );
+        }
+
+        let mut rules: Vec<(&String, &RuleSavings)> = self.rules.iter().collect();
+        rules.sort_by_key(|(_, savings)| Reverse(savings.bytes));
+
+        for (rule, savings) in rules {
+            report!(
+                info,
+                messages::STRIP_RULE,
+                rule = rule.as_str(),
+                tags = savings.tags,
+                bytes = savings.bytes,
+                size = HumanBytes(savings.bytes).to_string(),
+                percentage = percentage(savings.bytes, self.input_bytes)
+            );
+        }
+    }
+}
+
+/// Formats a part of a total as a percentage with one decimal place.
+#[cfg(feature = "native")]
+fn percentage(part: u64, total: u64) -> String {
+    let percentage = if total == 0 {
+        0.0
+    } else {
+        (part as f64 / total as f64) * 100.0
+    };
+
+    format!("{:.1}", percentage)
+}
+
+/// Optional rules applied by `strip_chunk` on top of the cached data it always removes.
+#[derive(Default)]
+pub struct StripRules {
+    /// If set, structure starts and references are removed, except for those of structures matching one of these
+    /// names. Names ending in `*` match every structure starting with the rest of the name.
+    pub structures: Option<Vec<String>>,
+    /// Removes the scheduled block and fluid ticks, which only delay updates the game will find again once the chunk
+    /// is loaded.
+    pub ticks: bool,
+    /// Removes the positions queued for post-processing, which are left behind by world generation.
+    pub postprocessing: bool,
+    /// Heightmaps which are kept within `Heightmaps`, which is otherwise removed as a whole.
+    pub keep_heightmaps: Vec<String>,
+    /// Data versions of the chunks whose light flags are set to false rather than only removed, for servers which
+    /// only relight a chunk when the flag is there to say it isn't lit.
+    pub force_relight: Option<RangeInclusive<i32>>,
+    /// Recomputes the heightmaps from the chunk's blocks once the old ones are removed, for chunks from 1.13 onwards.
+    /// Every chunk is rewritten, so that chunks which were stripped before get their heightmaps back.
+    pub recalculate_heightmaps: bool,
+}
+
+impl StripRules {
+    fn strips_structure(&self, name: &str) -> bool {
+        let keep = match &self.structures {
+            Some(keep) => keep,
+            None => return false,
+        };
+
+        // Structures were named without a namespace before 1.18, such as "Village" rather than "minecraft:village"
+        let name = qualify(name).to_lowercase();
+
+        !keep.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+
+            match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            }
+        })
+    }
+
+    fn strips_heightmap(&self, name: &str) -> bool {
+        !self.keep_heightmaps.iter().any(|keep| keep == name)
+    }
+}
+
+/// Checks whether a chunk contains any of the tags removed by `strip_chunk`, by scanning its serialized NBT rather than
+/// decoding it. This must be kept in sync with the tags removed by `strip_chunk`.
+pub fn needs_strip(data: &[u8], rules: &StripRules) -> Result<bool, io::Error> {
+    if rules.recalculate_heightmaps {
+        return Ok(true);
+    }
+
+    if let Some(versions) = &rules.force_relight {
+        if versions.contains(&find_int_tag(data, &["DataVersion"])?.unwrap_or(0)) {
+            return Ok(true);
+        }
+    }
+
+    contains_tag(data, |path| match path {
+        [b"Heightmaps"] => rules.keep_heightmaps.is_empty(),
+        [b"Heightmaps", name] => rules.strips_heightmap(&String::from_utf8_lossy(name)),
+        [b"isLightOn"] | [b"sections", b"SkyLight"] | [b"sections", b"BlockLight"] => true,
+        [b"structures", b"References", name]
+        | [b"structures", b"starts", name]
+        | [b"Level", b"Structures", b"References", name]
+        | [b"Level", b"Structures", b"Starts", name] => {
+            rules.strips_structure(&String::from_utf8_lossy(name))
+        }
+        [b"block_ticks"]
+        | [b"fluid_ticks"]
+        | [b"Level", b"TileTicks"]
+        | [b"Level", b"LiquidTicks"] => rules.ticks,
+        [b"PostProcessing"] | [b"Level", b"PostProcessing"] => rules.postprocessing,
+        _ => false,
+    })
+}
+
+/// The paths of the tags removed by `strip_chunk`, as recorded in the profile of an archive. This must be kept in sync
+/// with the tags removed by `strip_chunk`.
+pub const STRIP_PATHS: &[&str] = &[
+    "Heightmaps",
+    "isLightOn",
+    "sections[].SkyLight",
+    "sections[].BlockLight",
+];
+
+pub fn strip_chunk(
+    chunk: &Chunk,
+    rules: &StripRules,
+    report: &mut StripReport,
+) -> Result<Chunk, io::Error> {
+    strip_chunk_with(chunk, rules, report, |_, _| {})
+}
+
+/// Strips a chunk in the same way as `strip_chunk`, then passes its NBT to `edit` for any further changes before it is
+/// written back.
+pub fn strip_chunk_with<F>(
+    chunk: &Chunk,
+    rules: &StripRules,
+    report: &mut StripReport,
+    edit: F,
+) -> Result<Chunk, io::Error>
+where
+    F: FnOnce(&mut Value, &mut StripReport),
+{
+    let mut nbt = read_nbt(&chunk.data)?;
+
+    if rules.structures.is_some() {
+        strip_structures(&mut nbt, rules, report);
+    }
+
+    if rules.ticks || rules.postprocessing {
+        strip_pending_updates(&mut nbt, rules, report);
+    }
+
+    if let Value::Compound(level) = &mut nbt {
+        if rules.keep_heightmaps.is_empty() {
+            report.remove_tag("Heightmaps", level, "Heightmaps");
+        } else if let Some(Value::Compound(heightmaps)) = level.get_mut("Heightmaps") {
+            let stripped: Vec<String> = heightmaps
+                .keys()
+                .filter(|name| rules.strips_heightmap(name))
+                .cloned()
+                .collect();
+
+            for name in stripped {
+                report.remove_tag("Heightmaps", heightmaps, &name);
+            }
+        }
+        report.remove_tag("isLightOn", level, "isLightOn");
+
+        if let Some(Value::List(sections)) = level.get_mut("sections") {
+            for section in sections {
+                if let Value::Compound(section) = section {
+                    report.remove_tag("SkyLight", section, "SkyLight");
+                    report.remove_tag("BlockLight", section, "BlockLight");
+                }
+            }
+        }
+    }
+
+    if rules.recalculate_heightmaps {
+        recalculate_heightmaps(&mut nbt, report)?;
+    }
+
+    if let Some(versions) = &rules.force_relight {
+        if versions.contains(&data_version(&nbt)) {
+            force_relight(&mut nbt);
+            report.relit_chunks += 1;
+        }
+    }
+
+    edit(&mut nbt, report);
+
+    let rewritten_data = write_nbt(&nbt)?;
+
+    report.chunks += 1;
+    report.input_bytes += chunk.data.len() as u64;
+    report.output_bytes += rewritten_data.len() as u64;
+
+    let rewritten_chunk = chunk.with_data(rewritten_data.into_boxed_slice());
+
+    Ok(rewritten_chunk)
+}
+
+fn recalculate_heightmaps(nbt: &mut Value, report: &mut StripReport) -> Result<(), io::Error> {
+    let recalculated = replace_heightmaps(nbt).map_err(|reason| {
+        Message::new(messages::STRIP_HEIGHTMAPS_FAILED)
+            .arg("reason", reason)
+            .into_error(io::ErrorKind::InvalidData)
+    })?;
+
+    if recalculated {
+        report.recalculated_chunks += 1;
+    }
+
+    Ok(())
+}
+
+/// Replaces the heightmaps of a chunk with ones computed from its blocks, returning false for chunks from before 1.13,
+/// whose heightmaps can't be computed. Chunks saved since 1.18 keep them at the root, while older chunks keep them
+/// within the `Level` compound.
+pub fn replace_heightmaps(nbt: &mut Value) -> Result<bool, String> {
+    let heightmaps = match compute_heightmaps(nbt)? {
+        Some(heightmaps) => heightmaps,
+        None => return Ok(false),
+    };
+
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return Ok(false),
+    };
+
+    let compound = match root.get_mut("Level") {
+        Some(Value::Compound(level)) => level,
+        _ => root,
+    };
+
+    let heightmaps = heightmaps
+        .into_iter()
+        .map(|(name, data)| (String::from(name), Value::LongArray(LongArray::new(data))))
+        .collect();
+
+    compound.insert(String::from("Heightmaps"), Value::Compound(heightmaps));
+
+    Ok(true)
+}
+
+/// Marks a chunk as unlit, so that the game relights it when it is loaded. Chunks saved since 1.18 keep `isLightOn` at
+/// the root, while older chunks keep it within the `Level` compound, where versions before 1.14 used `LightPopulated`
+/// instead. Both are set in older chunks, since the game ignores the one it doesn't use.
+pub fn force_relight(nbt: &mut Value) {
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return,
+    };
+
+    match root.get_mut("Level") {
+        Some(Value::Compound(level)) => {
+            level.insert(String::from("isLightOn"), Value::Byte(0));
+            level.insert(String::from("LightPopulated"), Value::Byte(0));
+        }
+        _ => {
+            root.insert(String::from("isLightOn"), Value::Byte(0));
+        }
+    }
+}
+
+/// Removes the scheduled ticks and post-processing queues from a chunk. Chunks saved since 1.18 keep them at the root
+/// as `block_ticks`, `fluid_ticks` and `PostProcessing`, while older chunks keep them within the `Level` compound, with
+/// the ticks as `TileTicks` and `LiquidTicks`.
+fn strip_pending_updates(nbt: &mut Value, rules: &StripRules, report: &mut StripReport) {
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return,
+    };
+
+    let (compound, tick_lists) = match root.get_mut("Level") {
+        Some(Value::Compound(level)) => (level, ["TileTicks", "LiquidTicks"]),
+        _ => (root, ["block_ticks", "fluid_ticks"]),
+    };
+
+    if rules.ticks {
+        for name in &tick_lists {
+            report.remove_tag(name, compound, name);
+        }
+    }
+
+    if rules.postprocessing {
+        report.remove_tag("PostProcessing", compound, "PostProcessing");
+    }
+}
+
+/// Removes the starts and references of every structure which isn't kept from a chunk. Chunks saved since 1.18 keep
+/// them in `structures`, while older chunks keep them in `Structures` within the `Level` compound.
+fn strip_structures(nbt: &mut Value, rules: &StripRules, report: &mut StripReport) {
+    let root = match nbt {
+        Value::Compound(root) => root,
+        _ => return,
+    };
+
+    let structures = match root.get_mut("Level") {
+        Some(Value::Compound(level)) => level.get_mut("Structures"),
+        _ => root.get_mut("structures"),
+    };
+
+    let structures = match structures {
+        Some(Value::Compound(structures)) => structures,
+        _ => return,
+    };
+
+    for key in &["References", "starts", "Starts"] {
+        if let Some(Value::Compound(entries)) = structures.get_mut(*key) {
+            let stripped: Vec<String> = entries
+                .keys()
+                .filter(|name| rules.strips_structure(name))
+                .cloned()
+                .collect();
+
+            for name in stripped {
+                report.remove_tag(key, entries, &name);
+            }
+        }
+    }
+}
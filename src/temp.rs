@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+
+use crate::messages::{self, report};
+use crate::read_only;
+
+#[derive(Args)]
+pub struct TempOptions {
+    #[clap(
+        long,
+        global = true,
+        help = "Directory where each run keeps its temporary files (default is the system temporary directory)"
+    )]
+    temp_dir: Option<PathBuf>,
+}
+
+/// Every run directory is named with this prefix, followed by the process ID and start time of the run.
+const RUN_DIR_PREFIX: &str = "anvil-tools-run-";
+
+/// How old a run directory must be before it is considered stale, on platforms where we can't check whether the
+/// process which created it is still running.
+#[cfg(not(unix))]
+const STALE_AGE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+struct TempState {
+    base_dir: Option<PathBuf>,
+    run_dir: Option<PathBuf>,
+}
+
+static STATE: Mutex<TempState> = Mutex::new(TempState {
+    base_dir: None,
+    run_dir: None,
+});
+
+pub fn init(options: &TempOptions) {
+    STATE.lock().unwrap().base_dir = options.temp_dir.clone();
+}
+
+/// Returns the directory which contains the run directories.
+pub fn base_dir() -> PathBuf {
+    STATE
+        .lock()
+        .unwrap()
+        .base_dir
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Returns a path in this run's temporary directory, which is created the first time it is needed. Everything in it
+/// is removed when the run ends.
+pub fn path(name: &str) -> Result<PathBuf, io::Error> {
+    let mut state = STATE.lock().unwrap();
+
+    let run_dir = match &state.run_dir {
+        Some(run_dir) => run_dir.clone(),
+        None => {
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            let run_dir = state
+                .base_dir
+                .clone()
+                .unwrap_or_else(std::env::temp_dir)
+                .join(format!(
+                    "{}{}-{}",
+                    RUN_DIR_PREFIX,
+                    std::process::id(),
+                    started
+                ));
+
+            read_only::ensure_writable(&run_dir)?;
+            fs::create_dir_all(&run_dir)?;
+
+            state.run_dir = Some(run_dir.clone());
+            run_dir
+        }
+    };
+
+    Ok(run_dir.join(name))
+}
+
+/// Moves a file staged in this run's temporary directory over the file it replaces, so that the old file is never left
+/// half-written.
+pub fn replace(temp_path: &Path, path: &Path) -> Result<(), io::Error> {
+    if fs::rename(temp_path, path).is_ok() {
+        return Ok(());
+    }
+
+    // The temporary directory may be on another file system, so copy the file next to the old one first
+    let mut sibling_path = path.as_os_str().to_owned();
+    sibling_path.push(".tmp");
+
+    fs::copy(temp_path, &sibling_path)?;
+    fs::remove_file(temp_path)?;
+    fs::rename(&sibling_path, path)
+}
+
+/// Removes this run's temporary directory when dropped, which happens even if the run panics. Runs which are killed
+/// outright leave their directory behind for `cleanup-stale` to remove.
+pub struct Cleanup;
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        let run_dir = match STATE.lock() {
+            Ok(mut state) => state.run_dir.take(),
+            Err(poisoned) => poisoned.into_inner().run_dir.take(),
+        };
+
+        if let Some(run_dir) = run_dir {
+            if let Err(err) = fs::remove_dir_all(&run_dir) {
+                report!(
+                    warn,
+                    messages::TEMP_REMOVE_FAILED,
+                    path = run_dir.display().to_string(),
+                    error = err.to_string()
+                );
+            }
+        }
+    }
+}
+
+/// Finds the run directories in the base directory which were left behind by runs which are no longer running.
+pub fn find_stale_run_dirs() -> Result<Vec<PathBuf>, io::Error> {
+    let base_dir = base_dir();
+    let mut stale = Vec::new();
+
+    if !base_dir.exists() {
+        return Ok(stale);
+    }
+
+    for entry in fs::read_dir(&base_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        let pid = match name
+            .strip_prefix(RUN_DIR_PREFIX)
+            .and_then(|rest| rest.split('-').next())
+            .and_then(|pid| pid.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        if path.is_dir() && pid != std::process::id() && !is_running(pid, &path)? {
+            stale.push(path);
+        }
+    }
+
+    stale.sort();
+
+    Ok(stale)
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32, _run_dir: &Path) -> Result<bool, io::Error> {
+    // Signal 0 only checks whether the process exists, and EPERM means it exists but belongs to another user
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return Ok(true);
+    }
+
+    Ok(io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32, run_dir: &Path) -> Result<bool, io::Error> {
+    let modified = fs::metadata(run_dir)?.modified()?;
+
+    Ok(modified.elapsed().unwrap_or_default() < STALE_AGE)
+}
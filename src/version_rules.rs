@@ -0,0 +1,219 @@
+//! Rules files for `strip` and `nbt-edit`, which remove or set tags only in chunks saved by certain versions of the
+//! game. Worlds which were partly upgraded hold chunks of many versions, whose layouts differ (such as `Level.Heightmaps`
+//! before 1.18 and `Heightmaps` since), so each rule is scoped to a range of `DataVersion`s.
+//!
+//! Rules are written in TOML as an array of `rule` tables:
+//!
+//! ```toml
+//! [[rule]]
+//! data-version = "..2860"
+//! remove = ["Level.Heightmaps"]
+//!
+//! [[rule]]
+//! data-version = "2860.."
+//! set = ["isLightOn=0b"]
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use toml::Value;
+
+use crate::messages::{self, Message};
+use crate::nbt::find_int_tag;
+use crate::nbt_path::{parse_assignment, parse_nbt_path, Assignment, NbtPath};
+
+/// Tags to remove and set in every chunk whose `DataVersion` is within a range. Chunks saved before data versions were
+/// introduced are treated as version 0.
+pub struct VersionRule {
+    pub versions: RangeInclusive<i32>,
+    pub remove: Vec<NbtPath>,
+    pub set: Vec<Assignment>,
+}
+
+impl VersionRule {
+    /// Describes the rule's range of data versions for reports, with both ends inclusive.
+    pub fn describe_versions(&self) -> String {
+        match (*self.versions.start(), *self.versions.end()) {
+            (i32::MIN, i32::MAX) => String::from(".."),
+            (i32::MIN, end) => format!("..={}", end),
+            (start, i32::MAX) => format!("{}..", start),
+            (start, end) if start == end => start.to_string(),
+            (start, end) => format!("{}..={}", start, end),
+        }
+    }
+}
+
+pub struct VersionRules {
+    pub rules: Vec<VersionRule>,
+}
+
+impl VersionRules {
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let invalid = |kind: io::ErrorKind, error: &dyn fmt::Display| {
+            Message::new(messages::RULES_INVALID)
+                .arg("path", path.display())
+                .arg("error", error)
+                .into_error(kind)
+        };
+
+        let contents = fs::read_to_string(path).map_err(|err| invalid(err.kind(), &err))?;
+
+        let table = match contents.parse::<Value>() {
+            Ok(Value::Table(table)) => table,
+            Ok(_) => unreachable!(),
+            Err(err) => return Err(invalid(io::ErrorKind::InvalidData, &err)),
+        };
+
+        let mut rules = Vec::new();
+
+        for (key, value) in &table {
+            let entries = match (key.as_str(), value) {
+                ("rule", Value::Array(entries)) => entries,
+                ("rule", _) => {
+                    return Err(invalid(
+                        io::ErrorKind::InvalidData,
+                        &"rule must be an array of tables",
+                    ))
+                }
+                _ => {
+                    return Err(invalid(
+                        io::ErrorKind::InvalidData,
+                        &format!("Unknown key: {}", key),
+                    ))
+                }
+            };
+
+            for (index, entry) in entries.iter().enumerate() {
+                let rule = parse_rule(entry)
+                    .map_err(|err| format!("rule {}: {}", index + 1, err))
+                    .map_err(|err| invalid(io::ErrorKind::InvalidData, &err))?;
+
+                rules.push(rule);
+            }
+        }
+
+        Ok(VersionRules { rules })
+    }
+
+    /// Returns the rules which apply to chunks of a data version, in the order they were written.
+    pub fn matching(&self, data_version: i32) -> impl Iterator<Item = &VersionRule> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.versions.contains(&data_version))
+    }
+
+    /// Checks whether any rule applies to a chunk, reading its data version from its serialized NBT without decoding the
+    /// rest of it.
+    pub fn applies_to(&self, data: &[u8]) -> Result<bool, io::Error> {
+        let data_version = find_int_tag(data, &["DataVersion"])?.unwrap_or(0);
+
+        Ok(self.matching(data_version).next().is_some())
+    }
+}
+
+fn parse_rule(entry: &Value) -> Result<VersionRule, String> {
+    let table = match entry {
+        Value::Table(table) => table,
+        _ => return Err(String::from("Rule must be a table")),
+    };
+
+    let mut rule = VersionRule {
+        versions: i32::MIN..=i32::MAX,
+        remove: Vec::new(),
+        set: Vec::new(),
+    };
+
+    for (key, value) in table {
+        match key.as_str() {
+            "data-version" => {
+                rule.versions = match value {
+                    Value::Integer(version) => {
+                        let version = i32::try_from(*version)
+                            .map_err(|_| format!("Invalid data version: {}", version))?;
+
+                        version..=version
+                    }
+                    Value::String(range) => parse_version_range(range)?,
+                    _ => return Err(String::from("data-version must be a number or a range")),
+                }
+            }
+            "remove" => {
+                for path in strings(key, value)? {
+                    rule.remove.push(parse_nbt_path(path)?);
+                }
+            }
+            "set" => {
+                for assignment in strings(key, value)? {
+                    rule.set.push(parse_assignment(assignment)?);
+                }
+            }
+            _ => return Err(format!("Unknown key: {}", key)),
+        }
+    }
+
+    if rule.remove.is_empty() && rule.set.is_empty() {
+        return Err(String::from("Rule has nothing to remove or set"));
+    }
+
+    Ok(rule)
+}
+
+fn strings<'a>(key: &str, value: &'a Value) -> Result<Vec<&'a str>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        _ => return Err(format!("{} must be an array of strings", key)),
+    };
+
+    values
+        .iter()
+        .map(|value| {
+            value
+                .as_str()
+                .ok_or_else(|| format!("{} must be an array of strings", key))
+        })
+        .collect()
+}
+
+/// Parses a range of data versions, which is either a single version, an exclusive range (`a..b`), or an inclusive
+/// range (`a..=b`), where either end can be left out to leave the range open.
+pub fn parse_version_range(value: &str) -> Result<RangeInclusive<i32>, String> {
+    let value = value.trim();
+    let parse = |value: &str, open: i32| {
+        let value = value.trim();
+
+        if value.is_empty() {
+            return Ok(open);
+        }
+
+        value
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid data version: {}", value))
+    };
+
+    let range = if let Some((start, end)) = value.split_once("..=") {
+        parse(start, i32::MIN)?..=parse(end, i32::MAX)?
+    } else if let Some((start, end)) = value.split_once("..") {
+        let end = match end.trim() {
+            "" => i32::MAX,
+            end => parse(end, i32::MAX)?.saturating_sub(1),
+        };
+
+        parse(start, i32::MIN)?..=end
+    } else if value.is_empty() {
+        return Err(String::from("Data version is empty"));
+    } else {
+        let version = parse(value, 0)?;
+        version..=version
+    };
+
+    if range.is_empty() {
+        return Err(format!("Data version range is empty: {}", value));
+    }
+
+    Ok(range)
+}